@@ -0,0 +1,133 @@
+//! Read throughput of blk files at several sizes of the read buffer (`--read-buffer-bytes`).
+//!
+//!     cargo bench --bench blkfile
+//!     BLK_FILE=~/.bitcoin/blocks/blk01000.dat cargo bench --bench blkfile
+//!
+//! Without BLK_FILE a synthetic file is written to a temporary directory. Only block headers are
+//! parsed, so the numbers show the cost of reading. Repeated passes are served from the page
+//! cache, drop it between runs (e.g. `echo 3 > /proc/sys/vm/drop_caches`) to measure the disk.
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use bitcoin::hashes::{sha256d, Hash};
+use rusty_blockparser::blockchain::parser::blkfile::{BlkFile, DEFAULT_READ_BUFFER};
+use rusty_blockparser::blockchain::parser::types::{Bitcoin, CoinType};
+use rusty_blockparser::blockchain::proto::header::BlockHeader;
+use rusty_blockparser::blockchain::proto::tx::{EvaluatedTx, RawTx, TxInput, TxOutpoint, TxOutput};
+use rusty_blockparser::blockchain::proto::varuint::VarUint;
+use rusty_blockparser::blockchain::proto::ToRaw;
+
+const BUFFER_SIZES: [usize; 5] = [4 * 1024, DEFAULT_READ_BUFFER, 64 * 1024, 1 << 20, 8 << 20];
+const SYNTHETIC_BLOCKS: u32 = 256;
+const TXS_PER_BLOCK: u32 = 1000;
+// Passes per buffer size, the fastest one is reported
+const PASSES: usize = 3;
+
+/// Writes blocks of about 86 KB with the Bitcoin magic and returns the path of the file
+fn write_synthetic(dir: &Path, coin: &CoinType) -> PathBuf {
+    let mut txs = VarUint::from(TXS_PER_BLOCK).to_bytes();
+    for i in 0..TXS_PER_BLOCK {
+        let tx = RawTx {
+            version: 1,
+            in_count: VarUint::from(1u8),
+            inputs: vec![TxInput {
+                outpoint: TxOutpoint::new(sha256d::Hash::hash(&i.to_le_bytes()), 0),
+                script_len: VarUint::from(1u8),
+                script_sig: vec![0x51],
+                seq_no: 0xffffffff,
+                witness: vec![],
+            }],
+            out_count: VarUint::from(1u8),
+            outputs: vec![TxOutput {
+                value: 5000,
+                script_len: VarUint::from(25u8),
+                script_pubkey: [&[0x76, 0xa9, 0x14][..], &[0xab; 20], &[0x88, 0xac]].concat(),
+            }],
+            locktime: 0,
+            version_id: 0x00,
+        };
+        txs.extend(EvaluatedTx::from(tx).to_bytes());
+    }
+    let mut data = Vec::new();
+    for nonce in 0..SYNTHETIC_BLOCKS {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 1231006505 + nonce,
+            bits: 0x1d00ffff,
+            nonce,
+        };
+        let mut block = header.to_bytes();
+        block.extend(&txs);
+        data.extend(coin.magic.to_le_bytes());
+        data.extend((block.len() as u32).to_le_bytes());
+        data.extend(block);
+    }
+    let path = dir.join("blk00000.dat");
+    fs::write(&path, data).unwrap();
+    path
+}
+
+/// Offsets of all blocks in the file, stopping at the zero padding Bitcoin Core preallocates
+fn block_offsets(path: &Path) -> Vec<u64> {
+    let mut file = File::open(path).unwrap();
+    let len = file.metadata().unwrap().len();
+    let mut offsets = Vec::new();
+    let mut offset = 0u64;
+    let mut prefix = [0u8; 8];
+    while offset + 8 <= len {
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.read_exact(&mut prefix).unwrap();
+        if prefix[..4] == [0u8; 4] {
+            break;
+        }
+        offsets.push(offset + 8);
+        offset += 8 + u32::from_le_bytes(prefix[4..8].try_into().unwrap()) as u64;
+    }
+    offsets
+}
+
+fn main() {
+    let coin = CoinType::from(Bitcoin);
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = match env::var_os("BLK_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => write_synthetic(tmp_dir.path(), &coin),
+    };
+    let offsets = block_offsets(&path);
+    let size = fs::metadata(&path).unwrap().len();
+    println!(
+        "{}: {} blocks, {} bytes",
+        path.display(),
+        offsets.len(),
+        size
+    );
+
+    for read_buffer in BUFFER_SIZES {
+        let mut blk_files = BlkFile::from_files(std::slice::from_ref(&path), read_buffer).unwrap();
+        let blk_file = blk_files.values_mut().next().unwrap();
+        let mut secs = f64::MAX;
+        for _ in 0..PASSES {
+            let start = Instant::now();
+            for offset in &offsets {
+                blk_file
+                    .read_block_without_txs(*offset, &coin)
+                    .unwrap()
+                    .unwrap();
+            }
+            // Each pass opens the file again
+            blk_file.close();
+            secs = secs.min(start.elapsed().as_secs_f64());
+        }
+        println!(
+            "{:>9} bytes: {:9.1} MB/s, {:9.0} blocks/s",
+            read_buffer,
+            size as f64 / secs / 1_000_000.0,
+            offsets.len() as f64 / secs
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Merkle root throughput of the available double-SHA256 backends.
+//!
+//!     cargo bench --features sha-ni
+use std::time::{Duration, Instant};
+
+use bitcoin::hashes::{sha256d, Hash};
+use rusty_blockparser::common::hashing;
+use rusty_blockparser::common::utils;
+
+/// Roughly the number of transactions in a full block
+const TX_COUNT: usize = 4000;
+const MIN_DURATION: Duration = Duration::from_secs(2);
+
+fn main() {
+    let txids = (0..TX_COUNT as u32)
+        .map(|i| sha256d::Hash::hash(&i.to_le_bytes()))
+        .collect::<Vec<sha256d::Hash>>();
+    let expected = utils::merkle_root_with(&hashing::Software, txids.clone());
+
+    let mut baseline = None;
+    for backend in hashing::backends().into_iter().rev() {
+        assert_eq!(utils::merkle_root_with(backend, txids.clone()), expected);
+        let start = Instant::now();
+        let mut roots = 0u64;
+        while start.elapsed() < MIN_DURATION {
+            utils::merkle_root_with(backend, txids.clone());
+            roots += 1;
+        }
+        let per_sec = roots as f64 / start.elapsed().as_secs_f64();
+        let speedup = per_sec / *baseline.get_or_insert(per_sec);
+        println!(
+            "{:>8}: {:9.1} merkle roots/s of {} txids ({:.2}x)",
+            backend.name(),
+            per_sec,
+            TX_COUNT,
+            speedup
+        );
+    }
+}
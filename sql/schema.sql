@@ -22,6 +22,11 @@ CREATE TABLE `blocks` (
   `nTime` 			int(10) unsigned                    NOT NULL,
   `nBits` 			int(10) unsigned                    NOT NULL,
   `nNonce` 			int(10) unsigned                    NOT NULL,
+  `weight` 			int(10) unsigned                    NOT NULL,
+  `vsize` 			int(10) unsigned                    NOT NULL,
+  `target` 			binary(32)                          NULL,
+  `difficulty` 		double                              NULL,
+  `medianTime` 		int(10) unsigned                    NULL,
 
   PRIMARY KEY (`id`)
 ) ENGINE=InnoDB;
@@ -35,6 +40,10 @@ CREATE TABLE `transactions` (
   `hashBlock`       binary(32)                          NOT NULL,
   `version`         int(11) unsigned               		NOT NULL,
   `lockTime`        int(10) unsigned     				NOT NULL,
+  `weight`          int(10) unsigned     				NOT NULL,
+  `vsize`           int(10) unsigned     				NOT NULL,
+  `rbf_signaled`    bool                                NOT NULL,
+  `has_relative_timelock` bool                          NOT NULL,
 
   PRIMARY KEY (`id`)
 ) ENGINE=InnoDB;
@@ -91,16 +100,21 @@ SET sql_log_bin=0;
 # skip-innodb_doublewrite
 
 
+# The first line of each file names the columns, remove IGNORE 1 LINES for dumps written with --no-header
 TRUNCATE blocks;
 ## Load blocks into table
 LOAD DATA INFILE '/media/tmp/dump/blocks-0-393489.csv'
 INTO TABLE blocks
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
-(@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce)
+IGNORE 1 LINES
+(@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce, weight, vsize, @target, @difficulty, @medianTime)
 SET hash = unhex(@hash),
 	hashPrev = unhex(@hashPrev),
-    hashMerkleRoot = unhex(@hashMerkleRoot);
+    hashMerkleRoot = unhex(@hashMerkleRoot),
+    target = unhex(nullif(@target, '')),
+    difficulty = nullif(@difficulty, ''),
+    medianTime = nullif(@medianTime, '');
 COMMIT;
 
 
@@ -110,7 +124,8 @@ LOAD DATA INFILE '/media/tmp/dump/transactions-0-393489.csv'
 INTO TABLE transactions
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
-(@txid, @hashBlock, version, lockTime)
+IGNORE 1 LINES
+(@txid, @hashBlock, version, lockTime, weight, vsize, rbf_signaled, has_relative_timelock)
 SET txid = unhex(@txid),
 	hashBlock = unhex(@hashBlock);
 COMMIT;
@@ -122,6 +137,7 @@ LOAD DATA INFILE '/media/tmp/dump/tx_out-0-393489.csv'
 INTO TABLE tx_out
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
+IGNORE 1 LINES
 (@txid, indexOut, value, @scriptPubKey, address)
 SET txid = unhex(@txid),
 	scriptPubKey = unhex(@scriptPubKey);
@@ -134,6 +150,7 @@ LOAD DATA INFILE '/media/tmp/dump/tx_in-0-393489.csv'
 INTO TABLE tx_in
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
+IGNORE 1 LINES
 (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
 SET txid = unhex(@txid),
 	hashPrevOut = unhex(@hashPrevOut);
@@ -1,39 +1,82 @@
 use std::collections::HashMap;
 use std::convert::From;
 use std::fs::{self, DirEntry, File};
-use std::io::{self, Seek, SeekFrom};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
 use seek_bufread::BufReader;
 
 use crate::blockchain::parser::reader::BlockchainRead;
 use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::common::http::{Connection, RangeReader, Url};
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
+/// Size of the read buffer of local blk files, see `--read-buffer-bytes`
+pub const DEFAULT_READ_BUFFER: usize = 8 * 1024;
+
+/// Numbers of all blk files, see `--blk-from` and `--blk-to`
+pub const ALL_BLK_FILES: RangeInclusive<u64> = 0..=u64::MAX;
+
+/// Number the file given with `--bootstrap` is stored under
+pub const BOOTSTRAP_BLK_INDEX: u64 = 0;
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
 /// Holds all necessary data about a raw blk file
-#[derive(Debug)]
 pub struct BlkFile {
     pub path: PathBuf,
     pub size: u64,
-    reader: Option<BufReader<File>>,
+    // Capacity of the buffer used for reads of local files
+    read_buffer: usize,
+    // Set if the file is fetched with HTTP range requests, `path` holds the URL then
+    url: Option<Url>,
+    // Keeps the chunks fetched from `url` in this directory
+    http_cache: Option<PathBuf>,
+    reader: Option<Box<dyn ReadSeek>>,
 }
 
 impl BlkFile {
-    fn new(path: PathBuf, size: u64) -> BlkFile {
+    fn new(path: PathBuf, size: u64, read_buffer: usize) -> BlkFile {
         BlkFile {
             path,
             size,
+            read_buffer,
+            url: None,
+            http_cache: None,
             reader: None,
         }
     }
 
+    fn remote(url: Url, size: u64, http_cache: Option<PathBuf>) -> BlkFile {
+        BlkFile {
+            path: PathBuf::from(url.to_string()),
+            url: Some(url),
+            http_cache,
+            ..BlkFile::new(PathBuf::new(), size, DEFAULT_READ_BUFFER)
+        }
+    }
+
     /// Opens the file handle (does nothing if the file has been opened already)
-    fn open(&mut self) -> OpResult<&mut BufReader<File>> {
+    fn open(&mut self) -> OpResult<&mut Box<dyn ReadSeek>> {
         if self.reader.is_none() {
-            debug!(target: "blkfile", "Opening {} ...", &self.path.display());
-            self.reader = Some(BufReader::new(File::open(&self.path)?));
+            debug!(target: "blkfile", "Opening {} ({} bytes) ...", &self.path.display(), self.size);
+            let reader: Box<dyn ReadSeek> = match self.url {
+                Some(ref url) => Box::new(RangeReader::new(
+                    url.clone(),
+                    self.size,
+                    self.http_cache.clone(),
+                )),
+                None => Box::new(BufReader::with_capacity(
+                    self.read_buffer,
+                    File::open(&self.path)?,
+                )),
+            };
+            self.reader = Some(reader);
         }
         Ok(self.reader.as_mut().unwrap())
     }
@@ -46,17 +89,158 @@ impl BlkFile {
         }
     }
 
-    pub fn read_block(&mut self, offset: u64, coin: &CoinType) -> OpResult<Block> {
+    /// Reads the block at the given offset.
+    /// Returns None if the block is truncated, e.g. because a node is still writing to the file.
+    pub fn read_block(&mut self, offset: u64, coin: &CoinType) -> OpResult<Option<Block>> {
+        self.read(offset, coin, true, false)
+    }
+
+    /// Same as read_block(), but skips parsing the transactions.
+    /// The returned block only holds the header, AuxPow data and the transaction count.
+    pub fn read_block_without_txs(
+        &mut self,
+        offset: u64,
+        coin: &CoinType,
+    ) -> OpResult<Option<Block>> {
+        self.read(offset, coin, false, false)
+    }
+
+    /// Same as read_block() or read_block_without_txs(), but keeps a copy of the serialized block
+    /// in `block.raw`.
+    pub fn read_block_with_raw(
+        &mut self,
+        offset: u64,
+        coin: &CoinType,
+        with_txs: bool,
+    ) -> OpResult<Option<Block>> {
+        self.read(offset, coin, with_txs, true)
+    }
+
+    fn read(
+        &mut self,
+        offset: u64,
+        coin: &CoinType,
+        with_txs: bool,
+        with_raw: bool,
+    ) -> OpResult<Option<Block>> {
         let reader = self.open()?;
-        reader.seek(SeekFrom::Start(offset - 4))?;
-        let block_size = reader.read_u32::<LittleEndian>()?;
-        reader.read_block(block_size, coin)
+        // Each block is prefixed with the network magic and its size
+        reader.seek(SeekFrom::Start(offset - 8))?;
+        let mut prefix = [0u8; 8];
+        if !BlkFile::read_complete(reader, &mut prefix)? {
+            warn!(target: "blkfile", "Truncated block in {} at offset {}", self.path.display(), offset);
+            return Ok(None);
+        }
+        let block_size = BlkFile::parse_prefix(&prefix, offset, coin)?;
+        let mut data = vec![0u8; block_size as usize];
+        if !BlkFile::read_complete(reader, &mut data)? {
+            warn!(target: "blkfile", "Truncated block in {} at offset {} (expected {} bytes)",
+                  self.path.display(), offset, block_size);
+            return Ok(None);
+        }
+        Ok(Some(BlkFile::parse_block(
+            &data, block_size, coin, with_txs, with_raw,
+        )?))
     }
 
-    /// Collects all blk*.dat paths in the given directory
-    pub fn from_path(path: &Path) -> OpResult<HashMap<u64, BlkFile>> {
+    fn parse_block(
+        data: &[u8],
+        block_size: u32,
+        coin: &CoinType,
+        with_txs: bool,
+        with_raw: bool,
+    ) -> OpResult<Block> {
+        let mut reader = Cursor::new(data);
+        let mut block = match with_txs {
+            true => reader.read_block(block_size, coin)?,
+            false => reader.read_block_without_txs(block_size, coin)?,
+        };
+        if with_raw {
+            block.raw = Some(data.to_vec());
+        }
+        Ok(block)
+    }
+
+    /// Checks the network magic of the 8 byte block prefix and returns the block size
+    fn parse_prefix(prefix: &[u8], offset: u64, coin: &CoinType) -> OpResult<u32> {
+        let magic = LittleEndian::read_u32(&prefix[0..4]);
+        if magic != coin.magic {
+            let msg = format!(
+                "Invalid magic {:#010x} at offset {} (expected {:#010x} for {}).",
+                magic, offset, coin.magic, coin.name
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        Ok(LittleEndian::read_u32(&prefix[4..8]))
+    }
+
+    /// Scans the file from the start for blocks framed with the network magic and their size,
+    /// like in blk files or a bootstrap.dat. Returns the offset of each block along with its header.
+    /// Data not starting with the magic is skipped up to its next occurrence, as Bitcoin Core does
+    /// when importing blocks with `-loadblock`.
+    pub fn scan_headers(&mut self, coin: &CoinType) -> OpResult<Vec<(u64, BlockHeader)>> {
+        let magic = coin.magic.to_le_bytes();
+        let reader = self.open()?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut headers = Vec::new();
+        // Position of the reader and bytes which don't belong to a block
+        let (mut pos, mut skipped) = (0u64, 0u64);
+        let mut prefix = [0u8; 8];
+        let mut header = [0u8; 80];
+        'scan: while BlkFile::read_complete(reader, &mut prefix[..4])? {
+            pos += 4;
+            while prefix[..4] != magic {
+                prefix.copy_within(1..4, 0);
+                if !BlkFile::read_complete(reader, &mut prefix[3..4])? {
+                    skipped += 3;
+                    break 'scan;
+                }
+                pos += 1;
+                skipped += 1;
+            }
+            if !BlkFile::read_complete(reader, &mut prefix[4..8])? {
+                break;
+            }
+            pos += 4;
+            let size = LittleEndian::read_u32(&prefix[4..8]) as u64;
+            if size < header.len() as u64 {
+                skipped += 8;
+                continue;
+            }
+            if !BlkFile::read_complete(reader, &mut header)? {
+                warn!(target: "blkfile", "Truncated block in {} at offset {}", self.path.display(), pos);
+                break;
+            }
+            headers.push((pos, Cursor::new(&header[..]).read_block_header()?));
+            // Truncated blocks are only noticed when they are read
+            reader.seek(SeekFrom::Current(size as i64 - header.len() as i64))?;
+            pos += size;
+        }
+        if skipped > 0 {
+            warn!(target: "blkfile", "Skipped {} bytes of {} which are not part of a block", skipped, self.path.display());
+        }
+        self.close();
+        Ok(headers)
+    }
+
+    /// Fills the whole buffer, returns false if EOF was reached before
+    fn read_complete(reader: &mut Box<dyn ReadSeek>, buf: &mut [u8]) -> OpResult<bool> {
+        match reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(OpError::from(e)),
+        }
+    }
+
+    /// Collects all blk*.dat paths in the given directory with a number within `blk_range`.
+    /// The files are read through a buffer of `read_buffer` bytes.
+    pub fn from_path(
+        path: &Path,
+        read_buffer: usize,
+        blk_range: &RangeInclusive<u64>,
+    ) -> OpResult<HashMap<u64, BlkFile>> {
         info!(target: "blkfile", "Reading files from {} ...", path.display());
-        let mut collected = HashMap::with_capacity(4000);
+        let mut collected: HashMap<u64, BlkFile> = HashMap::with_capacity(4000);
 
         for entry in fs::read_dir(path)? {
             match entry {
@@ -69,12 +253,14 @@ impl BlkFile {
                     let file_name =
                         String::from(transform!(path.as_path().file_name().unwrap().to_str()));
                     // Check if it's a valid blk file
-                    if let Some(index) = BlkFile::parse_blk_index(&file_name, "blk", ".dat") {
-                        // Build BlkFile structures
-                        let size = fs::metadata(path.as_path())?.len();
-                        trace!(target: "blkfile", "Adding {} ... (index: {}, size: {})", path.display(), index, size);
-                        collected.insert(index, BlkFile::new(path, size));
-                    }
+                    let index = match BlkFile::parse_blk_index(&file_name, "blk", ".dat") {
+                        Some(index) if blk_range.contains(&index) => index,
+                        _ => continue,
+                    };
+                    // Build BlkFile structures
+                    let size = fs::metadata(path.as_path())?.len();
+                    trace!(target: "blkfile", "Adding {} ... (index: {}, size: {})", path.display(), index, size);
+                    collected.insert(index, BlkFile::new(path, size, read_buffer));
                 }
                 Err(msg) => {
                     warn!(target: "blkfile", "Unable to read blk file!: {}", msg);
@@ -82,12 +268,105 @@ impl BlkFile {
             }
         }
 
-        trace!(target: "blkfile", "Found {} blk files", collected.len());
         if collected.is_empty() {
-            Err(OpError::new(OpErrorKind::RuntimeError).join_msg("No blk files found!"))
-        } else {
-            Ok(collected)
+            let msg = match *blk_range == ALL_BLK_FILES {
+                true => String::from("No blk files found!"),
+                false => format!(
+                    "No blk files found from number {} to {}!",
+                    blk_range.start(),
+                    blk_range.end()
+                ),
+            };
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        // Pruned nodes and assembled archives may have gaps in the numbering
+        let mut indices = collected.keys().copied().collect::<Vec<u64>>();
+        indices.sort_unstable();
+        match *blk_range == ALL_BLK_FILES {
+            true => {
+                debug!(target: "blkfile", "Found {} blk files: {}", collected.len(), format_ranges(&indices))
+            }
+            false => {
+                info!(target: "blkfile", "Using {} blk files from number {} to {}: {}",
+                      collected.len(), blk_range.start(), blk_range.end(), format_ranges(&indices))
+            }
+        }
+        let missing = indices[indices.len() - 1] + 1 - indices.len() as u64;
+        if missing > 0 {
+            warn!(target: "blkfile", "{} blk files are missing between {} and {}, present: {}",
+                  missing, indices[0], indices[indices.len() - 1], format_ranges(&indices));
+        }
+        Ok(collected)
+    }
+
+    /// Uses exactly the given blk files instead of scanning a directory.
+    /// The file names must follow the blkNNNNN.dat scheme, because the block index refers to files by number.
+    pub fn from_files(paths: &[PathBuf], read_buffer: usize) -> OpResult<HashMap<u64, BlkFile>> {
+        let mut collected: HashMap<u64, BlkFile> = HashMap::with_capacity(paths.len());
+        for path in paths {
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let index = match BlkFile::parse_blk_index(file_name, "blk", ".dat") {
+                Some(index) => index,
+                None => {
+                    let msg = format!(
+                        "{} is not named like a blk file (blkNNNNN.dat).",
+                        path.display()
+                    );
+                    return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+                }
+            };
+            if collected.contains_key(&index) {
+                let msg = format!("blk file number {} is given more than once.", index);
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+            let size = fs::metadata(path)?.len();
+            trace!(target: "blkfile", "Adding {} ... (index: {}, size: {})", path.display(), index, size);
+            collected.insert(index, BlkFile::new(path.clone(), size, read_buffer));
         }
+        Ok(collected)
+    }
+
+    /// Uses a single file of concatenated blocks (e.g. bootstrap.dat) as blk file `BOOTSTRAP_BLK_INDEX`.
+    /// Unlike with `from_files()` the name doesn't matter.
+    pub fn from_bootstrap(path: &Path, read_buffer: usize) -> OpResult<HashMap<u64, BlkFile>> {
+        let size = fs::metadata(path)?.len();
+        let blk_file = BlkFile::new(path.to_path_buf(), size, read_buffer);
+        Ok(HashMap::from([(BOOTSTRAP_BLK_INDEX, blk_file)]))
+    }
+
+    /// Collects the blk files below the given http:// URL by probing blk00000.dat, blk00001.dat, ...
+    /// until a file is missing, because there is no directory listing.
+    pub fn from_url(base: &str, http_cache: Option<&Path>) -> OpResult<HashMap<u64, BlkFile>> {
+        let base = match Url::parse(base) {
+            Ok(base) => base,
+            Err(e) => {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&e.to_string()))
+            }
+        };
+        info!(target: "blkfile", "Reading files from {} ...", &base);
+        if let Some(dir) = http_cache {
+            fs::create_dir_all(dir)?;
+        }
+        let mut connection = Connection::new();
+        let mut collected: HashMap<u64, BlkFile> = HashMap::new();
+        for index in 0.. {
+            let url = base.join(&format!("blk{:05}.dat", index));
+            let size = match connection.content_length(&url)? {
+                Some(size) => size,
+                None => break,
+            };
+            trace!(target: "blkfile", "Adding {} ... (index: {}, size: {})", &url, index, size);
+            let http_cache = http_cache.map(Path::to_path_buf);
+            collected.insert(index, BlkFile::remote(url, size, http_cache));
+        }
+        if collected.is_empty() {
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg("No blk files found!"));
+        }
+        debug!(target: "blkfile", "Found {} blk files", collected.len());
+        Ok(collected)
     }
 
     /// Resolves a PathBuf for the given entry.
@@ -114,6 +393,25 @@ impl BlkFile {
     }
 }
 
+/// Formats sorted blk file indices as comma separated ranges, e.g. `0-3, 5, 7-9`
+fn format_ranges(indices: &[u64]) -> String {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for index in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *index => *end = *index,
+            _ => ranges.push((*index, *index)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,13 +437,334 @@ mod tests {
             13412451,
             BlkFile::parse_blk_index("blk13412451.dat", prefix, ext).unwrap()
         );
+        assert!(BlkFile::parse_blk_index("blkindex.dat", prefix, ext).is_none());
+        assert!(BlkFile::parse_blk_index("invalid.dat", prefix, ext).is_none());
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        assert_eq!(format_ranges(&[0, 1, 2, 3, 5, 7, 8, 9]), "0-3, 5, 7-9");
+        assert_eq!(format_ranges(&[4]), "4");
+        assert_eq!(format_ranges(&[]), "");
+    }
+
+    #[test]
+    fn test_from_path_with_gaps() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        for name in [
+            "blk00000.dat",
+            "blk00002.dat",
+            "blk00005.dat",
+            "rev00001.dat",
+        ] {
+            fs::write(tmp_dir.path().join(name), [0u8; 8]).unwrap();
+        }
+        let blk_files =
+            BlkFile::from_path(tmp_dir.path(), DEFAULT_READ_BUFFER, &ALL_BLK_FILES).unwrap();
+        let mut indices = blk_files.keys().copied().collect::<Vec<u64>>();
+        indices.sort_unstable();
+        assert_eq!(indices, vec![0, 2, 5]);
+        assert_eq!(blk_files[&5].path, tmp_dir.path().join("blk00005.dat"));
+
+        // Only files with a number in the range are collected
+        let blk_files = BlkFile::from_path(tmp_dir.path(), DEFAULT_READ_BUFFER, &(1..=5)).unwrap();
+        let mut indices = blk_files.keys().copied().collect::<Vec<u64>>();
+        indices.sort_unstable();
+        assert_eq!(indices, vec![2, 5]);
+        let err = BlkFile::from_path(tmp_dir.path(), DEFAULT_READ_BUFFER, &(3..=4))
+            .err()
+            .unwrap();
+        assert_eq!(err.message, "No blk files found from number 3 to 4!");
+    }
+
+    #[test]
+    fn test_from_files() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let nested = tmp_dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        let first = tmp_dir.path().join("blk00003.dat");
+        let second = nested.join("blk00001.dat");
+        fs::write(&first, [0u8; 8]).unwrap();
+        fs::write(&second, [0u8; 4]).unwrap();
+
+        let blk_files =
+            BlkFile::from_files(&[first.clone(), second.clone()], DEFAULT_READ_BUFFER).unwrap();
+        assert_eq!(blk_files.len(), 2);
+        assert_eq!(blk_files[&3].path, first);
+        assert_eq!(blk_files[&3].size, 8);
+        assert_eq!(blk_files[&1].size, 4);
+
+        let err = BlkFile::from_files(&[first.clone(), first.clone()], DEFAULT_READ_BUFFER)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        let err = BlkFile::from_files(&[tmp_dir.path().join("rev00003.dat")], DEFAULT_READ_BUFFER)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        assert!(
+            BlkFile::from_files(&[tmp_dir.path().join("blk00009.dat")], DEFAULT_READ_BUFFER)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_read_block_invalid_magic() {
+        use crate::blockchain::parser::types::{Bitcoin, Litecoin};
+        use std::io::Write;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("blk00000.dat");
+        // Litecoin magic followed by an empty block size
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[0xfb, 0xc0, 0xb6, 0xdb, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+
+        let mut blk_file = BlkFile::new(path, 8, DEFAULT_READ_BUFFER);
+        let err = blk_file
+            .read_block(8, &CoinType::from(Bitcoin))
+            .unwrap_err();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+        // The magic matches, so parsing fails later on the empty block instead
+        let err = blk_file
+            .read_block(8, &CoinType::from(Litecoin))
+            .unwrap_err();
+        assert!(!matches!(err.kind, OpErrorKind::ValidationError));
+    }
+
+    #[test]
+    fn test_read_truncated_block() {
+        use crate::blockchain::parser::types::Bitcoin;
+        use std::io::Write;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("blk00000.dat");
+        // Bitcoin magic and a block size of 285 bytes, but only 80 bytes of data
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[0xf9, 0xbe, 0xb4, 0xd9, 0x1d, 0x01, 0x00, 0x00])
+            .unwrap();
+        file.write_all(&[0u8; 80]).unwrap();
+
+        let coin = CoinType::from(Bitcoin);
+        let mut blk_file = BlkFile::new(path, 88, DEFAULT_READ_BUFFER);
+        assert!(blk_file.read_block(8, &coin).unwrap().is_none());
+        // Offset beyond the end of file
+        assert!(blk_file.read_block(200, &coin).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_buffer_size() {
+        use crate::blockchain::parser::types::Bitcoin;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let data = include_bytes!("testdata/blk00000.dat");
+        fs::write(tmp_dir.path().join("blk00000.dat"), data).unwrap();
+        let coin = CoinType::from(Bitcoin);
+        // Buffers smaller than a block and larger than the file read the same blocks
+        for read_buffer in [16, 1 << 20] {
+            let mut blk_files =
+                BlkFile::from_path(tmp_dir.path(), read_buffer, &ALL_BLK_FILES).unwrap();
+            let blk_file = blk_files.get_mut(&0).unwrap();
+            assert_eq!(blk_file.read_buffer, read_buffer);
+            let block = blk_file.read_block(8 + 285 + 8, &coin).unwrap().unwrap();
+            assert_eq!(
+                format!("{}", block.header.hash),
+                "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+            );
+            let block = blk_file.read_block(8, &coin).unwrap().unwrap();
+            assert_eq!(block.header.hash, coin.genesis_hash);
+        }
+    }
+
+    #[test]
+    fn test_read_genesis_only_blk_file() {
+        use crate::blockchain::parser::types::Bitcoin;
+        use bitcoin::hashes::{sha256d, Hash};
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let data = include_bytes!("testdata/genesis.dat");
+        fs::write(tmp_dir.path().join("blk00000.dat"), data).unwrap();
+        let coin = CoinType::from(Bitcoin);
+        let mut blk_files =
+            BlkFile::from_path(tmp_dir.path(), DEFAULT_READ_BUFFER, &ALL_BLK_FILES).unwrap();
+        let blk_file = blk_files.get_mut(&0).unwrap();
+
+        let block = blk_file.read_block(8, &coin).unwrap().unwrap();
+        assert_eq!(block.header.hash, coin.genesis_hash);
+        assert_eq!(block.header.value.prev_hash, sha256d::Hash::all_zeros());
+        assert_eq!(block.txs.len(), 1);
+        let coinbase = &block.txs[0];
+        assert!(coinbase.value.is_coinbase());
+        assert_eq!(coinbase.value.outputs.len(), 1);
+        assert_eq!(coinbase.value.outputs[0].out.value, 50 * 100_000_000);
+        assert_eq!(
+            coinbase.value.outputs[0].script.address.as_deref(),
+            Some("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")
+        );
+    }
+
+    #[test]
+    fn test_scan_headers() {
+        use crate::blockchain::parser::types::Bitcoin;
+
+        let coin = CoinType::from(Bitcoin);
+        let blocks = include_bytes!("testdata/blk00000.dat");
+        let (genesis, block1) = blocks.split_at(8 + 285);
+        // Garbage with a partial magic, a frame too small for a header and zero padding at the end
+        let too_small = [&coin.magic.to_le_bytes()[..], &[4, 0, 0, 0]].concat();
+        let data = [genesis, &[0xf9, 0xbe, 0x01], &too_small, block1, &[0u8; 16]].concat();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bootstrap.dat");
+        fs::write(&path, data).unwrap();
+
+        // Any name is accepted
+        let mut blk_files = BlkFile::from_bootstrap(&path, DEFAULT_READ_BUFFER).unwrap();
+        let blk_file = blk_files.get_mut(&BOOTSTRAP_BLK_INDEX).unwrap();
+        let headers = blk_file.scan_headers(&coin).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].0, 8);
+        assert_eq!(headers[1].0, 293 + 3 + 8 + 8);
+        assert_eq!(headers[1].1.prev_hash, coin.genesis_hash);
+        let block = blk_file.read_block(headers[1].0, &coin).unwrap().unwrap();
+        assert_eq!(
+            format!("{}", block.header.hash),
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+        );
+    }
+
+    #[test]
+    fn test_read_large_block() {
+        use crate::blockchain::parser::types::BitcoinCash;
+        use crate::blockchain::proto::header::BlockHeader;
+        use crate::blockchain::proto::tx::{EvaluatedTx, RawTx, TxInput, TxOutpoint, TxOutput};
+        use crate::blockchain::proto::varuint::VarUint;
+        use crate::blockchain::proto::ToRaw;
+        use bitcoin::hashes::{sha256d, Hash};
+
+        // Three transactions with a 3 MB output script each, far beyond the limits of Bitcoin
+        let script_size = 3_000_000u32;
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0x1d00ffff,
+            nonce: 0,
+        };
+        let mut block = header.to_bytes();
+        block.extend(VarUint::from(3u8).to_bytes());
+        for i in 0..3u32 {
+            let mut script_pubkey = vec![0x6a];
+            script_pubkey.resize(script_size as usize, i as u8);
+            let tx = RawTx {
+                version: 1,
+                in_count: VarUint::from(1u8),
+                inputs: vec![TxInput {
+                    outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), i),
+                    script_len: VarUint::from(1u8),
+                    script_sig: vec![0x51],
+                    seq_no: 0xffffffff,
+                    witness: vec![],
+                }],
+                out_count: VarUint::from(1u8),
+                outputs: vec![TxOutput {
+                    value: 0,
+                    script_len: VarUint::from(script_size),
+                    script_pubkey,
+                }],
+                locktime: 0,
+                version_id: 0x00,
+            };
+            block.extend(EvaluatedTx::from(tx).to_bytes());
+        }
+        let coin = CoinType::from(BitcoinCash);
+        let mut data = coin.magic.to_le_bytes().to_vec();
+        data.extend((block.len() as u32).to_le_bytes());
+        data.extend(&block);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("blk00000.dat");
+        fs::write(&path, &data).unwrap();
+        let mut blk_file = BlkFile::new(path.clone(), data.len() as u64, DEFAULT_READ_BUFFER);
+        let parsed = blk_file.read_block(8, &coin).unwrap().unwrap();
+        assert_eq!(parsed.size as usize, block.len());
+        assert!(parsed.size > 9_000_000);
+        assert_eq!(parsed.txs.len(), 3);
+        for (i, tx) in parsed.txs.iter().enumerate() {
+            let script = &tx.value.outputs[0].out.script_pubkey;
+            assert_eq!(script.len(), script_size as usize);
+            assert_eq!(script[1], i as u8);
+        }
+    }
+
+    #[test]
+    fn test_read_block_without_txs() {
+        use crate::blockchain::parser::types::Bitcoin;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let data = include_bytes!("testdata/blk00000.dat");
+        fs::write(tmp_dir.path().join("blk00000.dat"), data).unwrap();
+        let coin = CoinType::from(Bitcoin);
+        let mut blk_files =
+            BlkFile::from_path(tmp_dir.path(), DEFAULT_READ_BUFFER, &ALL_BLK_FILES).unwrap();
+        let blk_file = blk_files.get_mut(&0).unwrap();
+        let block = blk_file.read_block_without_txs(8, &coin).unwrap().unwrap();
+        assert_eq!(block.header.hash, coin.genesis_hash);
+        assert_eq!(block.size, 285);
+        assert_eq!(block.tx_count.value, 1);
+        assert!(block.txs.is_empty());
+        assert!(block.raw.is_none());
+
+        // The raw data is the block without the magic and size prefix
+        let block = blk_file
+            .read_block_with_raw(8, &coin, false)
+            .unwrap()
+            .unwrap();
+        let raw = block.raw.unwrap();
+        assert_eq!(raw.len(), 285);
         assert_eq!(
-            true,
-            BlkFile::parse_blk_index("blkindex.dat", prefix, ext).is_none()
+            raw[..],
+            fs::read(tmp_dir.path().join("blk00000.dat")).unwrap()[8..293]
         );
+    }
+
+    #[test]
+    fn test_read_remote_blk_files() {
+        use crate::blockchain::parser::types::Bitcoin;
+        use crate::common::http::tests::serve;
+
+        let data = include_bytes!("testdata/blk00000.dat").to_vec();
+        let files = HashMap::from([
+            (String::from("blk00000.dat"), data.clone()),
+            (String::from("blk00001.dat"), data),
+        ]);
+        let (url, _) = serve(files);
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("cache");
+        let mut blk_files = BlkFile::from_url(&url, Some(&cache_path)).unwrap();
+        assert_eq!(blk_files.len(), 2);
         assert_eq!(
-            true,
-            BlkFile::parse_blk_index("invalid.dat", prefix, ext).is_none()
+            blk_files[&0].path,
+            PathBuf::from(format!("{}/blk00000.dat", url))
         );
+
+        let coin = CoinType::from(Bitcoin);
+        for index in [0, 1] {
+            let blk_file = blk_files.get_mut(&index).unwrap();
+            let block = blk_file.read_block(8, &coin).unwrap().unwrap();
+            assert_eq!(block.header.hash, coin.genesis_hash);
+            let block = blk_file.read_block(8 + 285 + 8, &coin).unwrap().unwrap();
+            assert_eq!(block.header.value.prev_hash, coin.genesis_hash);
+        }
+        let authority = url.trim_start_matches("http://").replace(['/', ':'], "_");
+        for name in ["blk00000.dat", "blk00001.dat"] {
+            let chunk = format!("{}_{}.{}.0", authority, name, 516);
+            assert!(cache_path.join(chunk).exists());
+        }
+
+        let err = BlkFile::from_url("https://example.com/blocks", None)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
     }
 }
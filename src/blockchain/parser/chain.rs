@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 
-use crate::blockchain::parser::blkfile::BlkFile;
+use crate::blockchain::parser::blkfile::{BlkFile, ALL_BLK_FILES, BOOTSTRAP_BLK_INDEX};
 use crate::blockchain::parser::index::ChainIndex;
+use crate::blockchain::parser::manifest::{Manifest, ManifestEntry};
 use crate::blockchain::parser::types::CoinType;
-use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::block::{Block, BlockLocation};
 use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
@@ -13,41 +14,166 @@ pub struct ChainStorage {
     blk_files: HashMap<u64, BlkFile>, // maps blk_index to BlkFile
     coin: CoinType,
     verify: bool,
+    verify_block_hash: bool,
+    verify_witness_commitment: bool,
+    explicit_blk_files: bool, // only the blk files given with --blk-file or --blk-from/--blk-to are available
+    with_txs: bool,           // false if the callback only needs block headers
+    with_raw: bool,           // keeps the serialized blocks for the callback
 }
 
 impl ChainStorage {
     pub fn new(options: &ParserOptions) -> OpResult<Self> {
+        let mut blk_files = if let Some(ref path) = options.bootstrap {
+            info!(target: "chain", "Scanning {} for blocks, the block index of {} is not used",
+                  path.display(), options.blockchain_dir.display());
+            BlkFile::from_bootstrap(path, options.read_buffer)?
+        } else if let Some(ref url) = options.blk_url {
+            info!(target: "chain", "Fetching blk files from {}, {} is only used for the block index",
+                  url, options.blockchain_dir.display());
+            BlkFile::from_url(url, options.http_cache.as_deref())?
+        } else if options.blk_files.is_empty() {
+            BlkFile::from_path(
+                options.blockchain_dir.as_path(),
+                options.read_buffer,
+                &options.blk_range,
+            )?
+        } else {
+            if options.blk_range != ALL_BLK_FILES {
+                warn!(target: "chain", "Ignoring the blk file range, the given blk files are used");
+            }
+            info!(target: "chain", "Using {} given blk files, {} is only used for the block index",
+                  options.blk_files.len(), options.blockchain_dir.display());
+            BlkFile::from_files(&options.blk_files, options.read_buffer)?
+        };
+        if !options.callback.needs_full_data()
+            && !options.verify
+            && !options.verify_witness_commitment
+        {
+            info!(target: "chain", "Callback only needs block headers, skipping transactions");
+        }
+        let chain_index = match options.bootstrap {
+            Some(_) => {
+                let blk_file = blk_files.get_mut(&BOOTSTRAP_BLK_INDEX).unwrap();
+                let headers = blk_file.scan_headers(&options.coin)?;
+                info!(target: "chain", "Found {} blocks", headers.len());
+                ChainIndex::from_headers(headers, BOOTSTRAP_BLK_INDEX, options)?
+            }
+            None => ChainIndex::new(options)?,
+        };
         Ok(Self {
-            chain_index: ChainIndex::new(options)?,
-            blk_files: BlkFile::from_path(options.blockchain_dir.as_path())?,
+            chain_index,
+            blk_files,
             coin: options.coin.clone(),
             verify: options.verify,
+            verify_block_hash: options.verify || options.verify_block_hash,
+            verify_witness_commitment: options.verify_witness_commitment,
+            explicit_blk_files: !options.blk_files.is_empty()
+                || (options.blk_url.is_none() && options.blk_range != ALL_BLK_FILES),
+            with_txs: options.verify
+                || options.verify_witness_commitment
+                || options.callback.needs_full_data(),
+            with_raw: options.callback.needs_raw_data(),
         })
     }
 
-    /// Returns the next block and its height
-    pub fn get_block(&mut self, height: u64) -> Option<Block> {
+    /// Returns the first height starting from the given one which is stored in an available blk file.
+    /// Without explicitly given blk files or a blk file range the height is returned as is,
+    /// so a missing blk file still ends the parsing.
+    pub fn skip_missing(&self, height: u64) -> u64 {
+        if !self.explicit_blk_files {
+            return height;
+        }
+        let mut next = height;
+        while let Some(block_meta) = self.chain_index.get(next) {
+            if self.blk_files.contains_key(&block_meta.blk_index) {
+                break;
+            }
+            next += 1;
+        }
+        if next != height {
+            debug!(target: "chain", "Skipping heights {} to {}, their blk files are not given", height, next - 1);
+        }
+        next
+    }
+
+    /// Returns the block at the given height.
+    /// Fails if verification is enabled and the block is not valid
+    pub fn get_block(&mut self, height: u64) -> OpResult<Option<Block>> {
         // Read block
-        let block_meta = self.chain_index.get(height)?;
-        let blk_file = self.blk_files.get_mut(&block_meta.blk_index)?;
-        let block = blk_file
-            .read_block(block_meta.data_offset, &self.coin)
-            .ok()?;
+        let block_meta = match self.chain_index.get(height) {
+            Some(block_meta) => block_meta,
+            None => return Ok(None),
+        };
+        let blk_file = match self.blk_files.get_mut(&block_meta.blk_index) {
+            Some(blk_file) => blk_file,
+            None => {
+                warn!(target: "chain", "Block at height {} is stored in blk file {}, which is missing",
+                      height, block_meta.blk_index);
+                return Ok(None);
+            }
+        };
+        let result = match (self.with_raw, self.with_txs) {
+            (true, with_txs) => {
+                blk_file.read_block_with_raw(block_meta.data_offset, &self.coin, with_txs)
+            }
+            (false, true) => blk_file.read_block(block_meta.data_offset, &self.coin),
+            (false, false) => blk_file.read_block_without_txs(block_meta.data_offset, &self.coin),
+        };
 
         // Check if blk file can be closed
         if height == self.chain_index.max_height_by_blk(block_meta.blk_index) {
             blk_file.close()
         }
 
+        // Stop at the last complete block if the blk file is truncated
+        let mut block = match result? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        block.location = Some(BlockLocation {
+            path: blk_file.path.clone(),
+            offset: block_meta.data_offset,
+        });
+        block.median_time_past = self.chain_index.median_time_past(height);
+        block.confirmations = Some(self.chain_index.confirmations(height));
+
+        if self.verify_block_hash {
+            self.verify_block_hash(&block, height)?;
+        }
         if self.verify {
-            self.verify(&block, height).unwrap();
+            self.verify(&block, height)?;
+        }
+        if self.verify_witness_commitment {
+            block.verify_witness_commitment().map_err(|e| {
+                e.join_msg(&format!(
+                    "  -> height: {}\n  -> block: {}\n",
+                    height, &block.header.hash
+                ))
+            })?;
         }
 
-        Some(block)
+        Ok(Some(block))
+    }
+
+    /// Verifies that the header hash matches the hash recorded in the block index.
+    /// This catches disk corruption and blocks stored at unexpected offsets.
+    fn verify_block_hash(&self, block: &Block, height: u64) -> OpResult<()> {
+        let expected = &self
+            .chain_index
+            .get(height)
+            .expect("unable to fetch block in chain index")
+            .block_hash;
+        if &block.header.hash != expected {
+            let msg = format!(
+                "Block hash at height {} doesn't match the block index!\n  -> expected: {}\n  -> got: {}\n",
+                height, expected, &block.header.hash,
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        Ok(())
     }
 
     /// Verifies the given block in a chain.
-    /// Panics if not valid
     fn verify(&self, block: &Block, height: u64) -> OpResult<()> {
         block.verify_merkle_root()?;
         if height == 0 {
@@ -75,7 +201,73 @@ impl ChainStorage {
         Ok(())
     }
 
+    /// Returns the name of the blk file which contains the block at the given height
+    /// and the offset of the block within it
+    pub(crate) fn block_position(&self, height: u64) -> Option<(String, u64)> {
+        let block_meta = self.chain_index.get(height)?;
+        let blk_file = self.blk_files.get(&block_meta.blk_index)?;
+        let file_name = blk_file.path.file_name()?;
+        Some((
+            file_name.to_string_lossy().into_owned(),
+            block_meta.data_offset,
+        ))
+    }
+
+    /// Hashes all available blk files along with the number of main chain blocks they contain
+    pub fn manifest(&self) -> OpResult<Manifest> {
+        let mut blk_indexes = self.blk_files.keys().copied().collect::<Vec<u64>>();
+        blk_indexes.sort_unstable();
+        let mut manifest = Manifest::new();
+        for blk_index in blk_indexes {
+            let blk_file = &self.blk_files[&blk_index];
+            debug!(target: "chain", "Hashing {} ...", blk_file.path.display());
+            let entry = ManifestEntry::from_file(
+                &blk_file.path,
+                self.chain_index.block_count_by_blk(blk_index),
+            )?;
+            let file_name = blk_file.path.file_name().unwrap_or_default();
+            manifest.insert(file_name.to_string_lossy().into_owned(), entry);
+        }
+        Ok(manifest)
+    }
+
     pub(crate) fn max_height(&self) -> u64 {
         self.chain_index.max_height()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::types::Bitcoin;
+    use crate::callbacks::simplestats::SimpleStats;
+    use crate::callbacks::Callback;
+    use std::fs;
+
+    #[test]
+    fn test_bootstrap() {
+        // Genesis block and block 1, the genesis block is repeated at the end
+        let blocks = include_bytes!("testdata/blk00000.dat").to_vec();
+        let data = [&blocks[..], &blocks[..8 + 285]].concat();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bootstrap.dat");
+        fs::write(&path, data).unwrap();
+
+        let matches = SimpleStats::build_subcommand().get_matches_from(["simplestats"]);
+        let callback = Box::new(SimpleStats::new(&matches).unwrap());
+        // No block index is needed
+        let mut options = ParserOptions::new(callback, tmp_dir.path().join("missing"));
+        options.bootstrap = Some(path);
+        options.verify = true;
+        let mut chain_storage = ChainStorage::new(&options).unwrap();
+        assert_eq!(chain_storage.max_height(), 1);
+
+        let coin = CoinType::from(Bitcoin);
+        let block = chain_storage.get_block(0).unwrap().unwrap();
+        assert_eq!(block.header.hash, coin.genesis_hash);
+        let block = chain_storage.get_block(1).unwrap().unwrap();
+        assert_eq!(block.header.value.prev_hash, coin.genesis_hash);
+        assert_eq!(block.txs.len(), 1);
+        assert!(chain_storage.get_block(2).unwrap().is_none());
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Persists the last processed block height, so an interrupted run can be resumed
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub interval: u64,
+}
+
+impl Checkpoint {
+    pub fn new(path: PathBuf, interval: u64) -> OpResult<Self> {
+        if interval == 0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("--checkpoint-interval must be greater than 0"));
+        }
+        Ok(Self { path, interval })
+    }
+
+    /// Returns the last processed height stored in the checkpoint file
+    pub fn load(&self) -> OpResult<u64> {
+        let content = fs::read_to_string(&self.path)?;
+        content.trim().parse::<u64>().map_err(|_| {
+            OpError::new(OpErrorKind::RuntimeError)
+                .join_msg(&format!("Invalid checkpoint file: {}", self.path.display()))
+        })
+    }
+
+    /// Atomically stores the given height.
+    /// The height is written to a temporary file first which is renamed afterwards,
+    /// so an interrupt never leaves a partially written checkpoint behind.
+    pub fn save(&self, height: u64) -> OpResult<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(format!("{}\n", height).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        debug!(target: "checkpoint", "Saved checkpoint at height {} to {}", height, self.path.display());
+        Ok(())
+    }
+
+    /// Returns true if a checkpoint should be written after the given height
+    pub fn is_due(&self, height: u64) -> bool {
+        (height + 1).is_multiple_of(self.interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_save_load() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let checkpoint = Checkpoint::new(tmp_dir.path().join("checkpoint"), 100).unwrap();
+        assert!(checkpoint.load().is_err());
+
+        checkpoint.save(99).unwrap();
+        assert_eq!(checkpoint.load().unwrap(), 99);
+        checkpoint.save(199).unwrap();
+        assert_eq!(checkpoint.load().unwrap(), 199);
+        assert!(!tmp_dir.path().join("checkpoint.tmp").exists());
+
+        assert!(checkpoint.is_due(99));
+        assert!(!checkpoint.is_due(100));
+        assert!(Checkpoint::new(tmp_dir.path().join("checkpoint"), 0).is_err());
+    }
+}
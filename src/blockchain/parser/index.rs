@@ -1,33 +1,86 @@
 use bitcoin::hashes::{sha256d, Hash};
-use std::collections::HashMap;
+use bitcoin::pow::{CompactTarget, Target, Work};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::fmt;
+use std::fs;
 use std::io::Cursor;
+use std::ops::Range;
 use std::path::Path;
 
 use byteorder::ReadBytesExt;
 use rusty_leveldb::{LdbIterator, Options, DB};
 
-use crate::errors::OpResult;
+use crate::blockchain::parser::reader::BlockchainRead;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::ToRaw;
+use crate::common::hashing;
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
-const BLOCK_VALID_CHAIN: u64 = 4;
 const BLOCK_HAVE_DATA: u64 = 8;
+const BLOCK_HAVE_UNDO: u64 = 16;
+const BLOCK_FAILED_MASK: u64 = 32 | 64;
+// Number of previous blocks whose median timestamp is the median-time-past (BIP113)
+const MEDIAN_TIME_SPAN: u64 = 11;
+// Seconds a block may be ahead of the network-adjusted time of a node (MAX_FUTURE_BLOCK_TIME)
+const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
 
 /// Holds the index of longest valid chain
 pub struct ChainIndex {
     max_height: u64,
+    // Height of the best block in the index, regardless of the range
+    tip_height: u64,
     block_index: HashMap<u64, BlockIndexRecord>,
     max_height_blk_index: HashMap<u64, u64>, // Maps blk_index to max_height found in the file
+    block_count_blk_index: HashMap<u64, u64>, // Maps blk_index to the number of main chain blocks in the file
 }
 
 impl ChainIndex {
     pub fn new(options: &ParserOptions) -> OpResult<Self> {
         let path = options.blockchain_dir.join("index");
-        let mut block_index = get_block_index(&path)?;
+        let (block_index, stale_blocks) = get_block_index(&path, verify_pow(options))?;
+        ChainIndex::from_main_chain(block_index, &stale_blocks, options)
+    }
+
+    /// Builds the index from the headers of a file of concatenated blocks (see `--bootstrap`)
+    /// instead of the block index of Bitcoin Core. All blocks are stored in blk file `blk_index`.
+    pub fn from_headers(
+        headers: Vec<(u64, BlockHeader)>,
+        blk_index: u64,
+        options: &ParserOptions,
+    ) -> OpResult<Self> {
+        let (block_index, stale_blocks) =
+            get_bootstrap_index(headers, blk_index, verify_pow(options));
+        if block_index.is_empty() {
+            return Err(OpError::new(OpErrorKind::ValidationError)
+                .join_msg("No blocks starting with a genesis block found."));
+        }
+        ChainIndex::from_main_chain(block_index, &stale_blocks, options)
+    }
+
+    fn from_main_chain(
+        mut block_index: HashMap<u64, BlockIndexRecord>,
+        stale_blocks: &[BlockIndexRecord],
+        options: &ParserOptions,
+    ) -> OpResult<Self> {
+        if block_index.is_empty() {
+            return Err(OpError::new(OpErrorKind::ValidationError)
+                .join_msg("No blocks of the main chain found in the block index."));
+        }
+        if let Some(ref report_path) = options.report_orphans {
+            let report = format_stale_report(&block_index, stale_blocks, options.hash_order);
+            fs::write(report_path, report)?;
+            info!(target: "index", "Wrote stale block report to {}", report_path.display());
+        }
         let mut max_height_blk_index = HashMap::new();
+        let mut block_count_blk_index = HashMap::new();
 
         for (height, index_record) in &block_index {
+            *block_count_blk_index
+                .entry(index_record.blk_index)
+                .or_insert(0) += 1;
             match max_height_blk_index.get(&index_record.blk_index) {
                 Some(cur_height) if height > cur_height => {
                     max_height_blk_index.insert(index_record.blk_index, *height);
@@ -45,19 +98,28 @@ impl ChainIndex {
             Some(height) if height < max_known_height => height,
             Some(_) | None => max_known_height,
         };
+        if let Some(ref report_path) = options.report_timestamp_anomalies {
+            let (report, count) =
+                format_timestamp_report(&block_index, min_height, max_height, options.hash_order);
+            fs::write(report_path, report)?;
+            info!(target: "index", "Wrote {} timestamp anomalies to {}", count, report_path.display());
+        }
 
-        // Filter to only keep relevant block index
+        // Filter to only keep relevant block index, along with the predecessors
+        // needed to verify the first block and compute its median-time-past
         if !options.range.is_default() {
             info!(target: "index", "Trimming block index from height {} to {} ...", min_height, max_height);
             block_index.retain(|height, _| {
-                *height >= min_height.saturating_sub(1) && *height <= max_height
+                *height >= min_height.saturating_sub(MEDIAN_TIME_SPAN) && *height <= max_height
             });
         }
 
         Ok(Self {
             max_height,
+            tip_height: max_known_height,
             block_index,
             max_height_blk_index,
+            block_count_blk_index,
         })
     }
 
@@ -66,11 +128,34 @@ impl ChainIndex {
         self.block_index.get(&height)
     }
 
+    /// Returns the median timestamp of the up to 11 blocks before the given height (BIP113),
+    /// which lock times are compared against. The genesis block has no predecessors.
+    pub fn median_time_past(&self, height: u64) -> Option<u32> {
+        median_timestamp(
+            &self.block_index,
+            height.saturating_sub(MEDIAN_TIME_SPAN)..height,
+        )
+    }
+
+    /// Returns the number of confirmations of the block at the given height as of the tip,
+    /// the tip itself has one. The tip is the best block of the index even if the range ends earlier.
+    pub fn confirmations(&self, height: u64) -> u64 {
+        (self.tip_height + 1).saturating_sub(height)
+    }
+
     /// Returns the maximum height known
     pub fn max_height(&self) -> u64 {
         self.max_height
     }
 
+    /// Returns the number of main chain blocks stored in the given blk_index, regardless of the range
+    pub fn block_count_by_blk(&self, blk_index: u64) -> u64 {
+        self.block_count_blk_index
+            .get(&blk_index)
+            .copied()
+            .unwrap_or(0)
+    }
+
     /// Returns the maximum height that can be found in the given blk_index
     pub fn max_height_by_blk(&self, blk_index: u64) -> u64 {
         *self.max_height_blk_index.get(&blk_index).unwrap()
@@ -87,6 +172,7 @@ pub struct BlockIndexRecord {
     height: u64,
     status: u64,
     tx_count: u64,
+    header: BlockHeader,
 }
 
 impl BlockIndexRecord {
@@ -98,8 +184,20 @@ impl BlockIndexRecord {
         let height = read_varint(&mut reader)?;
         let status = read_varint(&mut reader)?;
         let tx_count = read_varint(&mut reader)?;
-        let blk_index = read_varint(&mut reader)?;
-        let data_offset = read_varint(&mut reader)?;
+        let blk_index = match status & (BLOCK_HAVE_DATA | BLOCK_HAVE_UNDO) {
+            0 => 0,
+            _ => read_varint(&mut reader)?,
+        };
+        let data_offset = match status & BLOCK_HAVE_DATA {
+            0 => 0,
+            _ => read_varint(&mut reader)?,
+        };
+        if status & BLOCK_HAVE_UNDO > 0 {
+            read_varint(&mut reader)?;
+        }
+
+        // Followed by the serialized block header
+        let header = reader.read_block_header()?;
 
         Ok(BlockIndexRecord {
             block_hash: sha256d::Hash::from_byte_array(block_hash),
@@ -109,8 +207,20 @@ impl BlockIndexRecord {
             tx_count,
             blk_index,
             data_offset,
+            header,
         })
     }
+
+    /// Returns the expected number of hashes required to produce this block
+    fn work(&self) -> Work {
+        Target::from_compact(CompactTarget::from_consensus(self.header.bits)).to_work()
+    }
+
+    /// Returns true if the stored header hashes to the block hash and meets the target of its nBits
+    fn has_valid_pow(&self) -> bool {
+        let hash = hashing::sha256d(&self.header.to_bytes());
+        hash == self.block_hash && self.header.meets_target(&hash)
+    }
 }
 
 impl fmt::Debug for BlockIndexRecord {
@@ -123,14 +233,21 @@ impl fmt::Debug for BlockIndexRecord {
             .field("n_tx", &self.tx_count)
             .field("n_file", &self.blk_index)
             .field("n_data_pos", &self.data_offset)
+            .field("prev_hash", &self.header.prev_hash)
+            .field("n_bits", &self.header.bits)
             .finish()
     }
 }
 
-pub fn get_block_index(path: &Path) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+/// Returns the main chain keyed by height along with all stale blocks
+/// With `verify_pow` blocks failing the proof of work are excluded from the main chain selection.
+pub fn get_block_index(
+    path: &Path,
+    verify_pow: bool,
+) -> OpResult<(HashMap<u64, BlockIndexRecord>, Vec<BlockIndexRecord>)> {
     info!(target: "index", "Reading index from {} ...", path.display());
 
-    let mut block_index = HashMap::with_capacity(900000);
+    let mut records = Vec::with_capacity(900000);
     let mut db_iter = DB::open(path, Options::default())?.new_iter()?;
     let (mut key, mut value) = (vec![], vec![]);
 
@@ -138,13 +255,274 @@ pub fn get_block_index(path: &Path) -> OpResult<HashMap<u64, BlockIndexRecord>>
         db_iter.current(&mut key, &mut value);
         if is_block_index_record(&key) {
             let record = BlockIndexRecord::from(&key[1..], &value)?;
-            if record.status & (BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA) > 0 {
-                block_index.insert(record.height, record);
+            if record.status & BLOCK_HAVE_DATA > 0 && record.status & BLOCK_FAILED_MASK == 0 {
+                records.push(record);
             }
         }
     }
+    if verify_pow {
+        records = reject_invalid_pow(records);
+    }
+    let (block_index, stale_blocks) = select_main_chain(records);
     info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
-    Ok(block_index)
+    if !stale_blocks.is_empty() {
+        info!(target: "index", "Found {} stale blocks not part of the longest chain", stale_blocks.len());
+    }
+    Ok((block_index, stale_blocks))
+}
+
+/// Returns the main chain keyed by height along with all stale blocks for the headers of a file
+/// of concatenated blocks, given along with the offset of their block. Heights are derived from
+/// the parent, which has to be stored before its children as in a bootstrap.dat.
+pub fn get_bootstrap_index(
+    headers: Vec<(u64, BlockHeader)>,
+    blk_index: u64,
+    verify_pow: bool,
+) -> (HashMap<u64, BlockIndexRecord>, Vec<BlockIndexRecord>) {
+    let mut heights: HashMap<sha256d::Hash, u64> = HashMap::with_capacity(headers.len());
+    let mut records = Vec::with_capacity(headers.len());
+    let (mut orphans, mut duplicates) = (0, 0);
+    for (offset, header) in headers {
+        let block_hash = hashing::sha256d(&header.to_bytes());
+        let height = match heights.get(&header.prev_hash) {
+            _ if header.prev_hash == sha256d::Hash::all_zeros() => 0,
+            Some(parent_height) => parent_height + 1,
+            None => {
+                orphans += 1;
+                continue;
+            }
+        };
+        if heights.insert(block_hash, height).is_some() {
+            duplicates += 1;
+            continue;
+        }
+        records.push(BlockIndexRecord {
+            block_hash,
+            blk_index,
+            data_offset: offset,
+            version: 0,
+            height,
+            status: BLOCK_HAVE_DATA,
+            tx_count: 0,
+            header,
+        });
+    }
+    if orphans > 0 {
+        warn!(target: "index", "Skipped {} blocks which are not preceded by their parent", orphans);
+    }
+    if duplicates > 0 {
+        warn!(target: "index", "Skipped {} blocks which are stored more than once", duplicates);
+    }
+    if verify_pow {
+        records = reject_invalid_pow(records);
+    }
+    let (block_index, stale_blocks) = select_main_chain(records);
+    info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
+    if !stale_blocks.is_empty() {
+        info!(target: "index", "Found {} stale blocks not part of the longest chain", stale_blocks.len());
+    }
+    (block_index, stale_blocks)
+}
+
+/// Returns true if blocks failing the proof of work are to be rejected, which is only possible
+/// if the coin has it in the header (see `CoinType::has_header_pow()`)
+fn verify_pow(options: &ParserOptions) -> bool {
+    if options.verify_pow && !options.coin.has_header_pow() {
+        warn!(target: "index", "Ignoring --verify-pow, the proof of work of {} blocks isn't their double-SHA256 hash \
+                                or is merge-mined.", options.coin.name);
+        return false;
+    }
+    options.verify_pow
+}
+
+/// Removes all blocks failing the proof of work along with their descendants
+fn reject_invalid_pow(mut records: Vec<BlockIndexRecord>) -> Vec<BlockIndexRecord> {
+    // Parents are processed before their children
+    records.sort_unstable_by_key(|record| record.height);
+
+    let mut rejected = HashSet::new();
+    let mut descendants = 0;
+    records.retain(|record| {
+        if rejected.contains(&record.header.prev_hash) {
+            rejected.insert(record.block_hash);
+            descendants += 1;
+            return false;
+        }
+        if !record.has_valid_pow() {
+            warn!(target: "index", "Rejecting block {} at height {}: invalid proof of work",
+                  &record.block_hash, record.height);
+            rejected.insert(record.block_hash);
+            return false;
+        }
+        true
+    });
+    if descendants > 0 {
+        warn!(target: "index", "Rejected {} descendants of blocks with invalid proof of work", descendants);
+    }
+    records
+}
+
+/// Builds a tree of all given blocks and returns the chain with the most cumulative work, keyed by height.
+/// Stale blocks share their height with main chain blocks and are returned separately, ordered by height.
+/// Ties are resolved in favour of the lower block hash to keep the result deterministic.
+fn select_main_chain(
+    mut records: Vec<BlockIndexRecord>,
+) -> (HashMap<u64, BlockIndexRecord>, Vec<BlockIndexRecord>) {
+    // Parents are processed before their children
+    records.sort_unstable_by_key(|record| record.height);
+
+    let mut chain_work: HashMap<sha256d::Hash, (Work, usize)> =
+        HashMap::with_capacity(records.len());
+    let mut tip: Option<(Work, usize)> = None;
+    for (i, record) in records.iter().enumerate() {
+        let work = match chain_work.get(&record.header.prev_hash) {
+            Some((parent_work, _)) => *parent_work + record.work(),
+            None => record.work(),
+        };
+        chain_work.insert(record.block_hash, (work, i));
+        tip = match tip {
+            Some((tip_work, tip_index))
+                if tip_work > work
+                    || (tip_work == work && records[tip_index].block_hash < record.block_hash) =>
+            {
+                Some((tip_work, tip_index))
+            }
+            _ => Some((work, i)),
+        };
+    }
+
+    // Walk back from the tip to collect the main chain
+    let mut in_main_chain = vec![false; records.len()];
+    let mut next = tip.map(|(_, i)| i);
+    while let Some(i) = next {
+        in_main_chain[i] = true;
+        next = chain_work
+            .get(&records[i].header.prev_hash)
+            .map(|(_, i)| *i);
+    }
+
+    let mut main_chain = HashMap::with_capacity(records.len());
+    let mut stale_blocks = Vec::new();
+    for (record, in_main_chain) in records.into_iter().zip(in_main_chain) {
+        match in_main_chain {
+            true => {
+                main_chain.insert(record.height, record);
+            }
+            false => stale_blocks.push(record),
+        }
+    }
+    (main_chain, stale_blocks)
+}
+
+/// Returns the median timestamp of the blocks at the given heights, None if none of them is known
+fn median_timestamp(
+    block_index: &HashMap<u64, BlockIndexRecord>,
+    heights: Range<u64>,
+) -> Option<u32> {
+    let mut timestamps = heights
+        .filter_map(|h| block_index.get(&h))
+        .map(|record| record.header.timestamp)
+        .collect::<Vec<u32>>();
+    if timestamps.is_empty() {
+        return None;
+    }
+    // Same as GetMedianTimePast() of Bitcoin Core, which takes the upper median
+    timestamps.sort_unstable();
+    Some(timestamps[timestamps.len() / 2])
+}
+
+/// Formats the blocks from `start` to `end` with an unusual timestamp as csv, one row per anomaly.
+/// Returns the report along with the number of anomalies:
+/// * `before_parent`: the timestamp is earlier than the one of the parent
+/// * `not_after_mtp`: the timestamp isn't after the median-time-past, which consensus requires
+/// * `future`: the timestamp is more than two hours ahead of the median of the 11 blocks centered on it.
+///   Nodes compare against their clock instead, neighbors are the best guess of the time the block was found.
+fn format_timestamp_report(
+    main_chain: &HashMap<u64, BlockIndexRecord>,
+    start: u64,
+    end: u64,
+    hash_order: HashOrder,
+) -> (String, u64) {
+    let mut report = String::from("height;hash;timestamp;anomaly;reference\n");
+    let mut count = 0;
+    let half_span = MEDIAN_TIME_SPAN / 2;
+    for height in start..=end {
+        let record = match main_chain.get(&height) {
+            Some(record) => record,
+            None => continue,
+        };
+        let timestamp = record.header.timestamp;
+        let mut anomalies = Vec::new();
+        if let Some(parent) = height.checked_sub(1).and_then(|h| main_chain.get(&h)) {
+            if timestamp < parent.header.timestamp {
+                anomalies.push(("before_parent", parent.header.timestamp));
+            }
+        }
+        let mtp_heights = height.saturating_sub(MEDIAN_TIME_SPAN)..height;
+        if let Some(median_time_past) = median_timestamp(main_chain, mtp_heights) {
+            if timestamp <= median_time_past {
+                anomalies.push(("not_after_mtp", median_time_past));
+            }
+        }
+        // Only blocks with all successors of the window known, the tip may still be ahead
+        if main_chain.contains_key(&(height + half_span)) {
+            let window = height.saturating_sub(half_span)..height + half_span + 1;
+            let median = median_timestamp(main_chain, window).unwrap();
+            if timestamp > median.saturating_add(MAX_FUTURE_BLOCK_TIME) {
+                anomalies.push(("future", median));
+            }
+        }
+        for (anomaly, reference) in anomalies {
+            report.push_str(&format!(
+                "{};{};{};{};{}\n",
+                height,
+                hash_order.fmt(&record.block_hash),
+                timestamp,
+                anomaly,
+                reference
+            ));
+            count += 1;
+        }
+    }
+    (report, count)
+}
+
+/// Formats the stale blocks as csv. The fork height is the height of the last main chain block
+/// the stale branch builds on, it is empty if the branch doesn't connect to the main chain.
+fn format_stale_report(
+    main_chain: &HashMap<u64, BlockIndexRecord>,
+    stale_blocks: &[BlockIndexRecord],
+    hash_order: HashOrder,
+) -> String {
+    let main_chain_heights: HashMap<sha256d::Hash, u64> = main_chain
+        .values()
+        .map(|record| (record.block_hash, record.height))
+        .collect();
+    let stale_parents: HashMap<sha256d::Hash, sha256d::Hash> = stale_blocks
+        .iter()
+        .map(|record| (record.block_hash, record.header.prev_hash))
+        .collect();
+
+    let mut report = String::from("height;hash;prevHash;blkIndex;forkHeight\n");
+    for record in stale_blocks {
+        // Follow the stale branch down to the main chain
+        let mut prev_hash = record.header.prev_hash;
+        while let Some(parent) = stale_parents.get(&prev_hash) {
+            prev_hash = *parent;
+        }
+        let fork_height = main_chain_heights
+            .get(&prev_hash)
+            .map_or_else(String::new, |height| height.to_string());
+        report.push_str(&format!(
+            "{};{};{};{};{}\n",
+            record.height,
+            hash_order.fmt(&record.block_hash),
+            hash_order.fmt(&record.header.prev_hash),
+            record.blk_index,
+            fork_height
+        ));
+    }
+    report
 }
 
 #[inline]
@@ -173,3 +551,276 @@ fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<u64> {
     }
     Ok(n)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_record(hash: u8, prev_hash: u8, height: u64, bits: u32) -> BlockIndexRecord {
+        BlockIndexRecord {
+            block_hash: sha256d::Hash::from_byte_array([hash; 32]),
+            blk_index: 0,
+            data_offset: 0,
+            version: 0,
+            height,
+            status: BLOCK_HAVE_DATA,
+            tx_count: 1,
+            header: BlockHeader {
+                version: 1,
+                prev_hash: sha256d::Hash::from_byte_array([prev_hash; 32]),
+                merkle_root: sha256d::Hash::all_zeros(),
+                timestamp: 0,
+                bits,
+                nonce: 0,
+            },
+        }
+    }
+
+    /// Record of a block on top of `prev_hash` with the first nonce meeting the target
+    fn mined_record(prev_hash: sha256d::Hash, height: u64, bits: u32) -> BlockIndexRecord {
+        let mut record = new_record(0, 0, height, bits);
+        record.header.prev_hash = prev_hash;
+        loop {
+            record.block_hash = sha256d::Hash::hash(&record.header.to_bytes());
+            if record.header.meets_target(&record.block_hash) {
+                return record;
+            }
+            record.header.nonce += 1;
+        }
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let mut block_index = HashMap::new();
+        // Timestamps of the Bitcoin genesis block and block 1, then out of order ones
+        let timestamps = [
+            1231006505, 1231469665, 1231469000, 1231470000, 1231460000, 1231480000, 1231475000,
+            1231475000, 1231490000, 1231485000, 1231495000, 1231500000, 1231510000,
+        ];
+        for (height, timestamp) in timestamps.iter().enumerate() {
+            let mut record = new_record(height as u8, 0, height as u64, 0);
+            record.header.timestamp = *timestamp;
+            block_index.insert(height as u64, record);
+        }
+        let chain_index = ChainIndex {
+            max_height: timestamps.len() as u64 - 1,
+            tip_height: timestamps.len() as u64 - 1,
+            block_index,
+            max_height_blk_index: HashMap::new(),
+            block_count_blk_index: HashMap::new(),
+        };
+        assert_eq!(chain_index.median_time_past(0), None);
+        assert_eq!(chain_index.median_time_past(1), Some(1231006505));
+        // The upper one of two middle timestamps
+        assert_eq!(chain_index.median_time_past(2), Some(1231469665));
+        assert_eq!(chain_index.median_time_past(3), Some(1231469000));
+        // Median of heights 0 to 10, the first with 11 predecessors
+        assert_eq!(chain_index.median_time_past(11), Some(1231475000));
+        // Median of heights 2 to 12, block 1 and the genesis block fell out of the window
+        assert_eq!(chain_index.median_time_past(13), Some(1231480000));
+
+        assert_eq!(chain_index.confirmations(12), 1);
+        assert_eq!(chain_index.confirmations(0), 13);
+    }
+
+    #[test]
+    fn test_timestamp_report() {
+        let genesis_time = 1231006505;
+        let mut timestamps = (0..16)
+            .map(|h| genesis_time + 600 * h)
+            .collect::<Vec<u32>>();
+        // Three hours ahead, the next block is back to normal
+        timestamps[6] += 3 * 3600;
+        // Equals the median-time-past
+        timestamps[12] = timestamps[7];
+        let mut main_chain = HashMap::new();
+        for (height, timestamp) in timestamps.iter().enumerate() {
+            let mut record = new_record(height as u8, 0, height as u64, 0);
+            record.header.timestamp = *timestamp;
+            main_chain.insert(height as u64, record);
+        }
+        let hash = |height: u64| main_chain.get(&height).unwrap().block_hash;
+
+        let (report, count) = format_timestamp_report(&main_chain, 0, 15, HashOrder::Display);
+        assert_eq!(count, 4);
+        assert_eq!(
+            report.lines().collect::<Vec<&str>>(),
+            vec![
+                String::from("height;hash;timestamp;anomaly;reference"),
+                format!("6;{};1231020905;future;1231010705", hash(6)),
+                format!("7;{};1231010705;before_parent;1231020905", hash(7)),
+                format!("12;{};1231010705;before_parent;1231013105", hash(12)),
+                format!("12;{};1231010705;not_after_mtp;1231010705", hash(12)),
+            ]
+        );
+
+        // Predecessors outside of the range are still compared with
+        let (report, count) = format_timestamp_report(&main_chain, 7, 11, HashOrder::Display);
+        assert_eq!(count, 1);
+        assert!(report.ends_with(&format!(
+            "7;{};1231010705;before_parent;1231020905\n",
+            hash(7)
+        )));
+        // Without all successors of the window a block is not checked for being ahead
+        main_chain.remove(&11);
+        assert_eq!(
+            format_timestamp_report(&main_chain, 6, 6, HashOrder::Display).1,
+            0
+        );
+    }
+
+    #[test]
+    fn test_select_main_chain_most_work() {
+        // 1 <- 2 <- 3 <- 4    (easy blocks, longer)
+        //      ^
+        //      +--- 5         (single block with more work than 3 and 4 combined)
+        let easy = 0x207fffff;
+        let hard = 0x1d00ffff;
+        let records = vec![
+            new_record(4, 3, 3, easy),
+            new_record(1, 0, 0, easy),
+            new_record(5, 2, 2, hard),
+            new_record(3, 2, 2, easy),
+            new_record(2, 1, 1, easy),
+        ];
+        let (main_chain, stale_blocks) = select_main_chain(records);
+        assert_eq!(main_chain.len(), 3);
+        assert_eq!(main_chain[&0].block_hash.to_byte_array(), [1; 32]);
+        assert_eq!(main_chain[&1].block_hash.to_byte_array(), [2; 32]);
+        assert_eq!(main_chain[&2].block_hash.to_byte_array(), [5; 32]);
+        assert!(!main_chain.contains_key(&3));
+
+        // The stale branch forks off after block 2 at height 1
+        let stale_hashes = stale_blocks
+            .iter()
+            .map(|record| record.block_hash.to_byte_array()[0])
+            .collect::<Vec<u8>>();
+        assert_eq!(stale_hashes, vec![3, 4]);
+        let report = format_stale_report(&main_chain, &stale_blocks, HashOrder::Display);
+        let lines = report.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "height;hash;prevHash;blkIndex;forkHeight");
+        assert!(lines[1].starts_with("2;"));
+        assert!(lines[2].starts_with("3;"));
+        assert!(lines[2].ends_with(";0;1"));
+    }
+
+    #[test]
+    fn test_reject_invalid_pow() {
+        let bits = 0x207fffff;
+        let block0 = mined_record(sha256d::Hash::all_zeros(), 0, bits);
+        let block1 = mined_record(block0.block_hash, 1, bits);
+        assert!(block1.has_valid_pow());
+
+        // Claims far more work than its competitor, but doesn't meet the target
+        let mut invalid = new_record(0, 0, 2, 0x1d00ffff);
+        invalid.header.prev_hash = block1.block_hash;
+        invalid.block_hash = sha256d::Hash::hash(&invalid.header.to_bytes());
+        assert!(!invalid.has_valid_pow());
+        let descendant = mined_record(invalid.block_hash, 3, bits);
+        let block2 = mined_record(block1.block_hash, 2, bits);
+        // The header meets the target, but is stored under another hash
+        let mut mismatch = mined_record(block2.block_hash, 3, bits);
+        mismatch.block_hash = sha256d::Hash::hash(b"injected");
+        assert!(!mismatch.has_valid_pow());
+
+        let expected = [block0.block_hash, block1.block_hash, block2.block_hash];
+        let records = vec![descendant, block2, invalid, mismatch, block1, block0];
+        let (main_chain, stale_blocks) = select_main_chain(reject_invalid_pow(records));
+        assert_eq!(main_chain.len(), 3);
+        assert!(stale_blocks.is_empty());
+        for (height, hash) in expected.iter().enumerate() {
+            assert_eq!(main_chain[&(height as u64)].block_hash, *hash);
+        }
+    }
+
+    #[test]
+    fn test_verify_pow_coins() {
+        use crate::blockchain::parser::types::{CoinType, Litecoin, Namecoin};
+        use crate::callbacks::benchmark::Benchmark;
+
+        // Headers of a scrypt or merge-mined chain don't meet their target with double-SHA256
+        let headers = || {
+            let mut prev_hash = sha256d::Hash::all_zeros();
+            (0..3)
+                .map(|i| {
+                    let mut record = new_record(0, 0, i, 0x1d00ffff);
+                    record.header.prev_hash = prev_hash;
+                    prev_hash = sha256d::Hash::hash(&record.header.to_bytes());
+                    (i * 100, record.header)
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut options = ParserOptions::new(Box::new(Benchmark), std::path::PathBuf::new());
+        options.verify_pow = true;
+        for coin in [CoinType::from(Litecoin), CoinType::from(Namecoin)] {
+            options.coin = coin;
+            let index = ChainIndex::from_headers(headers(), 0, &options).unwrap();
+            assert_eq!(index.max_height, 2);
+        }
+        // All blocks of Bitcoin are rejected, which leaves no chain
+        options.coin = CoinType::default();
+        let err = ChainIndex::from_headers(headers(), 0, &options)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+        let err = ChainIndex::from_main_chain(HashMap::new(), &[], &options)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+    }
+
+    #[test]
+    fn test_bootstrap_index() {
+        let header = |prev_hash: sha256d::Hash, nonce: u32| BlockHeader {
+            version: 1,
+            prev_hash,
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0x207fffff,
+            nonce,
+        };
+        let hash = |header: &BlockHeader| sha256d::Hash::hash(&header.to_bytes());
+        let genesis = header(sha256d::Hash::all_zeros(), 0);
+        let genesis_hash = hash(&genesis);
+        let block1 = header(genesis_hash, 0);
+        let block1_hash = hash(&block1);
+        let stale1 = header(genesis_hash, 1);
+        let stale1_hash = hash(&stale1);
+        let block2 = header(block1_hash, 0);
+        // Its parent is stored after it
+        let block3 = header(hash(&block2), 0);
+        let headers = vec![
+            (8, genesis),
+            (100, stale1),
+            (200, block1),
+            (300, block3),
+            (400, block2),
+            (500, header(genesis_hash, 0)),
+        ];
+        let (main_chain, stale_blocks) = get_bootstrap_index(headers, 0, false);
+        assert_eq!(main_chain.len(), 3);
+        assert_eq!(main_chain[&0].data_offset, 8);
+        assert_eq!(main_chain[&1].block_hash, block1_hash);
+        assert_eq!(main_chain[&2].data_offset, 400);
+        assert_eq!(stale_blocks.len(), 1);
+        assert_eq!(stale_blocks[0].block_hash, stale1_hash);
+    }
+
+    #[test]
+    fn test_select_main_chain_equal_work() {
+        // Longest chain wins if all blocks have the same difficulty
+        let bits = 0x1d00ffff;
+        let records = vec![
+            new_record(1, 0, 0, bits),
+            new_record(2, 1, 1, bits),
+            new_record(3, 1, 1, bits),
+            new_record(4, 3, 2, bits),
+        ];
+        let (main_chain, stale_blocks) = select_main_chain(records);
+        assert_eq!(main_chain.len(), 3);
+        assert_eq!(stale_blocks.len(), 1);
+        assert_eq!(main_chain[&1].block_hash.to_byte_array(), [3; 32]);
+        assert_eq!(main_chain[&2].block_hash.to_byte_array(), [4; 32]);
+    }
+}
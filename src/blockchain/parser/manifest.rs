@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Size, checksum and number of main chain blocks of a single blk file
+#[derive(Clone)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub sha256: sha256::Hash,
+    pub blocks: u64,
+}
+
+impl ManifestEntry {
+    /// Hashes the file as stored on disk
+    pub fn from_file(path: &Path, blocks: u64) -> OpResult<Self> {
+        let mut file = File::open(path)?;
+        let mut engine = sha256::Hash::engine();
+        let mut buf = vec![0u8; 1 << 20];
+        let mut size = 0;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            engine.input(&buf[..n]);
+            size += n as u64;
+        }
+        Ok(ManifestEntry {
+            size,
+            sha256: sha256::Hash::from_engine(engine),
+            blocks,
+        })
+    }
+}
+
+/// Maps blk file names to their entries, ordered by name
+pub type Manifest = BTreeMap<String, ManifestEntry>;
+
+/// Writes the manifest as csv
+pub fn write(path: &Path, manifest: &Manifest) -> OpResult<()> {
+    let mut content = String::from("file;size;sha256;blocks\n");
+    for (name, entry) in manifest {
+        content.push_str(&format!(
+            "{};{};{};{}\n",
+            name, entry.size, entry.sha256, entry.blocks
+        ));
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Reads a manifest written by write()
+pub fn read(path: &Path) -> OpResult<Manifest> {
+    let content = fs::read_to_string(path)?;
+    let mut manifest = Manifest::new();
+    for (i, line) in content.lines().enumerate().skip(1) {
+        let invalid = || {
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Invalid manifest {} at line {}",
+                path.display(),
+                i + 1
+            ))
+        };
+        let fields = line.split(';').collect::<Vec<&str>>();
+        if fields.len() != 4 {
+            return Err(invalid());
+        }
+        let entry = ManifestEntry {
+            size: fields[1].parse().map_err(|_| invalid())?,
+            sha256: sha256::Hash::from_str(fields[2]).map_err(|_| invalid())?,
+            blocks: fields[3].parse().map_err(|_| invalid())?,
+        };
+        manifest.insert(String::from(fields[0]), entry);
+    }
+    Ok(manifest)
+}
+
+/// Returns a description for each file which differs from the expected manifest
+pub fn compare(expected: &Manifest, actual: &Manifest) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, expected) in expected {
+        match actual.get(name) {
+            None => changes.push(format!("{}: missing", name)),
+            Some(actual) if actual.size != expected.size => changes.push(format!(
+                "{}: size changed from {} to {} bytes",
+                name, expected.size, actual.size
+            )),
+            Some(actual) if actual.sha256 != expected.sha256 => {
+                changes.push(format!("{}: checksum changed", name))
+            }
+            Some(actual) if actual.blocks != expected.blocks => changes.push(format!(
+                "{}: block count changed from {} to {}",
+                name, expected.blocks, actual.blocks
+            )),
+            Some(_) => {}
+        }
+    }
+    for name in actual.keys().filter(|name| !expected.contains_key(*name)) {
+        changes.push(format!("{}: not in manifest", name));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let blk_path = tmp_dir.path().join("blk00000.dat");
+        fs::write(&blk_path, b"abc").unwrap();
+        let entry = ManifestEntry::from_file(&blk_path, 2).unwrap();
+        assert_eq!(entry.size, 3);
+        assert_eq!(
+            entry.sha256.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let mut manifest = Manifest::new();
+        manifest.insert(String::from("blk00000.dat"), entry.clone());
+        manifest.insert(String::from("blk00001.dat"), entry);
+        let manifest_path = tmp_dir.path().join("manifest.csv");
+        write(&manifest_path, &manifest).unwrap();
+        let expected = read(&manifest_path).unwrap();
+        assert_eq!(expected, manifest);
+        assert!(compare(&expected, &manifest).is_empty());
+
+        // Flip a byte, drop a file and add another one
+        fs::write(&blk_path, b"abd").unwrap();
+        let mut actual = Manifest::new();
+        actual.insert(
+            String::from("blk00000.dat"),
+            ManifestEntry::from_file(&blk_path, 2).unwrap(),
+        );
+        actual.insert(
+            String::from("blk00002.dat"),
+            manifest["blk00001.dat"].clone(),
+        );
+        assert_eq!(
+            compare(&expected, &actual),
+            vec![
+                "blk00000.dat: checksum changed",
+                "blk00001.dat: missing",
+                "blk00002.dat: not in manifest"
+            ]
+        );
+
+        fs::write(
+            &manifest_path,
+            "file;size;sha256;blocks\nblk00000.dat;3;xyz;2\n",
+        )
+        .unwrap();
+        assert!(read(&manifest_path).is_err());
+    }
+}
@@ -1,14 +1,23 @@
+use std::collections::BTreeMap;
+use std::iter::FusedIterator;
 use std::time::{Duration, Instant};
 
 use crate::blockchain::parser::chain::ChainStorage;
+use crate::blockchain::parser::checkpoint::Checkpoint;
+use crate::blockchain::parser::progress::ProgressBar;
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::Callback;
-use crate::errors::OpResult;
+use crate::blockchain::proto::varuint::VarUint;
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::common::shutdown;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
-mod blkfile;
+pub mod blkfile;
 pub mod chain;
+pub mod checkpoint;
 mod index;
+pub mod manifest;
+mod progress;
 pub mod reader;
 pub mod types;
 
@@ -18,6 +27,11 @@ struct WorkerStats {
     pub last_log: Instant,
     pub last_height: u64,
     pub measure_frame: Duration,
+    pub parse_time: Duration,    // spent reading and parsing blocks
+    pub callback_time: Duration, // spent in on_block()
+    pub block_count: u64,
+    pub tx_count: u64,
+    pub bytes: u64, // sum of all block sizes
 }
 
 impl WorkerStats {
@@ -27,38 +41,177 @@ impl WorkerStats {
             last_log: Instant::now(),
             last_height: start_range,
             measure_frame: Duration::from_secs(10),
+            parse_time: Duration::ZERO,
+            callback_time: Duration::ZERO,
+            block_count: 0,
+            tx_count: 0,
+            bytes: 0,
         }
     }
 }
 
+/// Upper bound for the number of threads, transactions of a block don't keep more threads busy
+const MAX_THREADS: usize = 64;
+
 pub struct BlockchainParser {
     chain_storage: ChainStorage, // Hash storage with the longest chain
     stats: WorkerStats,          // struct for thread management & statistics
     callback: Box<dyn Callback>,
+    checkpoint: Option<Checkpoint>,
+    progress_bar: Option<ProgressBar>,
     cur_height: u64,
+    validate_only: bool,
+    skip_errors: bool,
+    benchmark: bool,
+    threads: usize,
+    failures: BTreeMap<String, Vec<Failure>>, // blocks which failed to parse, by blk file
+    no_coinbase: bool,
+}
+
+/// A block which failed to parse
+struct Failure {
+    height: u64,
+    offset: Option<u64>, // within the blk file
+    err: OpError,
 }
 
 impl BlockchainParser {
     /// Instantiates a new Parser.
     pub fn new(options: ParserOptions, chain_storage: ChainStorage) -> Self {
         info!(target: "parser", "Parsing {} blockchain ...", options.coin.name);
+        let start = start_height(
+            options.range.start,
+            options.max_depth,
+            chain_storage.max_height(),
+        );
+        if start != options.range.start {
+            info!(target: "parser", "Limiting to the last {} blocks ...", options.max_depth.unwrap());
+        }
         Self {
             chain_storage,
-            stats: WorkerStats::new(options.range.start),
+            stats: WorkerStats::new(start),
             callback: options.callback,
-            cur_height: options.range.start,
+            checkpoint: options.checkpoint,
+            progress_bar: match options.progress_bar {
+                true => Some(ProgressBar::new(start)),
+                false => None,
+            },
+            cur_height: start,
+            validate_only: options.validate_only,
+            skip_errors: options.skip_errors,
+            benchmark: options.benchmark,
+            threads: options.threads,
+            failures: BTreeMap::new(),
+            no_coinbase: options.no_coinbase,
         }
     }
 
     pub fn start(&mut self) -> OpResult<()> {
         debug!(target: "parser", "Starting worker ...");
+        self.check_start_height()?;
+        if self.no_coinbase && self.callback.needs_coinbase() {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("--no-coinbase can't be used with this callback, it needs the coinbase transactions."));
+        }
 
+        init_thread_pool(self.threads);
         self.on_start(self.cur_height)?;
-        while let Some(block) = self.chain_storage.get_block(self.cur_height) {
-            self.on_block(&block, self.cur_height)?;
-            self.cur_height += 1;
+        while let Some(result) = self.next_block() {
+            let (height, block) = result?;
+            self.on_block(&block, height)?;
+        }
+        self.on_complete(self.cur_height.saturating_sub(1))?;
+        self.check_failures()
+    }
+
+    /// Returns an iterator over the blocks of the selected range, as an alternative to
+    /// `start()` for code which doesn't implement `Callback`. See `BlockIterator`.
+    pub fn blocks(&mut self) -> BlockIterator<'_> {
+        let pending = self.check_start_height().err();
+        if pending.is_none() {
+            init_thread_pool(self.threads);
+        }
+        BlockIterator {
+            parser: self,
+            pending,
+            done: false,
+        }
+    }
+
+    fn check_start_height(&self) -> OpResult<()> {
+        if self.cur_height > self.chain_storage.max_height() {
+            let msg = format!(
+                "Start height {} is beyond the chain tip at height {}.",
+                self.cur_height,
+                self.chain_storage.max_height()
+            );
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        Ok(())
+    }
+
+    /// Reads the block at the current height and advances to the next one.
+    /// Returns None once the end of the range is reached or a shutdown was requested.
+    fn next_block(&mut self) -> Option<OpResult<(u64, Block)>> {
+        loop {
+            if shutdown::is_requested() {
+                warn!(target: "parser", "Interrupted, stopping at height {}. Press Ctrl-C again to exit immediately.",
+                      self.cur_height.saturating_sub(1));
+                return None;
+            }
+            self.cur_height = self.chain_storage.skip_missing(self.cur_height);
+            let height = self.cur_height;
+            let started_at = Instant::now();
+            let result = self.chain_storage.get_block(height);
+            self.stats.parse_time += started_at.elapsed();
+            match result {
+                Ok(Some(mut block)) => {
+                    if self.no_coinbase {
+                        strip_coinbase(&mut block);
+                    }
+                    self.cur_height += 1;
+                    return Some(Ok((height, block)));
+                }
+                Ok(None) => return None,
+                // Keep going to find all broken blocks. The index holds the offset of every block,
+                // so the following blocks can be read even if this one is corrupt.
+                Err(err) if self.validate_only || self.skip_errors => {
+                    self.on_failure(height, err);
+                    self.cur_height += 1;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    /// Reports the blocks which failed to parse in --validate-only or --skip-errors mode,
+    /// failures are only an error in --validate-only mode
+    fn check_failures(&self) -> OpResult<()> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        let count = self.failure_count();
+        if self.skip_errors {
+            warn!(target: "parser", "{}", format_failures("Skipped blocks:", &self.failures));
+            warn!(target: "parser", "Skipped {} blocks which failed to parse.", count);
+            return Ok(());
         }
-        self.on_complete(self.cur_height.saturating_sub(1))
+        error!(target: "parser", "{}", format_failures("Validation failed:", &self.failures));
+        Err(OpError::new(OpErrorKind::ValidationError)
+            .join_msg(&format!("{} blocks failed to parse.", count)))
+    }
+
+    /// Exit code requested by the callback after a completed run.
+    /// Blocks skipped with --skip-errors make the output incomplete.
+    pub fn exit_code(&self) -> i32 {
+        match self.callback.exit_code() {
+            0 if self.failure_count() > 0 => EXIT_INCONSISTENT,
+            code => code,
+        }
+    }
+
+    fn failure_count(&self) -> usize {
+        self.failures.values().map(Vec::len).sum()
     }
 
     /// Returns number of remaining blocks
@@ -73,6 +226,9 @@ impl BlockchainParser {
         let now = Instant::now();
         self.stats.started_at = now;
         self.stats.last_log = now;
+        if let Some(ref mut progress_bar) = self.progress_bar {
+            progress_bar.start();
+        }
         info!(target: "parser", "Processing blocks starting from height {} ...", height);
         self.callback.on_start(height)?;
         trace!(target: "parser", "on_start() called");
@@ -81,33 +237,466 @@ impl BlockchainParser {
 
     /// Triggers the on_block() callback and updates statistics.
     fn on_block(&mut self, block: &Block, height: u64) -> OpResult<()> {
+        let started_at = Instant::now();
         self.callback.on_block(block, height)?;
+        self.stats.callback_time += started_at.elapsed();
+        self.stats.block_count += 1;
+        self.stats.tx_count += block.tx_count.value;
+        self.stats.bytes += block.size as u64;
         trace!(target: "parser", "on_block(height={}) called", height);
+        if let Some(ref checkpoint) = self.checkpoint {
+            if checkpoint.is_due(height) {
+                checkpoint.save(height)?;
+            }
+        }
         if self.callback.show_progress() {
             self.print_progress(height);
         }
         Ok(())
     }
 
+    /// Records a block which failed to parse in --validate-only or --skip-errors mode.
+    fn on_failure(&mut self, height: u64, err: OpError) {
+        let (file_name, offset) = match self.chain_storage.block_position(height) {
+            Some((file_name, offset)) => (file_name, Some(offset)),
+            None => (String::from("unknown"), None),
+        };
+        warn!(target: "parser", "Unable to parse block {} in {} at offset {}: {}",
+              height, file_name, format_offset(offset), err);
+        self.failures.entry(file_name).or_default().push(Failure {
+            height,
+            offset,
+            err,
+        });
+    }
+
     /// Triggers the on_complete() callback and updates statistics.
     fn on_complete(&mut self, height: u64) -> OpResult<()> {
+        if let Some(ref mut progress_bar) = self.progress_bar {
+            progress_bar.finish();
+        }
         info!(target: "parser", "Done. Processed blocks up to height {} in {:.2} minutes.",
         height, (Instant::now() - self.stats.started_at).as_secs_f32() / 60.0);
+        info!(target: "parser", "Time spent: {}", format_time_split(self.stats.parse_time, self.stats.callback_time));
+        if self.benchmark {
+            info!(target: "parser", "{}", format_benchmark(&self.stats, self.stats.started_at.elapsed()));
+        }
 
         self.callback.on_complete(height)?;
         trace!(target: "parser", "on_complete() called");
+        if let Some(ref checkpoint) = self.checkpoint {
+            checkpoint.save(height)?;
+        }
         Ok(())
     }
 
     fn print_progress(&mut self, height: u64) {
+        let max_height = self.chain_storage.max_height();
+        if let Some(ref mut progress_bar) = self.progress_bar {
+            progress_bar.update(height, max_height);
+            return;
+        }
+
         let now = Instant::now();
         let blocks_speed = (height - self.stats.last_height) / self.stats.measure_frame.as_secs();
 
         if now - self.stats.last_log > self.stats.measure_frame {
-            info!(target: "parser", "Status: {:7} Blocks processed. (remaining: {:7}, speed: {:5.2} blocks/s)",
-              height, self.remaining(), blocks_speed);
+            info!(target: "parser", "Status: {:7} Blocks processed. (remaining: {:7}, speed: {:5.2} blocks/s, {})",
+              height, self.remaining(), blocks_speed,
+              format_time_split(self.stats.parse_time, self.stats.callback_time));
             self.stats.last_log = now;
             self.stats.last_height = height;
         }
     }
 }
+
+/// Iterator over the parsed blocks and their heights, returned by `BlockchainParser::blocks()`.
+///
+/// Blocks are read lazily, one per call to `next()`, in the order `Callback::on_block()` sees
+/// them: strictly ascending heights of the main chain within the selected range, each at most
+/// once. Heights are only skipped if their blk file was not given or the block failed to parse
+/// with `skip_errors`. `no_coinbase` applies as well, the callback of the options is not invoked
+/// but still decides whether transactions and raw blocks are read (see `Callback::needs_full_data`).
+///
+/// A block which fails to parse is returned as `Err` and ends the iteration, the iterator is
+/// fused. With `skip_errors` or `validate_only` such blocks are logged and skipped instead, at the
+/// end they are reported like in `start()`, which yields one last `Err` in `validate_only` mode.
+/// A start height beyond the chain tip is returned as `Err` before any block.
+/// The iteration also ends early once a shutdown is requested (see `common::shutdown`).
+///
+/// No progress, statistics or checkpoints are written for the iterated blocks.
+pub struct BlockIterator<'a> {
+    parser: &'a mut BlockchainParser,
+    pending: Option<OpError>,
+    done: bool,
+}
+
+impl Iterator for BlockIterator<'_> {
+    type Item = OpResult<(u64, Block)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(err) = self.pending.take() {
+            self.done = true;
+            return Some(Err(err));
+        }
+        match self.parser.next_block() {
+            Some(Ok(item)) => Some(Ok(item)),
+            Some(Err(err)) => {
+                self.done = true;
+                Some(Err(err))
+            }
+            None => {
+                self.done = true;
+                self.parser.check_failures().err().map(Err)
+            }
+        }
+    }
+}
+
+impl FusedIterator for BlockIterator<'_> {}
+
+/// Returns the number of threads to use, 0 requests one per logical core
+fn thread_count(requested: usize, available: usize) -> usize {
+    match requested {
+        0 => available,
+        n => n,
+    }
+    .clamp(1, MAX_THREADS)
+}
+
+/// Sizes the global thread pool which evaluates the transactions of a block in parallel.
+/// The pool can only be configured once per process, later parsers reuse it.
+fn init_thread_pool(requested: usize) {
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = thread_count(requested, available);
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+    {
+        Ok(()) => {
+            info!(target: "parser", "Using {} threads ({} logical cores available)", threads, available)
+        }
+        Err(_) => debug!(target: "parser", "Thread pool already initialized, using {} threads",
+                         rayon::current_num_threads()),
+    }
+}
+
+/// Removes the coinbase transaction of the block (--no-coinbase), all other transactions are
+/// left as they are stored. Blocks parsed without transactions are left as they are.
+fn strip_coinbase(block: &mut Block) {
+    match block.txs.first() {
+        Some(tx) if tx.value.is_coinbase() => {}
+        _ => return,
+    }
+    block.txs.remove(0);
+    block.tx_count = VarUint::compact(block.txs.len() as u64);
+}
+
+/// Returns the first height to parse.
+/// With `max_depth` only the last blocks up to `max_height` are considered,
+/// unless the given start (e.g. from a checkpoint) is already closer to the tip.
+fn start_height(start: u64, max_depth: Option<u64>, max_height: u64) -> u64 {
+    match max_depth {
+        Some(depth) => start.max((max_height + 1).saturating_sub(depth)),
+        None => start,
+    }
+}
+
+/// Shows how the time is split between parsing and the callback,
+/// this tells whether the callback or the parser is the bottleneck.
+fn format_time_split(parse_time: Duration, callback_time: Duration) -> String {
+    let total = (parse_time + callback_time).as_secs_f64().max(0.001);
+    format!(
+        "parser: {:.1}s ({:.1}%), callback: {:.1}s ({:.1}%)",
+        parse_time.as_secs_f64(),
+        parse_time.as_secs_f64() / total * 100.0,
+        callback_time.as_secs_f64(),
+        callback_time.as_secs_f64() / total * 100.0
+    )
+}
+
+/// Builds the throughput summary of --benchmark.
+/// Reading covers loading and parsing the blocks, callback the time spent in on_block().
+fn format_benchmark(stats: &WorkerStats, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64().max(0.001);
+    let mib = stats.bytes as f64 / (1024.0 * 1024.0);
+    let total = (stats.parse_time + stats.callback_time)
+        .as_secs_f64()
+        .max(0.001);
+    format!(
+        "Benchmark results ({:.2}s):\n\
+         \t-> blocks:       {:12} ({:10.2}/s)\n\
+         \t-> transactions: {:12} ({:10.2}/s)\n\
+         \t-> data:         {:9.2} MiB ({:10.2} MiB/s)\n\
+         \t-> reading:      {:11.2}s ({:5.1}%)\n\
+         \t-> callback:     {:11.2}s ({:5.1}%)",
+        secs,
+        stats.block_count,
+        stats.block_count as f64 / secs,
+        stats.tx_count,
+        stats.tx_count as f64 / secs,
+        mib,
+        mib / secs,
+        stats.parse_time.as_secs_f64(),
+        stats.parse_time.as_secs_f64() / total * 100.0,
+        stats.callback_time.as_secs_f64(),
+        stats.callback_time.as_secs_f64() / total * 100.0
+    )
+}
+
+fn format_offset(offset: Option<u64>) -> String {
+    match offset {
+        Some(offset) => offset.to_string(),
+        None => String::from("unknown"),
+    }
+}
+
+/// Builds a report of all failed blocks, grouped by blk file
+fn format_failures(title: &str, failures: &BTreeMap<String, Vec<Failure>>) -> String {
+    let mut report = String::from(title);
+    for (file_name, blocks) in failures {
+        report.push_str(&format!("\n  {}: {} blocks", file_name, blocks.len()));
+        for failure in blocks {
+            report.push_str(&format!(
+                "\n    -> height {} at offset {}: {}",
+                failure.height,
+                format_offset(failure.offset),
+                failure.err
+            ));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{RawTx, TxOutpoint};
+    use crate::callbacks::benchmark::Benchmark;
+    use crate::common::testutil::{BlockBuilder, TxBuilder};
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::path::Path;
+
+    fn new_tx(outpoints: &[TxOutpoint]) -> RawTx {
+        outpoints
+            .iter()
+            .fold(TxBuilder::new(), |tx, outpoint| {
+                tx.input(TxOutpoint::new(outpoint.txid, outpoint.index))
+                    .script_sig(vec![])
+            })
+            .output(50)
+            .build()
+    }
+
+    fn new_block(nonce: u32, txs: Vec<RawTx>) -> Block {
+        BlockBuilder::new().nonce(nonce).txs(txs).build()
+    }
+
+    #[test]
+    fn test_strip_coinbase() {
+        let coinbase = |nonce: u32| {
+            let mut tx = new_tx(&[TxOutpoint::new(sha256d::Hash::all_zeros(), 0xffffffff)]);
+            tx.locktime = nonce;
+            tx
+        };
+        let mut block = new_block(0, vec![coinbase(0)]);
+        let first_coinbase = block.txs[0].hash;
+        strip_coinbase(&mut block);
+        assert!(block.txs.is_empty());
+        assert_eq!(block.tx_count.value, 0);
+
+        // Transactions spending a coinbase output are kept as they are
+        let other = sha256d::Hash::hash(b"other");
+        let spend = new_tx(&[
+            TxOutpoint::new(first_coinbase, 0),
+            TxOutpoint::new(other, 1),
+        ]);
+        let mut block = new_block(1, vec![coinbase(1), spend]);
+        let spend_txid = block.txs[1].hash;
+        strip_coinbase(&mut block);
+        assert_eq!(block.tx_count.value, 1);
+        assert_eq!(block.txs[0].hash, spend_txid);
+        assert_eq!(block.txs[0].value.in_count.value, 2);
+        assert_eq!(block.txs[0].value.inputs[0].outpoint.txid, first_coinbase);
+
+        // Blocks without transactions are left as they are
+        let mut block = new_block(2, vec![]);
+        block.tx_count = VarUint::from(3u8);
+        strip_coinbase(&mut block);
+        assert_eq!(block.tx_count.value, 3);
+    }
+
+    /// Options parsing the genesis block and block 1 from a bootstrap file.
+    /// With `corrupt` the locktime of the coinbase of block 1 is changed, so its merkle root fails.
+    fn bootstrap_options(dir: &Path, corrupt: bool) -> ParserOptions {
+        let mut data = include_bytes!("testdata/blk00000.dat").to_vec();
+        if corrupt {
+            data[8 + 285 + 8 + 215 - 1] ^= 0x01;
+        }
+        let path = dir.join("bootstrap.dat");
+        std::fs::write(&path, data).unwrap();
+        let mut options = ParserOptions::new(Box::new(Benchmark), dir.to_path_buf());
+        options.bootstrap = Some(path);
+        options.verify = true;
+        options
+    }
+
+    fn new_parser(options: ParserOptions) -> BlockchainParser {
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        BlockchainParser::new(options, chain_storage)
+    }
+
+    #[test]
+    fn test_blocks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut parser = new_parser(bootstrap_options(tmp_dir.path(), false));
+        let blocks = parser
+            .blocks()
+            .collect::<OpResult<Vec<(u64, Block)>>>()
+            .unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0, 0);
+        assert_eq!(
+            blocks[0].1.header.hash,
+            crate::blockchain::parser::types::CoinType::default().genesis_hash
+        );
+        assert_eq!(blocks[1].0, 1);
+        assert_eq!(blocks[1].1.header.value.prev_hash, blocks[0].1.header.hash);
+        assert_eq!(blocks[1].1.txs.len(), 1);
+
+        let mut options = bootstrap_options(tmp_dir.path(), false);
+        options.no_coinbase = true;
+        options.range = crate::BlockHeightRange::new(1, None).unwrap();
+        let mut parser = new_parser(options);
+        let mut blocks = parser.blocks();
+        let (height, block) = blocks.next().unwrap().unwrap();
+        assert_eq!(height, 1);
+        assert!(block.txs.is_empty());
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn test_blocks_errors() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut options = bootstrap_options(tmp_dir.path(), false);
+        options.range = crate::BlockHeightRange::new(5, None).unwrap();
+        let mut parser = new_parser(options);
+        let mut blocks = parser.blocks();
+        assert!(blocks.next().unwrap().is_err());
+        assert!(blocks.next().is_none());
+
+        // The error ends the iteration
+        let mut parser = new_parser(bootstrap_options(tmp_dir.path(), true));
+        let mut blocks = parser.blocks();
+        assert_eq!(blocks.next().unwrap().unwrap().0, 0);
+        assert!(blocks.next().unwrap().is_err());
+        assert!(blocks.next().is_none());
+
+        let mut options = bootstrap_options(tmp_dir.path(), true);
+        options.skip_errors = true;
+        let mut parser = new_parser(options);
+        let heights = parser
+            .blocks()
+            .map(|result| result.unwrap().0)
+            .collect::<Vec<u64>>();
+        assert_eq!(heights, [0]);
+        assert_eq!(parser.exit_code(), EXIT_INCONSISTENT);
+
+        let mut options = bootstrap_options(tmp_dir.path(), true);
+        options.validate_only = true;
+        let mut parser = new_parser(options);
+        let mut blocks = parser.blocks();
+        assert!(blocks.next().unwrap().is_ok());
+        let err = blocks.next().unwrap().unwrap_err();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn test_start_height() {
+        assert_eq!(start_height(0, None, 1000), 0);
+        assert_eq!(start_height(0, Some(10), 1000), 991);
+        assert_eq!(start_height(995, Some(10), 1000), 995);
+        assert_eq!(start_height(0, Some(5000), 1000), 0);
+        assert_eq!(start_height(0, Some(1), 0), 0);
+    }
+
+    #[test]
+    fn test_thread_count() {
+        assert_eq!(thread_count(0, 8), 8);
+        assert_eq!(thread_count(2, 8), 2);
+        assert_eq!(thread_count(16, 8), 16);
+        assert_eq!(thread_count(0, 256), MAX_THREADS);
+        assert_eq!(thread_count(1000, 8), MAX_THREADS);
+        assert_eq!(thread_count(0, 0), 1);
+    }
+
+    #[test]
+    fn test_format_time_split() {
+        assert_eq!(
+            format_time_split(Duration::from_secs(30), Duration::from_secs(90)),
+            "parser: 30.0s (25.0%), callback: 90.0s (75.0%)"
+        );
+        assert_eq!(
+            format_time_split(Duration::ZERO, Duration::ZERO),
+            "parser: 0.0s (0.0%), callback: 0.0s (0.0%)"
+        );
+    }
+
+    #[test]
+    fn test_format_benchmark() {
+        let mut stats = WorkerStats::new(0);
+        stats.parse_time = Duration::from_secs(3);
+        stats.callback_time = Duration::from_secs(1);
+        stats.block_count = 1000;
+        stats.tx_count = 5000;
+        stats.bytes = 8 * 1024 * 1024;
+        assert_eq!(
+            format_benchmark(&stats, Duration::from_secs(4)),
+            "Benchmark results (4.00s):\n\
+             \t-> blocks:               1000 (    250.00/s)\n\
+             \t-> transactions:         5000 (   1250.00/s)\n\
+             \t-> data:              8.00 MiB (      2.00 MiB/s)\n\
+             \t-> reading:             3.00s ( 75.0%)\n\
+             \t-> callback:            1.00s ( 25.0%)"
+        );
+    }
+
+    #[test]
+    fn test_format_failures() {
+        let mut failures = BTreeMap::new();
+        failures.insert(
+            String::from("blk00001.dat"),
+            vec![
+                Failure {
+                    height: 10,
+                    offset: Some(1200),
+                    err: OpError::new(OpErrorKind::ValidationError).join_msg("Bad hash."),
+                },
+                Failure {
+                    height: 12,
+                    offset: Some(3400),
+                    err: OpError::new(OpErrorKind::RuntimeError),
+                },
+            ],
+        );
+        failures.insert(
+            String::from("unknown"),
+            vec![Failure {
+                height: 3,
+                offset: None,
+                err: OpError::new(OpErrorKind::RuntimeError),
+            }],
+        );
+        assert_eq!(
+            format_failures("Validation failed:", &failures),
+            "Validation failed:\n  blk00001.dat: 2 blocks\n    \
+             -> height 10 at offset 1200: Bad hash. Validation Error\n    \
+             -> height 12 at offset 3400: RuntimeError\n  \
+             unknown: 1 blocks\n    -> height 3 at offset unknown: RuntimeError"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::{stderr, Write};
+use std::time::{Duration, Instant};
+
+/// Single updating status line on stderr
+pub struct ProgressBar {
+    start_height: u64,
+    started_at: Instant,
+    last_draw: Option<Instant>,
+    refresh_rate: Duration,
+}
+
+impl ProgressBar {
+    pub fn new(start_height: u64) -> Self {
+        Self {
+            start_height,
+            started_at: Instant::now(),
+            last_draw: None,
+            refresh_rate: Duration::from_millis(250),
+        }
+    }
+
+    /// Resets the timer, should be called right before the first block is processed
+    pub fn start(&mut self) {
+        self.started_at = Instant::now();
+    }
+
+    /// Redraws the status line if the refresh rate allows it
+    pub fn update(&mut self, height: u64, max_height: u64) {
+        let now = Instant::now();
+        if let Some(last_draw) = self.last_draw {
+            if now - last_draw < self.refresh_rate {
+                return;
+            }
+        }
+        let processed = height.saturating_sub(self.start_height) + 1;
+        let total = max_height.saturating_sub(self.start_height) + 1;
+        let line = format_line(processed, total, now - self.started_at);
+        let _ = write!(stderr(), "\r{}\x1b[K", line);
+        self.last_draw = Some(now);
+    }
+
+    /// Terminates the status line
+    pub fn finish(&mut self) {
+        if self.last_draw.take().is_some() {
+            let _ = writeln!(stderr());
+        }
+    }
+}
+
+fn format_line(processed: u64, total: u64, elapsed: Duration) -> String {
+    let percentage = processed as f64 / total.max(1) as f64 * 100.0;
+    let speed = processed as f64 / elapsed.as_secs_f64().max(0.001);
+    let eta = if speed > 0.0 {
+        format_duration(Duration::from_secs_f64(
+            total.saturating_sub(processed) as f64 / speed,
+        ))
+    } else {
+        String::from("--:--:--")
+    };
+    format!(
+        "Blocks: {}/{} ({:5.1}%) | {:.2} blocks/s | ETA: {}",
+        processed, total, percentage, speed, eta
+    )
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line(250, 1000, Duration::from_secs(10)),
+            "Blocks: 250/1000 ( 25.0%) | 25.00 blocks/s | ETA: 00:00:30"
+        );
+        assert_eq!(
+            format_line(10, 100000, Duration::from_secs(1)),
+            "Blocks: 10/100000 (  0.0%) | 10.00 blocks/s | ETA: 02:46:39"
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use bitcoin::hashes::{sha256d, Hash};
 use std::borrow::BorrowMut;
-use std::io::{self};
+use std::io::{self, Read};
 
 use crate::blockchain::parser::types::CoinType;
 use byteorder::{LittleEndian, ReadBytesExt};
@@ -12,6 +12,10 @@ use crate::blockchain::proto::varuint::VarUint;
 use crate::blockchain::proto::MerkleBranch;
 use crate::errors::OpResult;
 
+/// Upper bound of the elements reserved before reading them. Lengths and counts are read from
+/// the data, a corrupt one must not allocate more memory than the remaining data needs.
+const MAX_PREALLOCATION: u64 = 4096;
+
 /// Trait for structured reading of blockchain data
 pub trait BlockchainRead: io::Read {
     fn read_256hash(&mut self) -> OpResult<[u8; 32]> {
@@ -20,9 +24,12 @@ pub trait BlockchainRead: io::Read {
         Ok(arr)
     }
 
-    fn read_u8_vec(&mut self, count: u32) -> OpResult<Vec<u8>> {
-        let mut arr = vec![0u8; count as usize];
-        self.read_exact(arr.borrow_mut())?;
+    fn read_u8_vec(&mut self, count: u64) -> OpResult<Vec<u8>> {
+        let mut arr = Vec::with_capacity(count.min(MAX_PREALLOCATION) as usize);
+        Read::take(&mut *self, count).read_to_end(&mut arr)?;
+        if arr.len() as u64 != count {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
         Ok(arr)
     }
 
@@ -30,17 +37,33 @@ pub trait BlockchainRead: io::Read {
     fn read_block(&mut self, size: u32, coin: &CoinType) -> OpResult<Block> {
         let header = self.read_block_header()?;
         // Parse AuxPow data if present
-        let aux_pow_extension = match coin.aux_pow_activation_version {
-            Some(version) if header.version >= version => {
-                Some(self.read_aux_pow_extension(coin.version_id)?)
-            }
-            _ => None,
+        let aux_pow_extension = match coin.has_aux_pow(header.version) {
+            true => Some(self.read_aux_pow_extension(coin.version_id)?),
+            false => None,
         };
         let tx_count = VarUint::read_from(self)?;
         let txs = self.read_txs(tx_count.value, coin.version_id)?;
         Ok(Block::new(size, header, aux_pow_extension, tx_count, txs))
     }
 
+    /// Same as read_block(), but stops after the transaction count.
+    /// The returned block holds no transactions.
+    fn read_block_without_txs(&mut self, size: u32, coin: &CoinType) -> OpResult<Block> {
+        let header = self.read_block_header()?;
+        let aux_pow_extension = match coin.has_aux_pow(header.version) {
+            true => Some(self.read_aux_pow_extension(coin.version_id)?),
+            false => None,
+        };
+        let tx_count = VarUint::read_from(self)?;
+        Ok(Block::new(
+            size,
+            header,
+            aux_pow_extension,
+            tx_count,
+            vec![],
+        ))
+    }
+
     fn read_block_header(&mut self) -> OpResult<BlockHeader> {
         let version = self.read_u32::<LittleEndian>()?;
         let prev_hash = sha256d::Hash::from_byte_array(self.read_256hash()?);
@@ -72,10 +95,9 @@ pub trait BlockchainRead: io::Read {
         let mut in_count = VarUint::read_from(self)?;
         if in_count.value == 0 {
             flags = self.read_u8()?;
-            // TODO: handle segwit data
             in_count = VarUint::read_from(self)?
         }
-        let inputs = self.read_tx_inputs(in_count.value)?;
+        let mut inputs = self.read_tx_inputs(in_count.value)?;
 
         // Parse transaction outputs
         let out_count = VarUint::read_from(self)?;
@@ -83,12 +105,14 @@ pub trait BlockchainRead: io::Read {
 
         // Check if the witness flag is present
         if flags & 1 > 0 {
-            for _ in 0..in_count.value {
+            for input in inputs.iter_mut() {
                 let item_count = VarUint::read_from(self)?;
-                for _ in 0..item_count.value {
-                    let witness_len = VarUint::read_from(self)?;
-                    let _ = self.read_u8_vec(witness_len.value as u32)?;
-                }
+                input.witness = (0..item_count.value)
+                    .map(|_| {
+                        let witness_len = VarUint::read_from(self)?;
+                        self.read_u8_vec(witness_len.value)
+                    })
+                    .collect::<OpResult<Vec<Vec<u8>>>>()?;
             }
         }
         let locktime = self.read_u32::<LittleEndian>()?;
@@ -112,28 +136,29 @@ pub trait BlockchainRead: io::Read {
     }
 
     fn read_tx_inputs(&mut self, input_count: u64) -> OpResult<Vec<TxInput>> {
-        let mut inputs = Vec::with_capacity(input_count as usize);
+        let mut inputs = Vec::with_capacity(input_count.min(MAX_PREALLOCATION) as usize);
         for _ in 0..input_count {
             let outpoint = self.read_tx_outpoint()?;
             let script_len = VarUint::read_from(self)?;
-            let script_sig = self.read_u8_vec(script_len.value as u32)?;
+            let script_sig = self.read_u8_vec(script_len.value)?;
             let seq_no = self.read_u32::<LittleEndian>()?;
             inputs.push(TxInput {
                 outpoint,
                 script_len,
                 script_sig,
                 seq_no,
+                witness: Vec::new(),
             });
         }
         Ok(inputs)
     }
 
     fn read_tx_outputs(&mut self, output_count: u64) -> OpResult<Vec<TxOutput>> {
-        let mut outputs = Vec::with_capacity(output_count as usize);
+        let mut outputs = Vec::with_capacity(output_count.min(MAX_PREALLOCATION) as usize);
         for _ in 0..output_count {
             let value = self.read_u64::<LittleEndian>()?;
             let script_len = VarUint::read_from(self)?;
-            let script_pubkey = self.read_u8_vec(script_len.value as u32)?;
+            let script_pubkey = self.read_u8_vec(script_len.value)?;
             outputs.push(TxOutput {
                 value,
                 script_len,
@@ -185,6 +210,7 @@ mod tests {
     use crate::blockchain::proto::script;
     use crate::blockchain::proto::script::ScriptPattern;
     use crate::blockchain::proto::tx::EvaluatedTx;
+    use crate::blockchain::proto::ToRaw;
     use crate::common::utils;
     use byteorder::{LittleEndian, ReadBytesExt};
     use seek_bufread::BufReader;
@@ -266,6 +292,10 @@ mod tests {
         // Block Metadata
         assert_eq!(0xd9b4bef9, magic);
         assert_eq!(285, block.size);
+        // Legacy blocks weigh 4 bytes per byte
+        assert_eq!(285, block.base_size());
+        assert_eq!(1140, block.weight());
+        assert_eq!(285, block.vsize());
 
         // Block Header
         assert_eq!(0x00000001, block.header.value.version);
@@ -317,7 +347,7 @@ mod tests {
 
         let script_pubkey = &block.txs[0].value.outputs[0].out.script_pubkey;
         assert_eq!("4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
-                                utils::arr_to_hex(&script_pubkey));
+                                utils::arr_to_hex(script_pubkey));
         assert_eq!(0x00000000, block.txs[0].value.locktime);
 
         assert_eq!(
@@ -368,13 +398,13 @@ mod tests {
             0xea, 0x86, 0xd0, 0x2f, 0xf8, 0xe3, 0x32, 0x8b, 0xbd, 0x02, 0x42, 0xb2, 0x0a, 0xf3,
             0x42, 0x59, 0x90, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
-        let inner = Cursor::new(raw_data);
+        let inner = Cursor::new(raw_data.clone());
         let mut reader = BufReader::with_capacity(200, inner);
         let txs: Vec<EvaluatedTx> = reader
             .read_txs(1, 0x00)
             .unwrap()
             .into_iter()
-            .map(|raw| EvaluatedTx::from(raw))
+            .map(EvaluatedTx::from)
             .collect();
         assert_eq!(txs.len(), 1);
 
@@ -389,7 +419,12 @@ mod tests {
             0xf2, 0xa2, 0x0d, 0xa7, 0x17, 0xe5, 0x54, 0x84, 0x06, 0xf7, 0xae, 0x8b, 0x4c, 0x81,
             0x10, 0x72, 0xf8, 0x56,
         ];
+        // The prevout is stored little-endian, txids are displayed in reversed byte order
         assert_eq!(tx.inputs[0].outpoint.txid.as_ref(), prev_hash);
+        assert_eq!(
+            tx.inputs[0].outpoint.txid.to_string(),
+            "56f87210814c8baef7068454e517a70da2f2103fc3ac7f687e32a228dc80e115"
+        );
         assert_eq!(tx.inputs[0].outpoint.index, 3);
         assert_eq!(tx.inputs[0].script_len.value, 23);
         assert_eq!(tx.inputs[0].seq_no, 0xffffffff);
@@ -409,6 +444,82 @@ mod tests {
         );
 
         assert_eq!(tx.locktime, 0);
+
+        // Assert witness, the txid is computed from the stripped serialization
+        assert!(tx.has_witness());
+        assert_eq!(tx.inputs[0].witness.len(), 2);
+        assert_eq!(tx.inputs[0].witness[0].len(), 72);
+        assert_eq!(
+            utils::arr_to_hex(&tx.inputs[0].witness[1]),
+            "038262a6c6cec93c2d3ecd6c6072efea86d02ff8e3328bbd0242b20af3425990ac"
+        );
+        assert_eq!(
+            sha256d::Hash::hash(&tx.to_bytes()).to_string(),
+            "8139979112e894a14f8370438a471d23984061ff83a9eba0bc7a34433327ec21"
+        );
+        assert_eq!(
+            tx.wtxid().to_string(),
+            "6bf4e4dfb860cf0906f49c836700b130ac78cc391c72a0911c94cdec4dcb10ec"
+        );
+        assert_eq!(tx.to_witness_bytes(), raw_data);
+
+        // 108 non-witness and 110 witness bytes, see the wiki page above
+        assert_eq!(tx.base_size(), 108);
+        assert_eq!(tx.witness_size(), 110);
+        assert_eq!(tx.weight(), 542);
+        assert_eq!(tx.vsize(), 136);
+
+        // Block with header, tx count and the tx above: 299 bytes, 189 of them without witness
+        let raw_txs = BufReader::with_capacity(200, Cursor::new(raw_data))
+            .read_txs(1, 0x00)
+            .unwrap();
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let block = Block::new(80 + 1 + 218, header, None, VarUint::from(1u8), raw_txs);
+        assert_eq!(block.base_size(), 189);
+        assert_eq!(block.weight(), 189 * 3 + 299);
+        assert_eq!(block.vsize(), 217);
+    }
+
+    #[test]
+    fn test_bitcoin_parse_rbf_tx() {
+        // Legacy tx signaling RBF (sequence < 0xfffffffe) with a height based locktime of 500000
+        let raw_data = utils::hex_to_vec(
+            "02000000011111111111111111111111111111111111111111111111111111111111111111\
+             010000000151fdffffff011027000000000000015120a10700",
+        );
+        let mut reader = BufReader::with_capacity(100, Cursor::new(raw_data.clone()));
+        let tx = EvaluatedTx::from(reader.read_tx(0x00).unwrap());
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.inputs[0].outpoint.index, 1);
+        assert_eq!(tx.inputs[0].seq_no, 0xfffffffd);
+        assert_eq!(tx.outputs[0].out.value, 10000);
+        assert_eq!(tx.locktime, 500000);
+        assert_eq!(tx.to_bytes(), raw_data);
+    }
+
+    #[test]
+    fn test_parse_tx_corrupt_script_len() {
+        let tx = |script_len: &str| {
+            utils::hex_to_vec(&format!(
+                "02000000011111111111111111111111111111111111111111111111111111111111111111\
+                 01000000{}51fdffffff011027000000000000015120a10700",
+                script_len
+            ))
+        };
+        // Lengths beyond the data fail instead of reserving the whole length upfront
+        let raw_data = tx("ffffffffffffffff7f");
+        assert!(Cursor::new(raw_data).read_tx(0x00).is_err());
+        // 2^32 + 1 isn't truncated to a length of 1
+        let raw_data = tx("ff0100000001000000");
+        assert!(Cursor::new(raw_data).read_tx(0x00).is_err());
+        assert!(Cursor::new(tx("01")).read_tx(0x00).is_ok());
     }
 
     #[test]
@@ -614,7 +725,7 @@ mod tests {
 
         let script_pubkey = &block.txs[0].value.outputs[0].out.script_pubkey;
         assert_eq!("410489fe91e62847575c98deeab020f65fdff17a3a870ebb05820b414f3d8097218ec9a65f1e0ae0ac35af7247bd79ed1f2a24675fffb5aa6f9620e1920ad4bf5aa6ac",
-                                utils::arr_to_hex(&script_pubkey));
+                                utils::arr_to_hex(script_pubkey));
         assert_eq!(0x00000000, block.txs[0].value.locktime);
 
         /*
@@ -913,7 +1024,7 @@ mod tests {
         let script_pubkey = &block.txs[0].value.outputs[0].out.script_pubkey;
         assert_eq!(
             "2103829125d35fad23dcc6526e73bce0b18aa7c0897e0fc5d39e75e43af96b950748ac",
-            utils::arr_to_hex(&script_pubkey)
+            utils::arr_to_hex(script_pubkey)
         );
         assert_eq!(0x00000000, block.txs[0].value.locktime);
 
@@ -922,4 +1033,31 @@ mod tests {
             script::eval_from_bytes(script_pubkey, Dogecoin.version_id()).address
         );
     }
+
+    #[test]
+    fn test_dogecoin_parse_block_without_auxpow() {
+        let dogecoin = CoinType::from_str("dogecoin").unwrap();
+        assert!(!dogecoin.has_aux_pow(0x00000002));
+        assert!(dogecoin.has_aux_pow(0x00620104));
+        assert!(!CoinType::from_str("bitcoin")
+            .unwrap()
+            .has_aux_pow(0x00620104));
+
+        // Versions above the activation only carry AuxPow data if the flag is set
+        let header = crate::blockchain::proto::header::BlockHeader {
+            version: 0x20620004,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits: 0x1e0ffff0,
+            nonce: 0,
+        };
+        assert!(!dogecoin.has_aux_pow(header.version));
+        let mut raw_data = header.to_bytes();
+        raw_data.push(0x00);
+        let mut reader = BufReader::with_capacity(raw_data.len(), Cursor::new(raw_data));
+        let block = reader.read_block(81, &dogecoin).unwrap();
+        assert!(block.aux_pow_extension.is_none());
+        assert_eq!(0, block.tx_count.value);
+    }
 }
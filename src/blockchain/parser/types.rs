@@ -16,19 +16,33 @@ pub trait Coin {
     fn version_id(&self) -> u8;
     // Returns genesis hash
     fn genesis(&self) -> sha256d::Hash;
-    // Activates AuxPow for the returned version and above
+    // Activates AuxPow for the returned version and above, when AUX_POW_VERSION_FLAG is set
     fn aux_pow_activation_version(&self) -> Option<u32> {
         None
     }
+    // Returns false if the proof of work of a block isn't its double-SHA256 hash (e.g. scrypt)
+    fn sha256d_pow(&self) -> bool {
+        true
+    }
     // Default working directory to look for datadir, for example .bitcoin
     fn default_folder(&self) -> PathBuf;
 }
 
+/// Version bit set in the headers of merge-mined blocks that carry AuxPow data,
+/// see https://en.bitcoin.it/wiki/Merged_mining_specification
+pub const AUX_POW_VERSION_FLAG: u32 = 1 << 8;
+
+/// Network id of Bitcoin regtest. Its address prefixes are the same as testnet except for
+/// the bech32 hrp, so it can't be identified by the P2PKH version byte 0x6f.
+pub const REGTEST_VERSION_ID: u8 = 0xff;
+
 // Implemented blockchain types.
 // If you want to add you own coin, create a struct with a Coin implementation
 // and add the coin name to from_str() below
 pub struct Bitcoin;
 pub struct TestNet3;
+pub struct Regtest;
+pub struct BitcoinCash;
 pub struct Namecoin;
 pub struct Litecoin;
 pub struct Dogecoin;
@@ -72,7 +86,48 @@ impl Coin for TestNet3 {
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
-        Path::new(".bitcoin").join("testnet3")
+        Path::new(".bitcoin").join("testnet3").join("blocks")
+    }
+}
+
+/// Bitcoin regtest
+impl Coin for Regtest {
+    fn name(&self) -> String {
+        String::from("Regtest")
+    }
+    fn magic(&self) -> u32 {
+        0xdab5bffa
+    }
+    fn version_id(&self) -> u8 {
+        REGTEST_VERSION_ID
+    }
+    fn genesis(&self) -> sha256d::Hash {
+        sha256d::Hash::from_str("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".bitcoin").join("regtest").join("blocks")
+    }
+}
+
+/// Bitcoin Cash, shares the history of Bitcoin up to height 478558
+impl Coin for BitcoinCash {
+    fn name(&self) -> String {
+        String::from("Bitcoin Cash")
+    }
+    // Only the network magic changed (0xe8f3e1e3), blk files keep the one of Bitcoin
+    fn magic(&self) -> u32 {
+        0xd9b4bef9
+    }
+    fn version_id(&self) -> u8 {
+        0x00
+    }
+    fn genesis(&self) -> sha256d::Hash {
+        sha256d::Hash::from_str("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".bitcoin").join("blocks")
     }
 }
 
@@ -112,6 +167,9 @@ impl Coin for Litecoin {
         sha256d::Hash::from_str("12a765e31ffd4059bada1e25190f6e98c99d9714d334efa41a195a7e7e04bfe2")
             .unwrap()
     }
+    fn sha256d_pow(&self) -> bool {
+        false
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".litecoin").join("blocks")
     }
@@ -134,6 +192,9 @@ impl Coin for Dogecoin {
     fn aux_pow_activation_version(&self) -> Option<u32> {
         Some(0x620102)
     }
+    fn sha256d_pow(&self) -> bool {
+        false
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".dogecoin").join("blocks")
     }
@@ -153,6 +214,10 @@ impl Coin for Myriadcoin {
         sha256d::Hash::from_str("00000ffde4c020b5938441a0ea3d314bf619eff0b38f32f78f7583cffa1ea485")
             .unwrap()
     }
+    // Mined with several algorithms
+    fn sha256d_pow(&self) -> bool {
+        false
+    }
     fn default_folder(&self) -> PathBuf {
         Path::new(".myriadcoin").join("blocks")
     }
@@ -213,6 +278,7 @@ pub struct CoinType {
     pub version_id: u8,
     pub genesis_hash: sha256d::Hash,
     pub aux_pow_activation_version: Option<u32>,
+    pub sha256d_pow: bool,
     pub default_folder: PathBuf,
 }
 
@@ -230,17 +296,37 @@ impl<T: Coin> From<T> for CoinType {
             version_id: coin.version_id(),
             genesis_hash: coin.genesis(),
             aux_pow_activation_version: coin.aux_pow_activation_version(),
+            sha256d_pow: coin.sha256d_pow(),
             default_folder: coin.default_folder(),
         }
     }
 }
 
+impl CoinType {
+    /// Returns true if a block header with the given version is followed by AuxPow data.
+    /// Blocks mined without a parent chain can still appear after the activation.
+    pub fn has_aux_pow(&self, version: u32) -> bool {
+        match self.aux_pow_activation_version {
+            Some(activation) => version >= activation && version & AUX_POW_VERSION_FLAG != 0,
+            None => false,
+        }
+    }
+
+    /// Returns true if the proof of work of every block can be checked from its header alone,
+    /// i.e. it is the double-SHA256 hash of the header and no parent chain is merge-mined
+    pub fn has_header_pow(&self) -> bool {
+        self.sha256d_pow && self.aux_pow_activation_version.is_none()
+    }
+}
+
 impl FromStr for CoinType {
     type Err = OpError;
     fn from_str(coin_name: &str) -> OpResult<Self> {
         match coin_name {
             "bitcoin" => Ok(CoinType::from(Bitcoin)),
             "testnet3" => Ok(CoinType::from(TestNet3)),
+            "regtest" => Ok(CoinType::from(Regtest)),
+            "bitcoincash" | "bch" => Ok(CoinType::from(BitcoinCash)),
             "namecoin" => Ok(CoinType::from(Namecoin)),
             "litecoin" => Ok(CoinType::from(Litecoin)),
             "dogecoin" => Ok(CoinType::from(Dogecoin)),
@@ -1,5 +1,6 @@
-use bitcoin::hashes::sha256d;
+use bitcoin::hashes::{sha256d, Hash};
 use std::fmt;
+use std::path::PathBuf;
 
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
@@ -7,7 +8,7 @@ use crate::blockchain::proto::header::BlockHeader;
 use crate::blockchain::proto::tx::{EvaluatedTx, RawTx};
 use crate::blockchain::proto::varuint::VarUint;
 use crate::blockchain::proto::{Hashed, MerkleBranch};
-use crate::common::utils;
+use crate::common::{hashing, utils};
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
 /// Basic block structure which holds all information
@@ -17,6 +18,25 @@ pub struct Block {
     pub aux_pow_extension: Option<AuxPowExtension>,
     pub tx_count: VarUint,
     pub txs: Vec<Hashed<EvaluatedTx>>,
+    // Where the block is stored, set for all blocks read through ChainStorage
+    pub location: Option<BlockLocation>,
+    // Median timestamp of the previous 11 blocks (BIP113), set for all blocks read through
+    // ChainStorage except the genesis block
+    pub median_time_past: Option<u32>,
+    // Number of blocks from this one up to the tip of the block index (both included),
+    // the same for all transactions of the block. Set for all blocks read through ChainStorage
+    pub confirmations: Option<u64>,
+    // Serialized block as stored in the blk file, only kept if the callback needs it
+    pub raw: Option<Vec<u8>>,
+}
+
+/// Position of a block in the blk files, as recorded in the block index
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockLocation {
+    pub path: PathBuf,
+    /// Offset of the block header from the start of the blk file.
+    /// The network magic and the block size precede it in the 8 bytes before.
+    pub offset: u64,
 }
 
 impl Block {
@@ -37,9 +57,37 @@ impl Block {
             aux_pow_extension,
             tx_count,
             txs,
+            location: None,
+            median_time_past: None,
+            confirmations: None,
+            raw: None,
         }
     }
 
+    /// Size of the block without witness data of its transactions
+    pub fn base_size(&self) -> u64 {
+        let witness_size: u64 = self.txs.iter().map(|tx| tx.value.witness_size()).sum();
+        (self.size as u64).saturating_sub(witness_size)
+    }
+
+    /// Block weight as defined in BIP141: base_size * 3 + total_size.
+    /// Equals 4 * size for blocks without witness data.
+    pub fn weight(&self) -> u64 {
+        self.base_size() * 3 + self.size as u64
+    }
+
+    /// Virtual size in vbytes, the weight divided by 4 and rounded up
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+
+    /// Returns true if the coinbase outputs can be spent by the block after the tip,
+    /// None if the confirmations are not known
+    pub fn is_coinbase_mature(&self) -> Option<bool> {
+        self.confirmations
+            .map(|confirmations| confirmations >= COINBASE_MATURITY)
+    }
+
     /// Computes merkle root for all containing transactions
     pub fn compute_merkle_root(&self) -> sha256d::Hash {
         let hashes = self
@@ -51,11 +99,22 @@ impl Block {
     }
 
     /// Calculates merkle root and verifies it against the field in BlockHeader.
-    /// panics if not valid.
+    /// Fails as well if the block duplicates transactions without changing the root (CVE-2012-2459).
     pub fn verify_merkle_root(&self) -> OpResult<()> {
-        let merkle_root = self.compute_merkle_root();
+        let hashes = self
+            .txs
+            .iter()
+            .map(|tx| tx.hash)
+            .collect::<Vec<sha256d::Hash>>();
+        let (merkle_root, mutated) = utils::merkle_root_mutated(hashes);
 
-        if merkle_root == self.header.value.merkle_root {
+        if mutated {
+            let msg = format!(
+                "Block {} contains duplicated transactions with a valid merkle_root (CVE-2012-2459)!\n",
+                &self.header.hash
+            );
+            Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
+        } else if merkle_root == self.header.value.merkle_root {
             Ok(())
         } else {
             let msg = format!(
@@ -65,8 +124,74 @@ impl Block {
             Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
         }
     }
+
+    /// Returns the witness commitment of the coinbase transaction (BIP141).
+    /// If several outputs match, the last one is used.
+    pub fn witness_commitment(&self) -> Option<[u8; 32]> {
+        let coinbase = self.txs.first()?;
+        coinbase
+            .value
+            .outputs
+            .iter()
+            .rev()
+            .map(|output| &output.out.script_pubkey)
+            .find(|script| script.len() >= 38 && script[0..6] == WITNESS_COMMITMENT_HEADER)
+            .map(|script| script[6..38].try_into().unwrap())
+    }
+
+    /// Computes the witness commitment from the wtxids of all transactions and the witness
+    /// reserved value in the coinbase witness: SHA256d(witness root || reserved value).
+    /// The wtxid of the coinbase counts as zero.
+    pub fn compute_witness_commitment(&self) -> OpResult<[u8; 32]> {
+        let reserved_value = match self
+            .txs
+            .first()
+            .map(|tx| tx.value.inputs[0].witness.as_slice())
+        {
+            Some([reserved_value]) if reserved_value.len() == 32 => reserved_value,
+            _ => {
+                return Err(OpError::new(OpErrorKind::ValidationError)
+                    .join_msg("Coinbase witness doesn't hold a 32 byte witness reserved value!\n"))
+            }
+        };
+        let mut wtxids = vec![sha256d::Hash::all_zeros()];
+        wtxids.extend(self.txs.iter().skip(1).map(|tx| tx.value.wtxid()));
+        let mut preimage = utils::merkle_root(wtxids).to_byte_array().to_vec();
+        preimage.extend_from_slice(reserved_value);
+        Ok(hashing::sha256d(&preimage).to_byte_array())
+    }
+
+    /// Verifies the witness commitment in the coinbase against the transactions.
+    /// Blocks without commitment must not contain witness data.
+    pub fn verify_witness_commitment(&self) -> OpResult<()> {
+        let commitment = match self.witness_commitment() {
+            Some(commitment) => commitment,
+            None if self.txs.iter().any(|tx| tx.value.has_witness()) => {
+                return Err(OpError::new(OpErrorKind::ValidationError)
+                    .join_msg("Block contains witness data without witness commitment!\n"))
+            }
+            None => return Ok(()),
+        };
+        let computed = self.compute_witness_commitment()?;
+        if computed == commitment {
+            Ok(())
+        } else {
+            let msg = format!(
+                "Invalid witness commitment!\n  -> expected: {}\n  -> got: {}\n",
+                utils::arr_to_hex(&commitment),
+                utils::arr_to_hex(&computed)
+            );
+            Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
+        }
+    }
 }
 
+/// Confirmations after which the outputs of a coinbase transaction can be spent
+pub const COINBASE_MATURITY: u64 = 100;
+
+/// OP_RETURN, push of 36 bytes and the commitment header 0xaa21a9ed
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
 impl fmt::Debug for Block {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Block")
@@ -93,7 +218,10 @@ pub fn get_base_reward(block_height: u64) -> u64 {
 
 #[cfg(test)]
 mod tests {
-    use super::get_base_reward;
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::blockchain::proto::ToRaw;
+    use crate::common::testutil::{null_outpoint, BlockBuilder, TxBuilder};
 
     #[test]
     fn test_get_base_reward() {
@@ -103,6 +231,108 @@ mod tests {
         assert_eq!(get_base_reward(419999), 2500000000);
         assert_eq!(get_base_reward(420000), 1250000000);
         assert_eq!(get_base_reward(629999), 1250000000);
-        assert_eq!(get_base_reward(630000), 0625000000);
+        assert_eq!(get_base_reward(630000), 625000000);
+    }
+
+    fn new_tx(inputs: Vec<(TxOutpoint, Vec<Vec<u8>>)>, outputs: Vec<Vec<u8>>) -> RawTx {
+        inputs
+            .into_iter()
+            .fold(TxBuilder::new(), |tx, (outpoint, witness)| {
+                tx.input(outpoint).witness(witness)
+            })
+            .pays(
+                outputs
+                    .into_iter()
+                    .map(|script_pubkey| (1000, script_pubkey)),
+            )
+            .build()
+    }
+
+    /// Block with a coinbase committing to the given value and a single segwit transaction
+    fn new_block(commitment: Option<[u8; 32]>, reserved_value: Vec<u8>) -> Block {
+        let mut outputs = vec![vec![0x51]];
+        if let Some(commitment) = commitment {
+            let mut script = WITNESS_COMMITMENT_HEADER.to_vec();
+            script.extend_from_slice(&commitment);
+            outputs.push(script);
+        }
+        let coinbase = new_tx(vec![(null_outpoint(), vec![reserved_value])], outputs);
+        let spend_input = TxOutpoint::new(sha256d::Hash::hash(b"prev"), 0);
+        let spend = new_tx(
+            vec![(spend_input, vec![vec![0x30; 71], vec![0x02; 33]])],
+            vec![vec![0x51]],
+        );
+        BlockBuilder::new()
+            .version(0x20000000)
+            .bits(0x1d00ffff)
+            .txs(vec![coinbase, spend])
+            .build()
+    }
+
+    #[test]
+    fn test_verify_merkle_root() {
+        let tx = |i: u8| {
+            let outpoint = TxOutpoint::new(sha256d::Hash::hash(&[i]), 0);
+            new_tx(vec![(outpoint, vec![])], vec![vec![0x51]])
+        };
+        let new_block = |txs: Vec<RawTx>| BlockBuilder::new().bits(0x1d00ffff).txs(txs).build();
+
+        let mut block = new_block(vec![tx(0), tx(1), tx(2)]);
+        assert!(block.verify_merkle_root().is_err());
+        let root = block.compute_merkle_root();
+        block.header.value.merkle_root = root;
+        block.verify_merkle_root().unwrap();
+
+        // Duplicating the last transaction yields the same root
+        let mut block = new_block(vec![tx(0), tx(1), tx(2), tx(2)]);
+        block.header.value.merkle_root = root;
+        assert_eq!(block.compute_merkle_root(), root);
+        let err = block.verify_merkle_root().err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+        assert!(err.to_string().contains("CVE-2012-2459"));
+    }
+
+    #[test]
+    fn test_verify_witness_commitment() {
+        let block = new_block(Some([0; 32]), vec![0; 32]);
+        let commitment = block.compute_witness_commitment().unwrap();
+        assert!(block.verify_witness_commitment().is_err());
+
+        let block = new_block(Some(commitment), vec![0; 32]);
+        assert_eq!(block.witness_commitment(), Some(commitment));
+        block.verify_witness_commitment().unwrap();
+
+        // Cross-check with the consensus serialization
+        let mut bytes = block.header.value.to_bytes();
+        bytes.push(block.txs.len() as u8);
+        for tx in &block.txs {
+            bytes.extend_from_slice(&tx.value.to_witness_bytes());
+        }
+        let consensus: bitcoin::Block = bitcoin::consensus::deserialize(&bytes).unwrap();
+        assert!(consensus.check_witness_commitment());
+
+        // A different reserved value changes the commitment
+        let block = new_block(Some(commitment), vec![1; 32]);
+        assert!(block.verify_witness_commitment().is_err());
+        let block = new_block(Some(commitment), vec![0; 31]);
+        assert!(block.verify_witness_commitment().is_err());
+
+        // Witness data requires a commitment
+        let err = new_block(None, vec![0; 32])
+            .verify_witness_commitment()
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+    }
+
+    #[test]
+    fn test_is_coinbase_mature() {
+        let mut block = new_block(None, vec![0; 32]);
+        assert_eq!(block.is_coinbase_mature(), None);
+        block.confirmations = Some(COINBASE_MATURITY - 1);
+        assert_eq!(block.is_coinbase_mature(), Some(false));
+        // Spendable by the next block
+        block.confirmations = Some(COINBASE_MATURITY);
+        assert_eq!(block.is_coinbase_mature(), Some(true));
     }
 }
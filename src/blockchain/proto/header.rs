@@ -1,4 +1,6 @@
 use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::pow::{CompactTarget, Target};
+use bitcoin::BlockHash;
 use std::fmt;
 
 use crate::blockchain::proto::ToRaw;
@@ -14,6 +16,54 @@ pub struct BlockHeader {
     pub nonce: u32,
 }
 
+impl BlockHeader {
+    /// Expands the compact nBits encoding into the 256 bit target.
+    /// Returns None for negative (sign bit 0x00800000 set) or overflowing targets like
+    /// Bitcoin Core's SetCompact(), both are invalid in a block header.
+    pub fn target(&self) -> Option<Target> {
+        let exponent = self.bits >> 24;
+        let mantissa = self.bits & 0x007fffff;
+        // Mantissa bytes below 256^0 are shifted out for exponents < 3
+        let word = match exponent {
+            0..=3 => mantissa >> (8 * (3 - exponent)),
+            _ => mantissa,
+        };
+        let negative = word != 0 && self.bits & 0x00800000 != 0;
+        let overflow = word != 0
+            && (exponent > 34
+                || (word > 0xff && exponent > 33)
+                || (word > 0xffff && exponent > 32));
+        if negative || overflow {
+            return None;
+        }
+        // Without the sign bit, which would otherwise leak into the shifted mantissa
+        let bits = self.bits & !0x00800000;
+        Some(Target::from_compact(CompactTarget::from_consensus(bits)))
+    }
+
+    /// Checks the proof of work: the block hash must not exceed the target.
+    /// Invalid and zero targets are never met.
+    /// The maximum target of the network (powLimit) is not checked.
+    pub fn meets_target(&self, hash: &sha256d::Hash) -> bool {
+        match self.target() {
+            Some(target) if target != Target::ZERO => {
+                target.is_met_by(BlockHash::from_raw_hash(*hash))
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the difficulty as reported by Bitcoin Core's getdifficulty,
+    /// the ratio between the maximum target of nBits 0x1d00ffff and the current one.
+    /// Returns None if the target is invalid or zero.
+    pub fn difficulty(&self) -> Option<f64> {
+        match self.target() {
+            Some(target) if target != Target::ZERO => Some(target.difficulty_float()),
+            _ => None,
+        }
+    }
+}
+
 impl ToRaw for BlockHeader {
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(80);
@@ -39,3 +89,116 @@ impl fmt::Debug for BlockHeader {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    fn header(bits: u32) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits,
+            nonce: 0,
+        }
+    }
+
+    fn target_hex(bits: u32) -> Option<String> {
+        header(bits).target().map(|target| {
+            utils::arr_to_hex(&target.to_be_bytes())
+                .trim_start_matches('0')
+                .to_string()
+        })
+    }
+
+    #[test]
+    fn test_target() {
+        // Test vectors of SetCompact() in Bitcoin Core's arith_uint256_tests.cpp
+        assert_eq!(target_hex(0x00123456), Some(String::new()));
+        assert_eq!(target_hex(0x01003456), Some(String::new()));
+        assert_eq!(target_hex(0x01123456), Some(String::from("12")));
+        assert_eq!(target_hex(0x02123456), Some(String::from("1234")));
+        assert_eq!(target_hex(0x03123456), Some(String::from("123456")));
+        assert_eq!(target_hex(0x04123456), Some(String::from("12345600")));
+        assert_eq!(target_hex(0x05009234), Some(String::from("92340000")));
+        assert_eq!(
+            target_hex(0x20123456),
+            Some(format!("123456{}", "0".repeat(58)))
+        );
+        // The sign bit only makes a non-zero target negative
+        assert_eq!(target_hex(0x01803456), Some(String::new()));
+        assert_eq!(target_hex(0x04923456), None);
+        // Overflow
+        assert_eq!(target_hex(0xff123456), None);
+        assert_eq!(target_hex(0x21010000), None);
+        assert!(target_hex(0x21000001).is_some());
+
+        // Genesis block
+        assert_eq!(
+            utils::arr_to_hex(&header(0x1d00ffff).target().unwrap().to_be_bytes()),
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_meets_target() {
+        // Mainnet genesis block
+        let genesis = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+                .parse()
+                .unwrap(),
+            timestamp: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        };
+        let hash = sha256d::Hash::hash(&genesis.to_bytes());
+        assert_eq!(
+            hash.to_string(),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert!(genesis.meets_target(&hash));
+
+        // Another nonce doesn't satisfy the target
+        let invalid = BlockHeader {
+            nonce: 0,
+            ..genesis.clone()
+        };
+        let invalid_hash = sha256d::Hash::hash(&invalid.to_bytes());
+        assert!(!invalid.meets_target(&invalid_hash));
+
+        // The hash equal to the target is still valid
+        let mut target = header(0x1d00ffff).target().unwrap().to_le_bytes();
+        let at_target = sha256d::Hash::from_byte_array(target);
+        assert!(header(0x1d00ffff).meets_target(&at_target));
+        target[0] = 1;
+        assert!(!header(0x1d00ffff).meets_target(&sha256d::Hash::from_byte_array(target)));
+
+        // Zero, negative and overflowing targets
+        assert!(!header(0x1d000000).meets_target(&sha256d::Hash::all_zeros()));
+        assert!(!header(0x04923456).meets_target(&sha256d::Hash::all_zeros()));
+        assert!(!header(0xff123456).meets_target(&sha256d::Hash::all_zeros()));
+    }
+
+    #[test]
+    fn test_difficulty() {
+        // Mainnet blocks 0, 32256, 100000 and 500000
+        assert_eq!(header(0x1d00ffff).difficulty(), Some(1.0));
+        let difficulty = header(0x1d00d86a).difficulty().unwrap();
+        assert!((difficulty - 1.182899534312841).abs() < 1e-12);
+        let difficulty = header(0x1b04864c).difficulty().unwrap();
+        assert!((difficulty - 14484.1623612254).abs() < 1e-9);
+        let difficulty = header(0x18009645).difficulty().unwrap();
+        assert!((difficulty - 1873105475221.611).abs() < 1e-2);
+        // Regtest
+        let difficulty = header(0x207fffff).difficulty().unwrap();
+        assert!((difficulty - 4.656542373906925e-10).abs() < 1e-20);
+
+        assert_eq!(header(0x1d000000).difficulty(), None);
+        assert_eq!(header(0x04923456).difficulty(), None);
+    }
+}
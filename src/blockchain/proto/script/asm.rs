@@ -0,0 +1,363 @@
+//! Disassembly of scripts into the notation of Bitcoin Core (`ScriptToAsmStr`)
+
+use crate::common::utils;
+
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1NEGATE: u8 = 0x4f;
+const OP_RESERVED: u8 = 0x50;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_NOP: u8 = 0x61;
+const OP_RETURN: u8 = 0x6a;
+const OP_INVALIDOPCODE: u8 = 0xff;
+const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Names of the opcodes from OP_NOP (0x61) to OP_CHECKSIGADD (0xba)
+const OP_NAMES: [&str; 90] = [
+    "OP_NOP",
+    "OP_VER",
+    "OP_IF",
+    "OP_NOTIF",
+    "OP_VERIF",
+    "OP_VERNOTIF",
+    "OP_ELSE",
+    "OP_ENDIF",
+    "OP_VERIFY",
+    "OP_RETURN",
+    "OP_TOALTSTACK",
+    "OP_FROMALTSTACK",
+    "OP_2DROP",
+    "OP_2DUP",
+    "OP_3DUP",
+    "OP_2OVER",
+    "OP_2ROT",
+    "OP_2SWAP",
+    "OP_IFDUP",
+    "OP_DEPTH",
+    "OP_DROP",
+    "OP_DUP",
+    "OP_NIP",
+    "OP_OVER",
+    "OP_PICK",
+    "OP_ROLL",
+    "OP_ROT",
+    "OP_SWAP",
+    "OP_TUCK",
+    "OP_CAT",
+    "OP_SUBSTR",
+    "OP_LEFT",
+    "OP_RIGHT",
+    "OP_SIZE",
+    "OP_INVERT",
+    "OP_AND",
+    "OP_OR",
+    "OP_XOR",
+    "OP_EQUAL",
+    "OP_EQUALVERIFY",
+    "OP_RESERVED1",
+    "OP_RESERVED2",
+    "OP_1ADD",
+    "OP_1SUB",
+    "OP_2MUL",
+    "OP_2DIV",
+    "OP_NEGATE",
+    "OP_ABS",
+    "OP_NOT",
+    "OP_0NOTEQUAL",
+    "OP_ADD",
+    "OP_SUB",
+    "OP_MUL",
+    "OP_DIV",
+    "OP_MOD",
+    "OP_LSHIFT",
+    "OP_RSHIFT",
+    "OP_BOOLAND",
+    "OP_BOOLOR",
+    "OP_NUMEQUAL",
+    "OP_NUMEQUALVERIFY",
+    "OP_NUMNOTEQUAL",
+    "OP_LESSTHAN",
+    "OP_GREATERTHAN",
+    "OP_LESSTHANOREQUAL",
+    "OP_GREATERTHANOREQUAL",
+    "OP_MIN",
+    "OP_MAX",
+    "OP_WITHIN",
+    "OP_RIPEMD160",
+    "OP_SHA1",
+    "OP_SHA256",
+    "OP_HASH160",
+    "OP_HASH256",
+    "OP_CODESEPARATOR",
+    "OP_CHECKSIG",
+    "OP_CHECKSIGVERIFY",
+    "OP_CHECKMULTISIG",
+    "OP_CHECKMULTISIGVERIFY",
+    "OP_NOP1",
+    "OP_CHECKLOCKTIMEVERIFY",
+    "OP_CHECKSEQUENCEVERIFY",
+    "OP_NOP4",
+    "OP_NOP5",
+    "OP_NOP6",
+    "OP_NOP7",
+    "OP_NOP8",
+    "OP_NOP9",
+    "OP_NOP10",
+    "OP_CHECKSIGADD",
+];
+
+/// Name of a non-push opcode, small numbers are written as decimal
+fn op_name(opcode: u8) -> String {
+    match opcode {
+        OP_PUSHDATA1 => String::from("OP_PUSHDATA1"),
+        OP_PUSHDATA2 => String::from("OP_PUSHDATA2"),
+        OP_PUSHDATA4 => String::from("OP_PUSHDATA4"),
+        OP_1NEGATE => String::from("-1"),
+        OP_RESERVED => String::from("OP_RESERVED"),
+        OP_1..=OP_16 => (opcode - OP_1 + 1).to_string(),
+        OP_INVALIDOPCODE => String::from("OP_INVALIDOPCODE"),
+        _ => match OP_NAMES.get(opcode.wrapping_sub(OP_NOP) as usize) {
+            Some(name) if opcode >= OP_NOP => String::from(*name),
+            _ => String::from("OP_UNKNOWN"),
+        },
+    }
+}
+
+/// Reads the next instruction at `pos`: the opcode and the data it pushes.
+/// Returns None if a push runs past the end of the script.
+fn next_op<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<(u8, &'a [u8])> {
+    let opcode = *bytes.get(*pos)?;
+    *pos += 1;
+    let len_bytes = match opcode {
+        OP_PUSHDATA1 => 1,
+        OP_PUSHDATA2 => 2,
+        OP_PUSHDATA4 => 4,
+        _ => 0,
+    };
+    let len = match opcode {
+        0..=0x4b => opcode as usize,
+        OP_PUSHDATA1..=OP_PUSHDATA4 => {
+            let prefix = bytes.get(*pos..*pos + len_bytes)?;
+            *pos += len_bytes;
+            prefix
+                .iter()
+                .rev()
+                .fold(0usize, |len, byte| (len << 8) | *byte as usize)
+        }
+        _ => return Some((opcode, &[])),
+    };
+    let data = bytes.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+    Some((opcode, data))
+}
+
+/// Decodes a little endian number with the sign in the most significant bit (CScriptNum)
+fn script_num(data: &[u8]) -> i64 {
+    let Some((last, _)) = data.split_last() else {
+        return 0;
+    };
+    let value = data
+        .iter()
+        .rev()
+        .fold(0i64, |value, byte| (value << 8) | *byte as i64);
+    match last & 0x80 {
+        0 => value,
+        _ => -(value & !(0x80 << (8 * (data.len() - 1)))),
+    }
+}
+
+/// Checks the strict DER encoding of a signature with its sighash type byte (BIP66)
+fn is_der_signature(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() || sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    !(len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0)
+}
+
+fn sighash_name(sighash: u8) -> Option<&'static str> {
+    match sighash {
+        0x01 => Some("ALL"),
+        0x02 => Some("NONE"),
+        0x03 => Some("SINGLE"),
+        0x81 => Some("ALL|ANYONECANPAY"),
+        0x82 => Some("NONE|ANYONECANPAY"),
+        0x83 => Some("SINGLE|ANYONECANPAY"),
+        _ => None,
+    }
+}
+
+/// Checks if the script consists of pushes only, counting OP_RESERVED and OP_1 to OP_16 as pushes
+pub fn is_push_only(bytes: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match next_op(bytes, &mut pos) {
+            Some((opcode, _)) if opcode <= OP_16 => continue,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Disassembles the script the way Bitcoin Core does for its RPC output.
+///
+/// Pushes of up to 4 bytes are written as decimal numbers, longer ones as hex. With
+/// `decode_sighash` (used for scriptSigs) pushes which are DER signatures end with their sighash
+/// type instead of the last byte, e.g. `3044...01` becomes `3044...[ALL]`. A push running past
+/// the end of the script is written as `[error]` and stops the disassembly.
+pub fn to_asm(bytes: &[u8], decode_sighash: bool) -> String {
+    let unspendable = bytes.first() == Some(&OP_RETURN) || bytes.len() > MAX_SCRIPT_SIZE;
+    let mut items = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let Some((opcode, data)) = next_op(bytes, &mut pos) else {
+            items.push(String::from("[error]"));
+            break;
+        };
+        let item = match opcode {
+            0..=OP_PUSHDATA4 if data.len() <= 4 => script_num(data).to_string(),
+            0..=OP_PUSHDATA4 if decode_sighash && !unspendable && is_der_signature(data) => {
+                let (sighash, sig) = data.split_last().unwrap();
+                match sighash_name(*sighash) {
+                    Some(name) => format!("{}[{}]", utils::arr_to_hex(sig), name),
+                    None => utils::arr_to_hex(data),
+                }
+            }
+            0..=OP_PUSHDATA4 => utils::arr_to_hex(data),
+            _ => op_name(opcode),
+        };
+        items.push(item);
+    }
+    items.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils::hex_to_vec;
+
+    #[test]
+    fn test_to_asm_opcodes() {
+        let p2pkh = hex_to_vec("76a914cbc20a7664f2f69e427507acb93d3c3e1d0c5d4188ac");
+        assert_eq!(
+            to_asm(&p2pkh, false),
+            "OP_DUP OP_HASH160 cbc20a7664f2f69e427507acb93d3c3e1d0c5d41 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        // Small numbers and pushes of up to 4 bytes are decimal
+        assert_eq!(
+            to_asm(
+                &[0x00, 0x4f, 0x51, 0x60, 0x01, 0x81, 0x02, 0xe8, 0x03, 0x01, 0x80],
+                false
+            ),
+            "0 -1 1 16 -1 1000 0"
+        );
+        assert_eq!(
+            to_asm(&[0xb1, 0xb2, 0xba, 0xbb, 0xff, 0x50, 0x4c, 0x00], false),
+            "OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_CHECKSIGADD OP_UNKNOWN \
+             OP_INVALIDOPCODE OP_RESERVED 0"
+        );
+        // Truncated pushes
+        assert_eq!(to_asm(&[0x6a, 0x05, 0x01], false), "OP_RETURN [error]");
+        assert_eq!(to_asm(&[0x4d, 0x01], false), "[error]");
+        assert_eq!(to_asm(&[], false), "");
+    }
+
+    #[test]
+    fn test_to_asm_pushdata() {
+        // Data pushed with OP_PUSHDATA1/2/4 is written like direct pushes
+        let data = "00112233445566778899";
+        for prefix in ["0a", "4c0a", "4d0a00", "4e0a000000"] {
+            let script = hex_to_vec(&format!("{}{}87", prefix, data));
+            assert_eq!(to_asm(&script, false), format!("{} OP_EQUAL", data));
+        }
+        // A 300 byte push needs OP_PUSHDATA2
+        let script = [&[0x4d, 0x2c, 0x01][..], &[0xab; 300]].concat();
+        assert_eq!(to_asm(&script, false), "ab".repeat(300));
+        // Length prefixes running past the end
+        assert_eq!(to_asm(&[0x4e, 0x01, 0x00], false), "[error]");
+        assert_eq!(to_asm(&[0x51, 0x4d, 0xff, 0x00, 0xab], false), "1 [error]");
+    }
+
+    #[test]
+    fn test_to_asm_standard_scripts() {
+        let check = |script: &str, asm: &str| assert_eq!(to_asm(&hex_to_vec(script), false), asm);
+        let key = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        check(&format!("21{}ac", key), &format!("{} OP_CHECKSIG", key));
+        check(
+            "a914748284390f9e263a4b766a75d0633c50426eb87587",
+            "OP_HASH160 748284390f9e263a4b766a75d0633c50426eb875 OP_EQUAL",
+        );
+        check(
+            &format!("5121{}21{}52ae", key, key),
+            &format!("1 {} {} 2 OP_CHECKMULTISIG", key, key),
+        );
+        check(
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "0 751e76e8199196d454941c45d1b3a323f1433bd6",
+        );
+        check(
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            "1 a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+        );
+        check("6a0568656c6c6f", "OP_RETURN 68656c6c6f");
+        // Timelocked P2PKH: the height 500000 is a 3 byte push
+        check(
+            "0320a107b17576a914751e76e8199196d454941c45d1b3a323f1433bd688ac",
+            "500000 OP_CHECKLOCKTIMEVERIFY OP_DROP OP_DUP OP_HASH160 \
+             751e76e8199196d454941c45d1b3a323f1433bd6 OP_EQUALVERIFY OP_CHECKSIG",
+        );
+        // 144 blocks relative timelock in a script path
+        check(
+            "63029000b275ac67ac68",
+            "OP_IF 144 OP_CHECKSEQUENCEVERIFY OP_DROP OP_CHECKSIG OP_ELSE OP_CHECKSIG OP_ENDIF",
+        );
+    }
+
+    #[test]
+    fn test_is_push_only() {
+        assert!(is_push_only(&[]));
+        assert!(is_push_only(&[
+            0x00, 0x50, 0x60, 0x02, 0xab, 0xcd, 0x4c, 0x01, 0xff
+        ]));
+        assert!(!is_push_only(&[0x01, 0xab, 0x61]));
+        assert!(!is_push_only(&[0x02, 0xab]));
+    }
+
+    #[test]
+    fn test_to_asm_sighash() {
+        let sig = "304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd41\
+                   0220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d09";
+        let script_sig = hex_to_vec(&format!("47{}01", sig));
+        assert_eq!(to_asm(&script_sig, true), format!("{}[ALL]", sig));
+        assert_eq!(to_asm(&script_sig, false), format!("{}01", sig));
+
+        let script_sig = hex_to_vec(&format!("47{}83", sig));
+        assert_eq!(
+            to_asm(&script_sig, true),
+            format!("{}[SINGLE|ANYONECANPAY]", sig)
+        );
+        // Undefined sighash types and non-DER data are kept as hex
+        let script_sig = hex_to_vec(&format!("47{}04", sig));
+        assert_eq!(to_asm(&script_sig, true), format!("{}04", sig));
+        let script_sig = hex_to_vec(&format!("47{}01", sig.replacen("30", "31", 1)));
+        assert!(to_asm(&script_sig, true).ends_with("01"));
+        // OP_RETURN data is never decoded
+        let script = hex_to_vec(&format!("6a47{}01", sig));
+        assert_eq!(to_asm(&script, true), format!("OP_RETURN {}01", sig));
+    }
+}
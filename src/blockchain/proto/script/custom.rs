@@ -1,7 +1,9 @@
 /// This custom Script implementation is for all networks other than Bitcoin and Bitcoin Testnet
+use crate::blockchain::parser::types::REGTEST_VERSION_ID;
 use crate::blockchain::proto::script::{EvaluatedScript, ScriptError, ScriptPattern};
 use crate::common::utils;
 use bitcoin::base58;
+use bitcoin::bech32::{self, ToBase32, Variant};
 use bitcoin::hashes::{hash160, sha256d, Hash};
 use bitcoin::opcodes::{all, All, Class, ClassifyContext};
 use std::fmt;
@@ -74,7 +76,7 @@ struct ScriptEvaluator<'a> {
 }
 
 impl<'a> ScriptEvaluator<'a> {
-    pub fn new(bytes: &'a [u8]) -> ScriptEvaluator {
+    pub fn new(bytes: &'a [u8]) -> ScriptEvaluator<'a> {
         ScriptEvaluator {
             bytes,
             n_bytes: bytes.len(),
@@ -182,6 +184,35 @@ impl<'a> ScriptEvaluator<'a> {
             return ScriptPattern::Pay2ScriptHash;
         }
 
+        // Version 0 witness program (p2wpkh or p2wsh)
+        // pubkey: OP_0 <20 or 32 bytes>
+        let witness_v0 = [
+            StackElement::Op(all::OP_PUSHBYTES_0),
+            StackElement::Data(Vec::new()),
+        ];
+        if ScriptEvaluator::match_stack_pattern(elements, &witness_v0) {
+            return match elements[1].data() {
+                Ok(data) if data.len() == 20 => ScriptPattern::Pay2WitnessPublicKeyHash,
+                Ok(data) if data.len() == 32 => ScriptPattern::Pay2WitnessScriptHash,
+                Ok(_) => ScriptPattern::WitnessProgram,
+                Err(_) => ScriptPattern::Error(ScriptError::InvalidFormat),
+            };
+        }
+
+        // Version 1 witness program (p2tr)
+        // pubkey: OP_1 <32 bytes>
+        let witness_v1 = [
+            StackElement::Op(all::OP_PUSHNUM_1),
+            StackElement::Data(Vec::new()),
+        ];
+        if ScriptEvaluator::match_stack_pattern(elements, &witness_v1) {
+            return match elements[1].data() {
+                Ok(data) if data.len() == 32 => ScriptPattern::Pay2Taproot,
+                Ok(_) => ScriptPattern::WitnessProgram,
+                Err(_) => ScriptPattern::Error(ScriptError::InvalidFormat),
+            };
+        }
+
         // Data output
         // pubkey: OP_RETURN <0 to 40 bytes of data>
         let data_output = [
@@ -195,17 +226,10 @@ impl<'a> ScriptEvaluator<'a> {
             };
         }
 
-        //TODO: implement n to m multisig
-        let multisig_2n3 = [
-            StackElement::Op(all::OP_PUSHNUM_2),
-            StackElement::Data(Vec::new()),
-            StackElement::Data(Vec::new()),
-            StackElement::Data(Vec::new()),
-            StackElement::Op(all::OP_PUSHNUM_3),
-            StackElement::Op(all::OP_CHECKMULTISIG),
-        ];
-        if ScriptEvaluator::match_stack_pattern(elements, &multisig_2n3) {
-            return ScriptPattern::Pay2MultiSig;
+        // Bare multisig
+        // pubkey: <m> <A pubkey>[B pubkey][C pubkey...] <n> OP_CHECKMULTISIG
+        if let Some(pattern) = ScriptEvaluator::eval_multisig_pattern(elements) {
+            return pattern;
         }
         /* TODO:
         // The Genesis Block, self-payments, and pay-by-IP-address payments look like:
@@ -218,6 +242,41 @@ impl<'a> ScriptEvaluator<'a> {
         ScriptPattern::NotRecognised
     }
 
+    /// Matches <m> <pubkey>... <n> OP_CHECKMULTISIG with n public keys and 0 < m <= n
+    fn eval_multisig_pattern(elements: &[StackElement]) -> Option<ScriptPattern> {
+        let (last, rest) = elements.split_last()?;
+        let (n_op, rest) = rest.split_last()?;
+        let (m_op, keys) = rest.split_first()?;
+        if *last != StackElement::Op(all::OP_CHECKMULTISIG) {
+            return None;
+        }
+        let m = ScriptEvaluator::pushnum(m_op)?;
+        let n = ScriptEvaluator::pushnum(n_op)?;
+        if m == 0 || m > n || n as usize != keys.len() {
+            return None;
+        }
+        for key in keys {
+            match *key {
+                StackElement::Data(ref d) if d.len() == 33 || d.len() == 65 => {}
+                _ => return None,
+            }
+        }
+        Some(ScriptPattern::Pay2MultiSig { m, n })
+    }
+
+    /// Returns the value of an OP_PUSHNUM_1 to OP_PUSHNUM_16 element
+    fn pushnum(element: &StackElement) -> Option<u8> {
+        match *element {
+            StackElement::Op(op)
+                if (all::OP_PUSHNUM_1.to_u8()..=all::OP_PUSHNUM_16.to_u8())
+                    .contains(&op.to_u8()) =>
+            {
+                Some(op.to_u8() - all::OP_PUSHNUM_1.to_u8() + 1)
+            }
+            _ => None,
+        }
+    }
+
     /// Read a script-encoded unsigned integer.
     fn read_uint(data: &[u8], size: usize) -> Result<usize, ScriptError> {
         if data.len() < size {
@@ -250,14 +309,8 @@ impl<'a> ScriptEvaluator<'a> {
 pub fn eval_from_bytes_custom(bytes: &[u8], version_id: u8) -> EvaluatedScript {
     match ScriptEvaluator::new(bytes).eval() {
         Ok(stack) => eval_from_stack(stack, version_id),
-        Err(ScriptError::UnexpectedEof) => EvaluatedScript {
-            address: None,
-            pattern: ScriptPattern::NotRecognised,
-        },
-        Err(err) => EvaluatedScript {
-            address: None,
-            pattern: ScriptPattern::Error(err),
-        },
+        Err(ScriptError::UnexpectedEof) => EvaluatedScript::new(None, ScriptPattern::NotRecognised),
+        Err(err) => EvaluatedScript::new(None, ScriptPattern::Error(err)),
     }
 }
 
@@ -266,44 +319,43 @@ fn compute_stack(stack: Stack, version_id: u8) -> Result<EvaluatedScript, Script
     let script = match stack.pattern {
         ref p @ ScriptPattern::Pay2PublicKey => {
             let pub_key = stack.elements[0].data()?;
-            EvaluatedScript {
-                address: Some(public_key_to_addr(&pub_key, version_id)),
-                pattern: p.clone(),
-            }
+            EvaluatedScript::new(Some(public_key_to_addr(&pub_key, version_id)), p.clone())
         }
         ref p @ ScriptPattern::Pay2PublicKeyHash => {
             let h160 = stack.elements[2].data()?;
-            EvaluatedScript {
-                address: Some(hash_160_to_address(&h160, version_id)),
-                pattern: p.clone(),
-            }
+            EvaluatedScript::new(Some(hash_160_to_address(&h160, version_id)), p.clone())
         }
         ref p @ ScriptPattern::Pay2ScriptHash => {
             let h160 = stack.elements[1].data()?;
-            EvaluatedScript {
-                address: Some(hash_160_to_address(&h160, 5)),
-                pattern: p.clone(),
-            }
+            EvaluatedScript::new(Some(hash_160_to_address(&h160, 5)), p.clone())
+        }
+        ref p @ ScriptPattern::Pay2WitnessPublicKeyHash
+        | ref p @ ScriptPattern::Pay2WitnessScriptHash => {
+            let program = stack.elements[1].data()?;
+            EvaluatedScript::new(
+                witness_program_to_address(0, &program, version_id),
+                p.clone(),
+            )
         }
-        ScriptPattern::OpReturn(ref data) => EvaluatedScript {
-            address: None,
-            pattern: ScriptPattern::OpReturn(data.clone()),
-        },
-        ref p @ ScriptPattern::Pay2MultiSig => {
-            stack.elements[1].data()?;
-            EvaluatedScript {
-                address: None,
-                pattern: p.clone(),
+        ref p @ ScriptPattern::Pay2Taproot => {
+            let program = stack.elements[1].data()?;
+            EvaluatedScript::new(
+                witness_program_to_address(1, &program, version_id),
+                p.clone(),
+            )
+        }
+        ScriptPattern::OpReturn(ref data) => {
+            EvaluatedScript::new(None, ScriptPattern::OpReturn(data.clone()))
+        }
+        ref p @ ScriptPattern::Pay2MultiSig { n, .. } => {
+            let mut addresses = Vec::with_capacity(n as usize);
+            for element in &stack.elements[1..=n as usize] {
+                addresses.push(public_key_to_addr(&element.data()?, version_id));
             }
+            EvaluatedScript::with_addresses(p.clone(), addresses)
         }
-        ref p @ ScriptPattern::NotRecognised => EvaluatedScript {
-            address: None,
-            pattern: p.clone(),
-        },
-        ref p => EvaluatedScript {
-            address: None,
-            pattern: p.clone(),
-        },
+        ref p @ ScriptPattern::NotRecognised => EvaluatedScript::new(None, p.clone()),
+        ref p => EvaluatedScript::new(None, p.clone()),
     };
     Ok(script)
 }
@@ -312,14 +364,8 @@ fn compute_stack(stack: Stack, version_id: u8) -> Result<EvaluatedScript, Script
 fn eval_from_stack(stack: Stack, version_id: u8) -> EvaluatedScript {
     match compute_stack(stack, version_id) {
         Ok(script) => script,
-        Err(ScriptError::UnexpectedEof) => EvaluatedScript {
-            address: None,
-            pattern: ScriptPattern::NotRecognised,
-        },
-        Err(err) => EvaluatedScript {
-            address: None,
-            pattern: ScriptPattern::Error(err),
-        },
+        Err(ScriptError::UnexpectedEof) => EvaluatedScript::new(None, ScriptPattern::NotRecognised),
+        Err(err) => EvaluatedScript::new(None, ScriptPattern::Error(err)),
     }
 }
 
@@ -340,9 +386,39 @@ fn hash_160_to_address(h160: &[u8], version: u8) -> String {
     base58::encode(&hash)
 }
 
+/// Returns the bech32 human readable part for the given network version id,
+/// or None if the network has no known segwit address format.
+fn bech32_hrp(version_id: u8) -> Option<&'static str> {
+    match version_id {
+        0x00 => Some("bc"),
+        0x6f => Some("tb"),
+        REGTEST_VERSION_ID => Some("bcrt"),
+        0x30 => Some("ltc"),
+        _ => None,
+    }
+}
+
+/// Takes a witness program and encodes it as bech32 address (BIP173)
+fn witness_program_to_address(
+    witness_version: u8,
+    program: &[u8],
+    version_id: u8,
+) -> Option<String> {
+    let hrp = bech32_hrp(version_id)?;
+    let mut data = vec![bech32::u5::try_from_u8(witness_version).ok()?];
+    data.extend_from_slice(&program.to_base32());
+    // Witness version 1+ addresses use bech32m, see BIP350
+    let variant = match witness_version {
+        0 => Variant::Bech32,
+        _ => Variant::Bech32m,
+    };
+    bech32::encode(hrp, data, variant).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::{eval_from_bytes_custom, eval_from_stack, ScriptEvaluator, ScriptPattern};
+    use crate::blockchain::proto::script::eval_from_bytes_bitcoin;
     use crate::common::utils;
 
     #[test]
@@ -419,7 +495,11 @@ mod tests {
                    03e3818b65bcc73a7d64064106a859cc1a5a728c4345ff0b641209fba0d90de6e9 \
                    021f2f6e1e50cb6a953935c3601284925decd3fd21bc445712576873fb8c6ebc18 OP_PUSHNUM_3 OP_CHECKMULTISIG",
                    format!("{:?}", stack));
-        assert_eq!(stack.pattern, ScriptPattern::Pay2MultiSig);
+        assert_eq!(stack.pattern, ScriptPattern::Pay2MultiSig { m: 2, n: 3 });
+
+        let script = eval_from_stack(stack, 0x30);
+        assert_eq!(script.address, None);
+        assert_eq!(script.addresses.len(), 3);
     }
 
     #[test]
@@ -446,6 +526,85 @@ mod tests {
         assert_eq!(script.pattern, ScriptPattern::Pay2ScriptHash);
     }
 
+    #[test]
+    fn test_bitcoin_script_p2wpkh() {
+        // See https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki#examples
+        // Raw output script: 0014751e76e8199196d454941c45d1b3a323f1433bd6
+        //                    OP_0 20 0x751e76e8199196d454941c45d1b3a323f1433bd6
+        let bytes = utils::hex_to_vec("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let mut script = ScriptEvaluator::new(&bytes);
+        let stack = script.eval().unwrap();
+        assert_eq!(
+            "OP_PUSHBYTES_0 751e76e8199196d454941c45d1b3a323f1433bd6",
+            format!("{:?}", stack)
+        );
+
+        let script = eval_from_stack(stack, 0x00);
+        assert_eq!(
+            script.address,
+            Some(String::from("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"))
+        );
+        assert_eq!(script.pattern, ScriptPattern::Pay2WitnessPublicKeyHash);
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2wsh() {
+        // See https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki#examples
+        let bytes = utils::hex_to_vec(
+            "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+        );
+        let script = eval_from_bytes_custom(&bytes, 0x00);
+        assert_eq!(
+            script.address,
+            Some(String::from(
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+            ))
+        );
+        assert_eq!(script.pattern, ScriptPattern::Pay2WitnessScriptHash);
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2tr() {
+        // OP_1 32 0x79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798
+        let bytes = utils::hex_to_vec(
+            "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        let script = eval_from_bytes_custom(&bytes, 0x00);
+        assert_eq!(script.pattern, ScriptPattern::Pay2Taproot);
+        assert_eq!(
+            script.address,
+            Some(String::from(
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+            ))
+        );
+        // Matches the rust-bitcoin implementation
+        assert_eq!(
+            script.address,
+            eval_from_bytes_bitcoin(&bytes, 0x00).address
+        );
+
+        // Only 32 byte programs are taproot outputs
+        let bytes = utils::hex_to_vec("51020001");
+        let script = eval_from_bytes_custom(&bytes, 0x00);
+        assert_eq!(script.pattern, ScriptPattern::WitnessProgram);
+    }
+
+    #[test]
+    fn test_litecoin_script_p2wpkh() {
+        let bytes = utils::hex_to_vec("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let script = eval_from_bytes_custom(&bytes, 0x30);
+        assert_eq!(
+            script.address,
+            Some(String::from("ltc1qw508d6qejxtdg4y5r3zarvary0c5xw7kgmn4n9"))
+        );
+        assert_eq!(script.pattern, ScriptPattern::Pay2WitnessPublicKeyHash);
+
+        // Networks without a known bech32 prefix don't get an address
+        let script = eval_from_bytes_custom(&bytes, 0x1e);
+        assert_eq!(script.address, None);
+        assert_eq!(script.pattern, ScriptPattern::Pay2WitnessPublicKeyHash);
+    }
+
     #[test]
     fn test_bitcoin_script_data_output() {
         // Raw output script: 6a13636861726c6579206c6f766573206865696469
@@ -1,13 +1,16 @@
+pub mod asm;
 mod custom;
 
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
 
+use crate::blockchain::parser::types::REGTEST_VERSION_ID;
 use crate::blockchain::proto::script::custom::eval_from_bytes_custom;
 use bitcoin::address::Payload;
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::hashes::{hash160, Hash};
+use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
 use bitcoin::{address, Address, Network, PubkeyHash, Script};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -38,8 +41,12 @@ pub enum ScriptPattern {
     /// Pay to Multisig [BIP11]
     /// Pubkey script: <m> <A pubkey>[B pubkey][C pubkey...] <n> OP_CHECKMULTISIG
     /// Signature script: OP_0 <A sig>[B sig][C sig...]
-    /// TODO: Implement Pay2MultiSig: For now only 2n3 MultiSigs are detected
-    Pay2MultiSig,
+    /// Only bare multisig can be detected from the pubkey script,
+    /// P2SH wrapped multisig is classified as Pay2ScriptHash.
+    Pay2MultiSig {
+        m: u8,
+        n: u8,
+    },
 
     /// Pay to Public Key (p2pk) scripts are a simplified form of the p2pkh,
     /// but aren't commonly used in new transactions anymore,
@@ -81,7 +88,7 @@ impl fmt::Display for ScriptPattern {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ScriptPattern::OpReturn(_) => write!(f, "OpReturn"),
-            ScriptPattern::Pay2MultiSig => write!(f, "Pay2MultiSig"),
+            ScriptPattern::Pay2MultiSig { m, n } => write!(f, "Pay2MultiSig({}-of-{})", m, n),
             ScriptPattern::Pay2PublicKey => write!(f, "Pay2PublicKey"),
             ScriptPattern::Pay2PublicKeyHash => write!(f, "Pay2PublicKeyHash"),
             ScriptPattern::Pay2ScriptHash => write!(f, "Pay2ScriptHash"),
@@ -96,31 +103,103 @@ impl fmt::Display for ScriptPattern {
     }
 }
 
+/// Coarse classification of output scripts, e.g. for statistics.
+/// Unlike ScriptPattern it ignores the OP_RETURN payload and the multisig threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ScriptCategory {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    MultiSig,
+    OpReturn,
+    /// Unknown witness versions, provably unspendable and invalid scripts are included here too
+    NonStandard,
+}
+
+impl fmt::Display for ScriptCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let str = match *self {
+            ScriptCategory::P2pk => "P2PK",
+            ScriptCategory::P2pkh => "P2PKH",
+            ScriptCategory::P2sh => "P2SH",
+            ScriptCategory::P2wpkh => "P2WPKH",
+            ScriptCategory::P2wsh => "P2WSH",
+            ScriptCategory::P2tr => "P2TR",
+            ScriptCategory::MultiSig => "MultiSig",
+            ScriptCategory::OpReturn => "OP_RETURN",
+            ScriptCategory::NonStandard => "NonStandard",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl ScriptPattern {
+    pub fn category(&self) -> ScriptCategory {
+        match *self {
+            ScriptPattern::Pay2PublicKey => ScriptCategory::P2pk,
+            ScriptPattern::Pay2PublicKeyHash => ScriptCategory::P2pkh,
+            ScriptPattern::Pay2ScriptHash => ScriptCategory::P2sh,
+            ScriptPattern::Pay2WitnessPublicKeyHash => ScriptCategory::P2wpkh,
+            ScriptPattern::Pay2WitnessScriptHash => ScriptCategory::P2wsh,
+            ScriptPattern::Pay2Taproot => ScriptCategory::P2tr,
+            ScriptPattern::Pay2MultiSig { .. } => ScriptCategory::MultiSig,
+            ScriptPattern::OpReturn(_) => ScriptCategory::OpReturn,
+            ScriptPattern::WitnessProgram
+            | ScriptPattern::Unspendable
+            | ScriptPattern::NotRecognised
+            | ScriptPattern::Error(_) => ScriptCategory::NonStandard,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct EvaluatedScript {
     pub address: Option<String>,
+    /// Addresses derived from each public key of a multisig script
+    pub addresses: Vec<String>,
     pub pattern: ScriptPattern,
 }
 
 impl EvaluatedScript {
     pub fn new(address: Option<String>, pattern: ScriptPattern) -> Self {
-        Self { address, pattern }
+        Self {
+            address,
+            addresses: Vec::new(),
+            pattern,
+        }
+    }
+
+    pub fn with_addresses(pattern: ScriptPattern, addresses: Vec<String>) -> Self {
+        Self {
+            address: None,
+            addresses,
+            pattern,
+        }
     }
 }
 
 /// Extracts evaluated address from ScriptPubKey
 pub fn eval_from_bytes(bytes: &[u8], version_id: u8) -> EvaluatedScript {
     match version_id {
-        0x00 | 0x6f => eval_from_bytes_bitcoin(bytes, version_id),
+        0x00 | 0x6f | REGTEST_VERSION_ID => eval_from_bytes_bitcoin(bytes, version_id),
         _ => eval_from_bytes_custom(bytes, version_id),
     }
 }
 
+/// Classifies the given ScriptPubKey
+pub fn classify(bytes: &[u8], version_id: u8) -> ScriptCategory {
+    eval_from_bytes(bytes, version_id).pattern.category()
+}
+
 /// Extracts evaluated address from script using `rust_bitcoin`
 pub fn eval_from_bytes_bitcoin(bytes: &[u8], version_id: u8) -> EvaluatedScript {
     let network = match version_id {
         0x00 => Network::Bitcoin,
         0x6f => Network::Testnet,
+        REGTEST_VERSION_ID => Network::Regtest,
         _ => panic!("invalid network version"),
     };
 
@@ -128,14 +207,25 @@ pub fn eval_from_bytes_bitcoin(bytes: &[u8], version_id: u8) -> EvaluatedScript
 
     // For OP_RETURN and provably unspendable scripts there is no point in parsing the address
     if script.is_op_return() {
-        // OP_RETURN 13 <data>
-        let data = String::from_utf8(script.to_bytes().into_iter().skip(2).collect());
+        let data = String::from_utf8(extract_op_return_data(bytes).unwrap_or_default());
         let pattern = ScriptPattern::OpReturn(data.unwrap_or_else(|_| String::from("")));
         return EvaluatedScript::new(None, pattern);
     } else if script.is_provably_unspendable() {
         return EvaluatedScript::new(None, ScriptPattern::Unspendable);
     }
 
+    if let Some((m, pub_keys)) = decode_multisig(script) {
+        let pattern = ScriptPattern::Pay2MultiSig {
+            m,
+            n: pub_keys.len() as u8,
+        };
+        let addresses = pub_keys
+            .iter()
+            .map(|pk| pub_key_to_address(pk, network))
+            .collect();
+        return EvaluatedScript::with_addresses(pattern, addresses);
+    }
+
     let address = match Address::from_script(script, network) {
         Ok(address) => Some(format!("{}", address)),
         Err(err) => {
@@ -168,6 +258,85 @@ pub fn eval_from_bytes_bitcoin(bytes: &[u8], version_id: u8) -> EvaluatedScript
     }
 }
 
+/// Extracts the data pushed after OP_RETURN.
+/// Handles direct pushes as well as OP_PUSHDATA1/2/4. If the script contains multiple pushes,
+/// their payloads are concatenated. Parsing stops at the first malformed push.
+/// Returns None if the script does not start with OP_RETURN.
+pub fn extract_op_return_data(bytes: &[u8]) -> Option<Vec<u8>> {
+    let script = Script::from_bytes(bytes);
+    if !script.is_op_return() {
+        return None;
+    }
+    let mut data = Vec::new();
+    for instruction in script.instructions().skip(1) {
+        match instruction {
+            Ok(Instruction::PushBytes(push)) => data.extend_from_slice(push.as_bytes()),
+            Ok(Instruction::Op(_)) => continue,
+            Err(_) => break,
+        }
+    }
+    Some(data)
+}
+
+/// Decodes a bare multisig script: <m> <pubkey>... <n> OP_CHECKMULTISIG.
+/// Returns the threshold m and the public keys, or None if the script is malformed,
+/// e.g. n does not match the number of keys, m > n or a key has an invalid length.
+fn decode_multisig(script: &Script) -> Option<(u8, Vec<Vec<u8>>)> {
+    let instructions = script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+        .ok()?;
+    let (first, rest) = instructions.split_first()?;
+    let (last, rest) = rest.split_last()?;
+    let (n_op, keys) = rest.split_last()?;
+
+    if *last != Instruction::Op(OP_CHECKMULTISIG) {
+        return None;
+    }
+    let m = pushnum(first)?;
+    let n = pushnum(n_op)?;
+    if m == 0 || m > n || n as usize != keys.len() {
+        return None;
+    }
+
+    let mut pub_keys = Vec::with_capacity(keys.len());
+    for key in keys {
+        match key {
+            Instruction::PushBytes(bytes) if is_pub_key(bytes.as_bytes()) => {
+                pub_keys.push(bytes.as_bytes().to_vec())
+            }
+            _ => return None,
+        }
+    }
+    Some((m, pub_keys))
+}
+
+/// Returns the value of an OP_PUSHNUM_1 to OP_PUSHNUM_16 instruction
+fn pushnum(instruction: &Instruction) -> Option<u8> {
+    match instruction {
+        Instruction::Op(op)
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+        {
+            Some(op.to_u8() - OP_PUSHNUM_1.to_u8() + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Checks the length and prefix of a compressed (33 bytes) or uncompressed (65 bytes) public key
+fn is_pub_key(bytes: &[u8]) -> bool {
+    match bytes.len() {
+        33 => bytes[0] == 0x02 || bytes[0] == 0x03,
+        65 => bytes[0] == 0x04,
+        _ => false,
+    }
+}
+
+fn pub_key_to_address(pub_key: &[u8], network: Network) -> String {
+    let pkh = PubkeyHash::from_raw_hash(hash160::Hash::hash(pub_key));
+    Address::new(network, Payload::PubkeyHash(pkh)).to_string()
+}
+
 /// Workaround to parse address from p2pk scripts
 /// See issue https://github.com/rust-bitcoin/rust-bitcoin/issues/441
 fn p2pk_to_string(script: &Script, network: Network) -> Option<String> {
@@ -181,15 +350,20 @@ fn p2pk_to_string(script: &Script, network: Network) -> Option<String> {
         _ => unreachable!(),
     };
 
-    let pkh = PubkeyHash::from_raw_hash(hash160::Hash::hash(pk.as_bytes()));
-    let address = Address::new(network, Payload::PubkeyHash(pkh));
-    Some(address.to_string())
+    Some(pub_key_to_address(pk.as_bytes(), network))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ScriptPattern;
-    use crate::blockchain::proto::script::eval_from_bytes_bitcoin;
+    use super::{classify, ScriptCategory, ScriptPattern};
+    use crate::blockchain::parser::types::REGTEST_VERSION_ID;
+    use crate::blockchain::proto::script::{eval_from_bytes_bitcoin, extract_op_return_data};
+
+    const P2MS_PUB_KEY: [u8; 33] = [
+        0x02, 0x2d, 0xf8, 0x75, 0x04, 0x80, 0xad, 0x5b, 0x26, 0x95, 0x0b, 0x25, 0xc7, 0xba, 0x79,
+        0xd3, 0xe3, 0x7d, 0x75, 0xf6, 0x40, 0xf8, 0xe5, 0xd9, 0xbc, 0xd5, 0xb1, 0x50, 0xa0, 0xf8,
+        0x50, 0x14, 0xda,
+    ];
 
     #[test]
     fn test_bitcoin_script_p2pkh() {
@@ -228,15 +402,12 @@ mod tests {
         assert_eq!(result.pattern, ScriptPattern::Pay2PublicKey);
     }
 
-    /*
-    // FIXME: See https://github.com/rust-bitcoin/rust-bitcoin/pull/657/files
     #[test]
     fn test_bitcoin_script_p2ms() {
         // 2-of-3 Multi sig output
         // OP_2 33 0x022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da
         // 33 0x03e3818b65bcc73a7d64064106a859cc1a5a728c4345ff0b641209fba0d90de6e9
         // 33 0x021f2f6e1e50cb6a953935c3601284925decd3fd21bc445712576873fb8c6ebc18 OP_3 OP_CHECKMULTISIG
-        //TODO: complete this test
         let bytes = [
             0x52, 0x21, 0x02, 0x2d, 0xf8, 0x75, 0x04, 0x80, 0xad, 0x5b, 0x26, 0x95, 0x0b, 0x25,
             0xc7, 0xba, 0x79, 0xd3, 0xe3, 0x7d, 0x75, 0xf6, 0x40, 0xf8, 0xe5, 0xd9, 0xbc, 0xd5,
@@ -248,9 +419,50 @@ mod tests {
             0xfb, 0x8c, 0x6e, 0xbc, 0x18, 0x53, 0xae,
         ];
         let result = eval_from_bytes_bitcoin(&bytes, 0x00);
-        assert_eq!(result.pattern, ScriptPattern::Pay2MultiSig);
+        assert_eq!(result.pattern, ScriptPattern::Pay2MultiSig { m: 2, n: 3 });
+        assert_eq!(result.address, None);
+        assert_eq!(result.addresses.len(), 3);
+        assert_eq!(format!("{}", result.pattern), "Pay2MultiSig(2-of-3)");
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2ms_1_of_1() {
+        // OP_1 33 0x022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da OP_1 OP_CHECKMULTISIG
+        let mut bytes = vec![0x51, 0x21];
+        bytes.extend_from_slice(&P2MS_PUB_KEY);
+        bytes.extend_from_slice(&[0x51, 0xae]);
+        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        assert_eq!(result.pattern, ScriptPattern::Pay2MultiSig { m: 1, n: 1 });
+
+        // The derived address equals the p2pkh address of the public key
+        let mut p2pk = vec![0x21];
+        p2pk.extend_from_slice(&P2MS_PUB_KEY);
+        p2pk.push(0xac);
+        let expected = eval_from_bytes_bitcoin(&p2pk, 0x00).address.unwrap();
+        assert_eq!(result.addresses, vec![expected]);
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2ms_malformed() {
+        // n does not match the number of keys: OP_1 <pubkey> OP_2 OP_CHECKMULTISIG
+        let mut bytes = vec![0x51, 0x21];
+        bytes.extend_from_slice(&P2MS_PUB_KEY);
+        bytes.extend_from_slice(&[0x52, 0xae]);
+        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        assert_eq!(result.pattern, ScriptPattern::NotRecognised);
+        assert!(result.addresses.is_empty());
+
+        // m > n: OP_2 <pubkey> OP_1 OP_CHECKMULTISIG
+        bytes[0] = 0x52;
+        bytes[35] = 0x51;
+        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        assert_eq!(result.pattern, ScriptPattern::NotRecognised);
+
+        // Invalid public key length: OP_1 <3 bytes> OP_1 OP_CHECKMULTISIG
+        let bytes = [0x51, 0x03, 0x02, 0x01, 0x02, 0x51, 0xae];
+        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        assert_eq!(result.pattern, ScriptPattern::NotRecognised);
     }
-    */
 
     #[test]
     fn test_bitcoin_script_p2sh() {
@@ -269,6 +481,58 @@ mod tests {
         assert_eq!(result.pattern, ScriptPattern::Pay2ScriptHash);
     }
 
+    #[test]
+    fn test_bitcoin_script_p2wpkh() {
+        // Raw output script: 0014751e76e8199196d454941c45d1b3a323f1433bd6
+        //                    OP_0 20 0x751e76e8199196d454941c45d1b3a323f1433bd6
+        let bytes = [
+            0x00, 0x14, // OP_0, OP_PUSHDATA0(20 bytes)
+            0x75, 0x1e, 0x76, 0xe8, 0x19, 0x91, 0x96, 0xd4, 0x54, 0x94, 0x1c, 0x45, 0xd1, 0xb3,
+            0xa3, 0x23, 0xf1, 0x43, 0x3b, 0xd6,
+        ];
+        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        assert_eq!(
+            result.address,
+            Some(String::from("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"))
+        );
+        assert_eq!(result.pattern, ScriptPattern::Pay2WitnessPublicKeyHash);
+    }
+
+    #[test]
+    fn test_bitcoin_script_testnet() {
+        // Same scripts as above with testnet (P2PKH 0x6f, P2SH 0xc4, hrp tb) and regtest (hrp bcrt) prefixes
+        let p2pkh = [
+            0x76, 0xa9, 0x14, 0x12, 0xab, 0x8d, 0xc5, 0x88, 0xca, 0x9d, 0x57, 0x87, 0xdd, 0xe7,
+            0xeb, 0x29, 0x56, 0x9d, 0xa6, 0x3c, 0x3a, 0x23, 0x8c, 0x88, 0xac,
+        ];
+        let p2sh = [
+            0xa9, 0x14, 0xe9, 0xc3, 0xdd, 0x0c, 0x07, 0xaa, 0xc7, 0x61, 0x79, 0xeb, 0xc7, 0x6a,
+            0x6c, 0x78, 0xd4, 0xd6, 0x7c, 0x6c, 0x16, 0x0a, 0x87,
+        ];
+        let p2wpkh = [
+            0x00, 0x14, 0x75, 0x1e, 0x76, 0xe8, 0x19, 0x91, 0x96, 0xd4, 0x54, 0x94, 0x1c, 0x45,
+            0xd1, 0xb3, 0xa3, 0x23, 0xf1, 0x43, 0x3b, 0xd6,
+        ];
+        for version_id in [0x6f, REGTEST_VERSION_ID] {
+            assert_eq!(
+                eval_from_bytes_bitcoin(&p2pkh, version_id).address,
+                Some(String::from("mhDfyGpn1DonDGchg8KtJYcFPNNT3uHSzK"))
+            );
+            assert_eq!(
+                eval_from_bytes_bitcoin(&p2sh, version_id).address,
+                Some(String::from("2NEZG4p5giVjQt3Uez2Gip9PxMkwtF1Wdi9"))
+            );
+        }
+        assert_eq!(
+            eval_from_bytes_bitcoin(&p2wpkh, 0x6f).address,
+            Some(String::from("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"))
+        );
+        assert_eq!(
+            eval_from_bytes_bitcoin(&p2wpkh, REGTEST_VERSION_ID).address,
+            Some(String::from("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"))
+        );
+    }
+
     #[test]
     fn test_bitcoin_script_op_return() {
         // Raw output script: 6a13636861726c6579206c6f766573206865696469
@@ -285,6 +549,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_extract_op_return_data() {
+        // OP_RETURN OP_PUSHDATA1 0x05 <data>
+        let bytes = [0x6a, 0x4c, 0x05, 0x68, 0x65, 0x6c, 0x6c, 0x6f];
+        assert_eq!(extract_op_return_data(&bytes), Some(b"hello".to_vec()));
+        let result = eval_from_bytes_bitcoin(&bytes, 0x00);
+        assert_eq!(
+            result.pattern,
+            ScriptPattern::OpReturn(String::from("hello"))
+        );
+
+        // OP_RETURN OP_PUSHDATA2 0x0300 <data> 02 <data>
+        let bytes = [0x6a, 0x4d, 0x03, 0x00, 0x01, 0x02, 0x03, 0x02, 0x04, 0x05];
+        assert_eq!(
+            extract_op_return_data(&bytes),
+            Some(vec![0x01, 0x02, 0x03, 0x04, 0x05])
+        );
+
+        // Truncated push keeps the data parsed so far
+        let bytes = [0x6a, 0x01, 0xaa, 0x4e, 0xff, 0x00, 0x00, 0x00];
+        assert_eq!(extract_op_return_data(&bytes), Some(vec![0xaa]));
+
+        // Bare OP_RETURN and non OP_RETURN scripts
+        assert_eq!(extract_op_return_data(&[0x6a]), Some(Vec::new()));
+        assert_eq!(extract_op_return_data(&[0x51]), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        let p2tr = crate::common::utils::hex_to_vec(
+            "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        assert_eq!(classify(&p2tr, 0x00), ScriptCategory::P2tr);
+        assert_eq!(classify(&p2tr, 0x30), ScriptCategory::P2tr);
+        assert_eq!(
+            classify(&[0x6a, 0x01, 0x00], 0x00),
+            ScriptCategory::OpReturn
+        );
+        assert_eq!(classify(&[0x73, 0x63], 0x00), ScriptCategory::NonStandard);
+
+        let mut p2ms = vec![0x51, 0x21];
+        p2ms.extend_from_slice(&P2MS_PUB_KEY);
+        p2ms.extend_from_slice(&[0x51, 0xae]);
+        assert_eq!(classify(&p2ms, 0x00), ScriptCategory::MultiSig);
+        assert_eq!(format!("{}", ScriptCategory::P2wpkh), "P2WPKH");
+    }
+
     #[test]
     fn test_bitcoin_script_non_standard() {
         // Raw output script: 736372697074
@@ -5,7 +5,7 @@ use std::fmt;
 use crate::blockchain::proto::script;
 use crate::blockchain::proto::varuint::VarUint;
 use crate::blockchain::proto::ToRaw;
-use crate::common::utils;
+use crate::common::{hashing, utils};
 
 pub struct RawTx {
     pub version: u32,
@@ -53,6 +53,76 @@ impl EvaluatedTx {
         }
     }
 
+    /// Returns true if at least one input carries witness data (BIP144)
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|i| !i.witness.is_empty())
+    }
+
+    /// Returns the witness txid, which commits to the witness data as well.
+    /// For transactions without witness data it equals the txid.
+    pub fn wtxid(&self) -> sha256d::Hash {
+        hashing::sha256d(&self.to_witness_bytes())
+    }
+
+    /// Serializes the transaction including the segwit marker, flag and witness stacks.
+    /// Falls back to the legacy serialization if there is no witness data.
+    pub fn to_witness_bytes(&self) -> Vec<u8> {
+        let bytes = self.to_bytes();
+        if !self.has_witness() {
+            return bytes;
+        }
+        let mut witness_bytes = Vec::with_capacity(bytes.len() + 2);
+        // Insert marker and flag after the version
+        witness_bytes.extend_from_slice(&bytes[0..4]);
+        witness_bytes.extend_from_slice(&[0x00, 0x01]);
+        witness_bytes.extend_from_slice(&bytes[4..bytes.len() - 4]);
+        for i in &self.inputs {
+            witness_bytes.extend_from_slice(&VarUint::compact(i.witness.len() as u64).to_bytes());
+            for item in &i.witness {
+                witness_bytes.extend_from_slice(&VarUint::compact(item.len() as u64).to_bytes());
+                witness_bytes.extend_from_slice(item);
+            }
+        }
+        witness_bytes.extend_from_slice(&bytes[bytes.len() - 4..]);
+        witness_bytes
+    }
+
+    /// Size of the serialization without witness data in bytes
+    pub fn base_size(&self) -> u64 {
+        self.to_bytes().len() as u64
+    }
+
+    /// Number of bytes the witness serialization adds: marker, flag and all witness stacks.
+    /// Zero for legacy transactions.
+    pub fn witness_size(&self) -> u64 {
+        if !self.has_witness() {
+            return 0;
+        }
+        let stacks: u64 = self
+            .inputs
+            .iter()
+            .map(|i| {
+                let items: u64 = i
+                    .witness
+                    .iter()
+                    .map(|item| VarUint::compact(item.len() as u64).to_bytes().len() + item.len())
+                    .sum::<usize>() as u64;
+                VarUint::compact(i.witness.len() as u64).to_bytes().len() as u64 + items
+            })
+            .sum();
+        2 + stacks
+    }
+
+    /// Transaction weight as defined in BIP141: base_size * 3 + total_size
+    pub fn weight(&self) -> u64 {
+        self.base_size() * 4 + self.witness_size()
+    }
+
+    /// Virtual size in vbytes, the weight divided by 4 and rounded up
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+
     pub fn is_coinbase(&self) -> bool {
         if self.in_count.value == 1 {
             let input = self.inputs.first().unwrap();
@@ -60,6 +130,20 @@ impl EvaluatedTx {
         }
         false
     }
+
+    /// Returns true if the transaction signals replaceability explicitly (BIP125),
+    /// i.e. any input has a sequence number below 0xfffffffe. Always false for coinbase transactions.
+    pub fn signals_rbf(&self) -> bool {
+        !self.is_coinbase() && self.inputs.iter().any(|i| i.signals_rbf())
+    }
+
+    /// Returns true if any input is subject to a relative timelock (BIP68).
+    /// The sequence numbers are only enforced as timelocks from transaction version 2 on.
+    pub fn has_relative_timelock(&self) -> bool {
+        self.version >= 2
+            && !self.is_coinbase()
+            && self.inputs.iter().any(|i| i.relative_timelock().is_some())
+    }
 }
 
 impl fmt::Debug for EvaluatedTx {
@@ -87,6 +171,7 @@ impl From<RawTx> for EvaluatedTx {
     }
 }
 
+/// Serializes the transaction without witness data, as used for the txid
 impl ToRaw for EvaluatedTx {
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes =
@@ -147,6 +232,62 @@ pub struct TxInput {
     pub script_len: VarUint,
     pub script_sig: Vec<u8>,
     pub seq_no: u32,
+    // Witness stack items (BIP144), empty for non-segwit inputs
+    pub witness: Vec<Vec<u8>>,
+}
+
+impl TxInput {
+    /// Disassembles the scriptSig like Bitcoin Core, with the sighash types of signatures decoded
+    pub fn script_sig_asm(&self) -> String {
+        script::asm::to_asm(&self.script_sig, true)
+    }
+
+    /// Returns true if the sequence number signals replaceability (BIP125)
+    pub fn signals_rbf(&self) -> bool {
+        self.seq_no < 0xfffffffe
+    }
+
+    /// Decodes the relative timelock of the sequence number (BIP68), None if the disable flag is set.
+    /// Whether it is enforced also depends on the transaction version, see `EvaluatedTx::has_relative_timelock()`.
+    pub fn relative_timelock(&self) -> Option<RelativeTimelock> {
+        if self.seq_no & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = (self.seq_no & SEQUENCE_LOCKTIME_MASK) as u16;
+        match self.seq_no & SEQUENCE_LOCKTIME_TYPE_FLAG {
+            0 => Some(RelativeTimelock::Blocks(value)),
+            _ => Some(RelativeTimelock::Time(value)),
+        }
+    }
+
+    /// Classifies the witness stack as Taproot key-path or script-path spend (BIP341).
+    ///
+    /// Only the witness is inspected, the spent output is not known here. The result is only
+    /// meaningful for inputs spending a Pay2Taproot output, other segwit spends may look alike.
+    pub fn taproot_spend(&self) -> Option<TaprootSpend> {
+        let mut stack = self.witness.as_slice();
+        // An annex is the last item starting with 0x50, if there are at least two items
+        if stack.len() >= 2 && stack[stack.len() - 1].first() == Some(&0x50) {
+            stack = &stack[..stack.len() - 1];
+        }
+        match stack {
+            // Schnorr signature, 65 bytes with an explicit sighash type
+            [signature] if signature.len() == 64 || signature.len() == 65 => {
+                Some(TaprootSpend::KeyPath)
+            }
+            // Leaf script followed by the control block: leaf version and parity byte,
+            // internal key and the merkle path with up to 128 hashes
+            [.., _, control_block]
+                if control_block.len() >= 33
+                    && (control_block.len() - 33) % 32 == 0
+                    && (control_block.len() - 33) / 32 <= 128
+                    && control_block[0] & 0xfe == 0xc0 =>
+            {
+                Some(TaprootSpend::ScriptPath)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl ToRaw for TxInput {
@@ -167,10 +308,53 @@ impl fmt::Debug for TxInput {
             .field("script_len", &self.script_len)
             .field("script_sig", &self.script_sig)
             .field("seq_no", &self.seq_no)
+            .field("witness_items", &self.witness.len())
             .finish()
     }
 }
 
+// Sequence number encoding of relative timelocks (BIP68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
+/// Relative timelock of an input, counted from the confirmation of the spent output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RelativeTimelock {
+    /// Number of blocks
+    Blocks(u16),
+    /// Number of 512 second intervals
+    Time(u16),
+}
+
+impl RelativeTimelock {
+    /// Returns the timelock in seconds, None for block based timelocks
+    pub fn seconds(&self) -> Option<u32> {
+        match self {
+            RelativeTimelock::Blocks(_) => None,
+            RelativeTimelock::Time(intervals) => Some(*intervals as u32 * 512),
+        }
+    }
+}
+
+/// How a Taproot output is spent
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TaprootSpend {
+    /// Signature for the tweaked output key
+    KeyPath,
+    /// Reveals one of the committed scripts with a control block
+    ScriptPath,
+}
+
+impl fmt::Display for TaprootSpend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaprootSpend::KeyPath => write!(f, "key_path"),
+            TaprootSpend::ScriptPath => write!(f, "script_path"),
+        }
+    }
+}
+
 /// Evaluates script_pubkey and wraps TxOutput
 pub struct EvaluatedTxOut {
     pub script: script::EvaluatedScript,
@@ -193,6 +377,13 @@ pub struct TxOutput {
     pub script_pubkey: Vec<u8>,
 }
 
+impl TxOutput {
+    /// Disassembles the scriptPubKey like Bitcoin Core, see `script::asm::to_asm()`
+    pub fn script_pubkey_asm(&self) -> String {
+        script::asm::to_asm(&self.script_pubkey, false)
+    }
+}
+
 impl ToRaw for TxOutput {
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(8 + 5 + self.script_len.value as usize);
@@ -212,3 +403,124 @@ impl fmt::Debug for TxOutput {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_input(witness: Vec<Vec<u8>>) -> TxInput {
+        TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 0),
+            script_len: VarUint::from(0u8),
+            script_sig: vec![],
+            seq_no: 0xffffffff,
+            witness,
+        }
+    }
+
+    #[test]
+    fn test_taproot_spend() {
+        // Key-path with default and explicit sighash type
+        let input = new_input(vec![vec![0x01; 64]]);
+        assert_eq!(input.taproot_spend(), Some(TaprootSpend::KeyPath));
+        let input = new_input(vec![vec![0x01; 65]]);
+        assert_eq!(input.taproot_spend(), Some(TaprootSpend::KeyPath));
+        let mut annex = vec![0x50];
+        annex.extend_from_slice(&[0x00; 4]);
+        let input = new_input(vec![vec![0x01; 64], annex.clone()]);
+        assert_eq!(input.taproot_spend(), Some(TaprootSpend::KeyPath));
+
+        // Script-path: signature, <pubkey> OP_CHECKSIG and a control block with one hash
+        let mut script = vec![0x20];
+        script.extend_from_slice(&[0x02; 32]);
+        script.push(0xac);
+        let mut control_block = vec![0xc1];
+        control_block.extend_from_slice(&[0x03; 64]);
+        let input = new_input(vec![vec![0x01; 64], script.clone(), control_block.clone()]);
+        assert_eq!(input.taproot_spend(), Some(TaprootSpend::ScriptPath));
+        let input = new_input(vec![vec![0x01; 64], script, control_block, annex]);
+        assert_eq!(input.taproot_spend(), Some(TaprootSpend::ScriptPath));
+        assert_eq!(TaprootSpend::ScriptPath.to_string(), "script_path");
+
+        // Legacy and P2WPKH inputs
+        assert_eq!(new_input(vec![]).taproot_spend(), None);
+        let input = new_input(vec![vec![0x30; 71], vec![0x02; 33]]);
+        assert_eq!(input.taproot_spend(), None);
+    }
+
+    fn new_tx(version: u32, prev_txid: sha256d::Hash, seq_nos: &[u32]) -> EvaluatedTx {
+        let inputs = seq_nos
+            .iter()
+            .map(|seq_no| TxInput {
+                outpoint: TxOutpoint::new(prev_txid, 0xFFFFFFFF),
+                script_len: VarUint::from(0u8),
+                script_sig: vec![],
+                seq_no: *seq_no,
+                witness: vec![],
+            })
+            .collect::<Vec<TxInput>>();
+        EvaluatedTx::new(
+            version,
+            VarUint::from(inputs.len() as u8),
+            inputs,
+            VarUint::from(0u8),
+            vec![],
+            0,
+            0x00,
+        )
+    }
+
+    #[test]
+    fn test_sequence_flags() {
+        let sequence = |seq_no| TxInput {
+            seq_no,
+            ..new_input(vec![])
+        };
+        // Final and locktime enabling sequence numbers don't signal replaceability
+        assert!(!sequence(0xffffffff).signals_rbf());
+        assert!(!sequence(0xfffffffe).signals_rbf());
+        assert!(sequence(0xfffffffd).signals_rbf());
+        assert!(sequence(0).signals_rbf());
+
+        // The disable flag turns off relative timelocks
+        assert_eq!(sequence(0xffffffff).relative_timelock(), None);
+        assert_eq!(sequence(0xfffffffd).relative_timelock(), None);
+        assert_eq!(sequence(0x80000000).relative_timelock(), None);
+        assert_eq!(
+            sequence(0x7fffffff).relative_timelock(),
+            Some(RelativeTimelock::Time(0xffff))
+        );
+        assert_eq!(
+            sequence(0).relative_timelock(),
+            Some(RelativeTimelock::Blocks(0))
+        );
+        // Bits outside of the type flag and mask are ignored
+        assert_eq!(
+            sequence(0x00010090).relative_timelock(),
+            Some(RelativeTimelock::Blocks(0x90))
+        );
+        let time = sequence(0x00400001).relative_timelock().unwrap();
+        assert_eq!(time, RelativeTimelock::Time(1));
+        assert_eq!(time.seconds(), Some(512));
+        assert_eq!(RelativeTimelock::Blocks(1).seconds(), None);
+
+        let prev_txid = sha256d::Hash::hash(b"prev");
+        let tx = new_tx(2, prev_txid, &[0xffffffff, 0xfffffffd]);
+        assert!(tx.signals_rbf());
+        assert!(!tx.has_relative_timelock());
+        let tx = new_tx(2, prev_txid, &[0xffffffff, 144]);
+        assert!(tx.signals_rbf());
+        assert!(tx.has_relative_timelock());
+        // Relative timelocks are only enforced from version 2 on
+        let tx = new_tx(1, prev_txid, &[144]);
+        assert!(!tx.has_relative_timelock());
+        let tx = new_tx(2, prev_txid, &[0xfffffffe]);
+        assert!(!tx.signals_rbf());
+        assert!(!tx.has_relative_timelock());
+
+        // Coinbase inputs don't count
+        let coinbase = new_tx(2, sha256d::Hash::all_zeros(), &[0]);
+        assert!(!coinbase.signals_rbf());
+        assert!(!coinbase.has_relative_timelock());
+    }
+}
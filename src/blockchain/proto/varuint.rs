@@ -30,6 +30,16 @@ impl VarUint {
         };
         Ok(vint)
     }
+
+    /// Creates a VarUint with the shortest possible encoding for the given value
+    pub fn compact(value: u64) -> VarUint {
+        match value {
+            0x00..=0xfc => VarUint::from(value as u8),
+            0xfd..=0xffff => VarUint::from(value as u16),
+            0x10000..=0xffffffff => VarUint::from(value as u32),
+            _ => VarUint::from(value),
+        }
+    }
 }
 
 impl From<u8> for VarUint {
@@ -85,6 +95,17 @@ mod tests {
     use crate::blockchain::proto::ToRaw;
     use std::io;
 
+    #[test]
+    fn test_varuint_compact() {
+        assert_eq!(vec![0xfc], VarUint::compact(0xfc).to_bytes());
+        assert_eq!(vec![0xfd, 0xfd, 0x00], VarUint::compact(0xfd).to_bytes());
+        assert_eq!(
+            vec![0xfe, 0x00, 0x00, 0x01, 0x00],
+            VarUint::compact(0x10000).to_bytes()
+        );
+        assert_eq!(9, VarUint::compact(0x100000000).to_bytes().len());
+    }
+
     #[test]
     fn test_varuint_u8() {
         let v: u8 = 250;
@@ -128,7 +149,7 @@ mod tests {
 
         let test = VarUint::from(v);
         assert_eq!(9000000000000000000, test.value);
-        assert_eq!(v, test.value as u64);
+        assert_eq!(v, test.value);
         assert_eq!(9, test.to_bytes().len());
         assert_eq!(
             vec![0xff, 0x00, 0x00, 0x84, 0xe2, 0x50, 0x6c, 0xe6, 0x7c],
@@ -0,0 +1,153 @@
+//! Known block headers and transactions to check the parsing and hashing code paths
+//! of the current build before starting a long run, see `--self-test`.
+use std::io::Cursor;
+
+use crate::blockchain::parser::reader::BlockchainRead;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::common::{hashing, utils};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Bitcoin genesis block header
+const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000\
+    000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+/// Header of Bitcoin block 1
+const BLOCK_1_HEADER: &str =
+    "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000\
+    982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+const BLOCK_1_HASH: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+
+/// Coinbase transaction of the genesis block, its txid is the merkle root of the block
+const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000\
+    ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e20\
+    6272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a0100000043410467\
+    8afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384d\
+    f7ba0b8d578a4c702b6bf11d5fac00000000";
+const GENESIS_COINBASE_TXID: &str =
+    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// P2SH-P2WPKH spend from https://en.bitcoin.it/wiki/Weight_units
+const SEGWIT_TX: &str = "0100000000010115e180dc28a2327e687facc33f10f2a20da717e5548406f7ae8b4c811072f856\
+    03000000171600141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928ffffffff019caef505000000001976a9141d7cd6c7\
+    5c2e86f4cbf98eaed221b30bd9a0b92888ac02483045022100f764287d3e99b1474da9bec7f7ed236d6c81e793b20c4b5a\
+    a1f3051b9a7daa63022016a198031d5554dbb855bdbe8534776a4be6958bd8d530dc001c32b828f6f0ab0121038262a6c6\
+    cec93c2d3ecd6c6072efea86d02ff8e3328bbd0242b20af3425990ac00000000";
+const SEGWIT_TXID: &str = "8139979112e894a14f8370438a471d23984061ff83a9eba0bc7a34433327ec21";
+const SEGWIT_WTXID: &str = "6bf4e4dfb860cf0906f49c836700b130ac78cc391c72a0911c94cdec4dcb10ec";
+
+/// Collects the results of all checks
+#[derive(Default)]
+struct Checks {
+    passed: usize,
+    failures: Vec<String>,
+}
+
+impl Checks {
+    fn expect(&mut self, name: &str, expected: &str, got: &str) {
+        if expected == got {
+            self.passed += 1;
+        } else {
+            self.failures.push(format!(
+                "{}:\n  -> expected: {}\n  -> got: {}",
+                name, expected, got
+            ));
+        }
+    }
+}
+
+fn check_header(checks: &mut Checks, name: &str, raw: &str, hash: &str) -> OpResult<()> {
+    let raw = utils::hex_to_vec(raw);
+    let header = Cursor::new(raw.as_slice()).read_block_header()?;
+    let serialized = utils::arr_to_hex(&header.to_bytes());
+    checks.expect(
+        &format!("{} serialization", name),
+        &utils::arr_to_hex(&raw),
+        &serialized,
+    );
+    checks.expect(
+        &format!("{} hash", name),
+        hash,
+        &Hashed::double_sha256(header).hash.to_string(),
+    );
+    Ok(())
+}
+
+fn read_tx(raw: &str) -> OpResult<Hashed<EvaluatedTx>> {
+    let raw = utils::hex_to_vec(raw);
+    let tx = Cursor::new(raw.as_slice()).read_tx(0x00)?;
+    Ok(Hashed::double_sha256(EvaluatedTx::from(tx)))
+}
+
+/// Parses and hashes the embedded headers and transactions.
+/// Returns the number of checks, or a ValidationError listing all mismatches.
+pub fn run() -> OpResult<usize> {
+    let mut checks = Checks::default();
+    check_header(&mut checks, "genesis header", GENESIS_HEADER, GENESIS_HASH)?;
+    check_header(&mut checks, "block 1 header", BLOCK_1_HEADER, BLOCK_1_HASH)?;
+    // Parsing only uses the fastest backend, so check all of them directly
+    for backend in hashing::backends() {
+        let hash = backend.hash(&utils::hex_to_vec(GENESIS_HEADER));
+        checks.expect(
+            &format!("genesis hash ({})", backend.name()),
+            GENESIS_HASH,
+            &hash.to_string(),
+        );
+    }
+
+    let coinbase = read_tx(GENESIS_COINBASE)?;
+    checks.expect(
+        "genesis coinbase txid",
+        GENESIS_COINBASE_TXID,
+        &coinbase.hash.to_string(),
+    );
+    checks.expect(
+        "genesis merkle root",
+        GENESIS_COINBASE_TXID,
+        &utils::merkle_root(vec![coinbase.hash]).to_string(),
+    );
+
+    let segwit = read_tx(SEGWIT_TX)?;
+    checks.expect("segwit txid", SEGWIT_TXID, &segwit.hash.to_string());
+    checks.expect(
+        "segwit wtxid",
+        SEGWIT_WTXID,
+        &segwit.value.wtxid().to_string(),
+    );
+    checks.expect(
+        "segwit serialization",
+        &utils::arr_to_hex(&utils::hex_to_vec(SEGWIT_TX)),
+        &utils::arr_to_hex(&segwit.value.to_witness_bytes()),
+    );
+
+    if checks.failures.is_empty() {
+        return Ok(checks.passed);
+    }
+    let msg = format!(
+        "{} of {} self-test checks failed:\n{}\n",
+        checks.failures.len(),
+        checks.passed + checks.failures.len(),
+        checks.failures.join("\n")
+    );
+    Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test() {
+        assert_eq!(run().unwrap(), 9 + hashing::backends().len());
+    }
+
+    #[test]
+    fn test_failed_check() {
+        let mut checks = Checks::default();
+        check_header(&mut checks, "block 1 header", BLOCK_1_HEADER, GENESIS_HASH).unwrap();
+        assert_eq!(checks.passed, 1);
+        assert_eq!(checks.failures.len(), 1);
+        assert!(checks.failures[0].starts_with("block 1 header hash"));
+    }
+}
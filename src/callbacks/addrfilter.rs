@@ -0,0 +1,309 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::callbacks::common::{self, Unit};
+use crate::callbacks::csvdump::{self, Dialect, PartWriter};
+use crate::callbacks::Callback;
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Dumps all transactions which pay to or spend from one of the given addresses,
+/// in the same csv format as csvdump.
+///
+/// Inputs only reference the outpoint they spend, so the outputs paying to a watched address are
+/// remembered until they are spent. Only these outputs are held in memory.
+pub struct AddrFilter {
+    dump_folder: PathBuf,
+    addresses: HashSet<String>,
+    // Unspent outputs paying to a watched address, key: txid + index
+    watched_outputs: HashSet<Vec<u8>>,
+    block_writer: PartWriter,
+    tx_writer: PartWriter,
+    txin_writer: PartWriter,
+    txout_writer: PartWriter,
+    unit: Unit,
+
+    start_height: u64,
+    block_count: u64,
+    tx_count: u64,
+    hash_order: HashOrder,
+}
+
+impl AddrFilter {
+    /// Reads one address per line, empty lines and lines starting with # are ignored
+    fn read_addresses(path: &Path) -> OpResult<HashSet<String>> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            OpError::from(e).join_msg(&format!("Unable to read {}:", path.display()))
+        })?;
+        let addresses = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect::<HashSet<String>>();
+        if addresses.is_empty() {
+            let msg = format!("{} contains no addresses.", path.display());
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        Ok(addresses)
+    }
+
+    fn with_addresses(
+        dump_folder: &Path,
+        addresses: HashSet<String>,
+        with_header: bool,
+        unit: Unit,
+        hash_order: HashOrder,
+    ) -> OpResult<Self> {
+        let header =
+            |table| with_header.then(|| csvdump::header_row(table, false, Dialect::Default));
+        Ok(AddrFilter {
+            dump_folder: PathBuf::from(dump_folder),
+            addresses,
+            watched_outputs: HashSet::new(),
+            block_writer: PartWriter::new(dump_folder, "blocks", None, header("blocks"))?,
+            tx_writer: PartWriter::new(dump_folder, "transactions", None, header("transactions"))?,
+            txin_writer: PartWriter::new(dump_folder, "tx_in", None, header("tx_in"))?,
+            txout_writer: PartWriter::new(dump_folder, "tx_out", None, header("tx_out"))?,
+            unit,
+            start_height: 0,
+            block_count: 0,
+            tx_count: 0,
+            hash_order,
+        })
+    }
+
+    /// Returns true if the transaction spends or creates an output of a watched address.
+    /// Spent watched outputs are forgotten and newly created ones are remembered.
+    fn matches(&mut self, tx: &Hashed<EvaluatedTx>) -> bool {
+        let mut matched = false;
+        if !tx.value.is_coinbase() {
+            for input in &tx.value.inputs {
+                matched |= self.watched_outputs.remove(&input.outpoint.to_bytes());
+            }
+        }
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            match output.script.address {
+                Some(ref address) if self.addresses.contains(address) => {
+                    self.watched_outputs
+                        .insert(TxOutpoint::new(tx.hash, i as u32).to_bytes());
+                    matched = true;
+                }
+                _ => {}
+            }
+        }
+        matched
+    }
+}
+
+impl Callback for AddrFilter {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("addrfilter")
+            .about("Dumps transactions involving the given addresses into CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("address-file")
+                    .help("File with one address per line")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(2)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_unit_arg(csvdump::add_header_arg(command)))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let address_file = PathBuf::from(matches.get_one::<String>("address-file").unwrap());
+        let dump_folder = common::dump_folder_from_matches(matches)?;
+        AddrFilter::with_addresses(
+            &dump_folder,
+            AddrFilter::read_addresses(&address_file)?,
+            !matches.get_flag("no-header"),
+            Unit::from_matches(matches),
+            common::hash_order_from_matches(matches),
+        )
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        if block_height > 0 {
+            warn!(target: "callback", "Not starting at the genesis block, inputs spending earlier outputs are not matched.");
+        }
+        info!(target: "callback", "Executing addrfilter for {} addresses with dump folder: {} ...",
+              self.addresses.len(), &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let block_hash = self.hash_order.fmt(&block.header.hash).to_string();
+        let mut block_matched = false;
+        for tx in &block.txs {
+            if !self.matches(tx) {
+                continue;
+            }
+            block_matched = true;
+            self.tx_count += 1;
+            self.tx_writer.write_row(&tx.as_csv(
+                &block_hash,
+                None,
+                self.unit,
+                Dialect::Default,
+                self.hash_order,
+            ))?;
+            let txid_str = self.hash_order.fmt(&tx.hash).to_string();
+            for input in &tx.value.inputs {
+                self.txin_writer.write_row(&input.as_csv(
+                    &txid_str,
+                    Dialect::Default,
+                    self.hash_order,
+                ))?;
+            }
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                self.txout_writer.write_row(&output.as_csv(
+                    &txid_str,
+                    i as u32,
+                    self.unit,
+                    Dialect::Default,
+                ))?;
+            }
+        }
+        // Only blocks containing a matched transaction are written
+        if block_matched {
+            self.block_count += 1;
+            self.block_writer.write_row(&block.as_csv(
+                block_height,
+                Dialect::Default,
+                self.hash_order,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        for writer in [
+            &mut self.block_writer,
+            &mut self.tx_writer,
+            &mut self.txin_writer,
+            &mut self.txout_writer,
+        ] {
+            writer.finish(self.start_height, block_height)?;
+        }
+        info!(target: "callback", "Done.\nDumped matching transactions from height {} to {}:\n\
+                                   \t-> blocks:       {:9}\n\
+                                   \t-> transactions: {:9}",
+             self.start_height, block_height, self.block_count, self.tx_count);
+        Ok(())
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::RawTx;
+    use crate::common::testutil::{new_block, p2pkh, TxBuilder};
+
+    fn new_tx(inputs: Vec<TxOutpoint>, outputs: Vec<(u64, Vec<u8>)>) -> RawTx {
+        TxBuilder::new().inputs(inputs).pays(outputs).build()
+    }
+
+    fn coinbase(outputs: Vec<(u64, Vec<u8>)>) -> RawTx {
+        TxBuilder::coinbase().pays(outputs).build()
+    }
+
+    #[test]
+    fn test_read_addresses() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("addresses.txt");
+        fs::write(
+            &path,
+            "# suspects\n1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n\n  bc1qxyz \n",
+        )
+        .unwrap();
+        let addresses = AddrFilter::read_addresses(&path).unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert!(addresses.contains("bc1qxyz"));
+
+        fs::write(&path, "# nothing\n").unwrap();
+        let err = AddrFilter::read_addresses(&path).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        assert!(AddrFilter::read_addresses(&tmp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_addrfilter() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // Block 0 pays to A and B
+        let block0 = new_block(vec![
+            coinbase(vec![(50, p2pkh(1))]),
+            coinbase(vec![(25, p2pkh(2))]),
+        ]);
+        let address_a = block0.txs[0].value.outputs[0]
+            .script
+            .address
+            .clone()
+            .unwrap();
+        let mut addresses = HashSet::new();
+        addresses.insert(address_a);
+        let mut cb = AddrFilter::with_addresses(
+            tmp_dir.path(),
+            addresses,
+            true,
+            Unit::Sat,
+            HashOrder::Display,
+        )
+        .unwrap();
+        cb.on_start(0).unwrap();
+        cb.on_block(&block0, 0).unwrap();
+
+        // Block 1 only spends from A, block 2 is unrelated
+        let spend = new_tx(
+            vec![TxOutpoint::new(block0.txs[0].hash, 0)],
+            vec![(40, p2pkh(3))],
+        );
+        let block1 = new_block(vec![coinbase(vec![(50, p2pkh(2))]), spend]);
+        cb.on_block(&block1, 1).unwrap();
+        let unrelated = new_tx(
+            vec![TxOutpoint::new(block0.txs[1].hash, 0)],
+            vec![(20, p2pkh(4))],
+        );
+        cb.on_block(&new_block(vec![unrelated]), 2).unwrap();
+        assert!(cb.watched_outputs.is_empty());
+        cb.on_complete(2).unwrap();
+
+        let read = |name: &str| fs::read_to_string(tmp_dir.path().join(name)).unwrap();
+        let txs = read("transactions-0-2.csv");
+        assert_eq!(txs.lines().count(), 3);
+        let rows = txs
+            .strip_prefix("txid;hashBlock;")
+            .unwrap()
+            .split_once('\n')
+            .unwrap()
+            .1;
+        assert!(rows.starts_with(&format!("{};", block0.txs[0].hash)));
+        assert!(txs.contains(&format!("{};", block1.txs[1].hash)));
+        assert_eq!(read("blocks-0-2.csv").lines().count(), 3);
+        assert_eq!(read("tx_in-0-2.csv").lines().count(), 3);
+        assert_eq!(read("tx_out-0-2.csv").lines().count(), 3);
+    }
+}
@@ -3,28 +3,131 @@ use std::fs::{self, File};
 use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 
+use byteorder::{ByteOrder, LittleEndian};
 use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::callbacks::common::{self, MissingPrevouts, SpillMap, Spillable, Unit, UnspentValue};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
 use crate::errors::OpResult;
 
-/// Dumps all addresses with non-zero balance in a csv file
+/// Running totals of a single address
+#[derive(Default)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+struct Account {
+    balance: u64,
+    tx_count: u64,
+}
+
+impl Spillable for Account {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Account {
+            balance: LittleEndian::read_u64(&bytes[0..8]),
+            tx_count: LittleEndian::read_u64(&bytes[8..16]),
+        }
+    }
+}
+
+impl ToRaw for Account {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.balance.to_le_bytes());
+        bytes.extend_from_slice(&self.tx_count.to_le_bytes());
+        bytes
+    }
+}
+
+/// Dumps all addresses with non-zero balance in a csv file.
+///
+/// Spent outputs are looked up in the set of unspent outputs to debit the address they were paid to.
+/// Spendable outputs without address are kept with an empty one, so inputs which spend unknown
+/// outputs can be told apart. Both the unspent outputs and the accounts can be moved to disk with `--spill-dir`.
 pub struct Balances {
     dump_folder: PathBuf,
     writer: BufWriter<File>,
+    unit: Unit,
 
     // key: txid + index
-    unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    unspents: SpillMap<UnspentValue>,
+    // key: address
+    accounts: SpillMap<Account>,
 
     start_height: u64,
     end_height: u64,
+    skipped_outputs: u64,
+    missing_prevouts: MissingPrevouts,
 }
 
 impl Balances {
     fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
         Ok(BufWriter::with_capacity(cap, File::create(path)?))
     }
+
+    /// Credits the outputs and debits the spent inputs of the given transaction.
+    /// Each address touched by the transaction counts it once.
+    fn apply_tx(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) -> OpResult<()> {
+        // (credit, debit) per address
+        let mut changes: HashMap<String, (u64, u64)> = HashMap::new();
+        if !tx.value.is_coinbase() {
+            let mut missing = 0;
+            for input in &tx.value.inputs {
+                match self.unspents.remove(&input.outpoint.to_bytes())? {
+                    Some(unspent) if unspent.address.is_empty() => {}
+                    Some(unspent) => changes.entry(unspent.address).or_default().1 += unspent.value,
+                    None => missing += 1,
+                }
+            }
+            self.missing_prevouts.record(missing);
+        }
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            let address = match output.script.address {
+                Some(ref address) => address.clone(),
+                None => {
+                    debug!(target: "callback", "Ignoring output without address in: {} ({})",
+                           &tx.hash, output.script.pattern);
+                    self.skipped_outputs += 1;
+                    // Provably unspendable outputs are never spent
+                    match output.script.pattern {
+                        ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable => continue,
+                        _ => String::new(),
+                    }
+                }
+            };
+            let unspent = UnspentValue {
+                block_height,
+                value: output.out.value,
+                address: address.clone(),
+            };
+            let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
+            // Only coinbase transactions were ever duplicated (BIP30),
+            // the replaced output is lost for its owner
+            if tx.value.is_coinbase() {
+                if let Some(replaced) = self.unspents.replace(key, unspent)? {
+                    common::warn_duplicate_output(&tx.hash, i as u32, block_height);
+                    if !replaced.address.is_empty() {
+                        changes.entry(replaced.address).or_default().1 += replaced.value;
+                    }
+                }
+            } else {
+                self.unspents.insert(key, unspent)?;
+            }
+            if !address.is_empty() {
+                changes.entry(address).or_default().0 += output.out.value;
+            }
+        }
+
+        for (address, (credit, debit)) in changes {
+            let key = address.into_bytes();
+            let mut account = self.accounts.remove(&key)?.unwrap_or_default();
+            account.balance = account.balance + credit - debit;
+            account.tx_count += 1;
+            self.accounts.insert(key, account)?;
+        }
+        Ok(())
+    }
 }
 
 impl Callback for Balances {
@@ -32,7 +135,7 @@ impl Callback for Balances {
     where
         Self: Sized,
     {
-        Command::new("balances")
+        let command = Command::new("balances")
             .about("Dumps all addresses with non-zero balance to CSV file")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
@@ -41,20 +144,25 @@ impl Callback for Balances {
                     .help("Folder to store csv file")
                     .index(1)
                     .required(true),
-            )
+            );
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(command)))
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
         let cb = Balances {
             dump_folder: PathBuf::from(dump_folder),
             writer: Balances::create_writer(4000000, dump_folder.join("balances.csv.tmp"))?,
-            unspents: HashMap::with_capacity(10000000),
+            unit: Unit::from_matches(matches),
+            unspents: SpillMap::from_matches(matches, "balances-unspents-spill")?,
+            accounts: SpillMap::from_matches(matches, "balances-accounts-spill")?,
             start_height: 0,
             end_height: 0,
+            skipped_outputs: 0,
+            missing_prevouts: MissingPrevouts::default(),
         };
         Ok(cb)
     }
@@ -65,17 +173,9 @@ impl Callback for Balances {
         Ok(())
     }
 
-    /// For each transaction in the block
-    ///   1. apply input transactions (remove (TxID == prevTxIDOut and prevOutID == spentOutID))
-    ///   2. apply output transactions (add (TxID + curOutID -> HashMapVal))
-    /// For each address, retain:
-    ///   * block height as "last modified"
-    ///   * output_val
-    ///   * address
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
         for tx in &block.txs {
-            common::remove_unspents(tx, &mut self.unspents);
-            common::insert_unspents(tx, block_height, &mut self.unspents);
+            self.apply_tx(tx, block_height)?;
         }
         Ok(())
     }
@@ -84,19 +184,24 @@ impl Callback for Balances {
         self.end_height = block_height;
 
         self.writer
-            .write_all(format!("{};{}\n", "address", "balance").as_bytes())?;
-
-        // Collect balances for each address
-        let mut balances: HashMap<&str, u64> = HashMap::new();
-        for unspent in self.unspents.values() {
-            let entry = balances.entry(&unspent.address).or_insert(0);
-            *entry += unspent.value
-        }
+            .write_all(format!("{};{};{}\n", "address", "balance", "tx_count").as_bytes())?;
 
-        for (address, balance) in balances.iter() {
-            self.writer
-                .write_all(format!("{};{}\n", address, balance).as_bytes())?;
-        }
+        let mut count = 0;
+        let writer = &mut self.writer;
+        let unit = self.unit;
+        self.accounts.for_each(|address, account| {
+            if account.balance > 0 {
+                writer.write_all(address)?;
+                writer.write_all(
+                    format!(";{};{}\n", unit.format(account.balance), account.tx_count).as_bytes(),
+                )?;
+                count += 1;
+            }
+            Ok(())
+        })?;
+        self.writer.flush()?;
+        self.unspents.destroy()?;
+        self.accounts.destroy()?;
 
         fs::rename(
             self.dump_folder.as_path().join("balances.csv.tmp"),
@@ -107,7 +212,150 @@ impl Callback for Balances {
         )
         .expect("Unable to rename tmp file!");
 
-        info!(target: "callback", "Done.\nDumped {} addresses.", balances.len());
+        if self.skipped_outputs > 0 {
+            warn!(target: "callback", "Skipped {} outputs without a decodable address.", self.skipped_outputs);
+        }
+        info!(target: "callback", "Done.\nDumped {} addresses.", count);
+        self.missing_prevouts.log_summary(
+            self.start_height,
+            false,
+            "they were not debited and the balances only cover outputs created from the start height on",
+        );
         Ok(())
     }
+
+    fn exit_code(&self) -> i32 {
+        match self
+            .missing_prevouts
+            .is_inconsistent(self.start_height, false)
+        {
+            true => EXIT_INCONSISTENT,
+            false => 0,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::RawTx;
+    use crate::common::testutil::{new_block, p2pkh, TxBuilder};
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn new_tx(inputs: Vec<TxOutpoint>, outputs: Vec<(u64, Vec<u8>)>) -> RawTx {
+        TxBuilder::new().inputs(inputs).pays(outputs).build()
+    }
+
+    fn coinbase(outputs: Vec<(u64, Vec<u8>)>) -> RawTx {
+        TxBuilder::coinbase().pays(outputs).build()
+    }
+
+    fn test_balances(spill_dir: Option<PathBuf>) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = Balances {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: Balances::create_writer(100, tmp_dir.path().join("balances.csv.tmp")).unwrap(),
+            unit: Unit::Sat,
+            unspents: SpillMap::new(spill_dir.as_ref().map(|p| p.join("unspents")), 1).unwrap(),
+            accounts: SpillMap::new(spill_dir.as_ref().map(|p| p.join("accounts")), 1).unwrap(),
+            start_height: 0,
+            end_height: 0,
+            skipped_outputs: 0,
+            missing_prevouts: MissingPrevouts::default(),
+        };
+
+        // Block 0 pays to A, the second output has no address
+        let block0 = new_block(vec![coinbase(vec![(50, p2pkh(1)), (1, vec![0x51])])]);
+        let address_a = block0.txs[0].value.outputs[0]
+            .script
+            .address
+            .clone()
+            .unwrap();
+        cb.on_block(&block0, 0).unwrap();
+
+        // Block 1 pays to B and moves A's coins to A and B, along with the output without
+        // address and one which was never seen
+        let spend = new_tx(
+            vec![
+                TxOutpoint::new(block0.txs[0].hash, 0),
+                TxOutpoint::new(block0.txs[0].hash, 1),
+                TxOutpoint::new(sha256d::Hash::hash(b"unknown"), 0),
+            ],
+            vec![(20, p2pkh(1)), (30, p2pkh(2))],
+        );
+        let block1 = new_block(vec![coinbase(vec![(50, p2pkh(2))]), spend]);
+        let address_b = block1.txs[0].value.outputs[0]
+            .script
+            .address
+            .clone()
+            .unwrap();
+        cb.on_block(&block1, 1).unwrap();
+
+        let mut accounts = HashMap::new();
+        cb.accounts
+            .for_each(|key, account| {
+                let account = Account::from_bytes(&account.to_bytes());
+                accounts.insert(String::from_utf8(key.to_vec()).unwrap(), account);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            accounts[&address_a],
+            Account {
+                balance: 20,
+                tx_count: 2
+            }
+        );
+        assert_eq!(
+            accounts[&address_b],
+            Account {
+                balance: 80,
+                tx_count: 2
+            }
+        );
+        assert_eq!(cb.skipped_outputs, 1);
+        assert_eq!(
+            (cb.missing_prevouts.inputs, cb.missing_prevouts.txs),
+            (1, 1)
+        );
+
+        // Block 2 repeats the coinbase of block 1 (BIP30), B only owns the later output
+        cb.on_block(&new_block(vec![coinbase(vec![(50, p2pkh(2))])]), 2)
+            .unwrap();
+        let account = cb.accounts.remove(address_b.as_bytes()).unwrap().unwrap();
+        assert_eq!(account.balance, 80);
+        cb.accounts
+            .insert(address_b.clone().into_bytes(), account)
+            .unwrap();
+
+        cb.on_complete(2).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("balances-0-2.csv")).unwrap();
+        assert_eq!(dump.lines().next(), Some("address;balance;tx_count"));
+        assert!(dump.contains(&format!("{};80;3\n", address_b)));
+        assert_eq!(dump.lines().count(), 3);
+        // All outputs should be known when starting at the genesis block
+        assert_eq!(cb.exit_code(), EXIT_INCONSISTENT);
+    }
+
+    #[test]
+    fn test_balances_in_memory() {
+        test_balances(None);
+    }
+
+    #[test]
+    fn test_balances_spilled() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        test_balances(Some(PathBuf::from(spill_dir.path())));
+        assert!(!spill_dir.path().join("unspents").exists());
+        assert!(!spill_dir.path().join("accounts").exists());
+    }
 }
@@ -0,0 +1,40 @@
+use clap::{ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::Callback;
+use crate::errors::OpResult;
+
+/// Does nothing with the parsed blocks, the parser reports its throughput at the end.
+///
+/// Used by `--benchmark` in place of a user callback, so it is not registered as subcommand.
+#[derive(Default)]
+pub struct Benchmark;
+
+impl Callback for Benchmark {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("benchmark").about("Measures the parse throughput without invoking a callback")
+    }
+
+    fn new(_: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Benchmark)
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Benchmarking ...");
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<()> {
+        Ok(())
+    }
+
+    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Dumps the blk file and byte offset of each block into a csv file,
+/// so blocks can be read again later without the LevelDB block index.
+pub struct BlockIndex {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+
+    start_height: u64,
+    hash_order: HashOrder,
+}
+
+impl BlockIndex {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    fn as_csv(block: &Block, block_height: u64, hash_order: HashOrder) -> OpResult<String> {
+        let location = block.location.as_ref().ok_or_else(|| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Block at height {} has no location.",
+                block_height
+            ))
+        })?;
+        let file_name = location
+            .path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        Ok(format!(
+            "{};{};{};{};{}\n",
+            block_height,
+            hash_order.fmt(&block.header.hash),
+            file_name,
+            location.offset,
+            block.size
+        ))
+    }
+}
+
+impl Callback for BlockIndex {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("blockindex")
+            .about("Dumps the blk file and offset of each block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(command)
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = BlockIndex {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: BlockIndex::create_writer(4000000, dump_folder.join("block_index.csv.tmp"))?,
+            start_height: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing blockindex with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                "height", "hash", "file", "offset", "size"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.writer
+            .write_all(BlockIndex::as_csv(block, block_height, self.hash_order)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        fs::rename(
+            self.dump_folder.as_path().join("block_index.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "block_index-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped block locations from height {} to {}.",
+             self.start_height, block_height);
+        Ok(())
+    }
+
+    fn needs_full_data(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::block::BlockLocation;
+    use crate::common::testutil::BlockBuilder;
+
+    #[test]
+    fn test_block_index_as_csv() {
+        let mut block = BlockBuilder::new().size(285).build();
+        assert!(BlockIndex::as_csv(&block, 0, HashOrder::Display).is_err());
+
+        block.location = Some(BlockLocation {
+            path: PathBuf::from("/home/user/.bitcoin/blocks/blk00000.dat"),
+            offset: 8,
+        });
+        assert_eq!(
+            BlockIndex::as_csv(&block, 0, HashOrder::Display).unwrap(),
+            format!("0;{};blk00000.dat;8;285\n", &block.header.hash)
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::callbacks::common::{self, MissingPrevouts, SpillMap};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::errors::OpResult;
+
+/// Address id of spendable outputs without address
+const NO_ADDRESS: u64 = u64::MAX;
+
+/// Disjoint-set forest over address ids, only non-root ids have an entry.
+/// The smaller id of two merged clusters becomes the root, so each cluster is
+/// identified by the id of its first seen address.
+struct DisjointSet {
+    // key: id, value: parent id
+    parents: SpillMap<u64>,
+}
+
+impl DisjointSet {
+    /// Returns the root of the given id and points all ids on the way directly to it
+    fn find(&mut self, id: u64) -> OpResult<u64> {
+        let mut path = Vec::new();
+        let mut root = id;
+        while let Some(parent) = self.parents.remove(&root.to_le_bytes())? {
+            path.push(root);
+            root = parent;
+        }
+        for id in path {
+            self.parents.insert(id.to_le_bytes().to_vec(), root)?;
+        }
+        Ok(root)
+    }
+
+    /// Merges the clusters of both ids, returns false if they already were the same
+    fn union(&mut self, a: u64, b: u64) -> OpResult<bool> {
+        let (a, b) = (self.find(a)?, self.find(b)?);
+        if a == b {
+            return Ok(false);
+        }
+        self.parents
+            .insert(a.max(b).to_le_bytes().to_vec(), a.min(b))?;
+        Ok(true)
+    }
+}
+
+/// Groups addresses into clusters with the common-input-ownership heuristic:
+/// all addresses spent from in the same transaction are assumed to belong to the same entity.
+///
+/// The unspent outputs, the address ids and the disjoint-set can be moved to disk with `--spill-dir`.
+pub struct Clusters {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+
+    // key: txid + index, value: address id
+    unspents: SpillMap<u64>,
+    // key: address, value: address id
+    address_ids: SpillMap<u64>,
+    clusters: DisjointSet,
+
+    start_height: u64,
+    address_count: u64,
+    merge_count: u64,
+    missing_prevouts: MissingPrevouts,
+}
+
+impl Clusters {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Returns the id of the given address, new addresses get the next free one
+    fn address_id(&mut self, address: &str) -> OpResult<u64> {
+        let key = address.as_bytes().to_vec();
+        let id = match self.address_ids.remove(&key)? {
+            Some(id) => id,
+            None => {
+                self.address_count += 1;
+                self.address_count - 1
+            }
+        };
+        self.address_ids.insert(key, id)?;
+        Ok(id)
+    }
+
+    /// Merges the clusters of all input addresses and records the outputs
+    fn apply_tx(&mut self, tx: &Hashed<EvaluatedTx>) -> OpResult<()> {
+        if !tx.value.is_coinbase() {
+            let mut first = None;
+            let mut missing = 0;
+            for input in &tx.value.inputs {
+                let id = match self.unspents.remove(&input.outpoint.to_bytes())? {
+                    Some(NO_ADDRESS) => continue,
+                    Some(id) => id,
+                    None => {
+                        missing += 1;
+                        continue;
+                    }
+                };
+                match first {
+                    Some(first) => {
+                        if self.clusters.union(first, id)? {
+                            self.merge_count += 1;
+                        }
+                    }
+                    None => first = Some(id),
+                }
+            }
+            self.missing_prevouts.record(missing);
+        }
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            let id = match output.script.address {
+                Some(ref address) => self.address_id(address)?,
+                // Provably unspendable outputs are never spent
+                None => match output.script.pattern {
+                    ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable => continue,
+                    _ => NO_ADDRESS,
+                },
+            };
+            let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
+            // Only coinbase transactions were ever duplicated (BIP30)
+            if tx.value.is_coinbase() {
+                self.unspents.replace(key, id)?;
+            } else {
+                self.unspents.insert(key, id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Callback for Clusters {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("clusters")
+            .about("Groups addresses by common input ownership and dumps their cluster to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_spill_args(command))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = Clusters {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Clusters::create_writer(4000000, dump_folder.join("clusters.csv.tmp"))?,
+            unspents: SpillMap::from_matches(matches, "clusters-unspents-spill")?,
+            address_ids: SpillMap::from_matches(matches, "clusters-addresses-spill")?,
+            clusters: DisjointSet {
+                parents: SpillMap::from_matches(matches, "clusters-parents-spill")?,
+            },
+            start_height: 0,
+            address_count: 0,
+            merge_count: 0,
+            missing_prevouts: MissingPrevouts::default(),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing clusters with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            self.apply_tx(tx)?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer
+            .write_all(format!("{};{}\n", "address", "cluster_id").as_bytes())?;
+
+        let writer = &mut self.writer;
+        let clusters = &mut self.clusters;
+        self.address_ids.for_each(|address, id| {
+            let cluster_id = clusters.find(*id)?;
+            writer.write_all(address)?;
+            writer.write_all(format!(";{}\n", cluster_id).as_bytes())?;
+            Ok(())
+        })?;
+        self.writer.flush()?;
+        self.unspents.destroy()?;
+        self.address_ids.destroy()?;
+        self.clusters.parents.destroy()?;
+
+        fs::rename(
+            self.dump_folder.as_path().join("clusters.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "clusters-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped {} addresses in {} clusters.",
+             self.address_count, self.address_count - self.merge_count);
+        self.missing_prevouts.log_summary(
+            self.start_height,
+            false,
+            "the addresses they were paid to are not clustered with the other inputs",
+        );
+        Ok(())
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self
+            .missing_prevouts
+            .is_inconsistent(self.start_height, false)
+        {
+            true => EXIT_INCONSISTENT,
+            false => 0,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::RawTx;
+    use crate::common::testutil::{new_block, null_outpoint, p2pkh, TxBuilder};
+    use std::collections::HashMap;
+
+    fn new_tx(inputs: Vec<TxOutpoint>, outputs: Vec<Vec<u8>>) -> RawTx {
+        let outputs = outputs
+            .into_iter()
+            .map(|script_pubkey| (1000, script_pubkey));
+        TxBuilder::new().inputs(inputs).pays(outputs).build()
+    }
+
+    fn coinbase(outputs: Vec<Vec<u8>>) -> RawTx {
+        new_tx(vec![null_outpoint()], outputs)
+    }
+
+    #[test]
+    fn test_disjoint_set() {
+        let mut set = DisjointSet {
+            parents: SpillMap::new(None, 0).unwrap(),
+        };
+        assert_eq!(set.find(7).unwrap(), 7);
+        assert!(set.union(3, 2).unwrap());
+        assert!(set.union(4, 3).unwrap());
+        assert!(!set.union(2, 4).unwrap());
+        assert!(set.union(5, 6).unwrap());
+        assert_eq!(set.find(4).unwrap(), 2);
+        assert_eq!(set.find(6).unwrap(), 5);
+        assert!(set.union(6, 4).unwrap());
+        for id in 2..=6 {
+            assert_eq!(set.find(id).unwrap(), 2);
+        }
+    }
+
+    fn test_clusters(spill_dir: Option<PathBuf>) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let spill_map =
+            |name: &str| SpillMap::new(spill_dir.as_ref().map(|p| p.join(name)), 1).unwrap();
+        let mut cb = Clusters {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: Clusters::create_writer(100, tmp_dir.path().join("clusters.csv.tmp")).unwrap(),
+            unspents: spill_map("unspents"),
+            address_ids: spill_map("addresses"),
+            clusters: DisjointSet {
+                parents: spill_map("parents"),
+            },
+            start_height: 0,
+            address_count: 0,
+            merge_count: 0,
+            missing_prevouts: MissingPrevouts::default(),
+        };
+
+        // Block 0 pays to A, B, C and D, the last output has no address
+        let block0 = new_block(vec![coinbase(vec![
+            p2pkh(1),
+            p2pkh(2),
+            p2pkh(3),
+            p2pkh(4),
+            vec![0x51],
+        ])]);
+        cb.on_start(0).unwrap();
+        cb.on_block(&block0, 0).unwrap();
+        let address = |i: usize| {
+            block0.txs[0].value.outputs[i]
+                .script
+                .address
+                .clone()
+                .unwrap()
+        };
+
+        // A and B are spent together paying to D, C is spent along with the output without address
+        let outpoint = |i| TxOutpoint::new(block0.txs[0].hash, i);
+        let block1 = new_block(vec![
+            coinbase(vec![p2pkh(5)]),
+            new_tx(vec![outpoint(0), outpoint(1)], vec![p2pkh(4)]),
+            new_tx(vec![outpoint(2), outpoint(4)], vec![p2pkh(6)]),
+        ]);
+        cb.on_block(&block1, 1).unwrap();
+        // D and F are spent together
+        let block2 = new_block(vec![
+            coinbase(vec![p2pkh(5)]),
+            new_tx(
+                vec![
+                    TxOutpoint::new(block1.txs[2].hash, 0),
+                    TxOutpoint::new(block1.txs[1].hash, 0),
+                ],
+                vec![p2pkh(7)],
+            ),
+        ]);
+        cb.on_block(&block2, 2).unwrap();
+        cb.on_complete(2).unwrap();
+        assert_eq!(cb.address_count, 7);
+        assert_eq!(cb.merge_count, 2);
+        // The output without address is known when spent
+        assert_eq!(cb.missing_prevouts.inputs, 0);
+        assert_eq!(cb.exit_code(), 0);
+
+        let dump = fs::read_to_string(tmp_dir.path().join("clusters-0-2.csv")).unwrap();
+        let mut lines = dump.lines();
+        assert_eq!(lines.next(), Some("address;cluster_id"));
+        let clusters = lines
+            .map(|line| {
+                let (address, id) = line.split_once(';').unwrap();
+                (address.to_string(), id.parse::<u64>().unwrap())
+            })
+            .collect::<HashMap<String, u64>>();
+        assert_eq!(clusters.len(), 7);
+        // Clusters are identified by the id of their first seen address
+        assert_eq!(clusters[&address(0)], 0);
+        assert_eq!(clusters[&address(1)], 0);
+        assert_eq!(clusters[&address(2)], 2);
+        assert_eq!(clusters[&address(3)], 3);
+        let address_f = block1.txs[2].value.outputs[0]
+            .script
+            .address
+            .clone()
+            .unwrap();
+        assert_eq!(clusters[&address_f], 3);
+        let address_e = block1.txs[0].value.outputs[0]
+            .script
+            .address
+            .clone()
+            .unwrap();
+        assert_eq!(clusters[&address_e], 4);
+        let address_g = block2.txs[1].value.outputs[0]
+            .script
+            .address
+            .clone()
+            .unwrap();
+        assert_eq!(clusters[&address_g], 6);
+    }
+
+    #[test]
+    fn test_clusters_in_memory() {
+        test_clusters(None);
+    }
+
+    #[test]
+    fn test_clusters_spilled() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        test_clusters(Some(PathBuf::from(spill_dir.path())));
+        assert!(!spill_dir.path().join("parents").exists());
+    }
+}
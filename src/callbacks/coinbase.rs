@@ -0,0 +1,260 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use bitcoin::blockdata::script::{Instruction, Script};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::{self, HashOrder};
+use crate::errors::OpResult;
+
+/// Minimal length of a printable run to be considered part of the coinbase message
+const MIN_MESSAGE_RUN: usize = 4;
+
+/// Dumps the coinbase scriptSig of each block along with the BIP34 height and the embedded message
+pub struct Coinbase {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+
+    start_height: u64,
+    bip34_count: u64,
+    hash_order: HashOrder,
+}
+
+impl Coinbase {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Returns the csv row for the coinbase of the given block and whether a BIP34 height was found
+    fn as_csv(block: &Block, block_height: u64, hash_order: HashOrder) -> (String, bool) {
+        let script_sig = match block.txs.first() {
+            Some(tx) if tx.value.is_coinbase() => &tx.value.inputs[0].script_sig,
+            _ => {
+                return (
+                    format!(
+                        "{};{};;;\n",
+                        block_height,
+                        hash_order.fmt(&block.header.hash)
+                    ),
+                    false,
+                )
+            }
+        };
+        // Blocks with version 2 and above are required to start the scriptSig with their height
+        let bip34_height = match block.header.value.version >= 2 {
+            true => parse_bip34_height(script_sig),
+            false => None,
+        };
+        let row = format!(
+            "{};{};{};{};{}\n",
+            block_height,
+            hash_order.fmt(&block.header.hash),
+            bip34_height.map_or_else(String::new, |h| h.to_string()),
+            utils::arr_to_hex(script_sig),
+            extract_message(script_sig)
+        );
+        (row, bip34_height.is_some())
+    }
+}
+
+/// Decodes the height pushed at the beginning of a coinbase scriptSig (BIP34).
+/// The height is serialized as minimal script number, so heights up to 16 are pushed with OP_0..OP_16.
+fn parse_bip34_height(script_sig: &[u8]) -> Option<u64> {
+    let (&opcode, rest) = script_sig.split_first()?;
+    match opcode {
+        0x00 => Some(0),
+        0x51..=0x60 => Some((opcode - 0x50) as u64),
+        0x01..=0x08 => {
+            let data = rest.get(..opcode as usize)?;
+            // The sign bit is set for negative numbers, which are no valid height
+            if data[data.len() - 1] & 0x80 != 0 {
+                return None;
+            }
+            Some(
+                data.iter()
+                    .rev()
+                    .fold(0u64, |height, byte| (height << 8) | *byte as u64),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Extracts the printable ASCII portion of a coinbase scriptSig.
+/// If the scriptSig consists of pushes only (e.g. the genesis block) the pushed data is scanned,
+/// otherwise the raw bytes are, because pool tags are often appended without a push opcode.
+/// Runs of printable characters are joined with a space; ';' is treated as unprintable to keep the csv intact.
+fn extract_message(script_sig: &[u8]) -> String {
+    let pushes = Script::from_bytes(script_sig)
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(push)) => Some(push.as_bytes()),
+            _ => None,
+        })
+        .collect::<Option<Vec<&[u8]>>>();
+    let chunks = match pushes {
+        Some(pushes) => pushes,
+        None => vec![script_sig],
+    };
+
+    let mut runs: Vec<String> = Vec::new();
+    for chunk in chunks {
+        for run in chunk.split(|b| !(0x20..=0x7e).contains(b) || *b == b';') {
+            let run = String::from_utf8_lossy(run);
+            let run = run.trim();
+            if run.len() >= MIN_MESSAGE_RUN {
+                runs.push(String::from(run));
+            }
+        }
+    }
+    runs.join(" ")
+}
+
+impl Callback for Coinbase {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("coinbase")
+            .about("Dumps coinbase scriptSigs with BIP34 height and embedded message to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(command)
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = Coinbase {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Coinbase::create_writer(4000000, dump_folder.join("coinbase.csv.tmp"))?,
+            start_height: 0,
+            bip34_count: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing coinbase with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                "height", "hash", "bip34Height", "scriptSig", "message"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let (row, has_bip34_height) = Coinbase::as_csv(block, block_height, self.hash_order);
+        if has_bip34_height {
+            self.bip34_count += 1;
+        }
+        self.writer.write_all(row.as_bytes())?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        fs::rename(
+            self.dump_folder.as_path().join("coinbase.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "coinbase-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped coinbases from height {} to {}:\n\
+                                   \t-> with BIP34 height: {:9}",
+             self.start_height, block_height, self.bip34_count);
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testutil::{BlockBuilder, TxBuilder};
+
+    fn coinbase_block(version: u32, script_sig: Vec<u8>) -> Block {
+        let tx = TxBuilder::coinbase()
+            .script_sig(script_sig)
+            .output(5000000000)
+            .build();
+        BlockBuilder::new().version(version).tx(tx).build()
+    }
+
+    #[test]
+    fn test_parse_bip34_height() {
+        // Block 227931 (BIP34 activation on mainnet): 3 byte push 0x5b7a03
+        assert_eq!(
+            parse_bip34_height(&[0x03, 0x5b, 0x7a, 0x03, 0xff]),
+            Some(227931)
+        );
+        // Heights with the high bit set need an extra zero byte, e.g. 128
+        assert_eq!(parse_bip34_height(&[0x02, 0x80, 0x00]), Some(128));
+        assert_eq!(parse_bip34_height(&[0x01, 0x80]), None);
+        assert_eq!(parse_bip34_height(&[0x53, 0x00]), Some(3));
+        // Truncated push and non-push opcode
+        assert_eq!(parse_bip34_height(&[0x03, 0x5b, 0x7a]), None);
+        assert_eq!(parse_bip34_height(&[0x76]), None);
+        assert_eq!(parse_bip34_height(&[]), None);
+    }
+
+    #[test]
+    fn test_extract_message() {
+        // Genesis block coinbase
+        let genesis = utils::hex_to_vec(
+            "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72\
+             206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73",
+        );
+        assert_eq!(
+            extract_message(&genesis),
+            "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks"
+        );
+        // Height push followed by a raw pool tag and binary extranonce, as commonly used by pools
+        let mut script_sig = vec![0x03, 0x5b, 0x7a, 0x03];
+        script_sig.extend_from_slice(b"/slush/");
+        script_sig.extend_from_slice(&[0xfa, 0xbe, 0x6d, 0x6d, 0x41, 0x00, 0x0f]);
+        script_sig.extend_from_slice(b"Mined by pool;xyz");
+        assert_eq!(extract_message(&script_sig), "/slush/ Mined by pool");
+    }
+
+    #[test]
+    fn test_coinbase_as_csv() {
+        let script_sig = vec![0x03, 0x5b, 0x7a, 0x03, 0x2f, 0x42, 0x57, 0x2f];
+        let block = coinbase_block(2, script_sig.clone());
+        let (row, has_height) = Coinbase::as_csv(&block, 227931, HashOrder::Display);
+        assert!(has_height);
+        assert_eq!(
+            row,
+            format!(
+                "227931;{};227931;035b7a032f42572f;/BW/\n",
+                &block.header.hash
+            )
+        );
+
+        // Version 1 blocks did not commit to their height
+        let block = coinbase_block(1, script_sig);
+        let (row, has_height) = Coinbase::as_csv(&block, 227931, HashOrder::Display);
+        assert!(!has_height);
+        assert!(row.contains(";;035b7a03"));
+    }
+}
@@ -0,0 +1,311 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::common::{self, SpillMap, Spillable, Unit};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+const SATOSHIS_PER_COIN: f64 = 100_000_000.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Value and creation time of an unspent output
+struct CoinAge {
+    value: u64,
+    timestamp: u32,
+}
+
+impl ToRaw for CoinAge {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12);
+        bytes.extend_from_slice(&self.value.to_le_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
+}
+
+impl Spillable for CoinAge {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        CoinAge {
+            value: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            timestamp: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        }
+    }
+}
+
+/// Converts satoshi-seconds to coin-days
+fn to_coin_days(satoshi_secs: u128) -> f64 {
+    satoshi_secs as f64 / (SATOSHIS_PER_COIN * SECONDS_PER_DAY)
+}
+
+/// Dumps the coin-days destroyed by each block: the value of every spent output in coins
+/// multiplied by the days since the block which created it.
+///
+/// The timestamps of blocks are not strictly ascending, an output spent in a block with an
+/// earlier timestamp than its creation counts as held for zero days.
+pub struct CoinDays {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    unit: Unit,
+    // key: txid + index
+    unspents: SpillMap<CoinAge>,
+
+    start_height: u64,
+    // Sum of value * seconds held of all spent outputs, in satoshi-seconds
+    total_destroyed: u128,
+    // Inputs spending outputs which are not known
+    missing_inputs: u64,
+    hash_order: HashOrder,
+}
+
+impl CoinDays {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Computes the coin-days destroyed by the block and returns its csv row
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<String> {
+        let timestamp = block.header.value.timestamp;
+        let mut spent_value = 0u64;
+        let mut destroyed = 0u128;
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                for input in &tx.value.inputs {
+                    match self.unspents.remove(&input.outpoint.to_bytes())? {
+                        Some(spent) => {
+                            let secs = timestamp.saturating_sub(spent.timestamp);
+                            spent_value += spent.value;
+                            destroyed += spent.value as u128 * secs as u128;
+                        }
+                        None => {
+                            warn!(target: "callback", "Transaction {} at height {} spends unknown output {}:{}.",
+                                  &tx.hash, block_height, &input.outpoint.txid, input.outpoint.index);
+                            self.missing_inputs += 1;
+                        }
+                    }
+                }
+            }
+            common::insert_spendable_outputs(&mut self.unspents, tx, block_height, |output| {
+                CoinAge {
+                    value: output.out.value,
+                    timestamp,
+                }
+            })?;
+        }
+        self.total_destroyed += destroyed;
+        Ok(format!(
+            "{};{};{};{};{:.8}\n",
+            block_height,
+            self.hash_order.fmt(&block.header.hash),
+            timestamp,
+            self.unit.format(spent_value),
+            to_coin_days(destroyed)
+        ))
+    }
+}
+
+impl Callback for CoinDays {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("coindays")
+            .about("Dumps the coin-days destroyed per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(command)))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = CoinDays {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: CoinDays::create_writer(4000000, dump_folder.join("coindays.csv.tmp"))?,
+            unit: Unit::from_matches(matches),
+            unspents: SpillMap::from_matches(matches, "coindays-spill")?,
+            start_height: 0,
+            total_destroyed: 0,
+            missing_inputs: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        if block_height != 0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(
+                "coindays needs to start at the genesis block to know the age of all spent outputs.",
+            ));
+        }
+        self.start_height = block_height;
+        info!(target: "callback", "Executing coindays with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                "height", "hash", "timestamp", "spent_value", "coindays_destroyed"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let row = self.apply_block(block, block_height)?;
+        self.writer.write_all(row.as_bytes())?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        self.unspents.destroy()?;
+        fs::rename(
+            self.dump_folder.as_path().join("coindays.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "coindays-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped coin-days destroyed from height {} to {}:\n\
+                                   \t-> destroyed:    {:.2}",
+             self.start_height, block_height, to_coin_days(self.total_destroyed));
+        if self.missing_inputs > 0 {
+            warn!(target: "callback", "{} inputs spent unknown outputs, the dump is incomplete.", self.missing_inputs);
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self.missing_inputs {
+            0 => 0,
+            _ => EXIT_INCONSISTENT,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{RawTx, TxOutpoint};
+    use crate::common::testutil::{coinbase, new_tx, BlockBuilder};
+
+    const COIN: u64 = 100_000_000;
+    const DAY: u32 = 86_400;
+
+    fn new_block(timestamp: u32, txs: Vec<RawTx>) -> Block {
+        BlockBuilder::new().timestamp(timestamp).txs(txs).build()
+    }
+
+    #[test]
+    fn test_coin_age_roundtrip() {
+        let age = CoinAge {
+            value: 5 * COIN,
+            timestamp: 1231006505,
+        };
+        let decoded = CoinAge::from_bytes(&age.to_bytes());
+        assert_eq!(decoded.value, age.value);
+        assert_eq!(decoded.timestamp, age.timestamp);
+    }
+
+    #[test]
+    fn test_coindays() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = CoinDays {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: CoinDays::create_writer(100, tmp_dir.path().join("coindays.csv.tmp")).unwrap(),
+            unit: Unit::Sat,
+            unspents: SpillMap::new(None, 0).unwrap(),
+            start_height: 0,
+            total_destroyed: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        };
+        assert!(cb.on_start(1).is_err());
+        cb.on_start(0).unwrap();
+
+        let start = 1_000_000;
+        cb.apply_block(&new_block(start, vec![coinbase(&[COIN])]), 0)
+            .unwrap();
+        let block1 = new_block(start, vec![coinbase(&[2 * COIN, 3 * COIN])]);
+        let coinbase_txid = block1.txs[0].hash;
+        assert_eq!(
+            cb.apply_block(&block1, 1).unwrap(),
+            format!("1;{};{};0;0.00000000\n", &block1.header.hash, start)
+        );
+
+        // 2 coins held for 10 days and, in a block with an earlier timestamp, 3 coins for 0 days
+        let block2 = new_block(
+            start + 10 * DAY,
+            vec![
+                coinbase(&[COIN + 1]),
+                new_tx(vec![TxOutpoint::new(coinbase_txid, 0)], &[2 * COIN]),
+            ],
+        );
+        assert_eq!(
+            cb.apply_block(&block2, 2).unwrap(),
+            format!(
+                "2;{};{};{};20.00000000\n",
+                &block2.header.hash,
+                start + 10 * DAY,
+                2 * COIN
+            )
+        );
+        let block3 = new_block(
+            start - 1,
+            vec![
+                coinbase(&[COIN + 2]),
+                new_tx(vec![TxOutpoint::new(coinbase_txid, 1)], &[3 * COIN]),
+            ],
+        );
+        assert!(cb
+            .apply_block(&block3, 3)
+            .unwrap()
+            .ends_with(&format!(";{};0.00000000\n", 3 * COIN)));
+        assert_eq!(cb.exit_code(), 0);
+
+        // The genesis output can't be spent
+        let genesis_txid = new_block(start, vec![coinbase(&[COIN])]).txs[0].hash;
+        let block4 = new_block(
+            start + DAY,
+            vec![
+                coinbase(&[COIN + 3]),
+                new_tx(vec![TxOutpoint::new(genesis_txid, 0)], &[COIN]),
+            ],
+        );
+        cb.on_block(&block4, 4).unwrap();
+        assert_eq!(cb.exit_code(), EXIT_INCONSISTENT);
+
+        cb.on_complete(4).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("coindays-0-4.csv")).unwrap();
+        assert_eq!(
+            dump.lines().collect::<Vec<&str>>(),
+            vec![
+                "height;hash;timestamp;spent_value;coindays_destroyed",
+                &format!("4;{};{};0;0.00000000", &block4.header.hash, start + DAY),
+            ]
+        );
+        assert_eq!(to_coin_days(cb.total_destroyed), 20.0);
+    }
+}
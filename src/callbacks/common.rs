@@ -1,9 +1,128 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
 
+use byteorder::{ByteOrder, LittleEndian};
+use clap::{Arg, ArgMatches, Command};
+use rusty_leveldb::{LdbIterator, Options, WriteBatch, DB};
+
+use bitcoin::hashes::sha256d;
+
+use crate::blockchain::proto::script::ScriptPattern;
 use crate::blockchain::proto::tx::EvaluatedTx;
-use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutpoint};
 use crate::blockchain::proto::Hashed;
 use crate::blockchain::proto::ToRaw;
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Dump folder argument to stream the output to stdout instead
+pub const STDOUT: &str = "-";
+
+/// Returns a buffered writer on stdout
+pub fn stdout_writer(cap: usize) -> BufWriter<Box<dyn Write>> {
+    BufWriter::with_capacity(cap, Box::new(io::stdout()))
+}
+
+const SATOSHIS_PER_COIN: u64 = 100_000_000;
+
+/// Denomination of the values written by callbacks, see `--unit`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Unit {
+    #[default]
+    Sat,
+    /// Decimal coins with 8 places, converted without floating point
+    Btc,
+}
+
+impl Unit {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.get_one::<String>("unit").map(String::as_str) {
+            Some("btc") => Unit::Btc,
+            _ => Unit::Sat,
+        }
+    }
+
+    /// Parses a value written in either unit, decimal coins are recognized by their point
+    pub fn parse(s: &str) -> Option<u64> {
+        match s.split_once('.') {
+            Some((coins, sats)) if sats.len() == 8 => coins
+                .parse::<u64>()
+                .ok()?
+                .checked_mul(SATOSHIS_PER_COIN)?
+                .checked_add(sats.parse::<u64>().ok()?),
+            Some(_) => None,
+            None => s.parse::<u64>().ok(),
+        }
+    }
+
+    /// Formats a value given in satoshis
+    pub fn format(&self, sats: u64) -> String {
+        match self {
+            Unit::Sat => sats.to_string(),
+            Unit::Btc => format!(
+                "{}.{:08}",
+                sats / SATOSHIS_PER_COIN,
+                sats % SATOSHIS_PER_COIN
+            ),
+        }
+    }
+}
+
+/// Adds the `--unit` argument to the given subcommand
+pub fn add_unit_arg(command: Command) -> Command {
+    command.arg(
+        Arg::new("unit")
+            .long("unit")
+            .value_name("UNIT")
+            .value_parser(["sat", "btc"])
+            .default_value("sat")
+            .help("Denomination of values, btc writes decimal coins with 8 places"),
+    )
+}
+
+/// Adds the `--overwrite` argument to the given subcommand
+pub fn add_overwrite_arg(command: Command) -> Command {
+    command.arg(
+        Arg::new("overwrite")
+            .long("overwrite")
+            .action(clap::ArgAction::SetTrue)
+            .help("Writes into the dump folder even if it is not empty"),
+    )
+}
+
+/// Returns true if the folder doesn't exist or contains no entries
+pub fn is_empty_folder(folder: &Path) -> OpResult<bool> {
+    match fs::read_dir(folder) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(OpError::from(err)),
+    }
+}
+
+/// Creates the dump folder if it is missing.
+/// A folder with existing files is only used with `overwrite`, files of the same name are replaced.
+pub fn create_dump_folder(folder: &Path, overwrite: bool) -> OpResult<()> {
+    if !overwrite && !is_empty_folder(folder)? {
+        return Err(
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                "Dump folder {} is not empty, use --overwrite to write into it.",
+                folder.display()
+            )),
+        );
+    }
+    fs::create_dir_all(folder)?;
+    Ok(())
+}
+
+/// Returns the `dump-folder` argument after creating it, see `create_dump_folder`
+pub fn dump_folder_from_matches(matches: &ArgMatches) -> OpResult<PathBuf> {
+    let folder = PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+    create_dump_folder(&folder, matches.get_flag("overwrite"))?;
+    Ok(folder)
+}
 
 pub struct UnspentValue {
     pub block_height: u64,
@@ -11,6 +130,30 @@ pub struct UnspentValue {
     pub address: String,
 }
 
+impl Spillable for UnspentValue {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        UnspentValue {
+            block_height: LittleEndian::read_u64(&bytes[0..8]),
+            value: LittleEndian::read_u64(&bytes[8..16]),
+            address: String::from_utf8_lossy(&bytes[16..]).into_owned(),
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        self.address.capacity()
+    }
+}
+
+impl ToRaw for UnspentValue {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + self.address.len());
+        bytes.extend_from_slice(&self.block_height.to_le_bytes());
+        bytes.extend_from_slice(&self.value.to_le_bytes());
+        bytes.extend_from_slice(self.address.as_bytes());
+        bytes
+    }
+}
+
 /// Iterates over transaction inputs and removes spent outputs from HashMap.
 /// Returns the total number of processed inputs.
 pub fn remove_unspents(
@@ -24,6 +167,12 @@ pub fn remove_unspents(
     tx.value.in_count.value
 }
 
+/// Returns true for the outputs of the genesis block. Bitcoin Core (and the chains derived
+/// from it) never added them to the UTXO set, so they can't be spent.
+pub fn is_unspendable_genesis_output(block_height: u64) -> bool {
+    block_height == 0
+}
+
 /// Iterates over transaction outputs and adds valid unspents to HashMap.
 /// Returns the total number of valid outputs.
 pub fn insert_unspents(
@@ -31,6 +180,9 @@ pub fn insert_unspents(
     block_height: u64,
     unspents: &mut HashMap<Vec<u8>, UnspentValue>,
 ) -> u64 {
+    if is_unspendable_genesis_output(block_height) {
+        return 0;
+    }
     let mut count = 0;
     for (i, output) in tx.value.outputs.iter().enumerate() {
         match &output.script.address {
@@ -42,7 +194,9 @@ pub fn insert_unspents(
                 };
 
                 let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
-                unspents.insert(key, unspent);
+                if unspents.insert(key, unspent).is_some() {
+                    warn_duplicate_output(&tx.hash, i as u32, block_height);
+                }
                 count += 1;
             }
             None => {
@@ -57,6 +211,442 @@ pub fn insert_unspents(
     count
 }
 
+/// Logs an output which replaces an unspent output with the same outpoint.
+/// Before BIP30 two coinbase transactions were duplicated (at heights 91842 and 91880),
+/// following consensus the later output replaces the earlier one, which became unspendable.
+pub fn warn_duplicate_output(txid: &sha256d::Hash, index: u32, block_height: u64) {
+    warn!(target: "callback", "Duplicate output {}:{} at height {}, replacing the earlier one (BIP30)",
+          txid, index, block_height);
+}
+
+/// Counts inputs spending outputs which are missing from the outpoint index of a callback,
+/// e.g. because they were created before the start height of a ranged parse.
+#[derive(Default)]
+pub struct MissingPrevouts {
+    pub inputs: u64,
+    // Transactions with at least one missing prevout
+    pub txs: u64,
+}
+
+impl MissingPrevouts {
+    /// Records the number of missing prevouts of a transaction
+    pub fn record(&mut self, inputs: u64) {
+        if inputs > 0 {
+            self.inputs += inputs;
+            self.txs += 1;
+        }
+    }
+
+    /// True if prevouts are missing although all outputs should be known, i.e. parsing started at
+    /// the genesis block or at the height of the loaded snapshot
+    pub fn is_inconsistent(&self, start_height: u64, snapshot: bool) -> bool {
+        self.inputs > 0 && (start_height == 0 || snapshot)
+    }
+
+    /// Logs how many prevouts were missing and what it means for the output of the callback
+    pub fn log_summary(&self, start_height: u64, snapshot: bool, consequence: &str) {
+        if self.inputs == 0 {
+            return;
+        }
+        if self.is_inconsistent(start_height, snapshot) {
+            warn!(target: "callback", "{} inputs of {} transactions spent unknown outputs, {}.",
+                  self.inputs, self.txs, consequence);
+        } else {
+            warn!(target: "callback", "{} inputs of {} transactions spent outputs created before height {}, {}. \
+                                       Use --utxo-snapshot to load them, if supported.",
+                  self.inputs, self.txs, start_height, consequence);
+        }
+    }
+}
+
+/// Adds the spendable outputs of the transaction to `unspents`, with the value built by `f`.
+/// Provably unspendable outputs and the genesis output are skipped, duplicated coinbase outputs
+/// replace the earlier ones (BIP30). Returns the number of added outputs.
+pub fn insert_spendable_outputs<V, F>(
+    unspents: &mut SpillMap<V>,
+    tx: &Hashed<EvaluatedTx>,
+    block_height: u64,
+    mut f: F,
+) -> OpResult<u64>
+where
+    V: Spillable,
+    F: FnMut(&EvaluatedTxOut) -> V,
+{
+    if is_unspendable_genesis_output(block_height) {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for (i, output) in tx.value.outputs.iter().enumerate() {
+        match output.script.pattern {
+            ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable => continue,
+            _ => {}
+        }
+        let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
+        // Only coinbase transactions were ever duplicated
+        if tx.value.is_coinbase() {
+            if unspents.replace(key, f(output))?.is_some() {
+                warn_duplicate_output(&tx.hash, i as u32, block_height);
+            }
+        } else {
+            unspents.insert(key, f(output))?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Values which can be moved to disk by a `SpillMap`
+pub trait Spillable: ToRaw + Sized {
+    fn from_bytes(bytes: &[u8]) -> Self;
+
+    /// Bytes allocated on the heap by the value, counted against `--max-mem`
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl ToRaw for u64 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+}
+
+impl Spillable for u64 {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        u64::from_le_bytes(bytes.try_into().expect("spill: malformed u64"))
+    }
+}
+
+// Key vector and control byte of a hash table slot, the key bytes are counted separately
+const ENTRY_OVERHEAD: usize = mem::size_of::<Vec<u8>>() + 1;
+// Key copy and tick in the insertion queue of a `SpillMap`
+const QUEUE_OVERHEAD: usize = mem::size_of::<(u64, Vec<u8>)>();
+// Stale keys left in the insertion queue by removals before it is compacted
+const MIN_STALE_KEYS: usize = 1024;
+
+/// Adds the arguments to configure a `SpillMap` to the given subcommand
+pub fn add_spill_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("spill-dir")
+                .long("spill-dir")
+                .value_name("DIR")
+                .help("Moves entries to an on-disk store in DIR once the threshold is exceeded"),
+        )
+        .arg(
+            Arg::new("spill-threshold")
+                .long("spill-threshold")
+                .value_name("COUNT")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10000000")
+                .help("Max number of entries held in memory before spilling to disk"),
+        )
+        .arg(
+            Arg::new("max-mem")
+                .long("max-mem")
+                .value_name("SIZE")
+                .requires("spill-dir")
+                .value_parser(parse_mem_size)
+                .help("Max memory held by entries before spilling to disk (e.g. 512M, 4G)"),
+        )
+}
+
+/// Adds the `--utxo-snapshot` argument to the given subcommand
+pub fn add_utxo_snapshot_arg(command: Command) -> Command {
+    command.arg(
+        Arg::new("utxo-snapshot")
+            .long("utxo-snapshot")
+            .value_name("FILE")
+            .help("Loads the unspent outputs at the start height from a utxodump csv file"),
+    )
+}
+
+/// Returns the global `--hash-order` argument, the display order if the subcommand was built
+/// without the main command
+pub fn hash_order_from_matches(matches: &ArgMatches) -> HashOrder {
+    match matches.try_get_one::<String>("hash-order") {
+        Ok(Some(order)) if order == "internal" => HashOrder::Internal,
+        _ => HashOrder::Display,
+    }
+}
+
+/// Returns the `--utxo-snapshot` argument
+pub fn utxo_snapshot_from_matches(matches: &ArgMatches) -> Option<PathBuf> {
+    matches
+        .get_one::<String>("utxo-snapshot")
+        .map(PathBuf::from)
+}
+
+/// Loads the values of the unspent outputs written by utxodump into `output_values`, keyed by
+/// txid + index. A file named `utxo-<start>-<end>.csv` has to end right before `start_height`.
+/// Returns the number of loaded outputs.
+pub fn load_utxo_snapshot(
+    path: &Path,
+    start_height: u64,
+    output_values: &mut SpillMap<u64>,
+    hash_order: HashOrder,
+) -> OpResult<u64> {
+    let end = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("utxo-")?.strip_suffix(".csv"))
+        .and_then(|range| range.split_once('-')?.1.parse::<u64>().ok());
+    if let Some(end) = end {
+        if end + 1 != start_height {
+            return Err(
+                OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                    "UTXO snapshot {} ends at height {}, but parsing starts at height {}.",
+                    path.display(),
+                    end,
+                    start_height
+                )),
+            );
+        }
+    }
+    let malformed = |line_no: usize| {
+        OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+            "Malformed line {} in UTXO snapshot {}.",
+            line_no,
+            path.display()
+        ))
+    };
+    let file = File::open(path)
+        .map_err(|e| OpError::from(e).join_msg(&format!("Unable to read {}:", path.display())))?;
+    let mut count = 0;
+    for (i, line) in BufReader::with_capacity(4000000, file).lines().enumerate() {
+        let line = line?;
+        // txid;indexOut;height;value;scriptPubKey
+        let mut fields = line.split(';');
+        let (txid, index, value) = match (fields.next(), fields.next(), fields.nth(1)) {
+            (Some("txid"), _, _) if i == 0 => continue,
+            (Some(txid), Some(index), Some(value)) => (txid, index, value),
+            _ => return Err(malformed(i + 1)),
+        };
+        // Written by utxodump in the same --hash-order
+        let txid = hash_order.parse(txid).ok_or_else(|| malformed(i + 1))?;
+        let index = index.parse::<u32>().map_err(|_| malformed(i + 1))?;
+        let value = Unit::parse(value).ok_or_else(|| malformed(i + 1))?;
+        output_values.insert(TxOutpoint::new(txid, index).to_bytes(), value)?;
+        count += 1;
+    }
+    info!(target: "callback", "Loaded {} unspent outputs from {}", count, path.display());
+    Ok(count)
+}
+
+/// Parses a byte size with an optional K, M or G suffix (powers of 1024)
+pub fn parse_mem_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size: {}", s))
+}
+
+/// Entry of a `SpillMap` with the tick it was inserted at
+struct SpillEntry<V> {
+    value: V,
+    touched: u64,
+}
+
+/// HashMap which moves its entries to an on-disk LevelDB once the configured
+/// threshold or memory budget is exceeded (if a spill directory is given).
+/// The least recently inserted or replaced entries are spilled first,
+/// `remove()` and `replace()` fault spilled entries back from disk.
+pub struct SpillMap<V: Spillable> {
+    entries: HashMap<Vec<u8>, SpillEntry<V>>,
+    spill: Option<DB>,
+    spill_path: Option<PathBuf>,
+    spill_threshold: usize,
+    max_mem: Option<usize>,
+    // Estimated memory held by the in-memory entries
+    mem_usage: usize,
+    tick: u64,
+    // Keys with the tick they were inserted at, oldest first, only kept with a spill store.
+    // Keys removed or inserted again since are stale and skipped.
+    order: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl<V: Spillable> SpillMap<V> {
+    pub fn new(spill_path: Option<PathBuf>, spill_threshold: usize) -> OpResult<Self> {
+        let spill = match spill_path {
+            Some(ref path) => {
+                // Leftovers from a previous run would corrupt the results
+                if path.exists() {
+                    return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                        .join_msg(&format!("Spill store {} already exists.", path.display())));
+                }
+                Some(DB::open(path, Options::default())?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            entries: HashMap::with_capacity(spill_threshold.min(10000000)),
+            spill,
+            spill_path,
+            spill_threshold,
+            max_mem: None,
+            mem_usage: 0,
+            tick: 0,
+            order: VecDeque::new(),
+        })
+    }
+
+    /// Creates a new map from the arguments added by `add_spill_args()`.
+    /// `name` is used as directory name for the on-disk store.
+    pub fn from_matches(matches: &ArgMatches, name: &str) -> OpResult<Self> {
+        let spill_path = matches
+            .get_one::<String>("spill-dir")
+            .map(|p| PathBuf::from(p).join(name));
+        let spill_threshold = *matches.get_one::<usize>("spill-threshold").unwrap();
+        let mut map = Self::new(spill_path, spill_threshold)?;
+        map.max_mem = matches.get_one::<usize>("max-mem").copied();
+        Ok(map)
+    }
+
+    /// Approximate memory held by an entry, including the hash table slot and the queued key
+    fn entry_size(key_len: usize, value: &V) -> usize {
+        2 * key_len
+            + value.heap_size()
+            + ENTRY_OVERHEAD
+            + QUEUE_OVERHEAD
+            + mem::size_of::<SpillEntry<V>>()
+    }
+
+    fn is_live(&self, touched: u64, key: &[u8]) -> bool {
+        self.entries
+            .get(key)
+            .is_some_and(|entry| entry.touched == touched)
+    }
+
+    fn exceeds_limits(&self) -> bool {
+        self.entries.len() > self.spill_threshold
+            || self.max_mem.is_some_and(|max_mem| self.mem_usage > max_mem)
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: V) -> OpResult<()> {
+        self.tick += 1;
+        let key_len = key.len();
+        self.mem_usage += Self::entry_size(key_len, &value);
+        if self.spill.is_some() {
+            self.order.push_back((self.tick, key.clone()));
+        }
+        let entry = SpillEntry {
+            value,
+            touched: self.tick,
+        };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.mem_usage -= Self::entry_size(key_len, &old.value);
+        }
+        if self.spill.is_some() && self.exceeds_limits() {
+            self.spill()?;
+        }
+        // Drops the stale keys once they outnumber the entries, which keeps the queue linear
+        // in the number of entries at amortized constant cost per insert
+        if self.order.len() > 2 * self.entries.len() + MIN_STALE_KEYS {
+            let mut order = mem::take(&mut self.order);
+            order.retain(|(touched, key)| self.is_live(*touched, key));
+            self.order = order;
+        }
+        Ok(())
+    }
+
+    /// Inserts the entry and returns the value it replaced, either from memory or disk.
+    /// This requires a lookup in the on-disk store, so `insert()` is preferred if the key is known to be new.
+    pub fn replace(&mut self, key: Vec<u8>, value: V) -> OpResult<Option<V>> {
+        let replaced = self.remove(&key)?;
+        self.insert(key, value)?;
+        Ok(replaced)
+    }
+
+    /// Removes the entry from memory or disk and returns it
+    pub fn remove(&mut self, key: &[u8]) -> OpResult<Option<V>> {
+        if let Some(entry) = self.entries.remove(key) {
+            self.mem_usage -= Self::entry_size(key.len(), &entry.value);
+            return Ok(Some(entry.value));
+        }
+        match self.spill.as_mut() {
+            Some(db) => match db.get(key) {
+                Some(value) => {
+                    db.delete(key)?;
+                    Ok(Some(V::from_bytes(&value)))
+                }
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Moves the least recently inserted entries from the front of the queue to the on-disk store
+    /// until a quarter of the budget is free again, so spills don't happen on every insert.
+    fn spill(&mut self) -> OpResult<()> {
+        if self.spill.is_none() {
+            return Ok(());
+        }
+        let max_len = self.spill_threshold / 4 * 3;
+        let max_mem = self.max_mem.map(|max_mem| max_mem / 4 * 3);
+
+        let mut batch = WriteBatch::new();
+        let mut count = 0;
+        loop {
+            let within_mem = max_mem.is_none_or(|max_mem| self.mem_usage <= max_mem);
+            if self.entries.len() <= max_len && within_mem {
+                break;
+            }
+            let (touched, key) = match self.order.pop_front() {
+                Some(queued) => queued,
+                None => break,
+            };
+            if !self.is_live(touched, &key) {
+                continue;
+            }
+            let entry = self.entries.remove(&key).unwrap();
+            self.mem_usage -= Self::entry_size(key.len(), &entry.value);
+            batch.put(&key, &entry.value.to_bytes());
+            count += 1;
+        }
+        debug!(target: "callback", "Spilling {} entries to disk, {} remain in memory ...",
+               count, self.entries.len());
+        transform!(self.spill.as_mut()).write(batch, false)?;
+        Ok(())
+    }
+
+    /// Calls `f` for each entry, including the spilled ones
+    pub fn for_each<F>(&mut self, mut f: F) -> OpResult<()>
+    where
+        F: FnMut(&[u8], &V) -> OpResult<()>,
+    {
+        for (key, entry) in self.entries.iter() {
+            f(key, &entry.value)?;
+        }
+        if let Some(db) = self.spill.as_mut() {
+            db.flush()?;
+            let mut iter = db.new_iter()?;
+            let (mut key, mut value) = (vec![], vec![]);
+            while iter.advance() {
+                iter.current(&mut key, &mut value);
+                f(&key, &V::from_bytes(&value))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the on-disk store if present
+    pub fn destroy(&mut self) -> OpResult<()> {
+        self.spill = None;
+        if let Some(path) = self.spill_path.take() {
+            fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,8 +698,8 @@ mod tests {
         let block1 = Block::new(0, header.clone(), None, VarUint::from(1u8), txs);
 
         for tx in &block1.txs {
-            remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 100000, &mut unspents);
+            remove_unspents(tx, &mut unspents);
+            insert_unspents(tx, 100000, &mut unspents);
         }
         let value = unspents
             .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
@@ -246,14 +836,12 @@ mod tests {
         let block2 = Block::new(0, header.clone(), None, VarUint::from(1u8), txs);
 
         for tx in &block2.txs {
-            remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 105001, &mut unspents);
+            remove_unspents(tx, &mut unspents);
+            insert_unspents(tx, 105001, &mut unspents);
         }
 
         // Original unspent should no longer exist in the hashmap
-        assert!(unspents
-            .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
-            .is_none());
+        assert!(!unspents.contains_key(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes()));
 
         let value = unspents
             .get(&TxOutpoint::new(block2.txs[0].hash, 0).to_bytes())
@@ -263,4 +851,230 @@ mod tests {
         assert_eq!(value.value, 9070000000);
         assert_eq!(value.address, "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL");
     }
+
+    #[test]
+    fn test_spill_map() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let spill_path = tmp_dir.path().join("spill");
+        let mut map: SpillMap<u64> = SpillMap::new(Some(spill_path.clone()), 2).unwrap();
+        for i in 0..5u64 {
+            map.insert(i.to_le_bytes().to_vec(), i * 10).unwrap();
+        }
+        // Entries are found regardless whether they have been spilled or not
+        assert_eq!(map.remove(&1u64.to_le_bytes()).unwrap(), Some(10));
+        assert_eq!(map.remove(&4u64.to_le_bytes()).unwrap(), Some(40));
+        assert_eq!(map.remove(&4u64.to_le_bytes()).unwrap(), None);
+        // Replacing a spilled entry doesn't leave the old value behind
+        assert_eq!(
+            map.replace(0u64.to_le_bytes().to_vec(), 5).unwrap(),
+            Some(0)
+        );
+        assert_eq!(map.replace(9u64.to_le_bytes().to_vec(), 90).unwrap(), None);
+        assert_eq!(map.remove(&9u64.to_le_bytes()).unwrap(), Some(90));
+
+        let mut values = Vec::new();
+        map.for_each(|_, value| {
+            values.push(*value);
+            Ok(())
+        })
+        .unwrap();
+        values.sort();
+        assert_eq!(values, vec![5, 20, 30]);
+
+        map.destroy().unwrap();
+        assert!(!spill_path.exists());
+    }
+
+    #[test]
+    fn test_spill_map_queue() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut map: SpillMap<u64> =
+            SpillMap::new(Some(tmp_dir.path().join("spill")), 100).unwrap();
+        // Removed keys don't pile up in the queue
+        for i in 0..10000u64 {
+            map.insert(i.to_le_bytes().to_vec(), i).unwrap();
+            assert_eq!(map.remove(&i.to_le_bytes()).unwrap(), Some(i));
+        }
+        assert!(map.order.len() <= MIN_STALE_KEYS + 1);
+
+        // Stale keys are skipped, only the oldest live entries are spilled
+        for i in 0..101u64 {
+            map.insert(i.to_le_bytes().to_vec(), i).unwrap();
+            if i < 50 {
+                map.remove(&i.to_le_bytes()).unwrap();
+            }
+        }
+        assert_eq!(map.entries.len(), 51);
+        for i in 101..151u64 {
+            map.insert(i.to_le_bytes().to_vec(), i).unwrap();
+        }
+        let mut in_memory = map
+            .entries
+            .keys()
+            .map(|key| LittleEndian::read_u64(key))
+            .collect::<Vec<u64>>();
+        in_memory.sort();
+        assert_eq!(in_memory, (76..151).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_spill_map_max_mem() {
+        assert_eq!(parse_mem_size("1024"), Ok(1024));
+        assert_eq!(parse_mem_size("4k"), Ok(4096));
+        assert_eq!(parse_mem_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_mem_size("2G"), Ok(2 << 30));
+        assert!(parse_mem_size("G").is_err());
+        assert!(parse_mem_size("1T").is_err());
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut map: SpillMap<u64> =
+            SpillMap::new(Some(tmp_dir.path().join("spill")), 1000).unwrap();
+        // Budget for four entries, so spilling leaves three in memory
+        let entry_size = SpillMap::<u64>::entry_size(8, &0);
+        map.max_mem = Some(4 * entry_size);
+        for i in 0..4u64 {
+            map.insert(i.to_le_bytes().to_vec(), i).unwrap();
+        }
+        assert_eq!(map.entries.len(), 4);
+        assert_eq!(map.mem_usage, 4 * entry_size);
+
+        // Touching 0 makes 1 and 2 the least recently touched entries
+        assert_eq!(
+            map.replace(0u64.to_le_bytes().to_vec(), 100).unwrap(),
+            Some(0)
+        );
+        map.insert(4u64.to_le_bytes().to_vec(), 4).unwrap();
+        let mut in_memory = map
+            .entries
+            .keys()
+            .map(|key| LittleEndian::read_u64(key))
+            .collect::<Vec<u64>>();
+        in_memory.sort();
+        assert_eq!(in_memory, vec![0, 3, 4]);
+        assert_eq!(map.mem_usage, 3 * entry_size);
+
+        // Spilled entries are faulted back on access
+        assert_eq!(
+            map.replace(1u64.to_le_bytes().to_vec(), 10).unwrap(),
+            Some(1)
+        );
+        assert!(map.entries.contains_key(1u64.to_le_bytes().as_slice()));
+        assert_eq!(map.remove(&2u64.to_le_bytes()).unwrap(), Some(2));
+        assert_eq!(map.remove(&2u64.to_le_bytes()).unwrap(), None);
+
+        let mut values = Vec::new();
+        map.for_each(|_, value| {
+            values.push(*value);
+            Ok(())
+        })
+        .unwrap();
+        values.sort();
+        assert_eq!(values, vec![3, 4, 10, 100]);
+        map.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_unit() {
+        assert_eq!(Unit::Sat.format(5000000000), "5000000000");
+        assert_eq!(Unit::Btc.format(0), "0.00000000");
+        assert_eq!(Unit::Btc.format(1), "0.00000001");
+        assert_eq!(Unit::Btc.format(5000000000), "50.00000000");
+        assert_eq!(Unit::Btc.format(2099999997690000), "20999999.97690000");
+        // Exact beyond the precision of f64
+        assert_eq!(Unit::Btc.format(u64::MAX), "184467440737.09551615");
+        assert_eq!(Unit::parse("5000000000"), Some(5000000000));
+        assert_eq!(Unit::parse("50.00000000"), Some(5000000000));
+        assert_eq!(Unit::parse("0.00000001"), Some(1));
+        assert_eq!(Unit::parse("0.5"), None);
+        assert_eq!(Unit::parse("184467440737.09551616"), None);
+
+        let command = add_unit_arg(Command::new("test"));
+        let matches = command.clone().get_matches_from(["test"]);
+        assert_eq!(Unit::from_matches(&matches), Unit::Sat);
+        let matches = command.clone().get_matches_from(["test", "--unit", "btc"]);
+        assert_eq!(Unit::from_matches(&matches), Unit::Btc);
+        assert!(command
+            .try_get_matches_from(["test", "--unit", "mbtc"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_create_dump_folder() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let folder = tmp_dir.path().join("dump").join("nested");
+        create_dump_folder(&folder, false).unwrap();
+        assert!(folder.is_dir());
+        // Empty folders are used as they are
+        create_dump_folder(&folder, false).unwrap();
+
+        fs::write(folder.join("blocks.csv"), "").unwrap();
+        let err = create_dump_folder(&folder, false).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        create_dump_folder(&folder, true).unwrap();
+
+        let command = add_overwrite_arg(Command::new("test").arg(Arg::new("dump-folder")));
+        let path = folder.to_str().unwrap();
+        let matches = command.clone().get_matches_from(["test", path]);
+        assert!(dump_folder_from_matches(&matches).is_err());
+        let matches = command.get_matches_from(["test", path, "--overwrite"]);
+        assert_eq!(dump_folder_from_matches(&matches).unwrap(), folder);
+    }
+
+    #[test]
+    fn test_load_utxo_snapshot() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let txid = sha256d::Hash::hash(b"tx");
+        let path = tmp_dir.path().join("utxo-0-9.csv");
+        fs::write(
+            &path,
+            format!(
+                "txid;indexOut;height;value;scriptPubKey\n\
+                 {txid};0;3;5000000000;51\n{txid};2;3;0.00001000;51\n"
+            ),
+        )
+        .unwrap();
+        let mut output_values = SpillMap::new(None, 0).unwrap();
+        assert_eq!(
+            load_utxo_snapshot(&path, 10, &mut output_values, HashOrder::Display).unwrap(),
+            2
+        );
+        let key = |i| TxOutpoint::new(txid, i).to_bytes();
+        assert_eq!(output_values.remove(&key(0)).unwrap(), Some(5000000000));
+        assert_eq!(output_values.remove(&key(1)).unwrap(), None);
+        assert_eq!(output_values.remove(&key(2)).unwrap(), Some(1000));
+
+        // Snapshots written with --hash-order internal
+        let internal = HashOrder::Internal.fmt(&txid).to_string();
+        fs::write(
+            &path,
+            format!("txid;indexOut;height;value;scriptPubKey\n{internal};0;3;5000000000;51\n"),
+        )
+        .unwrap();
+        load_utxo_snapshot(&path, 10, &mut output_values, HashOrder::Internal).unwrap();
+        assert_eq!(output_values.remove(&key(0)).unwrap(), Some(5000000000));
+
+        // The snapshot has to end right before the start height
+        let err = load_utxo_snapshot(&path, 9, &mut output_values, HashOrder::Display)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+
+        let path = tmp_dir.path().join("snapshot.csv");
+        fs::write(&path, format!("{txid};0;3\n")).unwrap();
+        assert!(load_utxo_snapshot(&path, 10, &mut output_values, HashOrder::Display).is_err());
+    }
+
+    #[test]
+    fn test_missing_prevouts() {
+        let mut missing = MissingPrevouts::default();
+        missing.record(0);
+        assert!(!missing.is_inconsistent(0, false));
+        missing.record(2);
+        missing.record(1);
+        assert_eq!((missing.inputs, missing.txs), (3, 2));
+        assert!(missing.is_inconsistent(0, false));
+        // Expected when starting within the chain, unless the snapshot should have covered them
+        assert!(!missing.is_inconsistent(10, false));
+        assert!(missing.is_inconsistent(10, true));
+    }
 }
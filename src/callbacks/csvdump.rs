@@ -1,34 +1,529 @@
 use std::fs::{self, File};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
-use crate::blockchain::proto::Hashed;
-use crate::callbacks::Callback;
-use crate::common::utils;
-use crate::errors::OpResult;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput, TxOutpoint};
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::callbacks::common::{self, MissingPrevouts, SpillMap, Unit};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::common::utils::{self, HashOrder};
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
-/// Dumps the whole blockchain into csv files
+/// Output format of the csv files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Dialect {
+    /// `;` separated values, absent values are left empty (see sql/schema.sql for MySQL)
+    Default,
+    /// Postgres `COPY` text format: tab separated, `\N` for absent values and escaped control characters
+    Postgres,
+}
+
+impl Dialect {
+    fn format_row(&self, fields: &[Option<String>]) -> String {
+        let (separator, null) = match self {
+            Dialect::Default => (";", ""),
+            Dialect::Postgres => ("\t", "\\N"),
+        };
+        let mut row = fields
+            .iter()
+            .map(|field| match field {
+                Some(value) if *self == Dialect::Postgres => Dialect::escape_postgres(value),
+                Some(value) => value.clone(),
+                None => String::from(null),
+            })
+            .collect::<Vec<String>>()
+            .join(separator);
+        row.push('\n');
+        row
+    }
+
+    fn escape_postgres(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u8)),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+}
+
+/// Version of the csv columns, increase it whenever columns are added, removed or reordered
+const SCHEMA_VERSION: u32 = 1;
+
+/// Table definitions for Postgres, keep in sync with the as_csv() implementations
+const POSTGRES_TABLES: [(&str, &str); 4] = [
+    (
+        "blocks",
+        "hash CHAR(64) NOT NULL, height BIGINT NOT NULL, version BIGINT NOT NULL, \
+         blocksize BIGINT NOT NULL, hashPrev CHAR(64) NOT NULL, hashMerkleRoot CHAR(64) NOT NULL, \
+         nTime BIGINT NOT NULL, nBits BIGINT NOT NULL, nNonce BIGINT NOT NULL, \
+         weight BIGINT NOT NULL, vsize BIGINT NOT NULL, target CHAR(64), difficulty DOUBLE PRECISION, \
+         medianTime BIGINT",
+    ),
+    (
+        "transactions",
+        "txid CHAR(64) NOT NULL, hashBlock CHAR(64) NOT NULL, version BIGINT NOT NULL, \
+         lockTime BIGINT NOT NULL, weight BIGINT NOT NULL, vsize BIGINT NOT NULL, \
+         rbf_signaled BOOLEAN NOT NULL, has_relative_timelock BOOLEAN NOT NULL",
+    ),
+    (
+        "tx_in",
+        "txid CHAR(64) NOT NULL, hashPrevOut CHAR(64) NOT NULL, indexPrevOut BIGINT NOT NULL, \
+         scriptSig TEXT NOT NULL, sequence BIGINT NOT NULL",
+    ),
+    (
+        "tx_out",
+        "txid CHAR(64) NOT NULL, indexOut BIGINT NOT NULL, value BIGINT NOT NULL, \
+         scriptPubKey TEXT NOT NULL, address TEXT",
+    ),
+];
+
+/// Returns the column definitions of the table, the fee column is only added if `with_fees` is set.
+/// Values written in coins (see `--unit`) are stored as NUMERIC.
+fn table_columns(table: &str, with_fees: bool, unit: Unit) -> String {
+    let (_, columns) = POSTGRES_TABLES
+        .iter()
+        .find(|(name, _)| *name == table)
+        .unwrap();
+    let value_type = match unit {
+        Unit::Sat => "BIGINT",
+        Unit::Btc => "NUMERIC",
+    };
+    match table {
+        "transactions" if with_fees => format!("{}, fee {}", columns, value_type),
+        "tx_out" => columns.replace("value BIGINT", &format!("value {}", value_type)),
+        _ => String::from(*columns),
+    }
+}
+
+/// Extracts the column names from the given column definitions
+fn column_names(columns: &str) -> Vec<&str> {
+    columns
+        .split(',')
+        .map(|c| c.split_whitespace().next().unwrap())
+        .collect()
+}
+
+/// Returns the header row naming the columns of the table
+pub(super) fn header_row(table: &str, with_fees: bool, dialect: Dialect) -> String {
+    let columns = table_columns(table, with_fees, Unit::Sat);
+    let names = column_names(&columns)
+        .into_iter()
+        .map(|name| Some(String::from(name)))
+        .collect::<Vec<Option<String>>>();
+    dialect.format_row(&names)
+}
+
+/// Adds the `--no-header` flag shared by all callbacks writing tables in the csvdump format
+pub(super) fn add_header_arg(command: Command) -> Command {
+    command.arg(
+        Arg::new("no-header")
+            .long("no-header")
+            .action(clap::ArgAction::SetTrue)
+            .help("Writes the csv files without a header row naming the columns"),
+    )
+}
+
+/// Writes the rows of a single table, optionally rotating into numbered part files
+pub(super) struct PartWriter {
+    folder: Option<PathBuf>, // None if streamed
+    table: &'static str,
+    rows_per_file: Option<u64>,
+    writer: BufWriter<Box<dyn Write>>,
+    // Written before the first row of each file, None if files are headerless
+    header: Option<String>,
+    header_pending: bool,
+    // Number of rows of each part without the header, the last one is currently written
+    parts: Vec<u64>,
+}
+
+impl PartWriter {
+    pub(super) fn new(
+        folder: &Path,
+        table: &'static str,
+        rows_per_file: Option<u64>,
+        header: Option<String>,
+    ) -> OpResult<Self> {
+        let path = folder.join(PartWriter::tmp_name(table, rows_per_file, 0));
+        Ok(PartWriter {
+            folder: Some(PathBuf::from(folder)),
+            table,
+            rows_per_file,
+            writer: CsvDump::create_writer(4000000, path)?,
+            header_pending: header.is_some(),
+            header,
+            parts: vec![0],
+        })
+    }
+
+    /// Writes all rows to the given writer, nothing is renamed on finish
+    fn streamed(
+        table: &'static str,
+        writer: BufWriter<Box<dyn Write>>,
+        header: Option<String>,
+    ) -> Self {
+        PartWriter {
+            folder: None,
+            table,
+            rows_per_file: None,
+            writer,
+            header_pending: header.is_some(),
+            header,
+            parts: vec![0],
+        }
+    }
+
+    /// Writes the header if the current file doesn't have one yet
+    fn write_pending_header(&mut self) -> OpResult<()> {
+        if self.header_pending {
+            if let Some(ref header) = self.header {
+                self.writer.write_all(header.as_bytes())?;
+            }
+            self.header_pending = false;
+        }
+        Ok(())
+    }
+
+    fn tmp_name(table: &str, rows_per_file: Option<u64>, part: usize) -> String {
+        match rows_per_file {
+            Some(_) => format!("{}.{:03}.csv.tmp", table, part),
+            None => format!("{}.csv.tmp", table),
+        }
+    }
+
+    fn final_name(&self, part: usize, start_height: u64, end_height: u64) -> String {
+        match self.rows_per_file {
+            Some(_) => format!(
+                "{}-{}-{}.{:03}.csv",
+                self.table, start_height, end_height, part
+            ),
+            None => format!("{}-{}-{}.csv", self.table, start_height, end_height),
+        }
+    }
+
+    /// Continues the finished file `name` instead of starting a new one.
+    /// It's moved back to the temp file until `finish()` renames it to cover the new range.
+    fn append_to(&mut self, name: &str) -> OpResult<()> {
+        // Appending is only supported for a single file
+        let folder = self.folder.as_ref().unwrap();
+        let tmp_path = folder.join(PartWriter::tmp_name(self.table, None, 0));
+        fs::rename(folder.join(name), &tmp_path)?;
+        let file = fs::OpenOptions::new().append(true).open(tmp_path)?;
+        self.writer = BufWriter::with_capacity(4000000, Box::new(file));
+        // The continued file already starts with its header
+        self.header_pending = false;
+        Ok(())
+    }
+
+    /// Writes the row and starts a new part file beforehand if the current one is full
+    pub(super) fn write_row(&mut self, row: &str) -> OpResult<()> {
+        let rows = self.parts.last_mut().unwrap();
+        if matches!(self.rows_per_file, Some(limit) if *rows >= limit) {
+            self.writer.flush()?;
+            self.parts.push(0);
+            let name = PartWriter::tmp_name(self.table, self.rows_per_file, self.parts.len() - 1);
+            // Streamed writers never rotate
+            let folder = self.folder.as_ref().unwrap();
+            self.writer = CsvDump::create_writer(4000000, folder.join(name))?;
+            self.header_pending = self.header.is_some();
+        }
+        self.write_pending_header()?;
+        self.writer.write_all(row.as_bytes())?;
+        *self.parts.last_mut().unwrap() += 1;
+        Ok(())
+    }
+
+    /// Flushes and renames all part files.
+    /// Returns the final file names along with their row count, which is empty if streamed.
+    pub(super) fn finish(
+        &mut self,
+        start_height: u64,
+        end_height: u64,
+    ) -> OpResult<Vec<(String, u64)>> {
+        // Files without rows still name their columns
+        self.write_pending_header()?;
+        self.writer.flush()?;
+        let folder = match self.folder {
+            Some(ref folder) => folder,
+            None => return Ok(Vec::new()),
+        };
+        let mut files = Vec::with_capacity(self.parts.len());
+        for (part, rows) in self.parts.iter().enumerate() {
+            let name = self.final_name(part, start_height, end_height);
+            fs::rename(
+                folder.join(PartWriter::tmp_name(self.table, self.rows_per_file, part)),
+                folder.join(&name),
+            )?;
+            files.push((name, *rows));
+        }
+        Ok(files)
+    }
+}
+
+/// Dumps the whole blockchain into csv files, or a single table to stdout
 pub struct CsvDump {
-    // Each structure gets stored in a separate csv file
-    dump_folder: PathBuf,
-    block_writer: BufWriter<File>,
-    tx_writer: BufWriter<File>,
-    txin_writer: BufWriter<File>,
-    txout_writer: BufWriter<File>,
+    // Each structure gets stored in a separate csv file, None if streamed to stdout
+    dump_folder: Option<PathBuf>,
+    dialect: Dialect,
+    with_header: bool,
+    unit: Unit,
+    // Maps outpoints to their value, only used if fees are tracked
+    output_values: Option<SpillMap<u64>>,
+    // Unspent outputs at the start height which are loaded into output_values
+    utxo_snapshot: Option<PathBuf>,
+    missing_prevouts: MissingPrevouts,
+    rows_per_file: Option<u64>,
+    block_writer: PartWriter,
+    tx_writer: PartWriter,
+    txin_writer: PartWriter,
+    txout_writer: PartWriter,
+    // Last height of the dump which is continued on resume, blocks up to it are skipped
+    appended_until: Option<u64>,
+    // Set if the dump folder held a finished dump without --overwrite, it may only be continued
+    keep_previous: bool,
 
     start_height: u64,
     tx_count: u64,
     in_count: u64,
     out_count: u64,
+    hash_order: HashOrder,
 }
 
 impl CsvDump {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<Box<dyn Write>>> {
+        Ok(BufWriter::with_capacity(cap, Box::new(File::create(path)?)))
+    }
+
+    /// Streams the selected table to stdout and discards all others
+    fn streamed_writers(table: &str, headers: [Option<String>; 4]) -> [PartWriter; 4] {
+        let mut headers = headers.into_iter();
+        ["blocks", "transactions", "tx_in", "tx_out"].map(|name| {
+            let header = headers.next().unwrap();
+            match name == table {
+                true => PartWriter::streamed(name, common::stdout_writer(4000000), header),
+                false => PartWriter::streamed(name, BufWriter::new(Box::new(io::sink())), header),
+            }
+        })
+    }
+
+    /// Returns the first line of the file, including the line break
+    fn read_first_line(path: &Path) -> OpResult<String> {
+        let mut line = String::new();
+        BufReader::new(File::open(path)?).read_line(&mut line)?;
+        Ok(line)
+    }
+
+    /// Computes the fee of the given transaction and updates the outpoint index.
+    /// For coinbase transactions the claimed block reward (subsidy + fees) is returned.
+    /// Returns None if a spent output is unknown, e.g. if parsing didn't start at the genesis block,
+    /// such inputs are recorded in `missing`.
+    pub(super) fn compute_fee(
+        output_values: &mut SpillMap<u64>,
+        tx: &Hashed<EvaluatedTx>,
+        block_height: u64,
+        missing: &mut MissingPrevouts,
+    ) -> OpResult<Option<u64>> {
+        let mut in_value = Some(0u64);
+        if !tx.value.is_coinbase() {
+            let mut missing_inputs = 0;
+            for input in &tx.value.inputs {
+                let value = output_values.remove(&input.outpoint.to_bytes())?;
+                if value.is_none() {
+                    missing_inputs += 1;
+                }
+                in_value = match (in_value, value) {
+                    (Some(total), Some(value)) => total.checked_add(value),
+                    _ => None,
+                };
+            }
+            missing.record(missing_inputs);
+        }
+
+        let mut out_value = 0u64;
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            out_value = out_value.saturating_add(output.out.value);
+            // Provably unspendable outputs are never spent
+            match output.script.pattern {
+                ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable => continue,
+                _ => {}
+            }
+            let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
+            // Only coinbase transactions were ever duplicated (BIP30)
+            if tx.value.is_coinbase() {
+                if output_values.replace(key, output.out.value)?.is_some() {
+                    common::warn_duplicate_output(&tx.hash, i as u32, block_height);
+                }
+            } else {
+                output_values.insert(key, output.out.value)?;
+            }
+        }
+
+        if tx.value.is_coinbase() {
+            Ok(Some(out_value))
+        } else {
+            Ok(in_value.and_then(|v| v.checked_sub(out_value)))
+        }
+    }
+
+    /// Height ranges of all finished dumps in the folder, parsed from `blocks-<start>-<end>.csv`
+    fn dump_ranges(dump_folder: &Path) -> OpResult<Vec<Option<(u64, u64)>>> {
+        let mut ranges = Vec::new();
+        for entry in fs::read_dir(dump_folder)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let range = name
+                .strip_prefix("blocks-")
+                .and_then(|name| name.strip_suffix(".csv"))
+                .and_then(|range| range.split_once('-'))
+                .and_then(|(start, end)| {
+                    Some((start.parse::<u64>().ok()?, end.parse::<u64>().ok()?))
+                });
+            ranges.push(range);
+        }
+        Ok(ranges)
+    }
+
+    /// Looks for a finished dump in the folder which the given start height continues,
+    /// i.e. `blocks-<start>-<end>.csv` with start < height <= end + 1. Returns its range.
+    fn find_previous_dump(dump_folder: &Path, block_height: u64) -> OpResult<Option<(u64, u64)>> {
+        let mut ranges = Vec::new();
+        for range in CsvDump::dump_ranges(dump_folder)? {
+            match range {
+                Some((start, end)) if start < block_height && block_height <= end + 1 => {
+                    ranges.push((start, end))
+                }
+                _ => {}
+            }
+        }
+        match ranges.as_slice() {
+            [] => Ok(None),
+            [range] => Ok(Some(*range)),
+            _ => Err(
+                OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                    "Found several dumps in {} to continue at height {}.",
+                    dump_folder.display(),
+                    block_height
+                )),
+            ),
+        }
+    }
+
+    /// Continues the dump with the given range in the folder, so a resumed run
+    /// extends the existing files instead of starting new ones.
+    fn append_to_previous_dump(&mut self, dump_folder: &Path, range: (u64, u64)) -> OpResult<()> {
+        let (start, end) = range;
+        for writer in [
+            &self.block_writer,
+            &self.tx_writer,
+            &self.txin_writer,
+            &self.txout_writer,
+        ] {
+            let path = dump_folder.join(format!("{}-{}-{}.csv", writer.table, start, end));
+            if !path.exists() {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg(&format!("Incomplete dump, {} is missing.", path.display())));
+            }
+            // Rows of different columns or a header in the middle of the file can't be loaded
+            let header = header_row(writer.table, self.output_values.is_some(), self.dialect);
+            if (CsvDump::read_first_line(&path)? == header) != self.with_header {
+                let msg = format!(
+                    "{} has a different header, start a new dump or use the options of the previous run.",
+                    path.display()
+                );
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+        }
+        for writer in [
+            &mut self.block_writer,
+            &mut self.tx_writer,
+            &mut self.txin_writer,
+            &mut self.txout_writer,
+        ] {
+            writer.append_to(&format!("{}-{}-{}.csv", writer.table, start, end))?;
+            // The .sql file is written again for the new range
+            let sql_path = dump_folder.join(format!("{}-{}-{}.sql", writer.table, start, end));
+            if sql_path.exists() {
+                fs::remove_file(sql_path)?;
+            }
+        }
+        let schema_path = dump_folder.join(format!("schema-{}-{}.csv", start, end));
+        if schema_path.exists() {
+            fs::remove_file(schema_path)?;
+        }
+        self.start_height = start;
+        self.appended_until = Some(end);
+        Ok(())
+    }
+
+    /// Writes the `CREATE TABLE` and `\copy` statements for the given table (to be used with psql).
+    /// Skipping the header row of text files requires PostgreSQL 15.
+    fn write_postgres_sql(
+        dump_folder: &Path,
+        table: &str,
+        columns: &str,
+        csv_names: &[String],
+        sql_name: &str,
+        with_header: bool,
+    ) -> OpResult<()> {
+        let column_names = column_names(columns).join(", ");
+        let options = match with_header {
+            true => " WITH (HEADER true)",
+            false => "",
+        };
+        let mut sql = format!("CREATE TABLE IF NOT EXISTS {} ({});\n", table, columns);
+        for csv_name in csv_names {
+            let csv_path = fs::canonicalize(dump_folder.join(csv_name))?;
+            sql.push_str(&format!(
+                "\\copy {} ({}) FROM '{}'{}\n",
+                table,
+                column_names,
+                csv_path.display(),
+                options
+            ));
+        }
+        fs::write(dump_folder.join(sql_name), sql)?;
+        Ok(())
+    }
+
+    /// Writes a manifest listing all part files along with their table and row count
+    fn write_manifest(
+        dump_folder: &Path,
+        name: &str,
+        files: &[(&str, Vec<(String, u64)>)],
+    ) -> OpResult<()> {
+        let mut manifest = String::from("table;file;rows\n");
+        for (table, parts) in files {
+            for (file, rows) in parts {
+                manifest.push_str(&format!("{};{};{}\n", table, file, rows));
+            }
+        }
+        fs::write(dump_folder.join(name), manifest)?;
+        Ok(())
+    }
+
+    /// Writes the schema version along with the columns of each table
+    fn write_schema(dump_folder: &Path, name: &str, with_fees: bool) -> OpResult<()> {
+        let mut schema = String::from("version;table;columns\n");
+        for (table, _) in POSTGRES_TABLES {
+            let columns = table_columns(table, with_fees, Unit::Sat);
+            schema.push_str(&format!(
+                "{};{};{}\n",
+                SCHEMA_VERSION,
+                table,
+                column_names(&columns).join(",")
+            ));
+        }
+        fs::write(dump_folder.join(name), schema)?;
+        Ok(())
     }
 }
 
@@ -37,67 +532,214 @@ impl Callback for CsvDump {
     where
         Self: Sized,
     {
-        Command::new("csvdump")
+        let command = Command::new("csvdump")
             .about("Dumps the whole blockchain into CSV files")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
             .arg(
                 Arg::new("dump-folder")
-                    .help("Folder to store csv files")
+                    .help("Folder to store csv files, use - to write the table given by --table to stdout")
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::new("table")
+                    .long("table")
+                    .value_name("NAME")
+                    .value_parser(["blocks", "transactions", "tx_in", "tx_out"])
+                    .help("Table to write to stdout, required if dump folder is -"),
+            )
+            .arg(
+                Arg::new("dialect")
+                    .long("dialect")
+                    .value_name("NAME")
+                    .value_parser(["default", "postgres"])
+                    .default_value("default")
+                    .help("Output format, postgres writes COPY compatible files along with .sql files"),
+            )
+            .arg(
+                Arg::new("rows-per-file")
+                    .long("rows-per-file")
+                    .value_name("COUNT")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .help("Rotates into numbered part files after COUNT rows and writes a manifest"),
+            )
+            .arg(
+                Arg::new("with-fees")
+                    .long("with-fees")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Adds a fee column to transactions.csv (keeps all outputs in memory, see --spill-dir)"),
+            );
+        let command = common::add_utxo_snapshot_arg(command);
+        let command = command.mut_arg("utxo-snapshot", |arg| arg.requires("with-fees"));
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(
+            add_header_arg(command),
+        )))
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
-        let cap = 4000000;
+        let dump_folder = matches.get_one::<String>("dump-folder").unwrap();
+        let table = matches.get_one::<String>("table");
+        let rows_per_file = matches.get_one::<u64>("rows-per-file").copied();
+        let dialect = match matches.get_one::<String>("dialect").map(String::as_str) {
+            Some("postgres") => Dialect::Postgres,
+            _ => Dialect::Default,
+        };
+        let with_fees = matches.get_flag("with-fees");
+        let with_header = !matches.get_flag("no-header");
+        let mut keep_previous = false;
+        let headers = ["blocks", "transactions", "tx_in", "tx_out"]
+            .map(|table| with_header.then(|| header_row(table, with_fees, dialect)));
+        let [block_header, tx_header, txin_header, txout_header] = headers.clone();
+        let (dump_folder, [block_writer, tx_writer, txin_writer, txout_writer]) =
+            match (dump_folder.as_str(), table) {
+                (common::STDOUT, Some(_)) if rows_per_file.is_some() => {
+                    return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                        .join_msg("--rows-per-file cannot be used when writing to stdout."))
+                }
+                (common::STDOUT, Some(table)) => (None, CsvDump::streamed_writers(table, headers)),
+                (common::STDOUT, None) => {
+                    return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                        .join_msg("--table is required when writing to stdout."))
+                }
+                (_, Some(_)) => {
+                    return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                        .join_msg("--table can only be used when writing to stdout."))
+                }
+                (folder, None) => {
+                    let folder = PathBuf::from(folder);
+                    // A finished dump in the folder may be continued, see on_start
+                    let overwrite = matches.get_flag("overwrite");
+                    keep_previous = !overwrite
+                        && folder.is_dir()
+                        && CsvDump::dump_ranges(&folder)?.iter().any(Option::is_some);
+                    common::create_dump_folder(&folder, overwrite || keep_previous)?;
+                    let writers = [
+                        PartWriter::new(&folder, "blocks", rows_per_file, block_header)?,
+                        PartWriter::new(&folder, "transactions", rows_per_file, tx_header)?,
+                        PartWriter::new(&folder, "tx_in", rows_per_file, txin_header)?,
+                        PartWriter::new(&folder, "tx_out", rows_per_file, txout_header)?,
+                    ];
+                    (Some(folder), writers)
+                }
+            };
+        let output_values = match with_fees {
+            true => Some(SpillMap::from_matches(matches, "csvdump-spill")?),
+            false => None,
+        };
         let cb = CsvDump {
-            dump_folder: PathBuf::from(dump_folder),
-            block_writer: CsvDump::create_writer(cap, dump_folder.join("blocks.csv.tmp"))?,
-            tx_writer: CsvDump::create_writer(cap, dump_folder.join("transactions.csv.tmp"))?,
-            txin_writer: CsvDump::create_writer(cap, dump_folder.join("tx_in.csv.tmp"))?,
-            txout_writer: CsvDump::create_writer(cap, dump_folder.join("tx_out.csv.tmp"))?,
+            dump_folder,
+            dialect,
+            with_header,
+            unit: Unit::from_matches(matches),
+            output_values,
+            utxo_snapshot: common::utxo_snapshot_from_matches(matches),
+            missing_prevouts: MissingPrevouts::default(),
+            rows_per_file,
+            block_writer,
+            tx_writer,
+            txin_writer,
+            txout_writer,
+            appended_until: None,
+            keep_previous,
             start_height: 0,
             tx_count: 0,
             in_count: 0,
             out_count: 0,
+            hash_order: common::hash_order_from_matches(matches),
         };
         Ok(cb)
     }
 
     fn on_start(&mut self, block_height: u64) -> OpResult<()> {
         self.start_height = block_height;
-        info!(target: "callback", "Executing csvdump with dump folder: {} ...", &self.dump_folder.display());
+        match self.dump_folder.clone() {
+            Some(folder) => {
+                info!(target: "callback", "Executing csvdump with dump folder: {} ...", folder.display());
+                // A run starting after the genesis block continues a dump ending right before it (e.g. --resume)
+                if block_height > 0 {
+                    if let Some((start, end)) = CsvDump::find_previous_dump(&folder, block_height)?
+                    {
+                        match self.rows_per_file {
+                            Some(_) => {
+                                info!(target: "callback", "Not appending to the dump from height {} to {} with --rows-per-file.", start, end)
+                            }
+                            None => {
+                                self.append_to_previous_dump(&folder, (start, end))?;
+                                info!(target: "callback", "Appending to the dump from height {} to {} ...", start, end);
+                            }
+                        }
+                    }
+                }
+                if self.keep_previous && self.appended_until.is_none() {
+                    return Err(
+                        OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                            "Dump folder {} holds a dump which isn't continued at height {}, use --overwrite to write into it.",
+                            folder.display(),
+                            block_height
+                        )),
+                    );
+                }
+            }
+            None => info!(target: "callback", "Executing csvdump to stdout ..."),
+        }
+        if let (Some(path), Some(output_values)) = (&self.utxo_snapshot, &mut self.output_values) {
+            common::load_utxo_snapshot(path, block_height, output_values, self.hash_order)?;
+        }
         Ok(())
     }
 
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        // Already part of the continued dump, e.g. if the checkpoint is older than the dump
+        if matches!(self.appended_until, Some(end) if block_height <= end) {
+            return Ok(());
+        }
         // serialize block
         self.block_writer
-            .write_all(block.as_csv(block_height).as_bytes())?;
+            .write_row(&block.as_csv(block_height, self.dialect, self.hash_order))?;
 
         // serialize transaction
-        let block_hash = format!("{}", &block.header.hash);
+        let block_hash = self.hash_order.fmt(&block.header.hash).to_string();
         for tx in &block.txs {
-            self.tx_writer
-                .write_all(tx.as_csv(&block_hash).as_bytes())?;
-            let txid_str = format!("{}", &tx.hash);
+            let fee = match self.output_values.as_mut() {
+                Some(output_values) => Some(CsvDump::compute_fee(
+                    output_values,
+                    tx,
+                    block_height,
+                    &mut self.missing_prevouts,
+                )?),
+                None => None,
+            };
+            self.tx_writer.write_row(&tx.as_csv(
+                &block_hash,
+                fee,
+                self.unit,
+                self.dialect,
+                self.hash_order,
+            ))?;
+            let txid_str = self.hash_order.fmt(&tx.hash).to_string();
 
             // serialize inputs
             for input in &tx.value.inputs {
-                self.txin_writer
-                    .write_all(input.as_csv(&txid_str).as_bytes())?;
+                self.txin_writer.write_row(&input.as_csv(
+                    &txid_str,
+                    self.dialect,
+                    self.hash_order,
+                ))?;
             }
             self.in_count += tx.value.in_count.value;
 
             // serialize outputs
             for (i, output) in tx.value.outputs.iter().enumerate() {
-                self.txout_writer
-                    .write_all(output.as_csv(&txid_str, i as u32).as_bytes())?;
+                self.txout_writer.write_row(&output.as_csv(
+                    &txid_str,
+                    i as u32,
+                    self.unit,
+                    self.dialect,
+                ))?;
             }
             self.out_count += tx.value.out_count.value;
         }
@@ -106,15 +748,55 @@ impl Callback for CsvDump {
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
-        // Keep in sync with c'tor
-        for f in ["blocks", "transactions", "tx_in", "tx_out"] {
-            // Rename temp files
-            fs::rename(
-                self.dump_folder.as_path().join(format!("{}.csv.tmp", f)),
-                self.dump_folder
-                    .as_path()
-                    .join(format!("{}-{}-{}.csv", f, self.start_height, block_height)),
-            )?;
+        if let Some(output_values) = self.output_values.as_mut() {
+            output_values.destroy()?;
+        }
+
+        // Rename temp files, keep in sync with POSTGRES_TABLES
+        let start_height = self.start_height;
+        // The continued dump may already reach further
+        let block_height = self
+            .appended_until
+            .map_or(block_height, |end| end.max(block_height));
+        let mut files = Vec::with_capacity(POSTGRES_TABLES.len());
+        for writer in [
+            &mut self.block_writer,
+            &mut self.tx_writer,
+            &mut self.txin_writer,
+            &mut self.txout_writer,
+        ] {
+            files.push((writer.table, writer.finish(start_height, block_height)?));
+        }
+
+        // Streamed tables have neither .sql files nor a manifest
+        if let Some(ref dump_folder) = self.dump_folder {
+            let with_fees = self.output_values.is_some();
+            if self.dialect == Dialect::Postgres {
+                for (table, parts) in &files {
+                    let columns = table_columns(table, with_fees, self.unit);
+                    let csv_names = parts
+                        .iter()
+                        .map(|(name, _)| name.clone())
+                        .collect::<Vec<String>>();
+                    let sql_name = format!("{}-{}-{}.sql", table, start_height, block_height);
+                    CsvDump::write_postgres_sql(
+                        dump_folder,
+                        table,
+                        &columns,
+                        &csv_names,
+                        &sql_name,
+                        self.with_header,
+                    )?;
+                }
+            }
+            if self.with_header {
+                let name = format!("schema-{}-{}.csv", start_height, block_height);
+                CsvDump::write_schema(dump_folder, &name, with_fees)?;
+            }
+            if self.rows_per_file.is_some() {
+                let name = format!("manifest-{}-{}.csv", start_height, block_height);
+                CsvDump::write_manifest(dump_folder, &name, &files)?;
+            }
         }
 
         info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
@@ -122,70 +804,476 @@ impl Callback for CsvDump {
                                    \t-> inputs:       {:9}\n\
                                    \t-> outputs:      {:9}",
              self.start_height, block_height, self.tx_count, self.in_count, self.out_count);
+        self.missing_prevouts.log_summary(
+            self.start_height,
+            self.utxo_snapshot.is_some(),
+            "their fee column is empty",
+        );
         Ok(())
     }
+
+    fn exit_code(&self) -> i32 {
+        match self
+            .missing_prevouts
+            .is_inconsistent(self.start_height, self.utxo_snapshot.is_some())
+        {
+            true => EXIT_INCONSISTENT,
+            false => 0,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        self.output_values.is_some()
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        self.output_values.is_some()
+    }
 }
 
 impl Block {
-    fn as_csv(&self, block_height: u64) -> String {
-        // (@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce)
-        format!(
-            "{};{};{};{};{};{};{};{};{}\n",
-            &self.header.hash,
-            &block_height,
-            &self.header.value.version,
-            &self.size,
-            &self.header.value.prev_hash,
-            &self.header.value.merkle_root,
-            &self.header.value.timestamp,
-            &self.header.value.bits,
-            &self.header.value.nonce
-        )
+    pub(super) fn as_csv(
+        &self,
+        block_height: u64,
+        dialect: Dialect,
+        hash_order: HashOrder,
+    ) -> String {
+        // (@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce, weight, vsize,
+        //  @target, difficulty, medianTime)
+        dialect.format_row(&[
+            Some(hash_order.fmt(&self.header.hash).to_string()),
+            Some(block_height.to_string()),
+            Some(self.header.value.version.to_string()),
+            Some(self.size.to_string()),
+            Some(hash_order.fmt(&self.header.value.prev_hash).to_string()),
+            Some(hash_order.fmt(&self.header.value.merkle_root).to_string()),
+            Some(self.header.value.timestamp.to_string()),
+            Some(self.header.value.bits.to_string()),
+            Some(self.header.value.nonce.to_string()),
+            Some(self.weight().to_string()),
+            Some(self.vsize().to_string()),
+            self.header
+                .value
+                .target()
+                .map(|t| utils::arr_to_hex(&t.to_be_bytes())),
+            self.header.value.difficulty().map(|d| d.to_string()),
+            self.median_time_past.map(|t| t.to_string()),
+        ])
     }
 }
 
 impl Hashed<EvaluatedTx> {
-    fn as_csv(&self, block_hash: &str) -> String {
-        // (@txid, @hashBlock, version, lockTime)
-        format!(
-            "{};{};{};{}\n",
-            &self.hash, &block_hash, &self.value.version, &self.value.locktime
-        )
+    /// The fee column is only added if `fee` is set, its value is absent if the fee is unknown
+    pub(super) fn as_csv(
+        &self,
+        block_hash: &str,
+        fee: Option<Option<u64>>,
+        unit: Unit,
+        dialect: Dialect,
+        hash_order: HashOrder,
+    ) -> String {
+        // (@txid, @hashBlock, version, lockTime, weight, vsize, rbf_signaled, has_relative_timelock[, fee])
+        let mut fields = vec![
+            Some(hash_order.fmt(&self.hash).to_string()),
+            Some(block_hash.to_string()),
+            Some(self.value.version.to_string()),
+            Some(self.value.locktime.to_string()),
+            Some(self.value.weight().to_string()),
+            Some(self.value.vsize().to_string()),
+            Some(u8::from(self.value.signals_rbf()).to_string()),
+            Some(u8::from(self.value.has_relative_timelock()).to_string()),
+        ];
+        if let Some(fee) = fee {
+            fields.push(fee.map(|f| unit.format(f)));
+        }
+        dialect.format_row(&fields)
     }
 }
 
 impl TxInput {
-    fn as_csv(&self, txid: &str) -> String {
+    pub(super) fn as_csv(&self, txid: &str, dialect: Dialect, hash_order: HashOrder) -> String {
         // (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
-        format!(
-            "{};{};{};{};{}\n",
-            &txid,
-            &self.outpoint.txid,
-            &self.outpoint.index,
-            &utils::arr_to_hex(&self.script_sig),
-            &self.seq_no
-        )
+        dialect.format_row(&[
+            Some(txid.to_string()),
+            Some(hash_order.fmt(&self.outpoint.txid).to_string()),
+            Some(self.outpoint.index.to_string()),
+            Some(utils::arr_to_hex(&self.script_sig)),
+            Some(self.seq_no.to_string()),
+        ])
     }
 }
 
 impl EvaluatedTxOut {
-    fn as_csv(&self, txid: &str, index: u32) -> String {
-        let address = match self.script.address.clone() {
-            Some(address) => address,
-            None => {
-                debug!(target: "csvdump", "Unable to evaluate address for utxo in txid: {} ({})", txid, self.script.pattern);
-                String::new()
+    pub(super) fn as_csv(&self, txid: &str, index: u32, unit: Unit, dialect: Dialect) -> String {
+        if self.script.address.is_none() {
+            debug!(target: "csvdump", "Unable to evaluate address for utxo in txid: {} ({})", txid, self.script.pattern);
+        }
+
+        // (@txid, indexOut, value, @scriptPubKey, address)
+        dialect.format_row(&[
+            Some(txid.to_string()),
+            Some(index.to_string()),
+            Some(unit.format(self.out.value)),
+            Some(utils::arr_to_hex(&self.out.script_pubkey)),
+            self.script.address.clone(),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::{EvaluatedScript, ScriptPattern};
+    use crate::blockchain::proto::tx::TxOutput;
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::testutil::{BlockBuilder, TxBuilder};
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn new_tx(outpoint: TxOutpoint, values: &[u64]) -> Hashed<EvaluatedTx> {
+        let tx = TxBuilder::new()
+            .input(outpoint)
+            .script_sig(vec![])
+            .outputs(values)
+            .build();
+        Hashed::double_sha256(EvaluatedTx::from(tx))
+    }
+
+    #[test]
+    fn test_compute_fee() {
+        let mut output_values = SpillMap::new(None, 0).unwrap();
+        let mut missing = MissingPrevouts::default();
+        let coinbase = new_tx(
+            TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+            &[30, 20],
+        );
+        assert_eq!(
+            CsvDump::compute_fee(&mut output_values, &coinbase, 0, &mut missing).unwrap(),
+            Some(50)
+        );
+
+        let tx = new_tx(TxOutpoint::new(coinbase.hash, 0), &[25]);
+        assert_eq!(
+            CsvDump::compute_fee(&mut output_values, &tx, 1, &mut missing).unwrap(),
+            Some(5)
+        );
+        assert_eq!(missing.inputs, 0);
+        // Output has been spent already
+        let tx = new_tx(TxOutpoint::new(coinbase.hash, 0), &[25]);
+        assert_eq!(
+            CsvDump::compute_fee(&mut output_values, &tx, 1, &mut missing).unwrap(),
+            None
+        );
+        assert_eq!((missing.inputs, missing.txs), (1, 1));
+        assert!(missing.is_inconsistent(0, false));
+        assert!(!missing.is_inconsistent(1, false));
+
+        assert_eq!(
+            tx.as_csv(
+                "abcd",
+                Some(None),
+                Unit::Sat,
+                Dialect::Default,
+                HashOrder::Display
+            ),
+            format!(
+                "{};abcd;1;0;{};{};0;0;\n",
+                &tx.hash,
+                tx.value.weight(),
+                tx.value.vsize()
+            )
+        );
+        assert_eq!(
+            tx.as_csv(
+                "abcd",
+                None,
+                Unit::Sat,
+                Dialect::Default,
+                HashOrder::Display
+            ),
+            format!(
+                "{};abcd;1;0;{};{};0;0\n",
+                &tx.hash,
+                tx.value.weight(),
+                tx.value.vsize()
+            )
+        );
+        assert!(tx
+            .as_csv(
+                "abcd",
+                Some(Some(5)),
+                Unit::Btc,
+                Dialect::Default,
+                HashOrder::Display
+            )
+            .ends_with(";0;0;0.00000005\n"));
+    }
+
+    #[test]
+    fn test_block_as_csv() {
+        let mut block = BlockBuilder::new()
+            .size(1)
+            .timestamp(1231006505)
+            .bits(0x1b04864c)
+            .nonce(1)
+            .build();
+        block.median_time_past = Some(1231006000);
+        assert!(block
+            .as_csv(100000, Dialect::Default, HashOrder::Display)
+            .ends_with(&format!(
+                ";1231006505;453281356;1;4;1;{}04864c{};14484.162361225399;1231006000\n",
+                "0".repeat(10),
+                "0".repeat(48)
+            )));
+        // Invalid targets and the median time of the genesis block are left empty
+        block.header.value.bits = 0x04923456;
+        block.median_time_past = None;
+        assert!(block
+            .as_csv(100000, Dialect::Postgres, HashOrder::Display)
+            .ends_with("\t\\N\t\\N\t\\N\n"));
+    }
+
+    #[test]
+    fn test_txin_as_csv() {
+        let mut tx = new_tx(TxOutpoint::new(sha256d::Hash::all_zeros(), 3), &[1]);
+        tx.value.inputs[0].seq_no = 0xfffffffd;
+        tx.value.locktime = 500000;
+        assert_eq!(
+            tx.value.inputs[0].as_csv("abcd", Dialect::Default, HashOrder::Display),
+            format!("abcd;{};3;;4294967293\n", sha256d::Hash::all_zeros())
+        );
+        // Coinbase inputs keep their null prevout
+        let coinbase = new_tx(
+            TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF),
+            &[1],
+        );
+        assert_eq!(
+            coinbase.value.inputs[0].as_csv("abcd", Dialect::Default, HashOrder::Display),
+            format!("abcd;{};4294967295;;4294967295\n", "0".repeat(64))
+        );
+        assert!(tx
+            .as_csv(
+                "abcd",
+                None,
+                Unit::Sat,
+                Dialect::Default,
+                HashOrder::Display
+            )
+            .starts_with(&format!("{};abcd;1;500000;", &tx.hash)));
+        // Signals replaceability without a relative timelock
+        assert!(tx
+            .as_csv(
+                "abcd",
+                None,
+                Unit::Sat,
+                Dialect::Default,
+                HashOrder::Display
+            )
+            .ends_with(";1;0\n"));
+        tx.value.version = 2;
+        tx.value.inputs[0].seq_no = 10;
+        assert!(tx
+            .as_csv(
+                "abcd",
+                None,
+                Unit::Sat,
+                Dialect::Postgres,
+                HashOrder::Display
+            )
+            .ends_with("\t1\t1\n"));
+    }
+
+    #[test]
+    fn test_txout_as_csv() {
+        let out = EvaluatedTxOut {
+            script: EvaluatedScript::new(None, ScriptPattern::NotRecognised),
+            out: TxOutput {
+                value: 5000000000,
+                script_len: VarUint::from(2u8),
+                script_pubkey: vec![0x51, 0xac],
+            },
+        };
+        assert_eq!(
+            out.as_csv("abcd", 1, Unit::Sat, Dialect::Default),
+            "abcd;1;5000000000;51ac;\n"
+        );
+        assert_eq!(
+            out.as_csv("abcd", 1, Unit::Sat, Dialect::Postgres),
+            "abcd\t1\t5000000000\t51ac\t\\N\n"
+        );
+
+        let out = EvaluatedTxOut {
+            script: EvaluatedScript::new(
+                Some(String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")),
+                ScriptPattern::Pay2PublicKeyHash,
+            ),
+            ..out
+        };
+        assert_eq!(
+            out.as_csv("abcd", 1, Unit::Sat, Dialect::Default),
+            "abcd;1;5000000000;51ac;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n"
+        );
+        assert_eq!(
+            out.as_csv("abcd", 1, Unit::Btc, Dialect::Default),
+            "abcd;1;50.00000000;51ac;1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\n"
+        );
+    }
+
+    #[test]
+    fn test_part_writer_rotation() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let header = Some(String::from("height\n"));
+        let mut writer = PartWriter::new(tmp_dir.path(), "blocks", Some(2), header).unwrap();
+        for i in 0..5 {
+            writer.write_row(&format!("{}\n", i)).unwrap();
+        }
+        let files = writer.finish(0, 4).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                (String::from("blocks-0-4.000.csv"), 2),
+                (String::from("blocks-0-4.001.csv"), 2),
+                (String::from("blocks-0-4.002.csv"), 1),
+            ]
+        );
+        // Each part starts with the header
+        let content = fs::read_to_string(tmp_dir.path().join("blocks-0-4.001.csv")).unwrap();
+        assert_eq!(content, "height\n2\n3\n");
+        let content = fs::read_to_string(tmp_dir.path().join("blocks-0-4.002.csv")).unwrap();
+        assert_eq!(content, "height\n4\n");
+
+        // Without a limit a single file is written
+        let mut writer = PartWriter::new(tmp_dir.path(), "tx_in", None, None).unwrap();
+        for i in 0..5 {
+            writer.write_row(&format!("{}\n", i)).unwrap();
+        }
+        let files = writer.finish(0, 4).unwrap();
+        assert_eq!(files, vec![(String::from("tx_in-0-4.csv"), 5)]);
+
+        // Files without rows only contain the header
+        let header = Some(String::from("txid\n"));
+        let mut writer = PartWriter::new(tmp_dir.path(), "tx_out", None, header).unwrap();
+        assert_eq!(writer.finish(0, 0).unwrap()[0].1, 0);
+        let content = fs::read_to_string(tmp_dir.path().join("tx_out-0-0.csv")).unwrap();
+        assert_eq!(content, "txid\n");
+    }
+
+    #[test]
+    fn test_header_row() {
+        assert_eq!(
+            header_row("tx_out", false, Dialect::Default),
+            "txid;indexOut;value;scriptPubKey;address\n"
+        );
+        assert_eq!(
+            header_row("transactions", true, Dialect::Postgres),
+            "txid\thashBlock\tversion\tlockTime\tweight\tvsize\trbf_signaled\thas_relative_timelock\tfee\n"
+        );
+        // Values in coins need a decimal column type
+        assert!(table_columns("tx_out", false, Unit::Btc).contains("value NUMERIC NOT NULL"));
+        assert!(table_columns("transactions", true, Unit::Btc).ends_with(", fee NUMERIC"));
+        assert!(table_columns("transactions", true, Unit::Sat).ends_with(", fee BIGINT"));
+        // The header names every column of the rows
+        let block = BlockBuilder::new().size(80).bits(0x1d00ffff).build();
+        assert_eq!(
+            header_row("blocks", false, Dialect::Default)
+                .split(';')
+                .count(),
+            block
+                .as_csv(0, Dialect::Default, HashOrder::Display)
+                .split(';')
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_append_on_resume() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let folder = tmp_dir.path().to_str().unwrap();
+        let blocks = (0..4)
+            .map(|nonce| BlockBuilder::new().size(80).nonce(nonce).build())
+            .collect::<Vec<Block>>();
+        let run = |args: &[&str], start: u64, end: u64| {
+            let matches = CsvDump::build_subcommand().get_matches_from(args);
+            let mut cb = CsvDump::new(&matches).unwrap();
+            cb.on_start(start)?;
+            for height in start..=end {
+                cb.on_block(&blocks[height as usize], height)?;
             }
+            cb.on_complete(end)
+        };
+        let read_heights = |name: &str| {
+            fs::read_to_string(tmp_dir.path().join(name))
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|row| row.split(';').nth(1).unwrap().to_string())
+                .collect::<Vec<String>>()
         };
 
-        // (@txid, indexOut, value, @scriptPubKey, address)
-        format!(
-            "{};{};{};{};{}\n",
-            &txid,
-            &index,
-            &self.out.value,
-            &utils::arr_to_hex(&self.out.script_pubkey),
-            &address
-        )
+        run(&["csvdump", folder], 0, 1).unwrap();
+        run(&["csvdump", folder], 2, 2).unwrap();
+        assert_eq!(read_heights("blocks-0-2.csv"), vec!["0", "1", "2"]);
+        assert!(!tmp_dir.path().join("blocks-0-1.csv").exists());
+        assert!(tmp_dir.path().join("tx_out-0-2.csv").exists());
+        let schema = fs::read_to_string(tmp_dir.path().join("schema-0-2.csv")).unwrap();
+        assert!(schema.starts_with("version;table;columns\n1;blocks;hash,height,version,"));
+        assert!(!tmp_dir.path().join("schema-0-1.csv").exists());
+        // A headerless run can't continue a dump with headers
+        let err = run(&["csvdump", folder, "--no-header"], 3, 3)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+
+        // Blocks already in the dump aren't appended twice
+        run(&["csvdump", folder], 2, 3).unwrap();
+        assert_eq!(read_heights("blocks-0-3.csv"), vec!["0", "1", "2", "3"]);
+        run(&["csvdump", folder], 1, 2).unwrap();
+        assert_eq!(read_heights("blocks-0-3.csv"), vec!["0", "1", "2", "3"]);
+
+        // Starting over writes a new dump, the folder holds a dump already
+        let err = run(&["csvdump", folder], 0, 0).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        run(&["csvdump", folder, "--overwrite"], 0, 0).unwrap();
+        assert_eq!(read_heights("blocks-0-0.csv"), vec!["0"]);
+        assert_eq!(read_heights("blocks-0-3.csv").len(), 4);
+        // Both dumps could be continued at height 1
+        let err = run(&["csvdump", folder], 1, 1).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+
+        fs::remove_file(tmp_dir.path().join("blocks-0-0.csv")).unwrap();
+        fs::remove_file(tmp_dir.path().join("tx_in-0-3.csv")).unwrap();
+        let err = run(&["csvdump", folder], 4, 4).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+    }
+
+    #[test]
+    fn test_new_stdout() {
+        let new = |args: &[&str]| {
+            let matches = CsvDump::build_subcommand().get_matches_from(args);
+            CsvDump::new(&matches)
+        };
+        let cb = new(&["csvdump", "-", "--table", "tx_out"]).unwrap();
+        assert!(cb.dump_folder.is_none());
+        assert_eq!(cb.txout_writer.table, "tx_out");
+
+        for args in [
+            &["csvdump", "-"][..],
+            &["csvdump", "-", "--table", "blocks", "--rows-per-file", "10"],
+            &["csvdump", "dump", "--table", "blocks"],
+        ] {
+            let err = new(args).err().unwrap();
+            assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        }
+    }
+
+    #[test]
+    fn test_escape_postgres() {
+        assert_eq!(
+            Dialect::escape_postgres("a\tb\nc\\d\re\u{1}"),
+            "a\\tb\\nc\\\\d\\re\\x01"
+        );
+        assert_eq!(
+            Dialect::Postgres.format_row(&[Some(String::from("a\tb")), None]),
+            "a\\tb\t\\N\n"
+        );
     }
 }
@@ -0,0 +1,273 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::common::{self, MissingPrevouts, SpillMap};
+use crate::callbacks::csvdump::CsvDump;
+use crate::callbacks::Callback;
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Returns min, median, mean and max of the given fee rates, or None if there are none.
+/// The median of an even number of rates is the mean of the two middle ones.
+fn summarize(rates: &mut [f64]) -> Option<(f64, f64, f64, f64)> {
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_by(f64::total_cmp);
+    let mid = rates.len() / 2;
+    let median = match rates.len() % 2 {
+        0 => (rates[mid - 1] + rates[mid]) / 2.0,
+        _ => rates[mid],
+    };
+    let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+    Some((rates[0], median, mean, rates[rates.len() - 1]))
+}
+
+/// Dumps min, median, mean and max fee rate in sat/vB of the transactions in each block.
+///
+/// All outputs are kept to look up the value of spent inputs (see `--spill-dir`),
+/// the fee rate is the fee divided by the virtual size of the transaction (BIP141).
+/// Parsing starts at the genesis block or at the height of a loaded `--utxo-snapshot`.
+pub struct FeeRates {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    // Maps outpoints to their value
+    output_values: SpillMap<u64>,
+    // Unspent outputs at the start height which are loaded into output_values
+    utxo_snapshot: Option<PathBuf>,
+
+    start_height: u64,
+    tx_count: u64,
+    hash_order: HashOrder,
+}
+
+impl FeeRates {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Computes the fee rates of the block and returns its csv row
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<String> {
+        let mut rates = Vec::with_capacity(block.txs.len());
+        for tx in &block.txs {
+            // Starting at the genesis block all spent outputs are known
+            let fee = match CsvDump::compute_fee(
+                &mut self.output_values,
+                tx,
+                block_height,
+                &mut MissingPrevouts::default(),
+            )? {
+                Some(fee) => fee,
+                None => {
+                    let msg = format!("Transaction {} spends an unknown output.", &tx.hash);
+                    return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+                }
+            };
+            // The coinbase claims the fees instead of paying them
+            if !tx.value.is_coinbase() {
+                rates.push(fee as f64 / tx.value.vsize() as f64);
+            }
+        }
+        self.tx_count += rates.len() as u64;
+        let count = rates.len();
+        let stats = match summarize(&mut rates) {
+            Some((min, median, mean, max)) => {
+                format!("{:.3};{:.3};{:.3};{:.3}", min, median, mean, max)
+            }
+            None => String::from(";;;"),
+        };
+        Ok(format!(
+            "{};{};{};{}\n",
+            block_height,
+            self.hash_order.fmt(&block.header.hash),
+            count,
+            stats
+        ))
+    }
+}
+
+impl Callback for FeeRates {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("feerates")
+            .about("Dumps min, median, mean and max fee rate per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_utxo_snapshot_arg(common::add_spill_args(
+            command,
+        )))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = FeeRates {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: FeeRates::create_writer(4000000, dump_folder.join("feerates.csv.tmp"))?,
+            output_values: SpillMap::from_matches(matches, "feerates-spill")?,
+            utxo_snapshot: common::utxo_snapshot_from_matches(matches),
+            start_height: 0,
+            tx_count: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        match self.utxo_snapshot {
+            Some(ref path) => {
+                common::load_utxo_snapshot(
+                    path,
+                    block_height,
+                    &mut self.output_values,
+                    self.hash_order,
+                )?;
+            }
+            None if block_height != 0 => {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(
+                    "feerates needs to start at the genesis block to know all fees, \
+                     or load the unspent outputs at the start height with --utxo-snapshot.",
+                ));
+            }
+            None => {}
+        }
+        self.start_height = block_height;
+        info!(target: "callback", "Executing feerates with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{}\n",
+                "height", "hash", "tx_count", "min", "median", "mean", "max"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let row = self.apply_block(block, block_height)?;
+        self.writer.write_all(row.as_bytes())?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        self.output_values.destroy()?;
+        fs::rename(
+            self.dump_folder.as_path().join("feerates.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "feerates-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped fee rates from height {} to {}:\n\
+                                   \t-> transactions: {:9}",
+             self.start_height, block_height, self.tx_count);
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::common::testutil::{coinbase, new_block, new_tx, TxBuilder};
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(summarize(&mut []), None);
+        assert_eq!(summarize(&mut [2.0]), Some((2.0, 2.0, 2.0, 2.0)));
+        assert_eq!(
+            summarize(&mut [4.0, 1.0, 10.0, 3.0]),
+            Some((1.0, 3.5, 4.5, 10.0))
+        );
+        assert_eq!(summarize(&mut [5.0, 1.0, 3.0]), Some((1.0, 3.0, 3.0, 5.0)));
+    }
+
+    #[test]
+    fn test_feerates() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = FeeRates {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: FeeRates::create_writer(100, tmp_dir.path().join("feerates.csv.tmp")).unwrap(),
+            output_values: SpillMap::new(None, 0).unwrap(),
+            utxo_snapshot: None,
+            start_height: 0,
+            tx_count: 0,
+            hash_order: HashOrder::Display,
+        };
+        assert!(cb.on_start(1).is_err());
+        cb.on_start(0).unwrap();
+
+        // Blocks with only a coinbase have no fee rates
+        let block0 = new_block(vec![coinbase(&[1000, 1000])]);
+        assert_eq!(
+            cb.apply_block(&block0, 0).unwrap(),
+            format!("0;{};0;;;;\n", &block0.header.hash)
+        );
+
+        // A legacy tx of 62 bytes paying 124 satoshis and a segwit tx of 72 vbytes paying 360
+        let legacy = new_tx(vec![TxOutpoint::new(block0.txs[0].hash, 0)], &[876]);
+        let segwit = TxBuilder::new()
+            .input(TxOutpoint::new(block0.txs[0].hash, 1))
+            .witness(vec![vec![0x01; 36]])
+            .output(640)
+            .build();
+        let block1 = new_block(vec![coinbase(&[484]), legacy, segwit]);
+        assert_eq!(block1.txs[1].value.vsize(), 62);
+        assert_eq!(block1.txs[2].value.vsize(), 72);
+        assert_eq!(
+            cb.apply_block(&block1, 1).unwrap(),
+            format!("1;{};2;2.000;3.500;3.500;5.000\n", &block1.header.hash)
+        );
+        assert_eq!(cb.tx_count, 2);
+
+        // Starting at height 1 with the outputs of block 0 gives the same rates
+        let snapshot = tmp_dir.path().join("utxo-0-0.csv");
+        let txid = &block0.txs[0].hash;
+        fs::write(
+            &snapshot,
+            format!(
+                "txid;indexOut;height;value;scriptPubKey\n\
+                 {txid};0;0;1000;51\n{txid};1;0;1000;51\n"
+            ),
+        )
+        .unwrap();
+        let mut cb = FeeRates {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: FeeRates::create_writer(100, tmp_dir.path().join("feerates.csv.tmp")).unwrap(),
+            output_values: SpillMap::new(None, 0).unwrap(),
+            utxo_snapshot: Some(snapshot),
+            start_height: 0,
+            tx_count: 0,
+            hash_order: HashOrder::Display,
+        };
+        assert!(cb.on_start(2).is_err());
+        cb.on_start(1).unwrap();
+        assert_eq!(
+            cb.apply_block(&block1, 1).unwrap(),
+            format!("1;{};2;2.000;3.500;3.500;5.000\n", &block1.header.hash)
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::HashOrder;
+use crate::errors::OpResult;
+
+/// Dumps the header fields of every block to a CSV file.
+///
+/// Only block headers are read from the blk files, so this is much faster than a csvdump.
+pub struct Headers {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+
+    start_height: u64,
+    headers: u64,
+    hash_order: HashOrder,
+}
+
+impl Headers {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    fn as_csv(block: &Block, block_height: u64, hash_order: HashOrder) -> String {
+        let header = &block.header.value;
+        format!(
+            "{};{};{};{};{};{};{};{}\n",
+            block_height,
+            hash_order.fmt(&block.header.hash),
+            hash_order.fmt(&header.prev_hash),
+            hash_order.fmt(&header.merkle_root),
+            header.timestamp,
+            header.bits,
+            header.nonce,
+            header.version
+        )
+    }
+}
+
+impl Callback for Headers {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("headers")
+            .about("Dumps the block headers to a CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store the csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(command)
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = Headers {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Headers::create_writer(4000000, dump_folder.join("headers.csv.tmp"))?,
+            start_height: 0,
+            headers: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing headers with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{}\n",
+                "height", "hash", "prev_hash", "merkle_root", "time", "bits", "nonce", "version"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.writer
+            .write_all(Headers::as_csv(block, block_height, self.hash_order).as_bytes())?;
+        self.headers += 1;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        fs::rename(
+            self.dump_folder.as_path().join("headers.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "headers-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped {} block headers from height {} to {}.",
+             self.headers, self.start_height, block_height);
+        Ok(())
+    }
+
+    fn needs_full_data(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_headers() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = Headers {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: Headers::create_writer(100, tmp_dir.path().join("headers.csv.tmp")).unwrap(),
+            start_height: 0,
+            headers: 0,
+            hash_order: HashOrder::Display,
+        };
+        // Genesis block
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            timestamp: 1231006505,
+            bits: 486604799,
+            nonce: 2083236893,
+        };
+        let block = Block::new(285, header, None, VarUint::from(1u8), vec![]);
+        cb.on_start(0).unwrap();
+        cb.on_block(&block, 0).unwrap();
+        cb.on_complete(0).unwrap();
+        assert_eq!(cb.headers, 1);
+
+        let csv = fs::read_to_string(tmp_dir.path().join("headers-0-0.csv")).unwrap();
+        assert_eq!(
+            csv,
+            "height;hash;prev_hash;merkle_root;time;bits;nonce;version\n\
+             0;000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f;\
+             0000000000000000000000000000000000000000000000000000000000000000;\
+             4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b;\
+             1231006505;486604799;2083236893;1\n"
+        );
+    }
+}
@@ -0,0 +1,653 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{asm, ScriptPattern};
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::common::{self, Unit};
+use crate::callbacks::Callback;
+use crate::common::utils::{self, HashOrder};
+use crate::errors::OpResult;
+
+/// Dumps the whole blockchain as JSON lines (one block per line), either to a file or to stdout.
+/// With `--format bitcoind` each line holds a transaction as returned by `getrawtransaction`.
+pub struct JsonDump {
+    dump_folder: Option<PathBuf>, // None if streamed to stdout
+    writer: BufWriter<Box<dyn Write>>,
+    bitcoind: bool,
+
+    start_height: u64,
+    tx_count: u64,
+    in_count: u64,
+    out_count: u64,
+    hash_order: HashOrder,
+}
+
+impl JsonDump {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<Box<dyn Write>>> {
+        Ok(BufWriter::with_capacity(cap, Box::new(File::create(path)?)))
+    }
+
+    /// Name of the dump file without extension
+    fn file_stem(bitcoind: bool) -> &'static str {
+        match bitcoind {
+            true => "transactions",
+            false => "blocks",
+        }
+    }
+}
+
+impl Callback for JsonDump {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("jsondump")
+            .about("Dumps the whole blockchain into a JSON lines file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store json file, use - to write to stdout")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .value_parser(["blocks", "bitcoind"])
+                    .default_value("blocks")
+                    .help("Writes one block per line, or one transaction per line in the format of bitcoind's getrawtransaction"),
+            );
+        common::add_overwrite_arg(command)
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = matches.get_one::<String>("dump-folder").unwrap();
+        let bitcoind = matches.get_one::<String>("format").map(String::as_str) == Some("bitcoind");
+        let (dump_folder, writer) = match dump_folder.as_str() {
+            common::STDOUT => (None, common::stdout_writer(4000000)),
+            folder => {
+                let folder = PathBuf::from(folder);
+                common::create_dump_folder(&folder, matches.get_flag("overwrite"))?;
+                let file_name = format!("{}.json.tmp", JsonDump::file_stem(bitcoind));
+                let writer = JsonDump::create_writer(4000000, folder.join(file_name))?;
+                (Some(folder), writer)
+            }
+        };
+        let cb = JsonDump {
+            dump_folder,
+            writer,
+            bitcoind,
+            start_height: 0,
+            tx_count: 0,
+            in_count: 0,
+            out_count: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        match self.dump_folder {
+            Some(ref folder) => {
+                info!(target: "callback", "Executing jsondump with dump folder: {} ...", folder.display())
+            }
+            None => info!(target: "callback", "Executing jsondump to stdout ..."),
+        }
+        Ok(())
+    }
+
+    /// Serializes the block with all its transactions into a single line.
+    /// The writer is flushed whenever its buffer is full, so memory usage stays constant.
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        if self.bitcoind {
+            for tx in &block.txs {
+                self.writer
+                    .write_all(tx.as_bitcoind_json(block, self.hash_order).as_bytes())?;
+                self.writer.write_all(b"\n")?;
+            }
+        } else {
+            self.writer
+                .write_all(block.as_json(block_height, self.hash_order).as_bytes())?;
+            self.writer.write_all(b"\n")?;
+        }
+
+        for tx in &block.txs {
+            self.in_count += tx.value.in_count.value;
+            self.out_count += tx.value.out_count.value;
+        }
+        self.tx_count += block.tx_count.value;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        if let Some(ref folder) = self.dump_folder {
+            let stem = JsonDump::file_stem(self.bitcoind);
+            fs::rename(
+                folder.join(format!("{}.json.tmp", stem)),
+                folder.join(format!(
+                    "{}-{}-{}.json",
+                    stem, self.start_height, block_height
+                )),
+            )?;
+        }
+
+        info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
+                                   \t-> transactions: {:9}\n\
+                                   \t-> inputs:       {:9}\n\
+                                   \t-> outputs:      {:9}",
+             self.start_height, block_height, self.tx_count, self.in_count, self.out_count);
+        Ok(())
+    }
+}
+
+/// Formats the value, or null if it is absent
+fn json_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::from("null"), |v| v.to_string())
+}
+
+impl Block {
+    fn as_json(&self, block_height: u64, hash_order: HashOrder) -> String {
+        let txs = self
+            .txs
+            .iter()
+            .map(|tx| {
+                // Only coinbase outputs need to mature before they can be spent
+                let mature = match tx.value.is_coinbase() {
+                    true => self.is_coinbase_mature(),
+                    false => self.confirmations.map(|_| true),
+                };
+                tx.as_json(self.confirmations, mature, hash_order)
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "{{\"hash\":\"{}\",\"height\":{},\"version\":{},\"size\":{},\"prev_hash\":\"{}\",\
+             \"merkle_root\":\"{}\",\"timestamp\":{},\"bits\":{},\"nonce\":{},\"confirmations\":{},\"txs\":[{}]}}",
+            hash_order.fmt(&self.header.hash),
+            block_height,
+            self.header.value.version,
+            self.size,
+            hash_order.fmt(&self.header.value.prev_hash),
+            hash_order.fmt(&self.header.value.merkle_root),
+            self.header.value.timestamp,
+            self.header.value.bits,
+            self.header.value.nonce,
+            json_option(self.confirmations),
+            txs
+        )
+    }
+}
+
+impl Hashed<EvaluatedTx> {
+    fn as_json(
+        &self,
+        confirmations: Option<u64>,
+        mature: Option<bool>,
+        hash_order: HashOrder,
+    ) -> String {
+        let inputs = self
+            .value
+            .inputs
+            .iter()
+            .map(|i| i.as_json(hash_order))
+            .collect::<Vec<String>>()
+            .join(",");
+        let outputs = self
+            .value
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, o)| o.as_json(i as u32))
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "{{\"txid\":\"{}\",\"wtxid\":\"{}\",\"version\":{},\"locktime\":{},\"confirmations\":{},\"mature\":{},\"inputs\":[{}],\"outputs\":[{}]}}",
+            hash_order.fmt(&self.hash),
+            hash_order.fmt(&self.value.wtxid()),
+            self.value.version,
+            self.value.locktime,
+            json_option(confirmations),
+            json_option(mature),
+            inputs,
+            outputs
+        )
+    }
+}
+
+/// Formats the strings as JSON array, they must not need escaping
+fn json_array<T: AsRef<str>>(items: &[T]) -> String {
+    let items = items
+        .iter()
+        .map(|item| format!("\"{}\"", item.as_ref()))
+        .collect::<Vec<String>>()
+        .join(",");
+    format!("[{}]", items)
+}
+
+impl Hashed<EvaluatedTx> {
+    /// Formats the transaction like `getrawtransaction <txid> true` of Bitcoin Core 0.21,
+    /// with `reqSigs` and `addresses` in the scriptPubKey which later versions dropped
+    fn as_bitcoind_json(&self, block: &Block, hash_order: HashOrder) -> String {
+        let tx = &self.value;
+        let is_coinbase = tx.is_coinbase();
+        let inputs = tx
+            .inputs
+            .iter()
+            .map(|i| i.as_bitcoind_json(is_coinbase, hash_order))
+            .collect::<Vec<String>>()
+            .join(",");
+        let outputs = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(n, o)| o.as_bitcoind_json(n))
+            .collect::<Vec<String>>()
+            .join(",");
+        let raw = tx.to_witness_bytes();
+        // Blocks without a known tip have no confirmations, like transactions outside the active chain
+        let confirmations = match block.confirmations {
+            Some(confirmations) => format!("\"confirmations\":{},", confirmations),
+            None => String::new(),
+        };
+        format!(
+            "{{\"txid\":\"{}\",\"hash\":\"{}\",\"version\":{},\"size\":{},\"vsize\":{},\"weight\":{},\
+             \"locktime\":{},\"vin\":[{}],\"vout\":[{}],\"hex\":\"{}\",\"blockhash\":\"{}\",{}\"time\":{},\"blocktime\":{}}}",
+            hash_order.fmt(&self.hash),
+            hash_order.fmt(&tx.wtxid()),
+            tx.version as i32,
+            raw.len(),
+            tx.vsize(),
+            tx.weight(),
+            tx.locktime,
+            inputs,
+            outputs,
+            utils::arr_to_hex(&raw),
+            hash_order.fmt(&block.header.hash),
+            confirmations,
+            block.header.value.timestamp,
+            block.header.value.timestamp
+        )
+    }
+}
+
+impl TxInput {
+    fn as_bitcoind_json(&self, is_coinbase: bool, hash_order: HashOrder) -> String {
+        let script_sig = match is_coinbase {
+            true => format!("\"coinbase\":\"{}\"", utils::arr_to_hex(&self.script_sig)),
+            false => format!(
+                "\"txid\":\"{}\",\"vout\":{},\"scriptSig\":{{\"asm\":\"{}\",\"hex\":\"{}\"}}",
+                hash_order.fmt(&self.outpoint.txid),
+                self.outpoint.index,
+                self.script_sig_asm(),
+                utils::arr_to_hex(&self.script_sig)
+            ),
+        };
+        let witness = match self.witness.is_empty() {
+            true => String::new(),
+            false => {
+                let items = self.witness.iter().map(|item| utils::arr_to_hex(item));
+                format!(
+                    "\"txinwitness\":{},",
+                    json_array(&items.collect::<Vec<String>>())
+                )
+            }
+        };
+        format!("{{{},{}\"sequence\":{}}}", script_sig, witness, self.seq_no)
+    }
+
+    fn as_json(&self, hash_order: HashOrder) -> String {
+        let witness = self
+            .witness
+            .iter()
+            .map(|item| format!("\"{}\"", utils::arr_to_hex(item)))
+            .collect::<Vec<String>>()
+            .join(",");
+        let taproot_spend = match self.taproot_spend() {
+            Some(spend) => format!("\"{}\"", spend),
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"prev_txid\":\"{}\",\"prev_index\":{},\"script_sig\":\"{}\",\"sequence\":{},\"witness\":[{}],\"taproot_spend\":{}}}",
+            hash_order.fmt(&self.outpoint.txid),
+            self.outpoint.index,
+            utils::arr_to_hex(&self.script_sig),
+            self.seq_no,
+            witness,
+            taproot_spend
+        )
+    }
+}
+
+impl EvaluatedTxOut {
+    fn as_json(&self, index: u32) -> String {
+        // Addresses are either base58 or bech32 encoded, so no escaping is needed
+        let address = match &self.script.address {
+            Some(address) => format!("\"{}\"", address),
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"index\":{},\"value\":{},\"script_pubkey\":\"{}\",\"address\":{}}}",
+            index,
+            self.out.value,
+            utils::arr_to_hex(&self.out.script_pubkey),
+            address
+        )
+    }
+}
+
+impl EvaluatedTxOut {
+    /// Output type as named by Bitcoin Core along with the required signatures and addresses.
+    /// Follows its `Solver`: OP_RETURN outputs must push data only, P2PK keys must have a valid
+    /// prefix and version 0 witness programs must have a length of 20 or 32 bytes.
+    fn bitcoind_type(&self) -> (&'static str, u8, Vec<&str>) {
+        let bytes = &self.out.script_pubkey;
+        let address = self.script.address.iter().map(String::as_str).collect();
+        match self.script.pattern {
+            ScriptPattern::OpReturn(_) if asm::is_push_only(&bytes[1..]) => ("nulldata", 0, vec![]),
+            ScriptPattern::Pay2PublicKey => {
+                let key = &bytes[1..bytes.len() - 1];
+                match (key.len(), key[0]) {
+                    (33, 0x02 | 0x03) | (65, 0x04 | 0x06 | 0x07) => ("pubkey", 1, address),
+                    _ => ("nonstandard", 0, vec![]),
+                }
+            }
+            ScriptPattern::Pay2MultiSig { m, .. } => (
+                "multisig",
+                m,
+                self.script.addresses.iter().map(String::as_str).collect(),
+            ),
+            ScriptPattern::Pay2PublicKeyHash => ("pubkeyhash", 1, address),
+            ScriptPattern::Pay2ScriptHash => ("scripthash", 1, address),
+            ScriptPattern::Pay2WitnessPublicKeyHash => ("witness_v0_keyhash", 1, address),
+            ScriptPattern::Pay2WitnessScriptHash => ("witness_v0_scripthash", 1, address),
+            ScriptPattern::Pay2Taproot => ("witness_v1_taproot", 1, address),
+            ScriptPattern::WitnessProgram if bytes[0] != 0x00 => ("witness_unknown", 1, address),
+            _ => ("nonstandard", 0, vec![]),
+        }
+    }
+
+    fn as_bitcoind_json(&self, n: usize) -> String {
+        let (script_type, req_sigs, addresses) = self.bitcoind_type();
+        // Without any address only the type is given
+        let script_type = match addresses.is_empty() {
+            true => format!("\"type\":\"{}\"", script_type),
+            false => format!(
+                "\"reqSigs\":{},\"type\":\"{}\",\"addresses\":{}",
+                req_sigs,
+                script_type,
+                json_array(&addresses)
+            ),
+        };
+        format!(
+            "{{\"value\":{},\"n\":{},\"scriptPubKey\":{{\"asm\":\"{}\",\"hex\":\"{}\",{}}}}}",
+            Unit::Btc.format(self.out.value),
+            n,
+            self.out.script_pubkey_asm(),
+            utils::arr_to_hex(&self.out.script_pubkey),
+            script_type
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::proto::script::{EvaluatedScript, ScriptPattern};
+    use crate::blockchain::proto::tx::{EvaluatedTxOut, TxInput, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils::HashOrder;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    #[test]
+    fn test_txin_as_json() {
+        let input = TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::all_zeros(), 1),
+            script_len: VarUint::from(0u8),
+            script_sig: vec![],
+            seq_no: 0xffffffff,
+            witness: vec![vec![0x30, 0x45], vec![0x02]],
+        };
+        assert_eq!(
+            input.as_json(HashOrder::Display),
+            format!(
+                "{{\"prev_txid\":\"{}\",\"prev_index\":1,\"script_sig\":\"\",\
+                 \"sequence\":4294967295,\"witness\":[\"3045\",\"02\"],\"taproot_spend\":null}}",
+                sha256d::Hash::all_zeros()
+            )
+        );
+    }
+
+    #[test]
+    fn test_txout_as_json() {
+        let out = EvaluatedTxOut {
+            script: EvaluatedScript::new(
+                Some(String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")),
+                ScriptPattern::Pay2PublicKeyHash,
+            ),
+            out: TxOutput {
+                value: 5000000000,
+                script_len: VarUint::from(2u8),
+                script_pubkey: vec![0x51, 0xac],
+            },
+        };
+        assert_eq!(
+            out.as_json(1),
+            "{\"index\":1,\"value\":5000000000,\"script_pubkey\":\"51ac\",\
+             \"address\":\"1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\"}"
+        );
+
+        let out = EvaluatedTxOut {
+            script: EvaluatedScript::new(None, ScriptPattern::NotRecognised),
+            ..out
+        };
+        assert_eq!(
+            out.as_json(0),
+            "{\"index\":0,\"value\":5000000000,\"script_pubkey\":\"51ac\",\"address\":null}"
+        );
+    }
+
+    #[test]
+    fn test_block_as_json_confirmations() {
+        use crate::common::testutil::{new_block, TxBuilder};
+
+        let tx = |txid: sha256d::Hash, index: u32| {
+            TxBuilder::new()
+                .input(TxOutpoint::new(txid, index))
+                .script_sig(vec![])
+                .build()
+        };
+        let coinbase = tx(sha256d::Hash::all_zeros(), 0xffffffff);
+        let spend = tx(sha256d::Hash::hash(b"prev"), 0);
+        let mut block = new_block(vec![coinbase, spend]);
+        let json = block.as_json(5, HashOrder::Display);
+        assert!(json.contains("\"nonce\":0,\"confirmations\":null,\"txs\""));
+        assert!(json.contains("\"confirmations\":null,\"mature\":null"));
+
+        // The coinbase is immature, the other transaction is not
+        block.confirmations = Some(99);
+        let json = block.as_json(5, HashOrder::Display);
+        assert!(json.contains("\"nonce\":0,\"confirmations\":99,\"txs\""));
+        let txs = json.split("{\"txid\"").skip(1).collect::<Vec<&str>>();
+        assert!(txs[0].contains("\"confirmations\":99,\"mature\":false"));
+        assert!(txs[1].contains("\"confirmations\":99,\"mature\":true"));
+    }
+
+    mod bitcoind {
+        use crate::blockchain::parser::reader::BlockchainRead;
+        use crate::blockchain::proto::block::Block;
+        use crate::blockchain::proto::varuint::VarUint;
+        use crate::common::testutil::BlockBuilder;
+        use crate::common::utils::{self, HashOrder};
+        use std::io::Cursor;
+
+        /// The genesis coinbase and the first transaction between two parties (block 170)
+        const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000\
+            ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e20\
+            6272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a0100000043410467\
+            8afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384d\
+            f7ba0b8d578a4c702b6bf11d5fac00000000";
+        const BLOCK_170_TX: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704\
+            000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8e\
+            ca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09\
+            c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1ba\
+            ded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b1\
+            48a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+        /// P2SH-P2WPKH spend from https://en.bitcoin.it/wiki/Weight_units
+        const SEGWIT_TX: &str = "0100000000010115e180dc28a2327e687facc33f10f2a20da717e5548406f7ae8b4c811072f856\
+            03000000171600141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928ffffffff019caef505000000001976a9141d7cd6c7\
+            5c2e86f4cbf98eaed221b30bd9a0b92888ac02483045022100f764287d3e99b1474da9bec7f7ed236d6c81e793b20c4b5a\
+            a1f3051b9a7daa63022016a198031d5554dbb855bdbe8534776a4be6958bd8d530dc001c32b828f6f0ab0121038262a6c6\
+            cec93c2d3ecd6c6072efea86d02ff8e3328bbd0242b20af3425990ac00000000";
+
+        fn new_block(raw_tx: &str, timestamp: u32, confirmations: Option<u64>) -> Block {
+            let raw = utils::hex_to_vec(raw_tx);
+            let tx = Cursor::new(raw.as_slice()).read_tx(0x00).unwrap();
+            let mut block = BlockBuilder::new()
+                .timestamp(timestamp)
+                .bits(0x1d00ffff)
+                .tx(tx)
+                .build();
+            block.confirmations = confirmations;
+            block
+        }
+
+        /// Compares with the output of `bitcoin-cli getrawtransaction <txid> true` (v0.21)
+        #[test]
+        fn test_genesis_coinbase() {
+            let block = new_block(GENESIS_COINBASE, 1231006505, Some(10));
+            let json = block.txs[0].as_bitcoind_json(&block, HashOrder::Display);
+            let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+            let script_sig = "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63\
+                656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73";
+            let pub_key =
+                "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f\
+                4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f";
+            assert_eq!(
+                json,
+                format!(
+                    "{{\"txid\":\"{txid}\",\"hash\":\"{txid}\",\"version\":1,\"size\":204,\"vsize\":204,\
+                     \"weight\":816,\"locktime\":0,\"vin\":[{{\"coinbase\":\"{script_sig}\",\
+                     \"sequence\":4294967295}}],\"vout\":[{{\"value\":50.00000000,\"n\":0,\
+                     \"scriptPubKey\":{{\"asm\":\"{pub_key} OP_CHECKSIG\",\"hex\":\"41{pub_key}ac\",\
+                     \"reqSigs\":1,\"type\":\"pubkey\",\"addresses\":[\"1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa\"]}}}}],\
+                     \"hex\":\"{}\",\"blockhash\":\"{}\",\"confirmations\":10,\"time\":1231006505,\
+                     \"blocktime\":1231006505}}",
+                    utils::arr_to_hex(&utils::hex_to_vec(GENESIS_COINBASE)),
+                    block.header.hash
+                )
+            );
+        }
+
+        #[test]
+        fn test_block_170_tx() {
+            let block = new_block(BLOCK_170_TX, 1231731025, None);
+            let json = block.txs[0].as_bitcoind_json(&block, HashOrder::Display);
+            let sig = "304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd41\
+                0220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d09";
+            let to_hal =
+                "04ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397\
+                f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84c";
+            let change =
+                "0411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb\
+                84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3";
+            let output = |value: &str, n: u32, pub_key: &str, address: &str| {
+                format!(
+                    "{{\"value\":{value},\"n\":{n},\"scriptPubKey\":{{\"asm\":\"{pub_key} OP_CHECKSIG\",\
+                     \"hex\":\"41{pub_key}ac\",\"reqSigs\":1,\"type\":\"pubkey\",\"addresses\":[\"{address}\"]}}}}"
+                )
+            };
+            let txid = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+            // Without known confirmations the field is left out
+            assert_eq!(
+                json,
+                format!(
+                    "{{\"txid\":\"{txid}\",\"hash\":\"{txid}\",\"version\":1,\"size\":275,\"vsize\":275,\
+                     \"weight\":1100,\"locktime\":0,\"vin\":[{{\"txid\":\
+                     \"0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9\",\"vout\":0,\
+                     \"scriptSig\":{{\"asm\":\"{sig}[ALL]\",\"hex\":\"47{sig}01\"}},\"sequence\":4294967295}}],\
+                     \"vout\":[{},{}],\"hex\":\"{}\",\"blockhash\":\"{}\",\"time\":1231731025,\
+                     \"blocktime\":1231731025}}",
+                    output("10.00000000", 0, to_hal, "1Q2TWHE3GMdB6BZKafqwxXtWAWgFt5Jvm3"),
+                    output("40.00000000", 1, change, "12cbQLTFMXRnSzktFkuoG3eHoMeFtpTu3S"),
+                    utils::arr_to_hex(&utils::hex_to_vec(BLOCK_170_TX)),
+                    block.header.hash
+                )
+            );
+        }
+
+        #[test]
+        fn test_segwit_tx() {
+            let block = new_block(SEGWIT_TX, 0, None);
+            let json = block.txs[0].as_bitcoind_json(&block, HashOrder::Display);
+            assert!(json.starts_with(
+                "{\"txid\":\"8139979112e894a14f8370438a471d23984061ff83a9eba0bc7a34433327ec21\",\
+                 \"hash\":\"6bf4e4dfb860cf0906f49c836700b130ac78cc391c72a0911c94cdec4dcb10ec\",\
+                 \"version\":1,\"size\":218,\"vsize\":136,\"weight\":542,\"locktime\":0,"
+            ));
+            assert!(json.contains(
+                "\"scriptSig\":{\"asm\":\"00141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928\",\
+                 \"hex\":\"1600141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928\"},\"txinwitness\":\
+                 [\"3045022100f764287d3e99b1474da9bec7f7ed236d6c81e793b20c4b5aa1f3051b9a7daa63022016a198031d\
+                 5554dbb855bdbe8534776a4be6958bd8d530dc001c32b828f6f0ab01\",\
+                 \"038262a6c6cec93c2d3ecd6c6072efea86d02ff8e3328bbd0242b20af3425990ac\"],\"sequence\":4294967295}"
+            ));
+            assert!(json.contains(
+                "{\"value\":0.99987100,\"n\":0,\"scriptPubKey\":{\"asm\":\"OP_DUP OP_HASH160 \
+                 1d7cd6c75c2e86f4cbf98eaed221b30bd9a0b928 OP_EQUALVERIFY OP_CHECKSIG\",\
+                 \"hex\":\"76a9141d7cd6c75c2e86f4cbf98eaed221b30bd9a0b92888ac\",\"reqSigs\":1,\
+                 \"type\":\"pubkeyhash\",\"addresses\":[\"1"
+            ));
+        }
+
+        #[test]
+        fn test_output_types() {
+            use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutput};
+
+            let out = |script: &str| {
+                let script_pubkey = utils::hex_to_vec(script);
+                let out = TxOutput {
+                    value: 1,
+                    script_len: VarUint::from(script_pubkey.len() as u64),
+                    script_pubkey,
+                };
+                let out = EvaluatedTxOut::eval_script(out, 0x00);
+                let (script_type, req_sigs, addresses) = out.bitcoind_type();
+                (script_type, req_sigs, addresses.len())
+            };
+            assert_eq!(out("6a0568656c6c6f"), ("nulldata", 0, 0));
+            // OP_RETURN followed by an opcode
+            assert_eq!(out("6a76"), ("nonstandard", 0, 0));
+            let key = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+            assert_eq!(out(&format!("21{}ac", key)), ("pubkey", 1, 1));
+            assert_eq!(
+                out(&format!("21{}ac", key.replacen("02", "05", 1))),
+                ("nonstandard", 0, 0)
+            );
+            assert_eq!(
+                out(&format!("5121{}21{}52ae", key, key)),
+                ("multisig", 1, 2)
+            );
+            assert_eq!(
+                out("0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+                ("witness_v0_keyhash", 1, 1)
+            );
+            assert_eq!(
+                out("5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c"),
+                ("witness_v1_taproot", 1, 1)
+            );
+            assert_eq!(out("5202751e"), ("witness_unknown", 1, 1));
+            assert_eq!(out("0003751e76"), ("nonstandard", 0, 0));
+        }
+    }
+}
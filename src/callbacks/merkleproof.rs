@@ -0,0 +1,263 @@
+use std::io::{BufWriter, Write};
+
+use bitcoin::hashes::sha256d;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::{self, HashOrder};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Merkle proof of a transaction, everything a SPV client needs to check it against the header
+struct Proof {
+    height: u64,
+    block_hash: sha256d::Hash,
+    // Serialized block header
+    header: Vec<u8>,
+    merkle_root: sha256d::Hash,
+    index: usize,
+    tx_count: usize,
+    branch: Vec<sha256d::Hash>,
+}
+
+impl Proof {
+    /// Hashes are written in the byte order of txids (see --hash-order)
+    fn as_json(&self, txid: &sha256d::Hash, hash_order: HashOrder) -> String {
+        let branch = self
+            .branch
+            .iter()
+            .map(|hash| format!("\"{}\"", hash_order.fmt(hash)))
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "{{\"txid\":\"{}\",\"block_hash\":\"{}\",\"height\":{},\"header\":\"{}\",\
+             \"merkle_root\":\"{}\",\"index\":{},\"tx_count\":{},\"branch\":[{}]}}",
+            hash_order.fmt(txid),
+            hash_order.fmt(&self.block_hash),
+            self.height,
+            utils::arr_to_hex(&self.header),
+            hash_order.fmt(&self.merkle_root),
+            self.index,
+            self.tx_count,
+            branch
+        )
+    }
+}
+
+/// Builds the merkle proof of a single transaction from the block containing it
+/// and writes it as JSON to stdout.
+pub struct MerkleProof {
+    txid: sha256d::Hash,
+    writer: BufWriter<Box<dyn Write>>,
+    // Set once the transaction has been found
+    proof: Option<Proof>,
+
+    start_height: u64,
+    hash_order: HashOrder,
+}
+
+impl MerkleProof {
+    fn with_writer(
+        txid: sha256d::Hash,
+        writer: BufWriter<Box<dyn Write>>,
+        hash_order: HashOrder,
+    ) -> Self {
+        MerkleProof {
+            txid,
+            writer,
+            proof: None,
+            start_height: 0,
+            hash_order,
+        }
+    }
+
+    /// Computes the proof if the block contains the transaction.
+    /// The proof is checked against the merkle root of the header before it is kept.
+    fn find_proof(&self, block: &Block, block_height: u64) -> OpResult<Option<Proof>> {
+        let index = match block.txs.iter().position(|tx| tx.hash == self.txid) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let txids = block.txs.iter().map(|tx| tx.hash).collect();
+        let (root, branch) = utils::merkle_branch(txids, index).unwrap();
+        let merkle_root = block.header.value.merkle_root;
+        if root != merkle_root || utils::merkle_root_from_branch(self.txid, index, &branch) != root
+        {
+            let msg = format!(
+                "Merkle root of block {} at height {} doesn't match its transactions.",
+                &block.header.hash, block_height
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        Ok(Some(Proof {
+            height: block_height,
+            block_hash: block.header.hash,
+            header: block.header.value.to_bytes(),
+            merkle_root,
+            index,
+            tx_count: block.txs.len(),
+            branch,
+        }))
+    }
+}
+
+impl Callback for MerkleProof {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("merkleproof")
+            .about("Writes the merkle proof of a transaction as JSON to stdout")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("txid")
+                    .help("Transaction to prove")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let hash_order = common::hash_order_from_matches(matches);
+        let txid = matches.get_one::<String>("txid").unwrap();
+        let txid = hash_order.parse(txid).ok_or_else(|| {
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!("Invalid txid: {}", txid))
+        })?;
+        Ok(MerkleProof::with_writer(
+            txid,
+            common::stdout_writer(4096),
+            hash_order,
+        ))
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing merkleproof for {} ...", &self.txid);
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        // Duplicated coinbase transactions (BIP30) are proven by their first block
+        if self.proof.is_none() {
+            self.proof = self.find_proof(block, block_height)?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let proof = match self.proof {
+            Some(ref proof) => proof,
+            None => {
+                let msg = format!(
+                    "Transaction {} not found from height {} to {}.",
+                    &self.txid, self.start_height, block_height
+                );
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+        };
+        self.writer
+            .write_all(proof.as_json(&self.txid, self.hash_order).as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        info!(target: "callback", "Done.\nFound transaction {} in block {} at height {}:\n\
+                                   \t-> index:        {:9}\n\
+                                   \t-> branch:       {:9}",
+             &self.txid, &proof.block_hash, proof.height, proof.index, proof.branch.len());
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::common::testutil::{new_tx, BlockBuilder};
+    use bitcoin::hashes::Hash;
+    use std::io;
+
+    /// Block with `tx_count` transactions and a valid merkle root
+    fn new_block(tx_count: u32) -> Block {
+        let txs = (0..tx_count)
+            .map(|index| {
+                new_tx(
+                    vec![TxOutpoint::new(sha256d::Hash::all_zeros(), index)],
+                    &[50],
+                )
+            })
+            .collect();
+        let mut block = BlockBuilder::new().bits(0x1d00ffff).txs(txs).build();
+        let txids = block.txs.iter().map(|tx| tx.hash).collect();
+        block.header.value.merkle_root = utils::merkle_root(txids);
+        block
+    }
+
+    fn new_callback(txid: sha256d::Hash) -> MerkleProof {
+        MerkleProof::with_writer(
+            txid,
+            BufWriter::new(Box::new(io::sink())),
+            HashOrder::Display,
+        )
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let block = new_block(7);
+        let txid = block.txs[5].hash;
+        let mut cb = new_callback(txid);
+        cb.on_start(0).unwrap();
+        cb.on_block(&new_block(1), 0).unwrap();
+        assert!(cb.proof.is_none());
+        cb.on_block(&block, 1).unwrap();
+        cb.on_complete(1).unwrap();
+
+        // The branch leads from the txid back to the merkle root of the header
+        let proof = cb.proof.unwrap();
+        assert_eq!(proof.height, 1);
+        assert_eq!(proof.index, 5);
+        assert_eq!(proof.branch.len(), 3);
+        assert_eq!(proof.header.len(), 80);
+        assert_eq!(proof.merkle_root, block.header.value.merkle_root);
+        assert_eq!(
+            utils::merkle_root_from_branch(txid, proof.index, &proof.branch),
+            block.header.value.merkle_root
+        );
+        let json = proof.as_json(&txid, HashOrder::Display);
+        assert!(json.starts_with(&format!(
+            "{{\"txid\":\"{}\",\"block_hash\":\"{}\",\"height\":1,\"header\":\"01000000",
+            txid, &block.header.hash
+        )));
+        assert!(json.ends_with(&format!(
+            "\"index\":5,\"tx_count\":7,\"branch\":[\"{}\",\"{}\",\"{}\"]}}",
+            proof.branch[0], proof.branch[1], proof.branch[2]
+        )));
+    }
+
+    #[test]
+    fn test_merkle_proof_errors() {
+        // Not part of the parsed range
+        let mut cb = new_callback(sha256d::Hash::hash(b"missing"));
+        cb.on_start(0).unwrap();
+        cb.on_block(&new_block(3), 0).unwrap();
+        let err = cb.on_complete(0).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+
+        // The merkle root of the header doesn't match the transactions
+        let mut block = new_block(3);
+        block.header.value.merkle_root = sha256d::Hash::all_zeros();
+        let mut cb = new_callback(block.txs[1].hash);
+        let err = cb.on_block(&block, 0).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::ValidationError));
+
+        let matches = MerkleProof::build_subcommand().get_matches_from(["merkleproof", "abcd"]);
+        assert!(MerkleProof::new(&matches).is_err());
+    }
+}
@@ -3,15 +3,44 @@ use clap::{ArgMatches, Command};
 use crate::blockchain::proto::block::Block;
 use crate::errors::OpResult;
 
+pub mod addrfilter;
 pub mod balances;
+pub mod benchmark;
+pub mod blockindex;
+pub mod clusters;
+pub mod coinbase;
+pub mod coindays;
 mod common;
 pub mod csvdump;
+pub mod feerates;
+pub mod headers;
+pub mod jsondump;
+pub mod merkleproof;
 pub mod opreturn;
+pub mod outputswithspend;
+pub mod periodstats;
+pub mod rawblocks;
+pub mod registry;
+pub mod retarget;
+pub mod segwitstats;
 pub mod simplestats;
+pub mod spentoutputs;
+pub mod sqlite;
+pub mod supply;
 pub mod unspentcsvdump;
+pub mod utxodump;
+pub mod validate;
+
+/// Exit code for callbacks which completed but found inconsistencies in the data.
+/// Errors exit with 1 and an interrupted run with 130.
+pub const EXIT_INCONSISTENT: i32 = 2;
 
 /// Implement this trait for a custom Callback.
-/// The parser ensures that the blocks arrive in the correct order.
+/// The parser ensures that the blocks arrive in the correct order: blocks are read and handed to
+/// on_block() one at a time on the thread running `BlockchainParser::start`, each height at most
+/// once and in strictly ascending order. Heights are only skipped if their blk file was not given
+/// with --blk-file, --blk-from or --blk-to, or the block failed to parse with --skip-errors, so
+/// stateful callbacks can rely on seeing a block after all its ancestors otherwise.
 /// At this stage the main chain is already determined and orphans/stales are removed.
 pub trait Callback {
     /// Builds Command to specify callback name and required args,
@@ -29,13 +58,48 @@ pub trait Callback {
     fn on_start(&mut self, block_height: u64) -> OpResult<()>;
 
     /// Gets called if a new block is available.
+    /// The block is fully parsed: header fields are accessible through `block.header.value`,
+    /// and each transaction in `block.txs` holds its inputs and evaluated outputs
+    /// (value, script pattern and address), so callbacks never need to decode raw bytes.
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()>;
 
     /// Gets called if the parser has finished and all blocks are handled
     fn on_complete(&mut self, block_height: u64) -> OpResult<()>;
 
+    /// Exit code of the process, queried after on_complete() succeeded.
+    /// Callbacks which check the data return `EXIT_INCONSISTENT` to report their findings
+    /// without failing the run, the output files are complete in that case.
+    fn exit_code(&self) -> i32 {
+        0
+    }
+
     /// Can be used to toggle whether the progress should be shown for specific callbacks or not
     fn show_progress(&self) -> bool {
         true
     }
+
+    /// Callbacks which only look at block headers can return false to skip parsing transactions.
+    /// `block.txs` is empty in on_block() then, `block.tx_count` and the header are still set.
+    /// Ignored with --verify and --verify-witness-commitment, which need the transactions.
+    fn needs_full_data(&self) -> bool {
+        true
+    }
+
+    /// Callbacks which need the serialized blocks return true to get them in `block.raw`.
+    fn needs_raw_data(&self) -> bool {
+        false
+    }
+
+    /// Callbacks which read the coinbase transaction or match inputs with the outputs they spend
+    /// return true, --no-coinbase is refused for them.
+    fn needs_coinbase(&self) -> bool {
+        false
+    }
+
+    /// Callbacks whose results depend on all blocks before the first parsed one, e.g. because they
+    /// keep the unspent outputs, return true. --resume is refused for them, as their state isn't
+    /// part of the checkpoint.
+    fn needs_previous_blocks(&self) -> bool {
+        false
+    }
 }
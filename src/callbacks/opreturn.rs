@@ -1,37 +1,116 @@
-use clap::{ArgMatches, Command};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::blockchain::proto::script::ScriptPattern;
-use crate::callbacks::Callback;
+use crate::blockchain::proto::script::{extract_op_return_data, ScriptPattern};
+use crate::callbacks::{common, Callback};
+use crate::common::utils::{self, HashOrder};
 use crate::errors::OpResult;
 
+/// Shows embedded OP_RETURN data that is representable as UTF8.
+/// If a dump folder is given, the raw pushed data of all OP_RETURN outputs is written to a csv file instead.
 #[derive(Default)]
-pub struct OpReturn;
+pub struct OpReturn {
+    dump_folder: Option<PathBuf>,
+    writer: Option<BufWriter<File>>,
+
+    start_height: u64,
+    out_count: u64,
+    hash_order: HashOrder,
+}
+
+impl OpReturn {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Returns a csv row for each OP_RETURN output of the given block.
+    /// Multiple pushes within one output are concatenated into a single row.
+    fn as_csv(block: &Block, block_height: u64, hash_order: HashOrder) -> Vec<String> {
+        let mut rows = Vec::new();
+        for tx in &block.txs {
+            for (i, out) in tx.value.outputs.iter().enumerate() {
+                if let Some(data) = extract_op_return_data(&out.out.script_pubkey) {
+                    rows.push(format!(
+                        "{};{};{};{}\n",
+                        hash_order.fmt(&tx.hash),
+                        i,
+                        block_height,
+                        utils::arr_to_hex(&data)
+                    ));
+                }
+            }
+        }
+        rows
+    }
+}
 
 impl Callback for OpReturn {
     fn build_subcommand() -> Command
     where
         Self: Sized,
     {
-        Command::new("opreturn")
+        let command = Command::new("opreturn")
             .about("Shows embedded OP_RETURN data that is representable as UTF8")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file with the raw data of all OP_RETURN outputs")
+                    .index(1),
+            );
+        common::add_overwrite_arg(command)
     }
 
-    fn new(_: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        Ok(OpReturn::default())
+        let dump_folder = matches.get_one::<String>("dump-folder").map(PathBuf::from);
+        let writer = match dump_folder {
+            Some(ref folder) => {
+                common::create_dump_folder(folder, matches.get_flag("overwrite"))?;
+                Some(OpReturn::create_writer(
+                    4000000,
+                    folder.join("opreturn.csv.tmp"),
+                )?)
+            }
+            None => None,
+        };
+        Ok(OpReturn {
+            dump_folder,
+            writer,
+            hash_order: common::hash_order_from_matches(matches),
+            ..Default::default()
+        })
     }
 
-    fn on_start(&mut self, _: u64) -> OpResult<()> {
-        info!(target: "callback", "Executing OpReturn ...");
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        match (&self.dump_folder, &mut self.writer) {
+            (Some(folder), Some(writer)) => {
+                info!(target: "callback", "Executing OpReturn with dump folder: {} ...", folder.display());
+                writer.write_all(
+                    format!("{};{};{};{}\n", "txid", "indexOut", "height", "data").as_bytes(),
+                )?;
+            }
+            _ => info!(target: "callback", "Executing OpReturn ..."),
+        }
         Ok(())
     }
 
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            for row in OpReturn::as_csv(block, block_height, self.hash_order) {
+                writer.write_all(row.as_bytes())?;
+                self.out_count += 1;
+            }
+            return Ok(());
+        }
+
         for tx in &block.txs {
             for out in tx.value.outputs.iter() {
                 if let ScriptPattern::OpReturn(data) = &out.script.pattern {
@@ -40,7 +119,9 @@ impl Callback for OpReturn {
                     }
                     println!(
                         "height: {: <9} txid: {}    data: {}",
-                        block_height, &tx.hash, data
+                        block_height,
+                        self.hash_order.fmt(&tx.hash),
+                        data
                     );
                 }
             }
@@ -48,11 +129,58 @@ impl Callback for OpReturn {
         Ok(())
     }
 
-    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        if let (Some(folder), Some(writer)) = (&self.dump_folder, &mut self.writer) {
+            writer.flush()?;
+            fs::rename(
+                folder.join("opreturn.csv.tmp"),
+                folder.join(format!(
+                    "opreturn-{}-{}.csv",
+                    self.start_height, block_height
+                )),
+            )?;
+            info!(target: "callback", "Done.\nDumped {} OP_RETURN outputs from height {} to {}.",
+                 self.out_count, self.start_height, block_height);
+        }
         Ok(())
     }
 
     fn show_progress(&self) -> bool {
-        false
+        self.writer.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::common::testutil::{new_block, TxBuilder};
+    use bitcoin::hashes::{sha256d, Hash};
+
+    #[test]
+    fn test_op_return_as_csv() {
+        // Output 1 contains a known OP_RETURN script:
+        //   6a13636861726c6579206c6f766573206865696469
+        //   OP_RETURN 13 0x636861726c6579206c6f766573206865696469 ("charley loves heidi")
+        let op_return = vec![
+            0x6a, 0x13, 0x63, 0x68, 0x61, 0x72, 0x6c, 0x65, 0x79, 0x20, 0x6c, 0x6f, 0x76, 0x65,
+            0x73, 0x20, 0x68, 0x65, 0x69, 0x64, 0x69,
+        ];
+        let tx = TxBuilder::new()
+            .input(TxOutpoint::new(sha256d::Hash::all_zeros(), 0))
+            .script_sig(vec![])
+            .output(1000)
+            .pay(0, op_return)
+            .build();
+        let block = new_block(vec![tx]);
+
+        let rows = OpReturn::as_csv(&block, 42, HashOrder::Display);
+        assert_eq!(
+            rows,
+            vec![format!(
+                "{};1;42;636861726c6579206c6f766573206865696469\n",
+                &block.txs[0].hash
+            )]
+        );
     }
 }
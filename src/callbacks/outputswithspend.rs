@@ -0,0 +1,393 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use bitcoin::hashes::{sha256d, Hash};
+use byteorder::{ByteOrder, LittleEndian};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::common::{self, SpillMap, Spillable, Unit};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::common::utils::HashOrder;
+use crate::errors::OpResult;
+
+/// Transaction spending an output
+struct Spend {
+    txid: sha256d::Hash,
+    index: u32,
+    block_height: u64,
+}
+
+impl Spillable for Spend {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Spend {
+            txid: sha256d::Hash::from_slice(&bytes[0..32]).expect("spill: malformed txid"),
+            index: LittleEndian::read_u32(&bytes[32..36]),
+            block_height: LittleEndian::read_u64(&bytes[36..44]),
+        }
+    }
+}
+
+impl ToRaw for Spend {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(44);
+        bytes.extend_from_slice(self.txid.as_byte_array());
+        bytes.extend_from_slice(&self.index.to_le_bytes());
+        bytes.extend_from_slice(&self.block_height.to_le_bytes());
+        bytes
+    }
+}
+
+/// Dumps every output created during the parse with a flag whether it is spent until the end
+/// height and, if so, the transaction spending it.
+///
+/// The outputs are written to a temporary file in the first pass while the spends are recorded
+/// by row number, the second pass in on_complete() appends the spend to each row.
+/// Both the unspent outputs and the spends can be moved to disk (see `--spill-dir`).
+pub struct OutputsWithSpend {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    unit: Unit,
+    // key: txid + index, value: row of the first output of the transaction
+    unspents: SpillMap<u64>,
+    // key: row
+    spends: SpillMap<Spend>,
+
+    start_height: u64,
+    out_count: u64,
+    spent_count: u64,
+    // Inputs spending outputs created before the start height
+    earlier_inputs: u64,
+    // Inputs spending outputs which are not known although parsing started at the genesis block
+    missing_inputs: u64,
+    hash_order: HashOrder,
+}
+
+impl OutputsWithSpend {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    fn row_key(row: u64) -> Vec<u8> {
+        row.to_be_bytes().to_vec()
+    }
+
+    /// Records the spends of the block and writes the outputs it creates
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                for (i, input) in tx.value.inputs.iter().enumerate() {
+                    let first_row = match self.unspents.remove(&input.outpoint.to_bytes())? {
+                        Some(first_row) => first_row,
+                        None if self.start_height > 0 => {
+                            self.earlier_inputs += 1;
+                            continue;
+                        }
+                        None => {
+                            warn!(target: "callback", "Transaction {} at height {} spends unknown output {}:{}.",
+                                  &tx.hash, block_height, &input.outpoint.txid, input.outpoint.index);
+                            self.missing_inputs += 1;
+                            continue;
+                        }
+                    };
+                    let spend = Spend {
+                        txid: tx.hash,
+                        index: i as u32,
+                        block_height,
+                    };
+                    let row = first_row + input.outpoint.index as u64;
+                    self.spends.insert(OutputsWithSpend::row_key(row), spend)?;
+                    self.spent_count += 1;
+                }
+            }
+            let first_row = self.out_count;
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                self.writer.write_all(
+                    format!(
+                        "{};{};{};{};{}\n",
+                        self.hash_order.fmt(&tx.hash),
+                        i,
+                        block_height,
+                        self.unit.format(output.out.value),
+                        output.script.address.as_deref().unwrap_or_default()
+                    )
+                    .as_bytes(),
+                )?;
+            }
+            self.out_count += tx.value.outputs.len() as u64;
+            common::insert_spendable_outputs(&mut self.unspents, tx, block_height, |_| first_row)?;
+        }
+        Ok(())
+    }
+
+    /// Appends the spend to every output row of the first pass
+    fn resolve_spends(&mut self, tmp_path: PathBuf, writer: &mut impl Write) -> OpResult<()> {
+        writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{}\n",
+                "txid",
+                "indexOut",
+                "height",
+                "value",
+                "address",
+                "spent",
+                "spendingTxid",
+                "indexIn",
+                "spendingHeight"
+            )
+            .as_bytes(),
+        )?;
+        let mut reader = BufReader::with_capacity(4000000, File::open(&tmp_path)?);
+        let mut line = Vec::new();
+        let mut row = 0;
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            line.pop();
+            writer.write_all(&line)?;
+            match self.spends.remove(&OutputsWithSpend::row_key(row))? {
+                Some(spend) => writer.write_all(
+                    format!(
+                        ";1;{};{};{}\n",
+                        self.hash_order.fmt(&spend.txid),
+                        spend.index,
+                        spend.block_height
+                    )
+                    .as_bytes(),
+                )?,
+                None => writer.write_all(b";0;;;\n")?,
+            }
+            line.clear();
+            row += 1;
+        }
+        fs::remove_file(tmp_path)?;
+        Ok(())
+    }
+}
+
+impl Callback for OutputsWithSpend {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("outputswithspend")
+            .about("Dumps all outputs with the transaction spending them, if any, to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(command)))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = OutputsWithSpend {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: OutputsWithSpend::create_writer(
+                4000000,
+                dump_folder.join("outputs.unresolved.tmp"),
+            )?,
+            unit: Unit::from_matches(matches),
+            unspents: SpillMap::from_matches(matches, "outputswithspend-unspents")?,
+            spends: SpillMap::from_matches(matches, "outputswithspend-spends")?,
+            start_height: 0,
+            out_count: 0,
+            spent_count: 0,
+            earlier_inputs: 0,
+            missing_inputs: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing outputswithspend with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.apply_block(block, block_height)
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        self.unspents.destroy()?;
+        info!(target: "callback", "Resolving the spends of {} outputs ...", self.out_count);
+        let tmp_path = self.dump_folder.join("outputs.csv.tmp");
+        let mut writer = OutputsWithSpend::create_writer(4000000, tmp_path.clone())?;
+        self.resolve_spends(self.dump_folder.join("outputs.unresolved.tmp"), &mut writer)?;
+        writer.flush()?;
+        self.spends.destroy()?;
+        fs::rename(
+            tmp_path,
+            self.dump_folder.as_path().join(format!(
+                "outputs-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped outputs from height {} to {}:\n\
+                                   \t-> outputs:      {:9}\n\
+                                   \t-> spent:        {:9}",
+             self.start_height, block_height, self.out_count, self.spent_count);
+        if self.earlier_inputs > 0 {
+            info!(target: "callback", "Ignored {} inputs spending outputs from before height {}.",
+                  self.earlier_inputs, self.start_height);
+        }
+        if self.missing_inputs > 0 {
+            warn!(target: "callback", "{} inputs spent unknown outputs, the dump is incomplete.", self.missing_inputs);
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self.missing_inputs {
+            0 => 0,
+            _ => EXIT_INCONSISTENT,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{RawTx, TxOutpoint};
+    use crate::common::testutil::{new_block, null_outpoint, TxBuilder};
+
+    // P2PKH script of 12higDjoCCNXSA95xZMWUdPvXNmkAduhWv
+    const P2PKH: &str = "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac";
+
+    fn new_tx(inputs: Vec<TxOutpoint>, scripts: &[(u64, &str)]) -> RawTx {
+        let outputs = scripts
+            .iter()
+            .map(|(value, script)| (*value, crate::common::utils::hex_to_vec(script)));
+        TxBuilder::new().inputs(inputs).pays(outputs).build()
+    }
+
+    fn coinbase(height: u8, values: &[u64]) -> RawTx {
+        let scripts = values
+            .iter()
+            .map(|value| (*value, P2PKH))
+            .collect::<Vec<(u64, &str)>>();
+        let mut tx = new_tx(vec![null_outpoint()], &scripts);
+        // Keeps the coinbase txids apart (BIP34)
+        tx.inputs[0].script_sig = vec![0x01, height];
+        tx
+    }
+
+    fn new_callback(dump_folder: &std::path::Path, spill_threshold: usize) -> OutputsWithSpend {
+        let spill_path =
+            |name: &str| Some(dump_folder.join(name)).filter(|_| spill_threshold < usize::MAX);
+        OutputsWithSpend {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: OutputsWithSpend::create_writer(
+                100,
+                dump_folder.join("outputs.unresolved.tmp"),
+            )
+            .unwrap(),
+            unit: Unit::Sat,
+            unspents: SpillMap::new(spill_path("unspents"), spill_threshold).unwrap(),
+            spends: SpillMap::new(spill_path("spends"), spill_threshold).unwrap(),
+            start_height: 0,
+            out_count: 0,
+            spent_count: 0,
+            earlier_inputs: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        }
+    }
+
+    #[test]
+    fn test_outputs_with_spend() {
+        // Spills all spends to disk before they are resolved
+        for spill_threshold in [usize::MAX, 1] {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let mut cb = new_callback(tmp_dir.path(), spill_threshold);
+            cb.on_start(0).unwrap();
+
+            let block0 = new_block(vec![coinbase(0, &[49])]);
+            let genesis_txid = block0.txs[0].hash;
+            cb.apply_block(&block0, 0).unwrap();
+            let block1 = new_block(vec![coinbase(1, &[30, 20])]);
+            let coinbase_txid = block1.txs[0].hash;
+            cb.apply_block(&block1, 1).unwrap();
+
+            // Spends the second coinbase output, the change is spent within the same block
+            let op_return = "6a0401020304";
+            let spend = new_tx(
+                vec![TxOutpoint::new(coinbase_txid, 1)],
+                &[(0, op_return), (19, P2PKH)],
+            );
+            let block2 = new_block(vec![coinbase(2, &[50]), spend]);
+            let spend_txid = block2.txs[1].hash;
+            cb.apply_block(&block2, 2).unwrap();
+            let chained = new_tx(vec![TxOutpoint::new(spend_txid, 1)], &[(18, "51")]);
+            let block3 = new_block(vec![coinbase(3, &[50]), chained]);
+            let chained_txid = block3.txs[1].hash;
+            cb.apply_block(&block3, 3).unwrap();
+            assert_eq!(cb.exit_code(), 0);
+
+            // The genesis output can't be spent
+            let invalid = new_tx(vec![TxOutpoint::new(genesis_txid, 0)], &[(49, P2PKH)]);
+            cb.apply_block(&new_block(vec![invalid]), 4).unwrap();
+            assert_eq!(cb.missing_inputs, 1);
+            assert_eq!(cb.exit_code(), EXIT_INCONSISTENT);
+
+            cb.on_complete(4).unwrap();
+            assert_eq!(cb.out_count, 9);
+            assert_eq!(cb.spent_count, 2);
+            assert!(!tmp_dir.path().join("outputs.unresolved.tmp").exists());
+            assert!(!tmp_dir.path().join("spends").exists());
+
+            let dump = fs::read_to_string(tmp_dir.path().join("outputs-0-4.csv")).unwrap();
+            let address = "12higDjoCCNXSA95xZMWUdPvXNmkAduhWv";
+            assert_eq!(
+                dump.lines().collect::<Vec<&str>>()[..8],
+                [
+                    "txid;indexOut;height;value;address;spent;spendingTxid;indexIn;spendingHeight",
+                    &format!("{};0;0;49;{};0;;;", genesis_txid, address),
+                    &format!("{};0;1;30;{};0;;;", coinbase_txid, address),
+                    &format!("{};1;1;20;{};1;{};0;2", coinbase_txid, address, spend_txid),
+                    &format!("{};0;2;50;{};0;;;", block2.txs[0].hash, address),
+                    &format!("{};0;2;0;;0;;;", spend_txid),
+                    &format!("{};1;2;19;{};1;{};0;3", spend_txid, address, chained_txid),
+                    &format!("{};0;3;50;{};0;;;", block3.txs[0].hash, address),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_start_within_chain() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = new_callback(tmp_dir.path(), usize::MAX);
+        cb.on_start(5).unwrap();
+        let earlier = new_tx(
+            vec![TxOutpoint::new(sha256d::Hash::all_zeros(), 0)],
+            &[(10, P2PKH)],
+        );
+        cb.apply_block(&new_block(vec![coinbase(5, &[50]), earlier]), 5)
+            .unwrap();
+        cb.on_complete(5).unwrap();
+        // Outputs created before the start height are not an inconsistency
+        assert_eq!(cb.earlier_inputs, 1);
+        assert_eq!(cb.exit_code(), 0);
+        let dump = fs::read_to_string(tmp_dir.path().join("outputs-5-5.csv")).unwrap();
+        assert_eq!(dump.lines().count(), 3);
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::{self, Block};
+use crate::callbacks::common::{self, Unit};
+use crate::callbacks::Callback;
+use crate::errors::OpResult;
+
+const SECONDS_PER_HOUR: u64 = 3_600;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Aggregates of the blocks with a timestamp within one period
+#[derive(Default)]
+struct Bucket {
+    blocks: u64,
+    txs: u64,
+    output_value: u64,
+    fees: u64,
+    // Sum of the timestamp differences to the previous block, negative ones included
+    interval_sum: i64,
+    intervals: u64,
+}
+
+/// Dumps aggregates of the blocks per UTC day or hour of their header timestamp.
+///
+/// Timestamps are not strictly ascending, so a block may belong to a period before the one of
+/// its parent. A period is only written once the median-time-past (BIP113) passed its end:
+/// every later block has a timestamp above its median-time-past, which never decreases.
+pub struct PeriodStats {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    period: u64,
+    unit: Unit,
+    // Open periods by their start
+    buckets: BTreeMap<u64, Bucket>,
+    // End of the last written period
+    written_until: u64,
+    last_timestamp: Option<u32>,
+
+    start_height: u64,
+    rows: u64,
+    // Blocks whose period was already written, counted in the first open period instead
+    late_blocks: u64,
+}
+
+impl PeriodStats {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Formats the start of the period as UTC date, with the hour for hourly periods
+    fn label(&self, start: u64) -> String {
+        let datetime = NaiveDateTime::from_timestamp_opt(start as i64, 0).unwrap();
+        match self.period {
+            SECONDS_PER_DAY => datetime.format("%F").to_string(),
+            _ => datetime.format("%F %H:00").to_string(),
+        }
+    }
+
+    fn as_csv(&self, start: u64, bucket: &Bucket) -> String {
+        let avg_interval = match bucket.intervals {
+            0 => String::new(),
+            n => format!("{:.2}", bucket.interval_sum as f64 / n as f64),
+        };
+        format!(
+            "{};{};{};{};{};{};{}\n",
+            self.label(start),
+            start,
+            bucket.blocks,
+            bucket.txs,
+            self.unit.format(bucket.output_value),
+            self.unit.format(bucket.fees),
+            avg_interval
+        )
+    }
+
+    /// Adds the block to the period of its timestamp
+    fn apply_block(&mut self, block: &Block, block_height: u64) {
+        let timestamp = block.header.value.timestamp;
+        let mut start = timestamp as u64 - timestamp as u64 % self.period;
+        if start < self.written_until {
+            warn!(target: "callback", "Block {} at height {} belongs to the already written period {}, counting it in the next one.",
+                  &block.header.hash, block_height, self.label(start));
+            start = self.written_until;
+            self.late_blocks += 1;
+        }
+
+        let mut output_value = 0u64;
+        let mut claimed = 0u64;
+        for tx in &block.txs {
+            let value: u64 = tx.value.outputs.iter().map(|o| o.out.value).sum();
+            output_value += value;
+            if tx.value.is_coinbase() {
+                claimed += value;
+            }
+        }
+        let bucket = self.buckets.entry(start).or_default();
+        bucket.blocks += 1;
+        bucket.txs += block.tx_count.value;
+        bucket.output_value += output_value;
+        bucket.fees += claimed.saturating_sub(block::get_base_reward(block_height));
+        if let Some(last) = self.last_timestamp {
+            bucket.interval_sum += timestamp as i64 - last as i64;
+            bucket.intervals += 1;
+        }
+        self.last_timestamp = Some(timestamp);
+    }
+
+    /// Writes all periods ending at or before `until`
+    fn flush(&mut self, until: u64) -> OpResult<()> {
+        while let Some((&start, _)) = self.buckets.first_key_value() {
+            if start + self.period > until {
+                break;
+            }
+            let bucket = self.buckets.remove(&start).unwrap();
+            let row = self.as_csv(start, &bucket);
+            self.writer.write_all(row.as_bytes())?;
+            self.written_until = start + self.period;
+            self.rows += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for PeriodStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("periodstats")
+            .about("Dumps block, transaction, value and fee aggregates per day or hour to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("PERIOD")
+                    .value_parser(["day", "hour"])
+                    .default_value("day")
+                    .help("Length of the UTC periods the blocks are grouped by"),
+            );
+        common::add_overwrite_arg(common::add_unit_arg(command))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let period = match matches.get_one::<String>("period").map(String::as_str) {
+            Some("hour") => SECONDS_PER_HOUR,
+            _ => SECONDS_PER_DAY,
+        };
+        let cb = PeriodStats {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: PeriodStats::create_writer(4000000, dump_folder.join("periodstats.csv.tmp"))?,
+            period,
+            unit: Unit::from_matches(matches),
+            buckets: BTreeMap::new(),
+            written_until: 0,
+            last_timestamp: None,
+            start_height: 0,
+            rows: 0,
+            late_blocks: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing periodstats with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{}\n",
+                "period", "start", "blocks", "txs", "output_value", "fees", "avg_interval"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.apply_block(block, block_height);
+        if let Some(median_time_past) = block.median_time_past {
+            self.flush(median_time_past as u64)?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.flush(u64::MAX)?;
+        self.writer.flush()?;
+        fs::rename(
+            self.dump_folder.as_path().join("periodstats.csv.tmp"),
+            self.dump_folder.as_path().join(format!(
+                "periodstats-{}-{}.csv",
+                self.start_height, block_height
+            )),
+        )?;
+        info!(target: "callback", "Done.\nDumped periods from height {} to {}:\n\
+                                   \t-> periods:      {:9}",
+             self.start_height, block_height, self.rows);
+        if self.late_blocks > 0 {
+            warn!(target: "callback", "{} blocks were counted in a later period than their timestamp.", self.late_blocks);
+        }
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutpoint;
+    use crate::common::testutil::{coinbase, new_tx, BlockBuilder};
+    use bitcoin::hashes::{sha256d, Hash};
+
+    const COIN: u64 = 100_000_000;
+    // 2009-01-03 18:15:05 UTC, the timestamp of the genesis block
+    const GENESIS_TIME: u32 = 1231006505;
+
+    /// Block with a coinbase claiming `claimed` and a transaction paying `value`
+    fn new_block(timestamp: u32, median_time_past: u32, claimed: u64, value: u64) -> Block {
+        let spend = new_tx(
+            vec![TxOutpoint::new(sha256d::Hash::hash(b"prev"), 0)],
+            &[value],
+        );
+        let mut block = BlockBuilder::new()
+            .timestamp(timestamp)
+            .txs(vec![coinbase(&[claimed]), spend])
+            .build();
+        block.median_time_past = Some(median_time_past);
+        block
+    }
+
+    fn new_callback(dump_folder: &std::path::Path, period: u64) -> PeriodStats {
+        PeriodStats {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: PeriodStats::create_writer(100, dump_folder.join("periodstats.csv.tmp"))
+                .unwrap(),
+            period,
+            unit: Unit::Sat,
+            buckets: BTreeMap::new(),
+            written_until: 0,
+            last_timestamp: None,
+            start_height: 0,
+            rows: 0,
+            late_blocks: 0,
+        }
+    }
+
+    #[test]
+    fn test_period_stats() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = new_callback(tmp_dir.path(), SECONDS_PER_DAY);
+        cb.on_start(0).unwrap();
+
+        // The second block is on the next day, the third one goes back before midnight
+        let midnight = 1231027200;
+        let blocks = [
+            new_block(midnight - 600, midnight - 1200, 50 * COIN, COIN),
+            new_block(midnight + 300, midnight - 900, 50 * COIN + 10, 2),
+            new_block(midnight - 100, midnight - 600, 50 * COIN, 3),
+        ];
+        for (height, block) in blocks.iter().enumerate() {
+            cb.on_block(block, height as u64).unwrap();
+        }
+        // The median-time-past hasn't passed midnight, both days are open
+        assert_eq!(cb.rows, 0);
+        assert_eq!(cb.buckets.len(), 2);
+        cb.on_block(&new_block(midnight + 900, midnight + 300, 50 * COIN, 4), 3)
+            .unwrap();
+        assert_eq!(cb.rows, 1);
+        assert_eq!(cb.written_until, midnight as u64);
+
+        // Violates the rule of the median-time-past, counted in the open day
+        cb.on_block(&new_block(midnight - 50, midnight + 300, 50 * COIN, 5), 4)
+            .unwrap();
+        assert_eq!(cb.late_blocks, 1);
+
+        cb.on_complete(4).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("periodstats-0-4.csv")).unwrap();
+        assert_eq!(
+            dump.lines().collect::<Vec<&str>>(),
+            vec![
+                "period;start;blocks;txs;output_value;fees;avg_interval",
+                &format!(
+                    "2009-01-03;{};2;4;{};0;-400.00",
+                    midnight - 86400,
+                    100 * COIN + COIN + 3
+                ),
+                // Intervals of 900, 1000 and -950 seconds
+                &format!(
+                    "2009-01-04;{};3;6;{};10;316.67",
+                    midnight,
+                    150 * COIN + 10 + 2 + 4 + 5
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hourly_label() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = new_callback(tmp_dir.path(), SECONDS_PER_HOUR);
+        cb.apply_block(&new_block(GENESIS_TIME, 0, 50 * COIN, 0), 0);
+        let (start, bucket) = cb.buckets.first_key_value().unwrap();
+        assert_eq!(*start, 1231005600);
+        assert_eq!(
+            cb.as_csv(*start, bucket),
+            format!("2009-01-03 18:00;1231005600;1;2;{};0;\n", 50 * COIN)
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Concatenated output of --single-file, the index maps each block to its offset
+struct SingleFile {
+    data_writer: BufWriter<File>,
+    index_writer: BufWriter<File>,
+    offset: u64,
+}
+
+/// Writes the serialized blocks as stored in the blk files,
+/// either to one file per block or to a single file with an index.
+pub struct RawBlocks {
+    dump_folder: PathBuf,
+    single_file: Option<SingleFile>,
+
+    start_height: u64,
+    block_count: u64,
+    hash_order: HashOrder,
+}
+
+impl RawBlocks {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    fn with_folder(
+        dump_folder: PathBuf,
+        single_file: bool,
+        hash_order: HashOrder,
+    ) -> OpResult<Self> {
+        let single_file = match single_file {
+            true => Some(SingleFile {
+                data_writer: RawBlocks::create_writer(4000000, dump_folder.join("blocks.dat.tmp"))?,
+                index_writer: RawBlocks::create_writer(100000, dump_folder.join("blocks.csv.tmp"))?,
+                offset: 0,
+            }),
+            false => None,
+        };
+        Ok(RawBlocks {
+            dump_folder,
+            single_file,
+            start_height: 0,
+            block_count: 0,
+            hash_order,
+        })
+    }
+}
+
+impl Callback for RawBlocks {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("rawblocks")
+            .about("Writes the serialized blocks to binary files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store the block files")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("single-file")
+                    .long("single-file")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Concatenates all blocks into one file with a csv index of offsets"),
+            );
+        common::add_overwrite_arg(command)
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = common::dump_folder_from_matches(matches)?;
+        RawBlocks::with_folder(
+            dump_folder,
+            matches.get_flag("single-file"),
+            common::hash_order_from_matches(matches),
+        )
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing rawblocks with dump folder: {} ...", &self.dump_folder.display());
+        if let Some(ref mut single_file) = self.single_file {
+            single_file.index_writer.write_all(
+                format!("{};{};{};{}\n", "height", "hash", "offset", "size").as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let raw = block.raw.as_ref().ok_or_else(|| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&format!(
+                "Block at height {} has no raw data.",
+                block_height
+            ))
+        })?;
+        match self.single_file {
+            Some(ref mut single_file) => {
+                single_file.data_writer.write_all(raw)?;
+                single_file.index_writer.write_all(
+                    format!(
+                        "{};{};{};{}\n",
+                        block_height,
+                        self.hash_order.fmt(&block.header.hash),
+                        single_file.offset,
+                        raw.len()
+                    )
+                    .as_bytes(),
+                )?;
+                single_file.offset += raw.len() as u64;
+            }
+            None => {
+                let name = format!(
+                    "{}_{}.blk",
+                    block_height,
+                    self.hash_order.fmt(&block.header.hash)
+                );
+                fs::write(self.dump_folder.join(name), raw)?;
+            }
+        }
+        self.block_count += 1;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        if let Some(ref mut single_file) = self.single_file {
+            single_file.data_writer.flush()?;
+            single_file.index_writer.flush()?;
+            for ext in ["dat", "csv"] {
+                fs::rename(
+                    self.dump_folder.join(format!("blocks.{}.tmp", ext)),
+                    self.dump_folder.join(format!(
+                        "blocks-{}-{}.{}",
+                        self.start_height, block_height, ext
+                    )),
+                )?;
+            }
+        }
+        info!(target: "callback", "Done.\nWrote {} raw blocks from height {} to {}.",
+             self.block_count, self.start_height, block_height);
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_full_data(&self) -> bool {
+        false
+    }
+
+    fn needs_raw_data(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testutil::BlockBuilder;
+
+    fn new_block(nonce: u32, raw: Vec<u8>) -> Block {
+        let mut block = BlockBuilder::new()
+            .size(raw.len() as u32)
+            .nonce(nonce)
+            .build();
+        block.raw = Some(raw);
+        block
+    }
+
+    #[test]
+    fn test_rawblocks() {
+        let blocks = [new_block(0, vec![1, 2, 3]), new_block(1, vec![4, 5])];
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb =
+            RawBlocks::with_folder(PathBuf::from(tmp_dir.path()), false, HashOrder::Display)
+                .unwrap();
+        cb.on_start(10).unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            cb.on_block(block, 10 + i as u64).unwrap();
+        }
+        cb.on_complete(11).unwrap();
+        let path = tmp_dir
+            .path()
+            .join(format!("11_{}.blk", &blocks[1].header.hash));
+        assert_eq!(fs::read(path).unwrap(), vec![4, 5]);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb =
+            RawBlocks::with_folder(PathBuf::from(tmp_dir.path()), true, HashOrder::Display)
+                .unwrap();
+        cb.on_start(10).unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            cb.on_block(block, 10 + i as u64).unwrap();
+        }
+        cb.on_complete(11).unwrap();
+        assert_eq!(
+            fs::read(tmp_dir.path().join("blocks-10-11.dat")).unwrap(),
+            vec![1, 2, 3, 4, 5]
+        );
+        let index = fs::read_to_string(tmp_dir.path().join("blocks-10-11.csv")).unwrap();
+        assert_eq!(
+            index,
+            format!(
+                "height;hash;offset;size\n10;{};0;3\n11;{};3;2\n",
+                &blocks[0].header.hash, &blocks[1].header.hash
+            )
+        );
+
+        // Blocks are only kept as raw data if the callback asks for it
+        let mut block = new_block(2, vec![]);
+        block.raw = None;
+        assert!(cb.on_block(&block, 12).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use clap::{ArgMatches, Command};
+
+use crate::callbacks::addrfilter::AddrFilter;
+use crate::callbacks::balances::Balances;
+use crate::callbacks::blockindex::BlockIndex;
+use crate::callbacks::clusters::Clusters;
+use crate::callbacks::coinbase::Coinbase;
+use crate::callbacks::coindays::CoinDays;
+use crate::callbacks::csvdump::CsvDump;
+use crate::callbacks::feerates::FeeRates;
+use crate::callbacks::headers::Headers;
+use crate::callbacks::jsondump::JsonDump;
+use crate::callbacks::merkleproof::MerkleProof;
+use crate::callbacks::opreturn::OpReturn;
+use crate::callbacks::outputswithspend::OutputsWithSpend;
+use crate::callbacks::periodstats::PeriodStats;
+use crate::callbacks::rawblocks::RawBlocks;
+use crate::callbacks::retarget::Retarget;
+use crate::callbacks::segwitstats::SegwitStats;
+use crate::callbacks::simplestats::SimpleStats;
+use crate::callbacks::spentoutputs::SpentOutputs;
+use crate::callbacks::sqlite::Sqlite;
+use crate::callbacks::supply::Supply;
+use crate::callbacks::unspentcsvdump::UnspentCsvDump;
+use crate::callbacks::utxodump::UtxoDump;
+use crate::callbacks::Callback;
+use crate::errors::OpResult;
+
+/// Instantiates a callback from the matches of its subcommand
+pub type NewCallback = fn(&ArgMatches) -> OpResult<Box<dyn Callback>>;
+
+struct Registration {
+    name: String,
+    build_subcommand: fn() -> Command,
+    new: NewCallback,
+}
+
+/// Callbacks which can be selected as subcommand, in the order they are listed by --help.
+///
+/// Crates adding their own callbacks register them along with the built-in ones:
+/// ```
+/// use rusty_blockparser::callbacks::registry::CallbackRegistry;
+/// use rusty_blockparser::callbacks::simplestats::SimpleStats;
+///
+/// let mut registry = CallbackRegistry::with_builtin();
+/// registry.register::<SimpleStats>();
+/// let command = clap::Command::new("parser").subcommands(registry.subcommands());
+/// let matches = command.get_matches_from(["parser", "simplestats"]);
+/// let (name, sub_matches) = matches.subcommand().unwrap();
+/// assert!(registry.instantiate(name, sub_matches).unwrap().is_ok());
+/// ```
+#[derive(Default)]
+pub struct CallbackRegistry {
+    registrations: Vec<Registration>,
+}
+
+fn new_callback<C: Callback + 'static>(matches: &ArgMatches) -> OpResult<Box<dyn Callback>> {
+    Ok(Box::new(C::new(matches)?))
+}
+
+impl CallbackRegistry {
+    /// Returns a registry with all callbacks of this crate
+    pub fn with_builtin() -> Self {
+        let mut registry = CallbackRegistry::default();
+        registry
+            .register::<UnspentCsvDump>()
+            .register::<CsvDump>()
+            .register::<JsonDump>()
+            .register::<SimpleStats>()
+            .register::<Balances>()
+            .register::<OpReturn>()
+            .register::<UtxoDump>()
+            .register::<Sqlite>()
+            .register::<Coinbase>()
+            .register::<AddrFilter>()
+            .register::<Supply>()
+            .register::<BlockIndex>()
+            .register::<RawBlocks>()
+            .register::<FeeRates>()
+            .register::<SpentOutputs>()
+            .register::<OutputsWithSpend>()
+            .register::<CoinDays>()
+            .register::<Clusters>()
+            .register::<MerkleProof>()
+            .register::<PeriodStats>()
+            .register::<SegwitStats>()
+            .register::<Retarget>()
+            .register::<Headers>();
+        registry
+    }
+
+    /// Adds the callback under the name of its subcommand.
+    /// A callback registered under the same name before is replaced in place.
+    pub fn register<C: Callback + 'static>(&mut self) -> &mut Self {
+        let registration = Registration {
+            name: C::build_subcommand().get_name().to_string(),
+            build_subcommand: C::build_subcommand,
+            new: new_callback::<C>,
+        };
+        match self
+            .registrations
+            .iter_mut()
+            .find(|r| r.name == registration.name)
+        {
+            Some(existing) => *existing = registration,
+            None => self.registrations.push(registration),
+        }
+        self
+    }
+
+    /// Names of all registered callbacks
+    pub fn names(&self) -> Vec<&str> {
+        self.registrations.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    /// Subcommands of all registered callbacks
+    pub fn subcommands(&self) -> Vec<Command> {
+        self.registrations
+            .iter()
+            .map(|r| (r.build_subcommand)())
+            .collect()
+    }
+
+    /// Instantiates the callback `name` with the matches of its subcommand.
+    /// Returns None if no callback is registered under the name.
+    pub fn instantiate(
+        &self,
+        name: &str,
+        matches: &ArgMatches,
+    ) -> Option<OpResult<Box<dyn Callback>>> {
+        self.registrations
+            .iter()
+            .find(|r| r.name == name)
+            .map(|r| (r.new)(matches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::block::Block;
+
+    /// Replaces simplestats, told apart by its exit code
+    struct Custom;
+
+    impl Callback for Custom {
+        fn build_subcommand() -> Command {
+            Command::new("simplestats").about("Custom stats")
+        }
+
+        fn new(_: &ArgMatches) -> OpResult<Self> {
+            Ok(Custom)
+        }
+
+        fn on_start(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_block(&mut self, _: &Block, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_complete(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn exit_code(&self) -> i32 {
+            11
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = CallbackRegistry::with_builtin();
+        let names = registry.names();
+        assert_eq!(names[..3], ["unspentcsvdump", "csvdump", "jsondump"]);
+        assert_eq!(names.len(), 23);
+        assert_eq!(registry.subcommands().len(), names.len());
+        let matches = ArgMatches::default();
+        assert!(registry.instantiate("validate", &matches).is_none());
+
+        // Registering a name again keeps its position
+        registry.register::<Custom>();
+        assert_eq!(registry.names().len(), 23);
+        assert_eq!(registry.names()[3], "simplestats");
+        assert_eq!(
+            registry.subcommands()[3].get_about().unwrap().to_string(),
+            "Custom stats"
+        );
+        let callback = registry.instantiate("simplestats", &matches).unwrap();
+        assert_eq!(callback.unwrap().exit_code(), 11);
+    }
+}
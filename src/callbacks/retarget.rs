@@ -0,0 +1,351 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use bitcoin::hashes::{sha256d, Hash};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::HashOrder;
+use crate::errors::OpResult;
+
+/// Blocks per difficulty period
+const RETARGET_INTERVAL: u64 = 2016;
+/// Expected duration of a period, two weeks
+const TARGET_TIMESPAN: i64 = 14 * 24 * 60 * 60;
+
+/// Header fields of the last block which are needed for the next one
+struct Previous {
+    height: u64,
+    timestamp: u32,
+    bits: u32,
+}
+
+/// Dumps the interval to the previous block for every block and an analysis of every
+/// difficulty retarget: the observed timespan of the period against two weeks and the resulting
+/// adjustment factor.
+///
+/// Like Bitcoin Core the timespan of a period is measured from its first to its last block,
+/// which covers 2015 intervals. Periods are only analysed if their first block was parsed.
+pub struct Retarget {
+    dump_folder: PathBuf,
+    interval_writer: BufWriter<File>,
+    retarget_writer: BufWriter<File>,
+
+    previous: Option<Previous>,
+    // Timestamp of the first block of the current period
+    period_start: Option<u32>,
+
+    start_height: u64,
+    retargets: u64,
+    hash_order: HashOrder,
+}
+
+impl Retarget {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Header with only the given nBits set, to decode them
+    fn header(bits: u32) -> BlockHeader {
+        BlockHeader {
+            version: 0,
+            prev_hash: sha256d::Hash::all_zeros(),
+            merkle_root: sha256d::Hash::all_zeros(),
+            timestamp: 0,
+            bits,
+            nonce: 0,
+        }
+    }
+
+    fn difficulty(bits: u32) -> String {
+        Retarget::header(bits)
+            .difficulty()
+            .map_or_else(String::new, |d| d.to_string())
+    }
+
+    fn interval_as_csv(
+        block: &Block,
+        block_height: u64,
+        previous: Option<&Previous>,
+        hash_order: HashOrder,
+    ) -> String {
+        let interval = match previous {
+            Some(previous) if previous.height + 1 == block_height => {
+                (block.header.value.timestamp as i64 - previous.timestamp as i64).to_string()
+            }
+            _ => String::new(),
+        };
+        format!(
+            "{};{};{};{};{:08x}\n",
+            block_height,
+            hash_order.fmt(&block.header.hash),
+            block.header.value.timestamp,
+            interval,
+            block.header.value.bits
+        )
+    }
+
+    /// Compares the period ending with `previous` to the target timespan.
+    /// The expected factor follows from the timespan clamped to a quarter and four times the
+    /// target, it is the one Bitcoin Core applies unless the target would exceed the maximum.
+    fn retarget_as_csv(
+        block: &Block,
+        block_height: u64,
+        period_start: u32,
+        previous: &Previous,
+        hash_order: HashOrder,
+    ) -> String {
+        let timespan = previous.timestamp as i64 - period_start as i64;
+        let clamped = timespan.clamp(TARGET_TIMESPAN / 4, TARGET_TIMESPAN * 4);
+        let expected_factor = TARGET_TIMESPAN as f64 / clamped as f64;
+        let old_difficulty = Retarget::header(previous.bits).difficulty();
+        let factor = match (old_difficulty, block.header.value.difficulty()) {
+            (Some(old), Some(new)) => format!("{:.6}", new / old),
+            _ => String::new(),
+        };
+        format!(
+            "{};{};{};{};{};{:.2};{:.6};{:08x};{:08x};{};{};{}\n",
+            block_height,
+            hash_order.fmt(&block.header.hash),
+            block_height - RETARGET_INTERVAL,
+            timespan,
+            TARGET_TIMESPAN,
+            timespan as f64 / (RETARGET_INTERVAL - 1) as f64,
+            expected_factor,
+            previous.bits,
+            block.header.value.bits,
+            Retarget::difficulty(previous.bits),
+            Retarget::difficulty(block.header.value.bits),
+            factor
+        )
+    }
+}
+
+impl Callback for Retarget {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("retarget")
+            .about("Dumps block intervals and the difficulty adjustment of every retarget period to CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(command)
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = Retarget {
+            dump_folder: PathBuf::from(dump_folder),
+            interval_writer: Retarget::create_writer(
+                4000000,
+                dump_folder.join("intervals.csv.tmp"),
+            )?,
+            retarget_writer: Retarget::create_writer(
+                100000,
+                dump_folder.join("retargets.csv.tmp"),
+            )?,
+            previous: None,
+            period_start: None,
+            start_height: 0,
+            retargets: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing retarget with dump folder: {} ...", &self.dump_folder.display());
+        self.interval_writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                "height", "hash", "timestamp", "interval", "bits"
+            )
+            .as_bytes(),
+        )?;
+        self.retarget_writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{};{};{};{}\n",
+                "height",
+                "hash",
+                "period_start",
+                "timespan",
+                "target_timespan",
+                "avg_interval",
+                "expected_factor",
+                "old_bits",
+                "new_bits",
+                "old_difficulty",
+                "new_difficulty",
+                "factor"
+            )
+            .as_bytes(),
+        )?;
+        if !block_height.is_multiple_of(RETARGET_INTERVAL) {
+            info!(target: "callback", "Parsing starts within a retarget period, it is skipped until height {}.",
+                  block_height + RETARGET_INTERVAL - block_height % RETARGET_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let row =
+            Retarget::interval_as_csv(block, block_height, self.previous.as_ref(), self.hash_order);
+        self.interval_writer.write_all(row.as_bytes())?;
+
+        if block_height.is_multiple_of(RETARGET_INTERVAL) {
+            // The genesis block starts the first period without ending one
+            match (self.period_start, &self.previous) {
+                (Some(period_start), Some(previous)) if previous.height + 1 == block_height => {
+                    let row = Retarget::retarget_as_csv(
+                        block,
+                        block_height,
+                        period_start,
+                        previous,
+                        self.hash_order,
+                    );
+                    self.retarget_writer.write_all(row.as_bytes())?;
+                    self.retargets += 1;
+                }
+                (Some(_), _) => {
+                    warn!(target: "callback", "Skipping the retarget at height {}, the last block of its period is missing.", block_height)
+                }
+                _ => {}
+            }
+            self.period_start = Some(block.header.value.timestamp);
+        }
+        self.previous = Some(Previous {
+            height: block_height,
+            timestamp: block.header.value.timestamp,
+            bits: block.header.value.bits,
+        });
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.interval_writer.flush()?;
+        self.retarget_writer.flush()?;
+        for name in ["intervals", "retargets"] {
+            fs::rename(
+                self.dump_folder.as_path().join(format!("{}.csv.tmp", name)),
+                self.dump_folder.as_path().join(format!(
+                    "{}-{}-{}.csv",
+                    name, self.start_height, block_height
+                )),
+            )?;
+        }
+        info!(target: "callback", "Done.\nDumped block intervals from height {} to {}:\n\
+                                   \t-> retargets:    {:9}",
+             self.start_height, block_height, self.retargets);
+        Ok(())
+    }
+
+    fn needs_full_data(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::varuint::VarUint;
+
+    fn new_block(timestamp: u32, bits: u32) -> Block {
+        let mut header = Retarget::header(bits);
+        header.timestamp = timestamp;
+        Block::new(80, header, None, VarUint::from(0u8), vec![])
+    }
+
+    fn new_callback(dump_folder: &std::path::Path) -> Retarget {
+        Retarget {
+            dump_folder: PathBuf::from(dump_folder),
+            interval_writer: Retarget::create_writer(100, dump_folder.join("intervals.csv.tmp"))
+                .unwrap(),
+            retarget_writer: Retarget::create_writer(100, dump_folder.join("retargets.csv.tmp"))
+                .unwrap(),
+            previous: None,
+            period_start: None,
+            start_height: 0,
+            retargets: 0,
+            hash_order: HashOrder::Display,
+        }
+    }
+
+    #[test]
+    fn test_retarget() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = new_callback(tmp_dir.path());
+        cb.on_start(0).unwrap();
+
+        // The first period takes one week, the second one 8 weeks
+        let start: u32 = 1231006505;
+        let week: u32 = 7 * 24 * 60 * 60;
+        for height in 0..RETARGET_INTERVAL {
+            let timestamp = start + (week as u64 * height / (RETARGET_INTERVAL - 1)) as u32;
+            cb.on_block(&new_block(timestamp, 0x1d00ffff), height)
+                .unwrap();
+        }
+        cb.on_block(&new_block(start + week, 0x1c7fff80), 2016)
+            .unwrap();
+        cb.on_block(&new_block(start + 9 * week, 0x1c7fff80), 4031)
+            .unwrap();
+        cb.on_block(&new_block(start + 9 * week, 0x1c7fff80), 4032)
+            .unwrap();
+        cb.on_complete(4032).unwrap();
+        assert_eq!(cb.retargets, 2);
+
+        let intervals = fs::read_to_string(tmp_dir.path().join("intervals-0-4032.csv")).unwrap();
+        let lines = intervals.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "height;hash;timestamp;interval;bits");
+        assert!(lines[1].ends_with(";1231006505;;1d00ffff"));
+        assert!(lines[2].ends_with(";1231006805;300;1d00ffff"));
+        // Heights 2017 to 4030 are missing, so no interval is known
+        assert!(lines[2018].starts_with("4031;"));
+        assert!(lines[2018].ends_with(&format!(";{};;1c7fff80", start + 9 * week)));
+        assert!(lines[2019].ends_with(&format!(";{};0;1c7fff80", start + 9 * week)));
+
+        let retargets = fs::read_to_string(tmp_dir.path().join("retargets-0-4032.csv")).unwrap();
+        let lines = retargets.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("2016;"));
+        assert!(lines[1].ends_with(&format!(
+            ";0;{};1209600;300.15;2.000000;1d00ffff;1c7fff80;1;{};2.000000",
+            week,
+            Retarget::difficulty(0x1c7fff80)
+        )));
+        // Eight weeks are clamped to four times the target timespan
+        assert!(lines[2].ends_with(&format!(
+            ";2016;{};1209600;2401.19;0.250000;1c7fff80;1c7fff80;{};{};1.000000",
+            8 * week,
+            Retarget::difficulty(0x1c7fff80),
+            Retarget::difficulty(0x1c7fff80)
+        )));
+    }
+
+    #[test]
+    fn test_start_within_period() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = new_callback(tmp_dir.path());
+        cb.on_start(2000).unwrap();
+        for height in 2000..=2016 {
+            cb.on_block(&new_block(height as u32 * 600, 0x1d00ffff), height)
+                .unwrap();
+        }
+        // The period from height 0 wasn't parsed, the one from height 2016 is open
+        assert_eq!(cb.retargets, 0);
+        assert_eq!(cb.period_start, Some(2016 * 600));
+    }
+}
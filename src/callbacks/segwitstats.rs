@@ -0,0 +1,393 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use bitcoin::hashes::sha256d;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::common::{self, SpillMap, Spillable};
+use crate::callbacks::Callback;
+use crate::common::utils::HashOrder;
+use crate::errors::OpResult;
+
+/// Outputs which can be spent with witness data, all others are legacy outputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WitnessOutput {
+    /// Native witness program (P2WPKH, P2WSH, P2TR or a future version)
+    Native,
+    /// P2SH, which may wrap a witness program (BIP141)
+    ScriptHash,
+}
+
+impl WitnessOutput {
+    fn from_pattern(pattern: &ScriptPattern) -> Option<Self> {
+        match pattern {
+            ScriptPattern::Pay2WitnessPublicKeyHash
+            | ScriptPattern::Pay2WitnessScriptHash
+            | ScriptPattern::Pay2Taproot
+            | ScriptPattern::WitnessProgram => Some(WitnessOutput::Native),
+            ScriptPattern::Pay2ScriptHash => Some(WitnessOutput::ScriptHash),
+            _ => None,
+        }
+    }
+}
+
+impl ToRaw for WitnessOutput {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            WitnessOutput::Native => vec![0],
+            WitnessOutput::ScriptHash => vec![1],
+        }
+    }
+}
+
+impl Spillable for WitnessOutput {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        match bytes {
+            [0] => WitnessOutput::Native,
+            _ => WitnessOutput::ScriptHash,
+        }
+    }
+}
+
+/// Segwit usage of the transactions in a block, coinbase transactions are not counted
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Adoption {
+    txs: u64,
+    // Transactions with witness data
+    segwit_txs: u64,
+    inputs: u64,
+    // Inputs spending a native witness output
+    native_inputs: u64,
+    // Inputs spending a P2SH output with witness data (P2SH-P2WPKH or P2SH-P2WSH)
+    nested_inputs: u64,
+}
+
+impl Adoption {
+    fn add(&mut self, other: &Adoption) {
+        self.txs += other.txs;
+        self.segwit_txs += other.segwit_txs;
+        self.inputs += other.inputs;
+        self.native_inputs += other.native_inputs;
+        self.nested_inputs += other.nested_inputs;
+    }
+
+    /// Share of segwit transactions and of inputs spending segwit outputs in percent
+    fn shares(&self) -> (String, String) {
+        let percent = |part: u64, total: u64| match total {
+            0 => String::new(),
+            _ => format!("{:.2}", part as f64 * 100.0 / total as f64),
+        };
+        (
+            percent(self.segwit_txs, self.txs),
+            percent(self.native_inputs + self.nested_inputs, self.inputs),
+        )
+    }
+}
+
+/// Dumps the segwit adoption of each block: the share of transactions with witness data
+/// and the share of inputs spending native or P2SH wrapped witness outputs.
+///
+/// Only witness and P2SH outputs are kept to classify the inputs (see `--spill-dir`),
+/// inputs spending outputs created before the start height count as legacy.
+pub struct SegwitStats {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    // key: txid + index
+    witness_outputs: SpillMap<WitnessOutput>,
+
+    start_height: u64,
+    total: Adoption,
+    // First block with a witness commitment in its coinbase (BIP141)
+    first_commitment: Option<(u64, sha256d::Hash)>,
+    hash_order: HashOrder,
+}
+
+impl SegwitStats {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Classifies the transactions of the block and returns its adoption
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<Adoption> {
+        if self.first_commitment.is_none() && block.witness_commitment().is_some() {
+            info!(target: "callback", "Found the first witness commitment in block {} at height {}.",
+                  &block.header.hash, block_height);
+            self.first_commitment = Some((block_height, block.header.hash));
+        }
+        let mut adoption = Adoption::default();
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                adoption.txs += 1;
+                if tx.value.has_witness() {
+                    adoption.segwit_txs += 1;
+                }
+                for input in &tx.value.inputs {
+                    adoption.inputs += 1;
+                    match self.witness_outputs.remove(&input.outpoint.to_bytes())? {
+                        Some(WitnessOutput::Native) => adoption.native_inputs += 1,
+                        Some(WitnessOutput::ScriptHash) if !input.witness.is_empty() => {
+                            adoption.nested_inputs += 1
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            if common::is_unspendable_genesis_output(block_height) {
+                continue;
+            }
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                if let Some(kind) = WitnessOutput::from_pattern(&output.script.pattern) {
+                    let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
+                    self.witness_outputs.insert(key, kind)?;
+                }
+            }
+        }
+        self.total.add(&adoption);
+        Ok(adoption)
+    }
+
+    fn as_csv(
+        block: &Block,
+        block_height: u64,
+        adoption: &Adoption,
+        hash_order: HashOrder,
+    ) -> String {
+        let (tx_share, input_share) = adoption.shares();
+        format!(
+            "{};{};{};{};{};{};{};{};{};{}\n",
+            block_height,
+            hash_order.fmt(&block.header.hash),
+            block.header.value.timestamp,
+            adoption.txs,
+            adoption.segwit_txs,
+            adoption.inputs,
+            adoption.native_inputs,
+            adoption.nested_inputs,
+            tx_share,
+            input_share
+        )
+    }
+}
+
+impl Callback for SegwitStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("segwitstats")
+            .about("Dumps the share of segwit transactions and inputs per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_spill_args(command))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = SegwitStats {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: SegwitStats::create_writer(4000000, dump_folder.join("segwit.csv.tmp"))?,
+            witness_outputs: SpillMap::from_matches(matches, "segwitstats-spill")?,
+            start_height: 0,
+            total: Adoption::default(),
+            first_commitment: None,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing segwitstats with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{};{}\n",
+                "height",
+                "hash",
+                "timestamp",
+                "txs",
+                "segwit_txs",
+                "inputs",
+                "native_inputs",
+                "nested_inputs",
+                "segwit_tx_pct",
+                "segwit_input_pct"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let adoption = self.apply_block(block, block_height)?;
+        self.writer.write_all(
+            SegwitStats::as_csv(block, block_height, &adoption, self.hash_order).as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        self.witness_outputs.destroy()?;
+        fs::rename(
+            self.dump_folder.as_path().join("segwit.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(format!("segwit-{}-{}.csv", self.start_height, block_height)),
+        )?;
+        let (tx_share, input_share) = self.total.shares();
+        info!(target: "callback", "Done.\nDumped segwit adoption from height {} to {}:\n\
+                                   \t-> segwit transactions: {:>6} %\n\
+                                   \t-> segwit inputs:       {:>6} %",
+             self.start_height, block_height, tx_share, input_share);
+        match self.first_commitment {
+            Some((height, ref hash)) => {
+                info!(target: "callback", "First witness commitment in block {} at height {}.", hash, height)
+            }
+            None => info!(target: "callback", "No block contains a witness commitment."),
+        }
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::RawTx;
+    use crate::common::testutil::{null_outpoint, BlockBuilder, TxBuilder};
+
+    const P2WPKH: [u8; 22] = [
+        0x00, 0x14, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab,
+        0xab, 0xab, 0xab, 0xab, 0xab, 0xab, 0xab,
+    ];
+    const P2SH: [u8; 23] = [
+        0xa9, 0x14, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd,
+        0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0xcd, 0x87,
+    ];
+
+    fn new_tx(inputs: Vec<(TxOutpoint, bool)>, scripts: &[&[u8]]) -> RawTx {
+        let tx = inputs
+            .into_iter()
+            .fold(TxBuilder::new(), |tx, (outpoint, witness)| {
+                let tx = tx.input(outpoint).script_sig(vec![]);
+                match witness {
+                    true => tx.witness(vec![vec![0x01; 33]]),
+                    false => tx,
+                }
+            });
+        tx.pays(scripts.iter().map(|script| (1000, script.to_vec())))
+            .build()
+    }
+
+    fn coinbase(scripts: &[&[u8]]) -> RawTx {
+        new_tx(vec![(null_outpoint(), false)], scripts)
+    }
+
+    fn new_block(txs: Vec<RawTx>) -> Block {
+        BlockBuilder::new().timestamp(1500000000).txs(txs).build()
+    }
+
+    #[test]
+    fn test_witness_output_roundtrip() {
+        for kind in [WitnessOutput::Native, WitnessOutput::ScriptHash] {
+            assert_eq!(WitnessOutput::from_bytes(&kind.to_bytes()), kind);
+        }
+    }
+
+    #[test]
+    fn test_segwit_stats() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = SegwitStats {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: SegwitStats::create_writer(100, tmp_dir.path().join("segwit.csv.tmp")).unwrap(),
+            witness_outputs: SpillMap::new(None, 0).unwrap(),
+            start_height: 0,
+            total: Adoption::default(),
+            first_commitment: None,
+            hash_order: HashOrder::Display,
+        };
+        cb.on_start(1).unwrap();
+
+        let block1 = new_block(vec![coinbase(&[&P2WPKH, &P2SH, &P2SH, &[0x51]])]);
+        let coinbase_txid = block1.txs[0].hash;
+        cb.on_block(&block1, 1).unwrap();
+
+        // Native and nested segwit spends, a P2SH spend without witness and a legacy spend
+        let outpoint = |index| TxOutpoint::new(coinbase_txid, index);
+        let segwit = new_tx(
+            vec![
+                (outpoint(0), true),
+                (outpoint(1), true),
+                (outpoint(2), false),
+            ],
+            &[&[0x51]],
+        );
+        let legacy = new_tx(vec![(outpoint(3), false)], &[&[0x51]]);
+        let mut commitment = vec![0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+        commitment.extend_from_slice(&[0u8; 32]);
+        let block2 = new_block(vec![coinbase(&[&[0x51], &commitment]), segwit, legacy]);
+        let adoption = cb.apply_block(&block2, 2).unwrap();
+        assert_eq!(
+            adoption,
+            Adoption {
+                txs: 2,
+                segwit_txs: 1,
+                inputs: 4,
+                native_inputs: 1,
+                nested_inputs: 1,
+            }
+        );
+        assert_eq!(
+            SegwitStats::as_csv(&block2, 2, &adoption, HashOrder::Display),
+            format!(
+                "2;{};1500000000;2;1;4;1;1;50.00;50.00\n",
+                &block2.header.hash
+            )
+        );
+        assert_eq!(cb.first_commitment, Some((2, block2.header.hash)));
+        // Spent outputs are removed
+        assert!(cb
+            .witness_outputs
+            .remove(&outpoint(0).to_bytes())
+            .unwrap()
+            .is_none());
+
+        cb.on_block(&new_block(vec![coinbase(&[&[0x51]])]), 3)
+            .unwrap();
+        cb.on_complete(3).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("segwit-1-3.csv")).unwrap();
+        let rows = dump.lines().collect::<Vec<&str>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[0],
+            "height;hash;timestamp;txs;segwit_txs;inputs;native_inputs;nested_inputs;segwit_tx_pct;segwit_input_pct"
+        );
+        // Blocks without transactions besides the coinbase have no shares
+        assert!(rows[2].ends_with(";1500000000;0;0;0;0;0;;"));
+        assert_eq!(
+            cb.total.shares(),
+            (String::from("50.00"), String::from("50.00"))
+        );
+    }
+}
@@ -1,16 +1,146 @@
 use bitcoin::hashes::{sha256d, Hash};
-use std::collections::HashMap;
-use std::io::{self, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
 
-use clap::{ArgMatches, Command};
+use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::{self, Block};
-use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::script::{ScriptCategory, ScriptPattern};
+use crate::blockchain::proto::tx::EvaluatedTxOut;
 use crate::blockchain::proto::ToRaw;
-use crate::callbacks::Callback;
-use crate::common::utils;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::{self, HashOrder};
 use crate::errors::OpResult;
 
+/// Counts values in a fixed number of buckets, the last bucket holds all larger values.
+/// With a base, bucket 0 holds zero and bucket i the values from base^(i-1) up to base^i,
+/// without a base each bucket holds a single value.
+struct Histogram {
+    name: &'static str,
+    base: Option<u64>,
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    fn new(name: &'static str, base: Option<u64>, buckets: usize) -> Self {
+        Histogram {
+            name,
+            base,
+            counts: vec![0; buckets],
+        }
+    }
+
+    fn bucket(&self, value: u64) -> usize {
+        let last = self.counts.len() - 1;
+        match self.base {
+            None => (value as usize).min(last),
+            Some(_) if value == 0 => 0,
+            Some(base) => {
+                let mut i = 1;
+                let mut bound = base;
+                while value >= bound && i < last {
+                    i += 1;
+                    bound = bound.saturating_mul(base);
+                }
+                i
+            }
+        }
+    }
+
+    fn add(&mut self, value: u64) {
+        let i = self.bucket(value);
+        self.counts[i] += 1;
+    }
+
+    /// Smallest value of the bucket and the first value of the next one, if it is bounded
+    fn bounds(&self, i: usize) -> (u64, Option<u64>) {
+        let lower = match self.base {
+            None => i as u64,
+            Some(_) if i == 0 => 0,
+            Some(base) => base.saturating_pow(i as u32 - 1),
+        };
+        if i == self.counts.len() - 1 {
+            return (lower, None);
+        }
+        let upper = match self.base {
+            None => i as u64 + 1,
+            Some(base) => base.saturating_pow(i as u32),
+        };
+        (lower, Some(upper))
+    }
+
+    fn label(&self, i: usize) -> String {
+        match self.bounds(i) {
+            (lower, None) => format!("{}+", lower),
+            (lower, Some(upper)) if upper == lower + 1 => format!("{}", lower),
+            (lower, Some(upper)) => format!("{}-{}", lower, upper - 1),
+        }
+    }
+
+    /// Prints the buckets from the first to the last non-empty one as bars
+    fn print(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Histogram of {}:", self.name)?;
+        let first = self.counts.iter().position(|count| *count > 0);
+        let last = self.counts.iter().rposition(|count| *count > 0);
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(()),
+        };
+        let max = *self.counts.iter().max().unwrap();
+        for i in first..=last {
+            let width = (self.counts[i] as f64 / max as f64 * HISTOGRAM_WIDTH as f64).ceil();
+            writeln!(
+                buffer,
+                "   -> {:>21}: {:>12} {}",
+                self.label(i),
+                self.counts[i],
+                "#".repeat(width as usize)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (i, count) in self.counts.iter().enumerate() {
+            let (lower, upper) = self.bounds(i);
+            let upper = upper.map(|u| u.to_string()).unwrap_or_default();
+            writeln!(writer, "{};{};{};{}", self.name, lower, upper, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maximum bar length of the printed histograms
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// Default dust relay fee of Bitcoin Core in satoshis per 1000 bytes
+const DUST_RELAY_FEE: u64 = 3000;
+
+/// Returns the value below which Bitcoin Core considers the output dust:
+/// spending it would cost more than a third of its value at the dust relay fee.
+/// The cost is the size of the output plus the size of a typical input spending it,
+/// inputs spending witness programs are discounted. Unspendable outputs are never dust.
+fn dust_threshold(output: &EvaluatedTxOut) -> u64 {
+    let input_size = match output.script.pattern {
+        ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable => return 0,
+        ScriptPattern::Pay2WitnessPublicKeyHash
+        | ScriptPattern::Pay2WitnessScriptHash
+        | ScriptPattern::Pay2Taproot
+        | ScriptPattern::WitnessProgram => 32 + 4 + 1 + 107 / 4 + 4,
+        _ => 32 + 4 + 1 + 107 + 4,
+    };
+    let script_len = output.out.script_pubkey.len() as u64;
+    let varint_size = match script_len {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffffffff => 5,
+        _ => 9,
+    };
+    (8 + varint_size + script_len + input_size) * DUST_RELAY_FEE / 1000
+}
+
 pub struct SimpleStats {
     n_valid_blocks: u64,
     block_sizes: Vec<u32>,
@@ -20,6 +150,11 @@ pub struct SimpleStats {
     n_tx_outputs: u64,
     n_tx_total_fee: u64,
     n_tx_total_volume: u64,
+    /// Outputs without value, e.g. OP_RETURN outputs
+    n_zero_value_outputs: u64,
+    /// Outputs with a value below their dust threshold, and their total value
+    n_dust_outputs: u64,
+    dust_value: u64,
 
     /// Biggest value transaction (value, height, txid)
     tx_biggest_value: (u64, u64, sha256d::Hash),
@@ -30,10 +165,18 @@ pub struct SimpleStats {
     /// First occurence of transaction type
     /// (block_height, txid, index)
     tx_first_occs: HashMap<ScriptPattern, (u64, sha256d::Hash, u32)>,
+    /// Output count and total value per script category
+    script_categories: BTreeMap<ScriptCategory, (u64, u64)>,
+
+    /// Distributions of tx sizes, output values and input/output counts
+    histograms: [Histogram; 4],
+    /// Writes the bucket counts to this csv file if given
+    histogram_path: Option<PathBuf>,
 
     /// Time stats
     t_between_blocks: Vec<u32>,
     last_timestamp: u32,
+    hash_order: HashOrder,
 }
 
 impl Default for SimpleStats {
@@ -46,12 +189,26 @@ impl Default for SimpleStats {
             n_tx_outputs: 0,
             n_tx_total_fee: 0,
             n_tx_total_volume: 0,
+            n_zero_value_outputs: 0,
+            n_dust_outputs: 0,
+            dust_value: 0,
             tx_biggest_value: (0, 0, sha256d::Hash::all_zeros()),
             tx_biggest_size: (0, 0, sha256d::Hash::all_zeros()),
             n_tx_types: HashMap::new(),
             tx_first_occs: HashMap::new(),
+            script_categories: BTreeMap::new(),
+            histograms: [
+                // Up to 4 MiB, the maximum block weight
+                Histogram::new("tx sizes (bytes)", Some(2), 24),
+                // Up to 1E16 units, beyond the Bitcoin supply
+                Histogram::new("output values (units)", Some(10), 18),
+                Histogram::new("inputs per tx", None, 21),
+                Histogram::new("outputs per tx", None, 21),
+            ],
+            histogram_path: None,
             t_between_blocks: vec![],
             last_timestamp: 0,
+            hash_order: HashOrder::Display,
         }
     }
 }
@@ -80,6 +237,24 @@ impl SimpleStats {
         }
     }
 
+    /// Tallies outputs without value and dust outputs separately
+    fn process_output_value(&mut self, output: &EvaluatedTxOut) {
+        let value = output.out.value;
+        if value == 0 {
+            self.n_zero_value_outputs += 1;
+        } else if value < dust_threshold(output) {
+            self.n_dust_outputs += 1;
+            self.dust_value += value;
+        }
+    }
+
+    /// Tallies the output by category of its script
+    fn process_script_category(&mut self, category: ScriptCategory, value: u64) {
+        let (count, total) = self.script_categories.entry(category).or_insert((0, 0));
+        *count += 1;
+        *total += value;
+    }
+
     fn print_simple_stats(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         writeln!(buffer, "SimpleStats:")?;
         writeln!(buffer, "   -> valid blocks:\t\t{}", self.n_valid_blocks)?;
@@ -98,6 +273,20 @@ impl SimpleStats {
             self.n_tx_total_volume as f64 * 1E-8,
             self.n_tx_total_volume
         )?;
+        writeln!(
+            buffer,
+            "   -> zero-value outputs:\t{} ({:.2}%)",
+            self.n_zero_value_outputs,
+            (self.n_zero_value_outputs as f64 / self.n_tx_outputs as f64) * 100.00
+        )?;
+        writeln!(
+            buffer,
+            "   -> dust outputs:\t\t{} ({:.2}%), total value: {:.8} ({} units)",
+            self.n_dust_outputs,
+            (self.n_dust_outputs as f64 / self.n_tx_outputs as f64) * 100.00,
+            self.dust_value as f64 * 1E-8,
+            self.dust_value
+        )?;
         Ok(())
     }
 
@@ -133,6 +322,13 @@ impl SimpleStats {
             "   -> avg value per output:\t{:.2}",
             self.n_tx_total_volume as f64 / self.n_tx_outputs as f64 * 1E-8
         )?;
+        // Zero-value and dust outputs carry data or spam rather than payments
+        let n_monetary = self.n_tx_outputs - self.n_zero_value_outputs - self.n_dust_outputs;
+        writeln!(
+            buffer,
+            "   -> avg value per non-dust output:\t{:.2}",
+            (self.n_tx_total_volume - self.dust_value) as f64 / n_monetary as f64 * 1E-8
+        )?;
         Ok(())
     }
 
@@ -147,14 +343,16 @@ impl SimpleStats {
         writeln!(
             buffer,
             "        seen in block #{}, txid: {}\n",
-            height, &txid
+            height,
+            self.hash_order.fmt(&txid)
         )?;
         let (value, height, txid) = self.tx_biggest_size;
         writeln!(buffer, "   -> biggest size tx:\t\t{} bytes", value,)?;
         writeln!(
             buffer,
             "        seen in block #{}, txid: {}\n",
-            height, &txid
+            height,
+            self.hash_order.fmt(&txid)
         )?;
         Ok(())
     }
@@ -174,7 +372,42 @@ impl SimpleStats {
             writeln!(
                 buffer,
                 "        first seen in block #{}, txid: {}\n",
-                pos.0, &pos.1
+                pos.0,
+                self.hash_order.fmt(&pos.1)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_histograms(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        for histogram in &self.histograms {
+            writeln!(buffer)?;
+            histogram.print(buffer)?;
+        }
+        Ok(())
+    }
+
+    fn write_histograms(&self, path: &PathBuf) -> OpResult<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "histogram;lower;upper;count")?;
+        for histogram in &self.histograms {
+            histogram.write_csv(&mut writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn print_script_categories(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Script Categories:")?;
+        for (category, (count, value)) in &self.script_categories {
+            writeln!(
+                buffer,
+                "   -> {}: {} ({:.2}%), total value: {:.8} ({} units)",
+                category,
+                count,
+                (*count as f64 / self.n_tx_outputs as f64) * 100.00,
+                *value as f64 * 1E-8,
+                value
             )?;
         }
         Ok(())
@@ -190,13 +423,23 @@ impl Callback for SimpleStats {
             .about("Shows various Blockchain stats")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("histograms")
+                    .long("histograms")
+                    .value_name("FILE")
+                    .help("Writes the bucket counts of the histograms to a csv file"),
+            )
     }
 
-    fn new(_: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        Ok(SimpleStats::default())
+        Ok(SimpleStats {
+            histogram_path: matches.get_one::<String>("histograms").map(PathBuf::from),
+            hash_order: common::hash_order_from_matches(matches),
+            ..Default::default()
+        })
     }
 
     fn on_start(&mut self, _: u64) -> OpResult<()> {
@@ -215,16 +458,20 @@ impl Callback for SimpleStats {
                 self.n_tx_total_fee += tx.value.outputs[0]
                     .out
                     .value
-                    .checked_sub(block::get_base_reward(block_height))
-                    .unwrap_or_default();
+                    .saturating_sub(block::get_base_reward(block_height));
             }
 
             self.n_tx_inputs += tx.value.in_count.value;
             self.n_tx_outputs += tx.value.out_count.value;
+            self.histograms[2].add(tx.value.in_count.value);
+            self.histograms[3].add(tx.value.out_count.value);
 
             let mut tx_value = 0;
             for (i, o) in tx.value.outputs.iter().enumerate() {
                 self.process_tx_pattern(o.script.pattern.clone(), block_height, tx.hash, i as u32);
+                self.process_script_category(o.script.pattern.category(), o.out.value);
+                self.process_output_value(o);
+                self.histograms[1].add(o.out.value);
                 tx_value += o.out.value;
             }
             // Calculate and save biggest value transaction
@@ -236,6 +483,7 @@ impl Callback for SimpleStats {
 
             // Calculate and save biggest size transaction
             let tx_size = tx.value.to_bytes().len();
+            self.histograms[0].add(tx_size as u64);
             if tx_size > self.tx_biggest_size.0 {
                 self.tx_biggest_size = (tx_size, block_height, tx.hash);
             }
@@ -247,8 +495,7 @@ impl Callback for SimpleStats {
                 .header
                 .value
                 .timestamp
-                .checked_sub(self.last_timestamp)
-                .unwrap_or_default();
+                .saturating_sub(self.last_timestamp);
             self.t_between_blocks.push(diff);
         }
         self.last_timestamp = block.header.value.timestamp;
@@ -263,7 +510,107 @@ impl Callback for SimpleStats {
         self.print_averages(&mut buffer)?;
         writeln!(&mut buffer)?;
         self.print_transaction_types(&mut buffer)?;
+        self.print_script_categories(&mut buffer)?;
+        self.print_histograms(&mut buffer)?;
         info!(target: "simplestats", "\n\n{}", String::from_utf8_lossy(&buffer));
+        if let Some(ref path) = self.histogram_path {
+            self.write_histograms(path)?;
+            info!(target: "callback", "Wrote histograms to {}", path.display());
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::TxOutput;
+    use crate::blockchain::proto::varuint::VarUint;
+
+    fn new_output(value: u64, script: &str) -> EvaluatedTxOut {
+        let script_pubkey = utils::hex_to_vec(script);
+        EvaluatedTxOut::eval_script(
+            TxOutput {
+                value,
+                script_len: VarUint::from(script_pubkey.len() as u8),
+                script_pubkey,
+            },
+            0x00,
+        )
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut sizes = Histogram::new("sizes", Some(2), 5);
+        for value in [0, 1, 2, 3, 4, 7, 8, 1000] {
+            sizes.add(value);
+        }
+        assert_eq!(sizes.counts, vec![1, 1, 2, 2, 2]);
+        assert_eq!(sizes.label(0), "0");
+        assert_eq!(sizes.label(1), "1");
+        assert_eq!(sizes.label(3), "4-7");
+        assert_eq!(sizes.label(4), "8+");
+
+        let mut counts = Histogram::new("counts", None, 3);
+        for value in [0, 1, 1, 2, 5] {
+            counts.add(value);
+        }
+        assert_eq!(counts.counts, vec![1, 2, 2]);
+        assert_eq!(counts.label(1), "1");
+        assert_eq!(counts.label(2), "2+");
+
+        let mut csv = Vec::new();
+        counts.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "counts;0;1;1\ncounts;1;2;2\ncounts;2;;2\n"
+        );
+
+        let mut buffer = Vec::new();
+        counts.print(&mut buffer).unwrap();
+        let printed = String::from_utf8(buffer).unwrap();
+        assert_eq!(printed.lines().count(), 4);
+        assert!(printed
+            .lines()
+            .nth(2)
+            .unwrap()
+            .ends_with(&"#".repeat(HISTOGRAM_WIDTH)));
+    }
+
+    #[test]
+    fn test_histogram_bounds_saturate() {
+        let mut values = Histogram::new("values", Some(10), 18);
+        values.add(u64::MAX);
+        assert_eq!(values.counts[17], 1);
+        assert_eq!(values.bounds(17), (10u64.pow(16), None));
+        assert_eq!(values.label(2), "10-99");
+    }
+
+    #[test]
+    fn test_dust() {
+        let p2pkh = "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac";
+        let p2wpkh = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+        let p2tr = format!("5120{}", "11".repeat(32));
+        // Same thresholds as Bitcoin Core
+        assert_eq!(dust_threshold(&new_output(0, p2pkh)), 546);
+        assert_eq!(dust_threshold(&new_output(0, p2wpkh)), 294);
+        assert_eq!(dust_threshold(&new_output(0, &p2tr)), 330);
+        assert_eq!(dust_threshold(&new_output(0, "6a0102")), 0);
+
+        let mut stats = SimpleStats::default();
+        for output in [
+            new_output(0, "6a0102"),
+            new_output(0, p2pkh),
+            new_output(545, p2pkh),
+            new_output(546, p2pkh),
+            new_output(300, p2wpkh),
+            new_output(329, &p2tr),
+            new_output(1, "6a0102"),
+        ] {
+            stats.process_output_value(&output);
+        }
+        assert_eq!(stats.n_zero_value_outputs, 2);
+        assert_eq!(stats.n_dust_outputs, 2);
+        assert_eq!(stats.dust_value, 545 + 329);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::common::{self, SpillMap, Unit, UnspentValue};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Dumps every output which is spent during the parse along with the transaction spending it,
+/// both ends with txid and height, for coin age and dormancy analysis.
+///
+/// Unspent outputs are kept to match spends to their creation (see `--spill-dir`),
+/// outputs without a decodable address are kept with an empty address.
+pub struct SpentOutputs {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    unit: Unit,
+    // key: txid + index
+    unspents: SpillMap<UnspentValue>,
+
+    start_height: u64,
+    spent_count: u64,
+    out_count: u64,
+    // Inputs spending outputs which are not known
+    missing_inputs: u64,
+    hash_order: HashOrder,
+}
+
+impl SpentOutputs {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Writes a row for each output spent by the block and records the outputs it creates
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                for (i, input) in tx.value.inputs.iter().enumerate() {
+                    let spent = match self.unspents.remove(&input.outpoint.to_bytes())? {
+                        Some(spent) => spent,
+                        None => {
+                            warn!(target: "callback", "Transaction {} at height {} spends unknown output {}:{}.",
+                                  &tx.hash, block_height, &input.outpoint.txid, input.outpoint.index);
+                            self.missing_inputs += 1;
+                            continue;
+                        }
+                    };
+                    self.writer.write_all(
+                        format!(
+                            "{};{};{};{};{};{};{};{}\n",
+                            self.hash_order.fmt(&input.outpoint.txid),
+                            input.outpoint.index,
+                            spent.block_height,
+                            self.unit.format(spent.value),
+                            spent.address,
+                            self.hash_order.fmt(&tx.hash),
+                            i,
+                            block_height
+                        )
+                        .as_bytes(),
+                    )?;
+                    self.spent_count += 1;
+                }
+            }
+            self.out_count +=
+                common::insert_spendable_outputs(&mut self.unspents, tx, block_height, |output| {
+                    UnspentValue {
+                        block_height,
+                        value: output.out.value,
+                        address: output.script.address.clone().unwrap_or_default(),
+                    }
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for SpentOutputs {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("spentoutputs")
+            .about("Dumps spent outputs along with the transaction spending them to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(command)))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = SpentOutputs {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: SpentOutputs::create_writer(4000000, dump_folder.join("spent.csv.tmp"))?,
+            unit: Unit::from_matches(matches),
+            unspents: SpillMap::from_matches(matches, "spentoutputs-spill")?,
+            start_height: 0,
+            spent_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        if block_height != 0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(
+                "spentoutputs needs to start at the genesis block to know all spent outputs.",
+            ));
+        }
+        self.start_height = block_height;
+        info!(target: "callback", "Executing spentoutputs with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{}\n",
+                "txid",
+                "indexOut",
+                "height",
+                "value",
+                "address",
+                "spendingTxid",
+                "indexIn",
+                "spendingHeight"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.apply_block(block, block_height)
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        self.unspents.destroy()?;
+        fs::rename(
+            self.dump_folder.as_path().join("spent.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(format!("spent-{}-{}.csv", self.start_height, block_height)),
+        )?;
+        info!(target: "callback", "Done.\nDumped spent outputs from height {} to {}:\n\
+                                   \t-> outputs:      {:9}\n\
+                                   \t-> spent:        {:9}",
+             self.start_height, block_height, self.out_count, self.spent_count);
+        if self.missing_inputs > 0 {
+            warn!(target: "callback", "{} inputs spent unknown outputs, the dump is incomplete.", self.missing_inputs);
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self.missing_inputs {
+            0 => 0,
+            _ => EXIT_INCONSISTENT,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{EvaluatedTx, RawTx, TxOutpoint};
+    use crate::blockchain::proto::Hashed;
+    use crate::common::testutil::{new_block, null_outpoint, TxBuilder};
+
+    // P2PKH script of 12higDjoCCNXSA95xZMWUdPvXNmkAduhWv
+    const P2PKH: &str = "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac";
+
+    fn new_tx(inputs: Vec<TxOutpoint>, values: &[u64]) -> RawTx {
+        let script = crate::common::utils::hex_to_vec(P2PKH);
+        values
+            .iter()
+            .fold(TxBuilder::new().inputs(inputs), |tx, value| {
+                tx.pay(*value, script.clone())
+            })
+            .build()
+    }
+
+    fn coinbase(values: &[u64]) -> RawTx {
+        new_tx(vec![null_outpoint()], values)
+    }
+
+    #[test]
+    fn test_spent_outputs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = SpentOutputs {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: SpentOutputs::create_writer(100, tmp_dir.path().join("spent.csv.tmp")).unwrap(),
+            unit: Unit::Btc,
+            unspents: SpillMap::new(None, 0).unwrap(),
+            start_height: 0,
+            spent_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        };
+        assert!(cb.on_start(1).is_err());
+        cb.on_start(0).unwrap();
+
+        let block0 = new_block(vec![coinbase(&[49])]);
+        let genesis_txid = block0.txs[0].hash;
+        cb.apply_block(&block0, 0).unwrap();
+        let block1 = new_block(vec![coinbase(&[30, 20])]);
+        let coinbase_txid = block1.txs[0].hash;
+        cb.apply_block(&block1, 1).unwrap();
+
+        // Spends the second coinbase output, the new output is spent within the same block
+        let spend = new_tx(vec![TxOutpoint::new(coinbase_txid, 1)], &[19]);
+        let spend_txid = Hashed::double_sha256(EvaluatedTx::from(new_tx(
+            vec![TxOutpoint::new(coinbase_txid, 1)],
+            &[19],
+        )))
+        .hash;
+        let chained = new_tx(vec![TxOutpoint::new(spend_txid, 0)], &[18]);
+        let block2 = new_block(vec![coinbase(&[50]), spend, chained]);
+        let chained_txid = block2.txs[2].hash;
+        cb.apply_block(&block2, 2).unwrap();
+        assert_eq!(cb.exit_code(), 0);
+
+        // The genesis output can't be spent
+        let invalid = new_tx(vec![TxOutpoint::new(genesis_txid, 0)], &[49]);
+        cb.apply_block(&new_block(vec![coinbase(&[51]), invalid]), 3)
+            .unwrap();
+        assert_eq!(cb.missing_inputs, 1);
+        assert_eq!(cb.exit_code(), EXIT_INCONSISTENT);
+
+        cb.on_complete(3).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("spent-0-3.csv")).unwrap();
+        let address = "12higDjoCCNXSA95xZMWUdPvXNmkAduhWv";
+        assert_eq!(
+            dump.lines().collect::<Vec<&str>>(),
+            vec![
+                "txid;indexOut;height;value;address;spendingTxid;indexIn;spendingHeight",
+                &format!(
+                    "{};1;1;0.00000020;{};{};0;2",
+                    coinbase_txid, address, spend_txid
+                ),
+                &format!(
+                    "{};0;2;0.00000019;{};{};0;2",
+                    spend_txid, address, chained_txid
+                ),
+            ]
+        );
+        assert_eq!(cb.out_count, 7);
+        assert_eq!(cb.spent_count, 2);
+    }
+}
@@ -0,0 +1,455 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command as Process, Stdio};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::{common, Callback};
+use crate::common::utils::{self, HashOrder};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Command line shell the statements are piped into
+const SHELL: &str = "sqlite3";
+
+/// Number of inserted rows after which the running transaction is committed
+const BATCH_SIZE: u64 = 5000;
+
+const SCHEMA: &str = "\
+PRAGMA journal_mode = OFF;
+PRAGMA synchronous = OFF;
+CREATE TABLE blocks (hash TEXT NOT NULL, height INTEGER NOT NULL, version INTEGER NOT NULL, \
+blocksize INTEGER NOT NULL, hashPrev TEXT NOT NULL, hashMerkleRoot TEXT NOT NULL, \
+nTime INTEGER NOT NULL, nBits INTEGER NOT NULL, nNonce INTEGER NOT NULL);
+CREATE TABLE transactions (txid TEXT NOT NULL, hashBlock TEXT NOT NULL, height INTEGER NOT NULL, \
+version INTEGER NOT NULL, lockTime INTEGER NOT NULL);
+CREATE TABLE tx_in (txid TEXT NOT NULL, hashPrevOut TEXT NOT NULL, indexPrevOut INTEGER NOT NULL, \
+scriptSig BLOB NOT NULL, sequence INTEGER NOT NULL);
+CREATE TABLE tx_out (txid TEXT NOT NULL, indexOut INTEGER NOT NULL, height INTEGER NOT NULL, \
+value INTEGER NOT NULL, scriptPubKey BLOB NOT NULL, address TEXT);
+";
+
+/// Created after all rows are inserted, which is considerably faster than maintaining them during the import
+const INDEXES: &str = "\
+CREATE INDEX blocks_hash ON blocks (hash);
+CREATE INDEX blocks_height ON blocks (height);
+CREATE INDEX transactions_txid ON transactions (txid);
+CREATE INDEX transactions_height ON transactions (height);
+CREATE INDEX tx_in_txid ON tx_in (txid);
+CREATE INDEX tx_out_txid ON tx_out (txid);
+CREATE INDEX tx_out_height ON tx_out (height);
+";
+
+/// Writes blocks, transactions, inputs and outputs into a single SQLite database.
+///
+/// There is no SQLite binding among the dependencies, so the statements are piped
+/// into the `sqlite3` command line shell which has to be available in `PATH`.
+pub struct Sqlite {
+    db_path: PathBuf,
+    process: Child,
+    // Statements of the running transaction, submitted to the shell on commit
+    batch: Vec<u8>,
+
+    start_height: u64,
+    pending_rows: u64,
+    tx_count: u64,
+    in_count: u64,
+    out_count: u64,
+    hash_order: HashOrder,
+}
+
+impl Sqlite {
+    /// Checks that the shell can be executed, before anything is parsed or an existing database
+    /// is removed
+    fn check_shell(program: &str) -> OpResult<()> {
+        let status = Process::new(program)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let msg = match status {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => format!("`{} -version` exited with {}.", program, status),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => format!(
+                "{} not found in PATH, the sqlite callback needs the SQLite command line shell.",
+                program
+            ),
+            Err(err) => format!("Unable to execute {}: {}.", program, err),
+        };
+        Err(OpError::new(OpErrorKind::CallbackError).join_msg(&msg))
+    }
+
+    /// Spawns the sqlite3 shell on the given database, its stdin is used to submit statements
+    fn spawn_shell(db_path: &PathBuf) -> OpResult<Child> {
+        Process::new(SHELL)
+            .arg("-batch")
+            .arg("-bail")
+            .arg(db_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                OpError::new(OpErrorKind::CallbackError)
+                    .join_msg(&format!("Unable to execute sqlite3: {}.", err))
+            })
+    }
+
+    /// Submits the buffered statements to the shell
+    fn submit(&mut self) -> OpResult<()> {
+        let stdin = transform!(self.process.stdin.as_mut());
+        stdin.write_all(&self.batch)?;
+        self.batch.clear();
+        Ok(())
+    }
+
+    /// Counts the inserted rows and starts a new transaction once the batch is full
+    fn write_row(&mut self, statement: &str) -> OpResult<()> {
+        self.batch.extend_from_slice(statement.as_bytes());
+        self.pending_rows += 1;
+        if self.pending_rows >= BATCH_SIZE {
+            self.batch
+                .extend_from_slice(b"COMMIT;\nBEGIN TRANSACTION;\n");
+            self.submit()?;
+            self.pending_rows = 0;
+        }
+        Ok(())
+    }
+
+    /// Quotes a string as SQL literal
+    fn quote(value: &str) -> String {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+
+    /// Formats bytes as SQL blob literal
+    fn blob(bytes: &[u8]) -> String {
+        format!("X'{}'", utils::arr_to_hex(bytes))
+    }
+}
+
+impl Callback for Sqlite {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("sqlite")
+            .about("Writes the whole blockchain into a SQLite database (requires sqlite3 in PATH)")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("db-file")
+                    .help("SQLite database file to create")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("overwrite")
+                    .long("overwrite")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Replaces the database file if it already exists"),
+            )
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let db_path = PathBuf::from(matches.get_one::<String>("db-file").unwrap());
+        if db_path.exists() && !matches.get_flag("overwrite") {
+            return Err(
+                OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+                    "Database {} already exists, use --overwrite to replace it.",
+                    db_path.display()
+                )),
+            );
+        }
+        Sqlite::check_shell(SHELL)?;
+        if db_path.exists() {
+            fs::remove_file(&db_path)?;
+        }
+        let cb = Sqlite {
+            process: Sqlite::spawn_shell(&db_path)?,
+            db_path,
+            batch: Vec::new(),
+            start_height: 0,
+            pending_rows: 0,
+            tx_count: 0,
+            in_count: 0,
+            out_count: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing sqlite with database: {} ...", &self.db_path.display());
+        self.batch.extend_from_slice(SCHEMA.as_bytes());
+        self.batch.extend_from_slice(b"BEGIN TRANSACTION;\n");
+        self.submit()
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.write_row(&block.as_sql(block_height, self.hash_order))?;
+
+        let block_hash = self.hash_order.fmt(&block.header.hash).to_string();
+        for tx in &block.txs {
+            self.write_row(&tx.as_sql(&block_hash, block_height, self.hash_order))?;
+            let txid_str = self.hash_order.fmt(&tx.hash).to_string();
+            for input in &tx.value.inputs {
+                self.write_row(&input.as_sql(&txid_str, self.hash_order))?;
+            }
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                self.write_row(&output.as_sql(&txid_str, i as u32, block_height))?;
+            }
+            self.in_count += tx.value.in_count.value;
+            self.out_count += tx.value.out_count.value;
+        }
+        self.tx_count += block.tx_count.value;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.batch.extend_from_slice(b"COMMIT;\n");
+        self.batch.extend_from_slice(INDEXES.as_bytes());
+        self.submit()?;
+
+        // Stdin gets closed before waiting, so the shell terminates once all statements are executed
+        info!(target: "callback", "Waiting for sqlite3 to create indexes ...");
+        let status = self.process.wait()?;
+        if !status.success() {
+            return Err(OpError::new(OpErrorKind::CallbackError)
+                .join_msg(&format!("sqlite3 exited with {}.", status)));
+        }
+
+        info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
+                                   \t-> transactions: {:9}\n\
+                                   \t-> inputs:       {:9}\n\
+                                   \t-> outputs:      {:9}",
+             self.start_height, block_height, self.tx_count, self.in_count, self.out_count);
+        Ok(())
+    }
+}
+
+impl Block {
+    fn as_sql(&self, block_height: u64, hash_order: HashOrder) -> String {
+        format!(
+            "INSERT INTO blocks VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {});\n",
+            Sqlite::quote(&hash_order.fmt(&self.header.hash).to_string()),
+            block_height,
+            self.header.value.version,
+            self.size,
+            Sqlite::quote(&hash_order.fmt(&self.header.value.prev_hash).to_string()),
+            Sqlite::quote(&hash_order.fmt(&self.header.value.merkle_root).to_string()),
+            self.header.value.timestamp,
+            self.header.value.bits,
+            self.header.value.nonce
+        )
+    }
+}
+
+impl Hashed<EvaluatedTx> {
+    fn as_sql(&self, block_hash: &str, block_height: u64, hash_order: HashOrder) -> String {
+        format!(
+            "INSERT INTO transactions VALUES ({}, {}, {}, {}, {});\n",
+            Sqlite::quote(&hash_order.fmt(&self.hash).to_string()),
+            Sqlite::quote(block_hash),
+            block_height,
+            self.value.version,
+            self.value.locktime
+        )
+    }
+}
+
+impl TxInput {
+    fn as_sql(&self, txid: &str, hash_order: HashOrder) -> String {
+        format!(
+            "INSERT INTO tx_in VALUES ({}, {}, {}, {}, {});\n",
+            Sqlite::quote(txid),
+            Sqlite::quote(&hash_order.fmt(&self.outpoint.txid).to_string()),
+            self.outpoint.index,
+            Sqlite::blob(&self.script_sig),
+            self.seq_no
+        )
+    }
+}
+
+impl EvaluatedTxOut {
+    fn as_sql(&self, txid: &str, index: u32, block_height: u64) -> String {
+        let address = match self.script.address {
+            Some(ref address) => Sqlite::quote(address),
+            None => String::from("NULL"),
+        };
+        format!(
+            "INSERT INTO tx_out VALUES ({}, {}, {}, {}, {}, {});\n",
+            Sqlite::quote(txid),
+            index,
+            block_height,
+            self.out.value,
+            Sqlite::blob(&self.out.script_pubkey),
+            address
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::script::{EvaluatedScript, ScriptPattern};
+    use crate::blockchain::proto::tx::{TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use bitcoin::hashes::{sha256d, Hash};
+
+    #[test]
+    fn test_tx_out_as_sql() {
+        let mut output = EvaluatedTxOut {
+            script: EvaluatedScript::new(
+                Some(String::from("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")),
+                ScriptPattern::Pay2PublicKeyHash,
+            ),
+            out: TxOutput {
+                value: 5000000000,
+                script_len: VarUint::from(2u8),
+                script_pubkey: vec![0x51, 0xac],
+            },
+        };
+        assert_eq!(
+            output.as_sql("abcd", 1, 42),
+            "INSERT INTO tx_out VALUES ('abcd', 1, 42, 5000000000, X'51ac', \
+             '1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa');\n"
+        );
+
+        output.script = EvaluatedScript::new(None, ScriptPattern::NotRecognised);
+        assert_eq!(
+            output.as_sql("abcd", 0, 42),
+            "INSERT INTO tx_out VALUES ('abcd', 0, 42, 5000000000, X'51ac', NULL);\n"
+        );
+        assert_eq!(Sqlite::quote("it's"), "'it''s'");
+    }
+
+    fn text_rows(txid: &str, address: &str) -> Vec<String> {
+        let header = BlockHeader {
+            version: 1,
+            prev_hash: sha256d::Hash::hash(b"prev"),
+            merkle_root: sha256d::Hash::hash(b"merkle"),
+            timestamp: 2,
+            bits: 3,
+            nonce: 4,
+        };
+        let block = Block::new(80, header, None, VarUint::from(0u8), vec![]);
+        let tx = Hashed::double_sha256(EvaluatedTx::new(
+            1,
+            VarUint::from(0u8),
+            vec![],
+            VarUint::from(0u8),
+            vec![],
+            0,
+            0x00,
+        ));
+        let input = TxInput {
+            outpoint: TxOutpoint::new(sha256d::Hash::hash(b"prevout"), 1),
+            script_len: VarUint::from(1u8),
+            script_sig: vec![0x27],
+            seq_no: 5,
+            witness: vec![],
+        };
+        let output = EvaluatedTxOut {
+            script: EvaluatedScript::new(Some(String::from(address)), ScriptPattern::NotRecognised),
+            out: TxOutput {
+                value: 1,
+                script_len: VarUint::from(0u8),
+                script_pubkey: vec![],
+            },
+        };
+        vec![
+            block.as_sql(7, HashOrder::Display),
+            tx.as_sql(txid, 7, HashOrder::Display),
+            input.as_sql(txid, HashOrder::Display),
+            output.as_sql(txid, 0, 7),
+        ]
+    }
+
+    #[test]
+    fn test_text_columns_are_quoted() {
+        let rows = text_rows("a'b", "'); DROP TABLE tx_out; --");
+        let hash = |data: &[u8]| {
+            HashOrder::Display
+                .fmt(&sha256d::Hash::hash(data))
+                .to_string()
+        };
+        assert!(rows[0].starts_with("INSERT INTO blocks VALUES ('"));
+        assert!(rows[0].contains(&format!(", '{}', '{}', 2,", hash(b"prev"), hash(b"merkle"))));
+        assert!(rows[1].starts_with("INSERT INTO transactions VALUES ('"));
+        assert!(rows[1].contains("', 'a''b', 7, 1, 0);"));
+        assert_eq!(
+            rows[2],
+            format!(
+                "INSERT INTO tx_in VALUES ('a''b', '{}', 1, X'27', 5);\n",
+                hash(b"prevout")
+            )
+        );
+        assert_eq!(
+            rows[3],
+            "INSERT INTO tx_out VALUES ('a''b', 0, 7, 1, X'', '''); DROP TABLE tx_out; --');\n"
+        );
+    }
+
+    #[test]
+    fn test_text_columns_round_trip() {
+        if Sqlite::check_shell(SHELL).is_err() {
+            return;
+        }
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db_path = tmp_dir.path().join("chain.db");
+        let address = "'); DROP TABLE tx_out; --\nline \"two\"";
+        let mut script = String::from(SCHEMA);
+        script.extend(text_rows("a'b", address));
+        let mut shell = Sqlite::spawn_shell(&db_path).unwrap();
+        shell
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(script.as_bytes())
+            .unwrap();
+        assert!(shell.wait().unwrap().success());
+
+        let select = |query: &str| {
+            let output = Process::new(SHELL)
+                .arg(&db_path)
+                .arg(query)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(select("SELECT hashBlock FROM transactions;"), "a'b\n");
+        assert_eq!(select("SELECT txid FROM tx_in;"), "a'b\n");
+        assert_eq!(
+            select("SELECT txid || '|' || address FROM tx_out;"),
+            format!("a'b|{}\n", address)
+        );
+        assert_eq!(select("SELECT count(*) FROM blocks;"), "1\n");
+    }
+
+    #[test]
+    fn test_missing_shell() {
+        let err = Sqlite::check_shell("sqlite3-does-not-exist").err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::CallbackError));
+        assert!(err.to_string().contains("not found in PATH"));
+    }
+
+    #[test]
+    fn test_existing_db_requires_overwrite() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db_path = tmp_dir.path().join("chain.db");
+        fs::write(&db_path, b"").unwrap();
+        let matches = Sqlite::build_subcommand()
+            .try_get_matches_from(["sqlite", db_path.to_str().unwrap()])
+            .unwrap();
+        let err = Sqlite::new(&matches).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        assert!(db_path.exists());
+    }
+}
@@ -0,0 +1,276 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::common::{self, MissingPrevouts, SpillMap, Unit};
+use crate::callbacks::csvdump::CsvDump;
+use crate::callbacks::Callback;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Initial block subsidy of 50 coins in satoshis
+const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+
+/// Returns the block subsidy at the given height, halved every `halving_interval` blocks
+/// (210000 on mainnet and testnet, 150 on regtest)
+fn block_subsidy(height: u64, halving_interval: u64) -> u64 {
+    let halvings = height / halving_interval;
+    // Shifting by 64 or more is undefined, the subsidy is zero long before anyway
+    if halvings >= 64 {
+        return 0;
+    }
+    INITIAL_SUBSIDY >> halvings
+}
+
+/// Tracks the coin issuance per block and validates the coinbase against the subsidy schedule.
+///
+/// The fees of a block are needed to know how much its coinbase may claim, so all outputs
+/// are kept to look up the value of spent inputs (see `--spill-dir`).
+pub struct Supply {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    halving_interval: u64,
+    unit: Unit,
+    // Maps outpoints to their value
+    output_values: SpillMap<u64>,
+
+    start_height: u64,
+    supply: u64,
+    under_claimed_blocks: u64,
+    under_claimed: u64,
+    over_claimed_blocks: u64,
+}
+
+impl Supply {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Applies the block to the supply and returns its csv row
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<String> {
+        let mut fees = 0u64;
+        let mut claimed = 0u64;
+        for tx in &block.txs {
+            // Starting at the genesis block all spent outputs are known, unless blocks were skipped
+            let fee = match CsvDump::compute_fee(
+                &mut self.output_values,
+                tx,
+                block_height,
+                &mut MissingPrevouts::default(),
+            )? {
+                Some(fee) => fee,
+                None => {
+                    let msg = format!("Transaction {} spends an unknown output.", &tx.hash);
+                    return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+                }
+            };
+            match tx.value.is_coinbase() {
+                true => claimed += fee,
+                false => fees += fee,
+            }
+        }
+        let subsidy = block_subsidy(block_height, self.halving_interval);
+        let allowed = subsidy + fees;
+        let status = if claimed > allowed {
+            warn!(target: "callback", "Block {} at height {} claims {} satoshis more than allowed",
+                  &block.header.hash, block_height, claimed - allowed);
+            self.over_claimed_blocks += 1;
+            "over"
+        } else if claimed < allowed {
+            debug!(target: "callback", "Block {} at height {} claims {} satoshis less than allowed",
+                   &block.header.hash, block_height, allowed - claimed);
+            self.under_claimed_blocks += 1;
+            self.under_claimed += allowed - claimed;
+            "under"
+        } else {
+            "ok"
+        };
+        // Fees only move existing coins, everything else claimed by the coinbase is new
+        self.supply = (self.supply + claimed).saturating_sub(fees);
+        Ok(format!(
+            "{};{};{};{};{};{}\n",
+            block_height,
+            self.unit.format(subsidy),
+            self.unit.format(fees),
+            self.unit.format(claimed),
+            self.unit.format(self.supply),
+            status
+        ))
+    }
+}
+
+impl Callback for Supply {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("supply")
+            .about("Dumps the cumulative coin supply per block to CSV file and checks the block subsidy")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("halving-interval")
+                    .long("halving-interval")
+                    .value_name("BLOCKS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("210000")
+                    .help("Number of blocks between subsidy halvings (150 on regtest)"),
+            );
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(command)))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = Supply {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: Supply::create_writer(4000000, dump_folder.join("supply.csv.tmp"))?,
+            halving_interval: *matches.get_one::<u64>("halving-interval").unwrap(),
+            unit: Unit::from_matches(matches),
+            output_values: SpillMap::from_matches(matches, "supply-spill")?,
+            start_height: 0,
+            supply: 0,
+            under_claimed_blocks: 0,
+            under_claimed: 0,
+            over_claimed_blocks: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        if block_height != 0 {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("supply needs to start at the genesis block to know all fees."));
+        }
+        self.start_height = block_height;
+        info!(target: "callback", "Executing supply with dump folder: {} ...", &self.dump_folder.display());
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{}\n",
+                "height", "subsidy", "fees", "claimed", "supply", "status"
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let row = self.apply_block(block, block_height)?;
+        self.writer.write_all(row.as_bytes())?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.flush()?;
+        self.output_values.destroy()?;
+        fs::rename(
+            self.dump_folder.as_path().join("supply.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(format!("supply-{}-{}.csv", self.start_height, block_height)),
+        )?;
+        if self.over_claimed_blocks > 0 {
+            warn!(target: "callback", "{} blocks claimed more than allowed.", self.over_claimed_blocks);
+        }
+        info!(target: "callback", "Done.\nSupply at height {}: {} satoshis\n\
+                                   \t-> under-claimed blocks: {:9} ({} satoshis never issued)",
+             block_height, self.supply, self.under_claimed_blocks, self.under_claimed);
+        Ok(())
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{RawTx, TxOutpoint};
+    use crate::common::testutil::{self, new_block, new_tx};
+
+    fn coinbase(value: u64) -> RawTx {
+        testutil::coinbase(&[value])
+    }
+
+    #[test]
+    fn test_block_subsidy() {
+        assert_eq!(block_subsidy(0, 210000), 5000000000);
+        assert_eq!(block_subsidy(209999, 210000), 5000000000);
+        assert_eq!(block_subsidy(210000, 210000), 2500000000);
+        assert_eq!(block_subsidy(420000, 210000), 1250000000);
+        assert_eq!(block_subsidy(630000, 210000), 625000000);
+        assert_eq!(block_subsidy(840000, 210000), 312500000);
+        // The last halving with a non-zero subsidy of 1 satoshi
+        assert_eq!(block_subsidy(210000 * 33 - 1, 210000), 1);
+        assert_eq!(block_subsidy(210000 * 33, 210000), 0);
+        assert_eq!(block_subsidy(210000 * 64, 210000), 0);
+        assert_eq!(block_subsidy(u64::MAX, 210000), 0);
+        assert_eq!(block_subsidy(150, 150), 2500000000);
+    }
+
+    #[test]
+    fn test_supply() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = Supply {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: Supply::create_writer(100, tmp_dir.path().join("supply.csv.tmp")).unwrap(),
+            halving_interval: 2,
+            unit: Unit::Sat,
+            output_values: SpillMap::new(None, 0).unwrap(),
+            start_height: 0,
+            supply: 0,
+            under_claimed_blocks: 0,
+            under_claimed: 0,
+            over_claimed_blocks: 0,
+        };
+        assert!(cb.on_start(1).is_err());
+        cb.on_start(0).unwrap();
+
+        let block0 = new_block(vec![coinbase(5000000000)]);
+        cb.on_block(&block0, 0).unwrap();
+        // Spends 10 satoshis as fee, the coinbase claims subsidy and fee
+        let spend = new_tx(vec![TxOutpoint::new(block0.txs[0].hash, 0)], &[4999999990]);
+        let block1 = new_block(vec![coinbase(5000000010), spend]);
+        assert_eq!(
+            cb.apply_block(&block1, 1).unwrap(),
+            "1;5000000000;10;5000000010;10000000000;ok\n"
+        );
+        // Halved, the coinbase leaves 1 satoshi unclaimed
+        let block2 = new_block(vec![coinbase(2499999999)]);
+        assert_eq!(
+            cb.apply_block(&block2, 2).unwrap(),
+            "2;2500000000;0;2499999999;12499999999;under\n"
+        );
+        let block3 = new_block(vec![coinbase(2500000001)]);
+        cb.unit = Unit::Btc;
+        assert_eq!(
+            cb.apply_block(&block3, 3).unwrap(),
+            "3;25.00000000;0.00000000;25.00000001;150.00000000;over\n"
+        );
+        assert_eq!(cb.under_claimed, 1);
+        assert_eq!(cb.over_claimed_blocks, 1);
+
+        cb.on_complete(3).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("supply-0-3.csv")).unwrap();
+        assert_eq!(
+            dump.lines().next(),
+            Some("height;subsidy;fees;claimed;supply;status")
+        );
+        assert_eq!(dump.lines().count(), 2);
+    }
+}
@@ -8,13 +8,16 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
+use crate::callbacks::common::{self, Unit};
+use crate::callbacks::Callback;
+use crate::common::utils::HashOrder;
 use crate::errors::OpResult;
 
 /// Dumps the UTXOs along with address in a csv file
 pub struct UnspentCsvDump {
     dump_folder: PathBuf,
     writer: BufWriter<File>,
+    unit: Unit,
 
     // key: txid + index
     unspents: HashMap<Vec<u8>, common::UnspentValue>,
@@ -23,6 +26,7 @@ pub struct UnspentCsvDump {
     tx_count: u64,
     in_count: u64,
     out_count: u64,
+    hash_order: HashOrder,
 }
 
 impl UnspentCsvDump {
@@ -36,7 +40,7 @@ impl Callback for UnspentCsvDump {
     where
         Self: Sized,
     {
-        Command::new("unspentcsvdump")
+        let command = Command::new("unspentcsvdump")
             .about("Dumps the unspent outputs to CSV file")
             .version("0.1")
             .author("fsvm88 <fsvm88@gmail.com>")
@@ -45,22 +49,25 @@ impl Callback for UnspentCsvDump {
                     .help("Folder to store csv file")
                     .index(1)
                     .required(true),
-            )
+            );
+        common::add_overwrite_arg(common::add_unit_arg(command))
     }
 
     fn new(matches: &ArgMatches) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
         let cb = UnspentCsvDump {
             dump_folder: PathBuf::from(dump_folder),
             writer: UnspentCsvDump::create_writer(4000000, dump_folder.join("unspent.csv.tmp"))?,
+            unit: Unit::from_matches(matches),
             unspents: HashMap::with_capacity(10000000),
             start_height: 0,
             tx_count: 0,
             in_count: 0,
             out_count: 0,
+            hash_order: common::hash_order_from_matches(matches),
         };
         Ok(cb)
     }
@@ -74,6 +81,7 @@ impl Callback for UnspentCsvDump {
     /// For each transaction in the block
     ///   1. apply input transactions (remove (TxID == prevTxIDOut and prevOutID == spentOutID))
     ///   2. apply output transactions (add (TxID + curOutID -> HashMapVal))
+    ///
     /// For each address, retain:
     ///   * block height as "last modified"
     ///   * output_val
@@ -101,10 +109,10 @@ impl Callback for UnspentCsvDump {
             self.writer.write_all(
                 format!(
                     "{};{};{};{};{}\n",
-                    txid,
+                    self.hash_order.fmt(&txid),
                     index.read_u32::<LittleEndian>()?,
                     value.block_height,
-                    value.value,
+                    self.unit.format(value.value),
                     value.address
                 )
                 .as_bytes(),
@@ -126,4 +134,12 @@ impl Callback for UnspentCsvDump {
              self.start_height, block_height, self.tx_count, self.in_count, self.out_count);
         Ok(())
     }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,534 @@
+use bitcoin::hashes::{sha256d, Hash};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use byteorder::{ByteOrder, LittleEndian};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::common::{self, SpillMap, Spillable, Unit};
+use crate::callbacks::{Callback, EXIT_INCONSISTENT};
+use crate::common::utils::{self, HashOrder};
+use crate::errors::OpResult;
+
+/// Holds all necessary data about an unspent output
+#[cfg_attr(test, derive(Debug, PartialEq))]
+struct UtxoValue {
+    height: u64,
+    value: u64,
+    script_pubkey: Vec<u8>,
+}
+
+impl Spillable for UtxoValue {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        UtxoValue {
+            height: LittleEndian::read_u64(&bytes[0..8]),
+            value: LittleEndian::read_u64(&bytes[8..16]),
+            script_pubkey: Vec::from(&bytes[16..]),
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        self.script_pubkey.capacity()
+    }
+}
+
+impl ToRaw for UtxoValue {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + self.script_pubkey.len());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.value.to_le_bytes());
+        bytes.extend_from_slice(&self.script_pubkey);
+        bytes
+    }
+}
+
+/// Dumps the UTXO set along with the raw script_pubkey in a csv file.
+///
+/// The whole UTXO set is held in memory unless `--spill-dir` is given,
+/// in which case entries are moved to disk once `--spill-threshold` is exceeded.
+pub struct UtxoDump {
+    dump_folder: PathBuf,
+    writer: BufWriter<File>,
+    utxo_set: SpillMap<UtxoValue>,
+    unit: Unit,
+
+    start_height: u64,
+    in_count: u64,
+    out_count: u64,
+    // Inputs spending outputs which are not in the UTXO set, only counted from the genesis block
+    missing_inputs: u64,
+    hash_order: HashOrder,
+}
+
+impl UtxoDump {
+    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    }
+
+    /// Applies all transactions of the given block to the UTXO set
+    fn apply_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                for input in &tx.value.inputs {
+                    let spent = self.utxo_set.remove(&input.outpoint.to_bytes())?;
+                    // Starting later, the outputs created before are unknown
+                    if spent.is_none() && self.start_height == 0 {
+                        warn!(target: "callback", "Transaction {} at height {} spends unknown output {}:{}.",
+                              &tx.hash, block_height, &input.outpoint.txid, input.outpoint.index);
+                        self.missing_inputs += 1;
+                    }
+                    self.in_count += 1;
+                }
+            }
+            self.out_count +=
+                common::insert_spendable_outputs(&mut self.utxo_set, tx, block_height, |output| {
+                    UtxoValue {
+                        height: block_height,
+                        value: output.out.value,
+                        script_pubkey: output.out.script_pubkey.clone(),
+                    }
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for UtxoDump {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        let command = Command::new("utxodump")
+            .about("Dumps the UTXO set with raw script_pubkeys to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            );
+        common::add_overwrite_arg(common::add_spill_args(common::add_unit_arg(command)))
+    }
+
+    fn new(matches: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let dump_folder = &common::dump_folder_from_matches(matches)?;
+        let cb = UtxoDump {
+            dump_folder: PathBuf::from(dump_folder),
+            writer: UtxoDump::create_writer(4000000, dump_folder.join("utxo.csv.tmp"))?,
+            utxo_set: SpillMap::from_matches(matches, "utxodump-spill")?,
+            unit: Unit::from_matches(matches),
+            start_height: 0,
+            in_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: common::hash_order_from_matches(matches),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing utxodump with dump folder: {} ...", &self.dump_folder.display());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.apply_block(block, block_height)
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                "txid", "indexOut", "height", "value", "scriptPubKey"
+            )
+            .as_bytes(),
+        )?;
+
+        let mut count = 0;
+        let writer = &mut self.writer;
+        let unit = self.unit;
+        self.utxo_set.for_each(|key, value| {
+            let txid = sha256d::Hash::from_slice(&key[0..32]).unwrap();
+            let index = LittleEndian::read_u32(&key[32..]);
+            writer.write_all(
+                format!(
+                    "{};{};{};{};{}\n",
+                    self.hash_order.fmt(&txid),
+                    index,
+                    value.height,
+                    unit.format(value.value),
+                    utils::arr_to_hex(&value.script_pubkey)
+                )
+                .as_bytes(),
+            )?;
+            count += 1;
+            Ok(())
+        })?;
+        self.writer.flush()?;
+        self.utxo_set.destroy()?;
+
+        fs::rename(
+            self.dump_folder.as_path().join("utxo.csv.tmp"),
+            self.dump_folder
+                .as_path()
+                .join(format!("utxo-{}-{}.csv", self.start_height, block_height)),
+        )?;
+
+        info!(target: "callback", "Done.\nDumped {} unspent outputs from height {} to {}:\n\
+                                   \t-> inputs:       {:9}\n\
+                                   \t-> outputs:      {:9}",
+             count, self.start_height, block_height, self.in_count, self.out_count);
+        if self.missing_inputs > 0 {
+            warn!(target: "callback", "{} inputs spent unknown outputs, the UTXO set is inconsistent.", self.missing_inputs);
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self.missing_inputs {
+            0 => 0,
+            _ => EXIT_INCONSISTENT,
+        }
+    }
+
+    fn needs_coinbase(&self) -> bool {
+        true
+    }
+
+    fn needs_previous_blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{EvaluatedTx, RawTx, TxOutpoint};
+    use crate::blockchain::proto::Hashed;
+    use crate::common::testutil::{coinbase, new_block, new_tx};
+    use std::collections::HashMap;
+
+    fn collect(utxo_set: &mut SpillMap<UtxoValue>) -> HashMap<Vec<u8>, UtxoValue> {
+        let mut collected = HashMap::new();
+        utxo_set
+            .for_each(|key, value| {
+                collected.insert(Vec::from(key), UtxoValue::from_bytes(&value.to_bytes()));
+                Ok(())
+            })
+            .unwrap();
+        collected
+    }
+
+    fn test_utxo_set(spill_dir: Option<PathBuf>) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = UtxoDump {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: UtxoDump::create_writer(100, tmp_dir.path().join("utxo.csv.tmp")).unwrap(),
+            utxo_set: SpillMap::new(spill_dir, 1).unwrap(),
+            unit: Unit::Sat,
+            start_height: 0,
+            in_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        };
+
+        // Block 1: coinbase with two outputs, the genesis block at height 0 can't be spent
+        let block0 = new_block(vec![coinbase(&[30, 20])]);
+        let coinbase_txid = block0.txs[0].hash;
+        cb.apply_block(&block0, 1).unwrap();
+
+        // Block 2: spends the first coinbase output and creates a new one.
+        // A second tx spends the new output within the same block.
+        let reward = coinbase(&[50]);
+        let spend = new_tx(vec![TxOutpoint::new(coinbase_txid, 0)], &[25]);
+        let spend_txid = Hashed::double_sha256(EvaluatedTx::from(new_tx(
+            vec![TxOutpoint::new(coinbase_txid, 0)],
+            &[25],
+        )))
+        .hash;
+        let chained = new_tx(vec![TxOutpoint::new(spend_txid, 0)], &[24]);
+        let block1 = new_block(vec![reward, spend, chained]);
+        cb.apply_block(&block1, 2).unwrap();
+
+        let utxos = collect(&mut cb.utxo_set);
+        assert_eq!(utxos.len(), 3);
+        assert_eq!(
+            utxos.get(&TxOutpoint::new(coinbase_txid, 1).to_bytes()),
+            Some(&UtxoValue {
+                height: 1,
+                value: 20,
+                script_pubkey: vec![0x51]
+            })
+        );
+        assert_eq!(
+            utxos
+                .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
+                .unwrap()
+                .value,
+            50
+        );
+        assert_eq!(
+            utxos
+                .get(&TxOutpoint::new(block1.txs[2].hash, 0).to_bytes())
+                .unwrap()
+                .value,
+            24
+        );
+        assert!(!utxos.contains_key(&TxOutpoint::new(coinbase_txid, 0).to_bytes()));
+        assert!(!utxos.contains_key(&TxOutpoint::new(spend_txid, 0).to_bytes()));
+
+        // Block 3 repeats the coinbase of block 1, like block 91880 did with block 91722.
+        // The unspent output is replaced and the spent one is created again.
+        let duplicate = coinbase(&[30, 20]);
+        cb.apply_block(&new_block(vec![duplicate]), 3).unwrap();
+        let utxos = collect(&mut cb.utxo_set);
+        assert_eq!(utxos.len(), 4);
+        assert_eq!(
+            utxos
+                .get(&TxOutpoint::new(coinbase_txid, 1).to_bytes())
+                .unwrap()
+                .height,
+            3
+        );
+
+        cb.on_complete(3).unwrap();
+        let dump = fs::read_to_string(tmp_dir.path().join("utxo-0-3.csv")).unwrap();
+        assert_eq!(dump.lines().count(), 5);
+        assert_eq!(cb.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_genesis_block() {
+        use crate::blockchain::parser::reader::BlockchainRead;
+        use crate::blockchain::parser::types::{Bitcoin, CoinType};
+        use std::io::Cursor;
+
+        // Magic, size and the genesis block as stored in blk00000.dat
+        let data = include_bytes!("../blockchain/parser/testdata/genesis.dat");
+        let coin = CoinType::from(Bitcoin);
+        let block = Cursor::new(&data[8..]).read_block(285, &coin).unwrap();
+        assert_eq!(block.header.hash, coin.genesis_hash);
+        assert_eq!(block.header.value.prev_hash, sha256d::Hash::all_zeros());
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = UtxoDump {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: UtxoDump::create_writer(100, tmp_dir.path().join("utxo.csv.tmp")).unwrap(),
+            utxo_set: SpillMap::new(None, 0).unwrap(),
+            unit: Unit::Sat,
+            start_height: 0,
+            in_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        };
+        cb.on_block(&block, 0).unwrap();
+        // The genesis output can't be spent, the same block at a later height counts
+        assert_eq!(cb.out_count, 0);
+        assert!(collect(&mut cb.utxo_set).is_empty());
+        cb.apply_block(&block, 1).unwrap();
+        assert_eq!(cb.out_count, 1);
+    }
+
+    /// Regtest genesis block, which only differs from the one of mainnet in its header
+    fn regtest_genesis() -> Block {
+        use crate::blockchain::parser::reader::BlockchainRead;
+        use crate::blockchain::parser::types::{Bitcoin, CoinType};
+        use std::io::Cursor;
+
+        let data = include_bytes!("../blockchain/parser/testdata/genesis.dat");
+        let mainnet = Cursor::new(&data[8..])
+            .read_block(285, &CoinType::from(Bitcoin))
+            .unwrap();
+        let mut header = mainnet.header.value.clone();
+        header.timestamp = 1296688602;
+        header.bits = 0x207fffff;
+        header.nonce = 2;
+        let mut block = mainnet;
+        block.header = Hashed::double_sha256(header);
+        block
+    }
+
+    #[test]
+    fn test_regtest_chain() {
+        use crate::blockchain::parser::chain::ChainStorage;
+        use crate::blockchain::parser::types::{CoinType, Regtest};
+        use crate::blockchain::parser::BlockchainParser;
+        use crate::callbacks::benchmark::Benchmark;
+        use crate::common::testutil::{blk_record, p2pkh, BlockBuilder, TxBuilder};
+        use crate::ParserOptions;
+
+        const COIN: u64 = 100_000_000;
+        let coin = CoinType::from(Regtest);
+        let genesis = regtest_genesis();
+        assert_eq!(genesis.header.hash, coin.genesis_hash);
+
+        // Coinbases push their height (BIP34), so each of them has its own txid.
+        // The parser doesn't check the coinbase maturity, rewards are spent right away.
+        let coinbase = |height: u8, value: u64| {
+            TxBuilder::coinbase()
+                .script_sig(vec![0x01, height])
+                .output(value)
+                .build()
+        };
+        let txid = |tx: RawTx| Hashed::double_sha256(EvaluatedTx::from(tx)).hash;
+        let c1 = || coinbase(1, 50 * COIN);
+        let c2 = || coinbase(2, 50 * COIN);
+        // Pays two addresses, the change is spent again in the same block
+        let pay = || {
+            TxBuilder::new()
+                .input(TxOutpoint::new(txid(c1()), 0))
+                .pay(30 * COIN, p2pkh(1))
+                .pay(20 * COIN - 10000, p2pkh(2))
+                .build()
+        };
+        let change = || {
+            TxBuilder::new()
+                .input(TxOutpoint::new(txid(pay()), 1))
+                .pay(20 * COIN - 20000, p2pkh(3))
+                .build()
+        };
+        let c3 = || coinbase(3, 50 * COIN + 20000);
+        // Merges the second reward with the first payment
+        let merge = || {
+            TxBuilder::new()
+                .input(TxOutpoint::new(txid(c2()), 0))
+                .input(TxOutpoint::new(txid(pay()), 0))
+                .pay(80 * COIN - 10000, p2pkh(4))
+                .build()
+        };
+        let c4 = || coinbase(4, 50 * COIN + 10000);
+        let c5 = || coinbase(5, 50 * COIN);
+
+        let mut prev_hash = genesis.header.hash;
+        let mut data = blk_record(coin.magic, &genesis);
+        for (height, txs) in [
+            vec![c1()],
+            vec![c2()],
+            vec![c3(), pay(), change()],
+            vec![c4(), merge()],
+            vec![c5()],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let block = BlockBuilder::new()
+                .version(0x20000000)
+                .prev_hash(prev_hash)
+                .timestamp(1296688602 + 600 * (height as u32 + 1))
+                .bits(0x207fffff)
+                .txs(txs)
+                .mine();
+            prev_hash = block.header.hash;
+            data.extend_from_slice(&blk_record(coin.magic, &block));
+        }
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bootstrap.dat");
+        fs::write(&path, data).unwrap();
+        let mut options = ParserOptions::new(Box::new(Benchmark), tmp_dir.path().to_path_buf());
+        options.coin = coin;
+        options.bootstrap = Some(path);
+        options.verify = true;
+        options.verify_pow = true;
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        let mut parser = BlockchainParser::new(options, chain_storage);
+
+        let mut cb = UtxoDump {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: UtxoDump::create_writer(100, tmp_dir.path().join("utxo.csv.tmp")).unwrap(),
+            utxo_set: SpillMap::new(None, 0).unwrap(),
+            unit: Unit::Sat,
+            start_height: 0,
+            in_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        };
+        cb.on_start(0).unwrap();
+        for result in parser.blocks() {
+            let (height, block) = result.unwrap();
+            cb.on_block(&block, height).unwrap();
+        }
+
+        let utxo = |txid, height, value, script_pubkey| {
+            (
+                TxOutpoint::new(txid, 0).to_bytes(),
+                UtxoValue {
+                    height,
+                    value,
+                    script_pubkey,
+                },
+            )
+        };
+        let expected = HashMap::from([
+            utxo(txid(c3()), 3, 50 * COIN + 20000, vec![0x51]),
+            utxo(txid(change()), 3, 20 * COIN - 20000, p2pkh(3)),
+            utxo(txid(c4()), 4, 50 * COIN + 10000, vec![0x51]),
+            utxo(txid(merge()), 4, 80 * COIN - 10000, p2pkh(4)),
+            utxo(txid(c5()), 5, 50 * COIN, vec![0x51]),
+        ]);
+        assert_eq!(collect(&mut cb.utxo_set), expected);
+        assert_eq!((cb.in_count, cb.out_count), (4, 9));
+
+        cb.on_complete(5).unwrap();
+        assert_eq!(cb.exit_code(), 0);
+        let dump = fs::read_to_string(tmp_dir.path().join("utxo-0-5.csv")).unwrap();
+        assert_eq!(dump.lines().count(), 1 + expected.len());
+    }
+
+    #[test]
+    fn test_missing_inputs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut cb = UtxoDump {
+            dump_folder: PathBuf::from(tmp_dir.path()),
+            writer: UtxoDump::create_writer(100, tmp_dir.path().join("utxo.csv.tmp")).unwrap(),
+            utxo_set: SpillMap::new(None, 0).unwrap(),
+            unit: Unit::Btc,
+            start_height: 5,
+            in_count: 0,
+            out_count: 0,
+            missing_inputs: 0,
+            hash_order: HashOrder::Display,
+        };
+        let unknown = new_tx(
+            vec![TxOutpoint::new(sha256d::Hash::hash(b"prev"), 0)],
+            &[10],
+        );
+        let block = new_block(vec![unknown]);
+
+        // Outputs created before the start height are expected to be unknown
+        cb.apply_block(&block, 5).unwrap();
+        assert_eq!(cb.exit_code(), 0);
+
+        cb.start_height = 0;
+        cb.apply_block(&block, 5).unwrap();
+        assert_eq!(cb.missing_inputs, 1);
+        cb.on_complete(5).unwrap();
+        assert_eq!(cb.exit_code(), EXIT_INCONSISTENT);
+        let dump = fs::read_to_string(tmp_dir.path().join("utxo-0-5.csv")).unwrap();
+        assert!(dump.ends_with(";5;0.00000010;51\n"));
+    }
+
+    #[test]
+    fn test_utxo_set_in_memory() {
+        test_utxo_set(None);
+    }
+
+    #[test]
+    fn test_utxo_set_spilled() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let spill_path = spill_dir.path().join("utxodump-spill");
+        test_utxo_set(Some(spill_path.clone()));
+        assert!(!spill_path.exists());
+    }
+}
@@ -0,0 +1,51 @@
+use clap::{ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::Callback;
+use crate::errors::OpResult;
+
+/// Counts blocks and transactions without producing any output.
+///
+/// Used by `--validate-only` in place of a user callback, so it is not registered as subcommand.
+#[derive(Default)]
+pub struct Validate {
+    start_height: u64,
+    block_count: u64,
+    tx_count: u64,
+}
+
+impl Callback for Validate {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("validate").about("Parses all blocks without invoking a callback")
+    }
+
+    fn new(_: &ArgMatches) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Validate::default())
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Validating blocks ...");
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+        self.block_count += 1;
+        self.tx_count += block.tx_count.value;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        info!(target: "callback", "Done.\nParsed blocks from height {} to {}:\n\
+                                   \t-> blocks:       {:9}\n\
+                                   \t-> transactions: {:9}",
+             self.start_height, block_height, self.block_count, self.tx_count);
+        Ok(())
+    }
+}
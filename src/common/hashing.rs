@@ -0,0 +1,204 @@
+//! Double-SHA256 used to hash headers, transactions and merkle trees.
+//!
+//! The portable implementation of `bitcoin_hashes` is always available. With the `sha-ni`
+//! feature an implementation using the x86 SHA extensions is added, which is used if the
+//! CPU supports it. See `cargo bench --features sha-ni` for a comparison.
+use std::sync::OnceLock;
+
+use bitcoin::hashes::{sha256d, Hash};
+
+/// A double-SHA256 implementation
+pub trait Backend: Sync {
+    /// Short name for logs and benchmarks
+    fn name(&self) -> &'static str;
+    fn hash(&self, data: &[u8]) -> sha256d::Hash;
+}
+
+/// Portable implementation of `bitcoin_hashes`
+pub struct Software;
+
+impl Backend for Software {
+    fn name(&self) -> &'static str {
+        "software"
+    }
+
+    fn hash(&self, data: &[u8]) -> sha256d::Hash {
+        sha256d::Hash::hash(data)
+    }
+}
+
+/// Returns all backends supported by this build and CPU, the fastest one first.
+pub fn backends() -> Vec<&'static dyn Backend> {
+    let mut backends: Vec<&'static dyn Backend> = Vec::with_capacity(2);
+    #[cfg(all(feature = "sha-ni", target_arch = "x86_64"))]
+    if x86::ShaNi::is_supported() {
+        backends.push(&x86::ShaNi);
+    }
+    backends.push(&Software);
+    backends
+}
+
+/// Returns the fastest backend, it is selected on first use.
+pub fn backend() -> &'static dyn Backend {
+    static BACKEND: OnceLock<&'static dyn Backend> = OnceLock::new();
+    *BACKEND.get_or_init(|| backends()[0])
+}
+
+/// Double-SHA256 of the given data using the fastest backend
+#[inline]
+pub fn sha256d(data: &[u8]) -> sha256d::Hash {
+    backend().hash(data)
+}
+
+#[cfg(all(feature = "sha-ni", target_arch = "x86_64"))]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use bitcoin::hashes::{sha256d, Hash};
+
+    use super::Backend;
+
+    const H: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    /// SHA256 using the SHA extensions of x86 CPUs (Intel since Goldmont, AMD since Zen)
+    pub struct ShaNi;
+
+    impl ShaNi {
+        pub fn is_supported() -> bool {
+            is_x86_feature_detected!("sha")
+                && is_x86_feature_detected!("sse2")
+                && is_x86_feature_detected!("ssse3")
+                && is_x86_feature_detected!("sse4.1")
+        }
+    }
+
+    impl Backend for ShaNi {
+        fn name(&self) -> &'static str {
+            "sha-ni"
+        }
+
+        fn hash(&self, data: &[u8]) -> sha256d::Hash {
+            let first = sha256(data);
+            sha256d::Hash::from_byte_array(sha256(&first))
+        }
+    }
+
+    /// Pads the message and compresses it, see FIPS 180-4 section 5.1.1
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut state = H;
+        let full = data.len() - data.len() % 64;
+        // A message of n bytes needs n + 9 bytes with padding, so at most two more blocks
+        let mut tail = [0u8; 128];
+        let rest = data.len() - full;
+        tail[..rest].copy_from_slice(&data[full..]);
+        tail[rest] = 0x80;
+        let tail_len = if rest < 56 { 64 } else { 128 };
+        tail[tail_len - 8..tail_len].copy_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+        // Safe since ShaNi is only handed out if the CPU supports the used instructions
+        unsafe {
+            compress(&mut state, &data[..full]);
+            compress(&mut state, &tail[..tail_len]);
+        }
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Message schedule for the next four rounds
+    #[inline(always)]
+    unsafe fn schedule(w0: __m128i, w1: __m128i, w2: __m128i, w3: __m128i) -> __m128i {
+        let t = _mm_add_epi32(_mm_sha256msg1_epu32(w0, w1), _mm_alignr_epi8(w3, w2, 4));
+        _mm_sha256msg2_epu32(t, w3)
+    }
+
+    /// Four rounds with the message words `w` and constants `K[4 * i..4 * i + 4]`
+    #[inline(always)]
+    unsafe fn rounds(abef: &mut __m128i, cdgh: &mut __m128i, w: __m128i, i: usize) {
+        let k = _mm_loadu_si128(K.as_ptr().add(4 * i) as *const __m128i);
+        let wk = _mm_add_epi32(w, k);
+        *cdgh = _mm_sha256rnds2_epu32(*cdgh, *abef, wk);
+        *abef = _mm_sha256rnds2_epu32(*abef, *cdgh, _mm_shuffle_epi32(wk, 0x0E));
+    }
+
+    /// Compresses the 64 byte blocks of `blocks` into `state`
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    unsafe fn compress(state: &mut [u32; 8], blocks: &[u8]) {
+        // Reverses the bytes of each word, the message is big endian
+        let mask = _mm_set_epi64x(0x0c0d0e0f08090a0bu64 as i64, 0x0405060700010203u64 as i64);
+
+        // The rounds instruction expects the state as ABEF and CDGH
+        let dcba = _mm_loadu_si128(state.as_ptr() as *const __m128i);
+        let hgfe = _mm_loadu_si128(state.as_ptr().add(4) as *const __m128i);
+        let cdab = _mm_shuffle_epi32(dcba, 0xB1);
+        let efgh = _mm_shuffle_epi32(hgfe, 0x1B);
+        let mut abef = _mm_alignr_epi8(cdab, efgh, 8);
+        let mut cdgh = _mm_blend_epi16(efgh, cdab, 0xF0);
+
+        for block in blocks.chunks_exact(64) {
+            let (abef_prev, cdgh_prev) = (abef, cdgh);
+            let ptr = block.as_ptr() as *const __m128i;
+            let mut w = [
+                _mm_shuffle_epi8(_mm_loadu_si128(ptr), mask),
+                _mm_shuffle_epi8(_mm_loadu_si128(ptr.add(1)), mask),
+                _mm_shuffle_epi8(_mm_loadu_si128(ptr.add(2)), mask),
+                _mm_shuffle_epi8(_mm_loadu_si128(ptr.add(3)), mask),
+            ];
+            for (i, word) in w.iter().enumerate() {
+                rounds(&mut abef, &mut cdgh, *word, i);
+            }
+            for i in 4..16 {
+                let next = schedule(w[i % 4], w[(i + 1) % 4], w[(i + 2) % 4], w[(i + 3) % 4]);
+                w[i % 4] = next;
+                rounds(&mut abef, &mut cdgh, next, i);
+            }
+            abef = _mm_add_epi32(abef, abef_prev);
+            cdgh = _mm_add_epi32(cdgh, cdgh_prev);
+        }
+
+        let feba = _mm_shuffle_epi32(abef, 0x1B);
+        let dchg = _mm_shuffle_epi32(cdgh, 0xB1);
+        let dcba = _mm_blend_epi16(feba, dchg, 0xF0);
+        let hgef = _mm_alignr_epi8(dchg, feba, 8);
+        _mm_storeu_si128(state.as_mut_ptr() as *mut __m128i, dcba);
+        _mm_storeu_si128(state.as_mut_ptr().add(4) as *mut __m128i, hgef);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backends_agree() {
+        let backends = backends();
+        assert_eq!(backends.last().unwrap().name(), "software");
+        assert_eq!(backend().name(), backends[0].name());
+
+        // Covers messages ending right before, at and after the block and padding boundaries
+        let data = (0..300u32).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+        for len in 0..data.len() {
+            let expected = sha256d::Hash::hash(&data[..len]);
+            for backend in &backends {
+                assert_eq!(backend.hash(&data[..len]), expected, "{}", backend.name());
+            }
+        }
+    }
+}
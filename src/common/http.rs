@@ -0,0 +1,614 @@
+//! Minimal HTTP/1.1 client for range requests, used to read blk files from a web server or an
+//! S3-compatible object store instead of the local filesystem.
+//!
+//! Only plain `http://` URLs are supported, there is no TLS implementation.
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Bytes fetched per range request, blk files are read in chunks of this size
+pub const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parsed `http://host[:port]/path` URL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> io::Result<Url> {
+        let invalid =
+            |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{} {}", msg, url));
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => {
+                return Err(invalid(
+                    "HTTPS is not supported, use a plain HTTP endpoint or a local proxy for",
+                ))
+            }
+            _ => return Err(invalid("Expected an http:// URL, got")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host, port),
+                Err(_) => return Err(invalid("Invalid port in")),
+            },
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("Missing host in"));
+        }
+        Ok(Url {
+            host: String::from(host),
+            port,
+            path: String::from(path),
+        })
+    }
+
+    /// Appends the file name to the path, treating the path as directory
+    pub fn join(&self, name: &str) -> Url {
+        let mut path = self.path.clone();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(name);
+        Url {
+            host: self.host.clone(),
+            port: self.port,
+            path,
+        }
+    }
+}
+
+impl Url {
+    /// Value of the `Host` header, the port is only omitted if it is the default one
+    fn host_header(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    // False if the server closes the connection after this response
+    keep_alive: bool,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.header("content-length")?.trim().parse().ok()
+    }
+}
+
+/// Reads a line without the trailing CRLF, fails if the stream ends before
+fn read_line<R: BufRead>(reader: &mut R, msg: &str) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\r\n") {
+        return Err(invalid_data(msg));
+    }
+    line.truncate(line.len() - 2);
+    Ok(line)
+}
+
+/// Reads a single response, the body is delimited by its length so the connection can be reused
+fn read_response<R: BufRead>(reader: &mut R, head_only: bool) -> io::Result<Response> {
+    let status_line = read_line(reader, "Incomplete HTTP response header")?;
+    let status = String::from_utf8_lossy(&status_line)
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid_data("Invalid HTTP status line"))?;
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader, "Incomplete HTTP response header")?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = String::from_utf8_lossy(&line).split_once(':') {
+            headers.push((String::from(key.trim()), String::from(value.trim())));
+        }
+    }
+    let mut response = Response {
+        status,
+        headers,
+        body: Vec::new(),
+        keep_alive: true,
+    };
+    if let Some(connection) = response.header("connection") {
+        response.keep_alive = !connection.eq_ignore_ascii_case("close");
+    }
+    if head_only {
+        return Ok(response);
+    }
+    response.body = match response.header("transfer-encoding") {
+        Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => decode_chunked(reader)?,
+        _ => match response.content_length() {
+            Some(len) => {
+                let mut body = vec![0u8; len as usize];
+                reader.read_exact(&mut body).map_err(|e| match e.kind() {
+                    io::ErrorKind::UnexpectedEof => invalid_data("HTTP response body is truncated"),
+                    _ => e,
+                })?;
+                body
+            }
+            // Without a length the body ends with the connection
+            None => {
+                response.keep_alive = false;
+                let mut body = Vec::new();
+                reader.read_to_end(&mut body)?;
+                body
+            }
+        },
+    };
+    Ok(response)
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`
+fn decode_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, "Invalid HTTP chunk")?;
+        let size = String::from_utf8_lossy(&line);
+        // Chunk extensions follow a semicolon
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| invalid_data("Invalid HTTP chunk size"))?;
+        if size == 0 {
+            // Trailer fields up to the final empty line
+            while !read_line(reader, "HTTP response body is truncated")?.is_empty() {}
+            return Ok(body);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(|_| invalid_data("HTTP response body is truncated"))?;
+        if !read_line(reader, "HTTP response body is truncated")?.is_empty() {
+            return Err(invalid_data("Invalid HTTP chunk"));
+        }
+    }
+}
+
+fn status_error(url: &Url, status: u16) -> io::Error {
+    io::Error::other(format!("Unexpected HTTP status {} for {}", status, url))
+}
+
+/// Keep-alive connection which sends all requests over the same TCP connection.
+/// It reconnects if the server closed it or a request goes to another host.
+#[derive(Default)]
+pub struct Connection {
+    stream: Option<((String, u16), BufReader<TcpStream>)>,
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        Connection { stream: None }
+    }
+
+    fn request(
+        &mut self,
+        url: &Url,
+        method: &str,
+        range: Option<(u64, u64)>,
+    ) -> io::Result<Response> {
+        let addr = (url.host.clone(), url.port);
+        if self.stream.as_ref().map(|(a, _)| a) == Some(&addr) {
+            // Servers close idle connections at any time, the request is retried on a new one
+            match self.send(url, method, range) {
+                Ok(response) => return Ok(response),
+                Err(e) => debug!(target: "http", "Reconnecting to {}: {}", url, e),
+            }
+        }
+        let stream = TcpStream::connect((url.host.as_str(), url.port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        self.stream = Some((addr, BufReader::new(stream)));
+        self.send(url, method, range)
+    }
+
+    /// Sends the request over the open connection, which is dropped after errors and unless the
+    /// server keeps it alive
+    fn send(&mut self, url: &Url, method: &str, range: Option<(u64, u64)>) -> io::Result<Response> {
+        let (_, reader) = self.stream.as_mut().unwrap();
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rusty-blockparser/{}\r\n",
+            method,
+            url.path,
+            url.host_header(),
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some((start, end)) = range {
+            head.push_str(&format!("Range: bytes={}-{}\r\n", start, end));
+        }
+        head.push_str("\r\n");
+        let response = reader
+            .get_mut()
+            .write_all(head.as_bytes())
+            .and_then(|_| read_response(reader, method == "HEAD"));
+        if !response.as_ref().is_ok_and(|response| response.keep_alive) {
+            self.stream = None;
+        }
+        response
+    }
+
+    /// Returns the size of the resource, or None if it doesn't exist
+    pub fn content_length(&mut self, url: &Url) -> io::Result<Option<u64>> {
+        let response = self.request(url, "HEAD", None)?;
+        match response.status {
+            200..=299 => response
+                .content_length()
+                .map(Some)
+                .ok_or_else(|| invalid_data("HTTP response without Content-Length")),
+            404 => Ok(None),
+            status => Err(status_error(url, status)),
+        }
+    }
+
+    /// Fetches `len` bytes starting at `start`, fewer if the resource ends before
+    pub fn get_range(&mut self, url: &Url, start: u64, len: u64) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let response = self.request(url, "GET", Some((start, start + len - 1)))?;
+        match response.status {
+            206 => Ok(response.body),
+            // The server ignored the range, the whole file would be downloaded for every chunk
+            200 => Err(io::Error::other(format!(
+                "{} doesn't support range requests, it sent the whole file",
+                url
+            ))),
+            416 => Ok(Vec::new()),
+            status => Err(status_error(url, status)),
+        }
+    }
+}
+
+/// Seekable reader over a remote file which fetches one chunk at a time as it advances.
+///
+/// All chunks are fetched over one keep-alive connection. With a cache directory fetched chunks
+/// are stored as `<host>_<port>_<path>.<size>.<chunk>`, with the slashes of the path replaced,
+/// and read from there on later runs, so a file is only downloaded once. Files of other servers
+/// or of another size, e.g. a blk file which was still being written, never share chunks.
+pub struct RangeReader {
+    url: Url,
+    size: u64,
+    pos: u64,
+    chunk_size: u64,
+    chunk: Option<(u64, Vec<u8>)>,
+    cache_dir: Option<PathBuf>,
+    connection: Connection,
+}
+
+impl RangeReader {
+    pub fn new(url: Url, size: u64, cache_dir: Option<PathBuf>) -> Self {
+        RangeReader {
+            url,
+            size,
+            pos: 0,
+            chunk_size: CHUNK_SIZE,
+            chunk: None,
+            cache_dir,
+            connection: Connection::new(),
+        }
+    }
+
+    fn cache_path(&self, index: u64) -> Option<PathBuf> {
+        let name = format!(
+            "{}_{}_{}.{}.{}",
+            self.url.host,
+            self.url.port,
+            self.url.path.trim_start_matches('/').replace('/', "_"),
+            self.size,
+            index
+        );
+        self.cache_dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    fn fetch_chunk(&mut self, index: u64) -> io::Result<Vec<u8>> {
+        let cache_path = self.cache_path(index);
+        if let Some(ref path) = cache_path {
+            if path.exists() {
+                return fs::read(path);
+            }
+        }
+        let start = index * self.chunk_size;
+        let len = self.chunk_size.min(self.size - start);
+        debug!(target: "http", "Fetching {} bytes at offset {} of {} ...", len, start, &self.url);
+        let data = self.connection.get_range(&self.url, start, len)?;
+        if data.len() as u64 != len {
+            return Err(invalid_data(&format!(
+                "Expected {} bytes at offset {} of {}, got {}",
+                len,
+                start,
+                &self.url,
+                data.len()
+            )));
+        }
+        if let Some(path) = cache_path {
+            // Written to a tmp file first, so an interrupted run leaves no partial chunk behind
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            fs::write(&tmp_path, &data)?;
+            fs::rename(&tmp_path, &path)?;
+        }
+        Ok(data)
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.pos / self.chunk_size;
+        if self.chunk.as_ref().map(|(i, _)| *i) != Some(index) {
+            self.chunk = Some((index, self.fetch_chunk(index)?));
+        }
+        let (_, chunk) = self.chunk.as_ref().unwrap();
+        let offset = (self.pos - index * self.chunk_size) as usize;
+        let len = buf.len().min(chunk.len() - offset);
+        buf[..len].copy_from_slice(&chunk[offset..offset + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Requests and connections served by the test server so far
+    #[derive(Clone, Default)]
+    pub struct Served {
+        pub requests: Arc<AtomicUsize>,
+        pub connections: Arc<AtomicUsize>,
+    }
+
+    /// Serves the given files with range support and keep-alive on a local port, except for
+    /// `close.dat` whose responses close the connection. Returns the base URL and the counters.
+    pub fn serve(files: HashMap<String, Vec<u8>>) -> (String, Served) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let url = format!("http://{}/blocks", addr);
+        let served = Served::default();
+        let counters = served.clone();
+        let files = Arc::new(files);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                counters.connections.fetch_add(1, Ordering::SeqCst);
+                let (files, counters) = (Arc::clone(&files), counters.clone());
+                thread::spawn(move || handle(stream.unwrap(), addr, &files, &counters));
+            }
+        });
+        (url, served)
+    }
+
+    fn handle(
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        files: &HashMap<String, Vec<u8>>,
+        served: &Served,
+    ) {
+        loop {
+            let mut head = Vec::new();
+            let mut byte = [0u8];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            if !head.ends_with(b"\r\n\r\n") {
+                // Closed by the client
+                return;
+            }
+            served.requests.fetch_add(1, Ordering::SeqCst);
+            let head = String::from_utf8(head).unwrap();
+            let host = format!("Host: {}\r\n", addr);
+            assert!(head.contains(&host), "{}", head);
+            let mut parts = head.split(' ');
+            let method = parts.next().unwrap();
+            let path = parts.next().unwrap().trim_start_matches("/blocks/");
+            let range = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Range: bytes="))
+                .and_then(|range| range.split_once('-'))
+                .map(|(s, e)| (s.parse::<usize>().unwrap(), e.parse::<usize>().unwrap()));
+            let close = path == "close.dat";
+            let (status, body) = match (files.get(path), range) {
+                (None, _) => ("404 Not Found", &[][..]),
+                (Some(data), Some((start, end))) if path != "norange.dat" => (
+                    "206 Partial Content",
+                    &data[start..=end.min(data.len() - 1)],
+                ),
+                (Some(data), _) => ("200 OK", &data[..]),
+            };
+            let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, body.len());
+            if close {
+                response.push_str("Connection: close\r\n");
+            }
+            response.push_str("\r\n");
+            let mut response = response.into_bytes();
+            if method != "HEAD" {
+                response.extend_from_slice(body);
+            }
+            stream.write_all(&response).unwrap();
+            if close {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let url = Url::parse("http://minio.local:9000/bucket/blocks").unwrap();
+        assert_eq!(url.host, "minio.local");
+        assert_eq!(url.port, 9000);
+        assert_eq!(url.join("blk00000.dat").path, "/bucket/blocks/blk00000.dat");
+        assert_eq!(
+            Url::parse("http://127.0.0.1").unwrap().to_string(),
+            "http://127.0.0.1:80/"
+        );
+        assert_eq!(url.host_header(), "minio.local:9000");
+        assert_eq!(
+            Url::parse("http://minio.local/").unwrap().host_header(),
+            "minio.local"
+        );
+        let err = Url::parse("https://s3.amazonaws.com/bucket").unwrap_err();
+        assert!(err.to_string().contains("HTTPS is not supported"));
+        assert!(Url::parse("/home/user/.bitcoin/blocks").is_err());
+        assert!(Url::parse("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_read_response() {
+        let read = |data: &[u8]| read_response(&mut &data[..], false);
+        let mut data = &b"HTTP/1.1 206 Partial\r\nContent-Length: 3\r\n\r\nabcHTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"[..];
+        let response = read_response(&mut data, false).unwrap();
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"abc");
+        assert!(response.keep_alive);
+        // The next response on the same connection follows the body
+        assert_eq!(read_response(&mut data, false).unwrap().status, 404);
+        assert!(data.is_empty());
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;x=y\r\nde\r\n0\r\n\r\n";
+        assert_eq!(read(chunked).unwrap().body, b"abcde");
+        let response = read(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nabc").unwrap();
+        assert_eq!(response.body, b"abc");
+        assert!(!response.keep_alive);
+        let response = read(b"HTTP/1.1 200 OK\r\n\r\nabc").unwrap();
+        assert_eq!(response.body, b"abc");
+        assert!(!response.keep_alive);
+        let head = read_response(
+            &mut &b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n"[..],
+            true,
+        );
+        assert_eq!(head.unwrap().content_length(), Some(9));
+
+        assert!(read(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nabc").is_err());
+        assert!(read(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab").is_err());
+        assert!(read(b"garbage").is_err());
+        assert!(read(b"").is_err());
+    }
+
+    #[test]
+    fn test_range_reader() {
+        let data = (0..100u8).collect::<Vec<u8>>();
+        let files = HashMap::from([
+            (String::from("blk00000.dat"), data.clone()),
+            (String::from("norange.dat"), data.clone()),
+            (String::from("close.dat"), data.clone()),
+        ]);
+        let (base, served) = serve(files);
+        let base = Url::parse(&base).unwrap();
+        let url = base.join("blk00000.dat");
+        let mut connection = Connection::new();
+        assert_eq!(connection.content_length(&url).unwrap(), Some(100));
+        let missing = base.join("blk00001.dat");
+        assert_eq!(connection.content_length(&missing).unwrap(), None);
+        assert_eq!(connection.get_range(&url, 98, 10).unwrap(), vec![98, 99]);
+
+        // Servers ignoring the range are refused
+        let norange = base.join("norange.dat");
+        assert!(connection.get_range(&norange, 0, 10).is_err());
+        assert_eq!(served.connections.load(Ordering::SeqCst), 1);
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut reader = RangeReader::new(url.clone(), 100, Some(cache_dir.path().to_path_buf()));
+        reader.chunk_size = 30;
+        reader.seek(SeekFrom::Start(25)).unwrap();
+        let mut buf = [0u8; 10];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf.to_vec(), (25..35).collect::<Vec<u8>>());
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[35..]);
+        // All four chunks were fetched over a single connection
+        assert_eq!(served.connections.load(Ordering::SeqCst), 2);
+        let chunk = format!("{}_{}_blocks_blk00000.dat.100.3", base.host, base.port);
+        assert!(cache_dir.path().join(chunk).exists());
+
+        // All chunks are cached now, no further requests are needed
+        let requests = served.requests.load(Ordering::SeqCst);
+        let mut reader = RangeReader::new(url.clone(), 100, Some(cache_dir.path().to_path_buf()));
+        reader.chunk_size = 30;
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+        assert_eq!(served.requests.load(Ordering::SeqCst), requests);
+        assert!(reader.seek(SeekFrom::Current(-200)).is_err());
+
+        // A file of another size doesn't use the cached chunks
+        let mut reader = RangeReader::new(url, 50, Some(cache_dir.path().to_path_buf()));
+        reader.chunk_size = 30;
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data[..50]);
+        assert_eq!(served.requests.load(Ordering::SeqCst), requests + 2);
+        assert_eq!(served.connections.load(Ordering::SeqCst), 3);
+
+        // Connections closed by the server are reopened for the next chunk
+        let mut reader = RangeReader::new(base.join("close.dat"), 100, None);
+        reader.chunk_size = 30;
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+        assert_eq!(served.connections.load(Ordering::SeqCst), 3 + 4);
+    }
+}
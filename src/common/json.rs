@@ -0,0 +1,245 @@
+//! Minimal JSON reader for configuration files.
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    // Keeps the order of the keys as given
+    Object(Vec<(String, Value)>),
+}
+
+/// Parses a complete JSON document
+pub fn parse(input: &str) -> OpResult<Value> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> OpError {
+        OpError::new(OpErrorKind::InvalidArgsError)
+            .join_msg(&format!("Invalid JSON at offset {}: {}.", self.pos, msg))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> OpResult<()> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn parse_value(&mut self) -> OpResult<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => Ok(Value::String(self.parse_string()?)),
+            Some('t') => self.parse_literal("true", Value::Bool(true)),
+            Some('f') => self.parse_literal("false", Value::Bool(false)),
+            Some('n') => self.parse_literal("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Value) -> OpResult<Value> {
+        let end = self.pos + literal.len();
+        if end <= self.chars.len()
+            && self.chars[self.pos..end]
+                .iter()
+                .copied()
+                .eq(literal.chars())
+        {
+            self.pos = end;
+            Ok(value)
+        } else {
+            Err(self.error("unknown literal"))
+        }
+    }
+
+    fn parse_number(&mut self) -> OpResult<Value> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.pos += 1;
+        }
+        let number = self.chars[start..self.pos].iter().collect::<String>();
+        number
+            .parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn parse_string(&mut self) -> OpResult<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let c = self
+                .peek()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    match escaped {
+                        '"' | '\\' | '/' => string.push(escaped),
+                        'n' => string.push('\n'),
+                        't' => string.push('\t'),
+                        'r' => string.push('\r'),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        'u' => {
+                            let hex = self
+                                .chars
+                                .get(self.pos..self.pos + 4)
+                                .map(|hex| hex.iter().collect::<String>())
+                                .ok_or_else(|| self.error("truncated unicode escape"))?;
+                            self.pos += 4;
+                            let c = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid unicode escape"))?;
+                            string.push(c);
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> OpResult<Value> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> OpResult<Value> {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            entries.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(entries));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(
+            r#" {"dir": "/home/user/.bitcoin", "end": 1000, "verify": true,
+                "args": ["a\"b", "ä\n"], "none": null, "empty": {}, "neg": -1.5e1} "#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (
+                    String::from("dir"),
+                    Value::String(String::from("/home/user/.bitcoin"))
+                ),
+                (String::from("end"), Value::Number(1000.0)),
+                (String::from("verify"), Value::Bool(true)),
+                (
+                    String::from("args"),
+                    Value::Array(vec![
+                        Value::String(String::from("a\"b")),
+                        Value::String(String::from("ä\n"))
+                    ])
+                ),
+                (String::from("none"), Value::Null),
+                (String::from("empty"), Value::Object(vec![])),
+                (String::from("neg"), Value::Number(-15.0)),
+            ])
+        );
+        assert_eq!(parse("[]").unwrap(), Value::Array(vec![]));
+
+        for invalid in [
+            "",
+            "{",
+            "{\"a\" 1}",
+            "[1,]",
+            "tru",
+            "\"abc",
+            "{} x",
+            "{\"a\": 1,}",
+        ] {
+            let err = parse(invalid).err().unwrap();
+            assert!(
+                matches!(err.kind, OpErrorKind::InvalidArgsError),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
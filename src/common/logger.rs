@@ -1,21 +1,54 @@
 use chrono::{DateTime, Utc};
-use std::io::{stderr, stdout, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{stderr, Write};
+use std::path::Path;
+use std::sync::Mutex;
 use std::time::SystemTime;
 
-use log::{self, Level, LevelFilter, Metadata, Record, SetLoggerError};
+use log::{self, LevelFilter, Metadata, Record, SetLoggerError};
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
 pub struct SimpleLogger {
     level_filter: LevelFilter,
+    // Every record is also appended to this file if set
+    log_file: Option<Mutex<File>>,
 }
 
 impl SimpleLogger {
     pub fn init(level_filter: LevelFilter) -> Result<(), SetLoggerError> {
-        let logger = SimpleLogger { level_filter };
+        let logger = SimpleLogger {
+            level_filter,
+            log_file: None,
+        };
         log::set_boxed_logger(Box::new(logger))?;
         log::set_max_level(level_filter);
         Ok(())
     }
 
+    /// Same as init(), but also appends all records to the given file
+    pub fn init_with_file(level_filter: LevelFilter, path: &Path) -> OpResult<()> {
+        let logger = SimpleLogger::with_file(level_filter, path)?;
+        log::set_boxed_logger(Box::new(logger))
+            .map_err(|e| OpError::new(OpErrorKind::RuntimeError).join_msg(&e.to_string()))?;
+        log::set_max_level(level_filter);
+        Ok(())
+    }
+
+    fn with_file(level_filter: LevelFilter, path: &Path) -> OpResult<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                OpError::from(e).join_msg(&format!("Unable to open log file {}:", path.display()))
+            })?;
+        Ok(SimpleLogger {
+            level_filter,
+            log_file: Some(Mutex::new(file)),
+        })
+    }
+
     fn format_log(&self, record: &Record) -> String {
         let datetime: DateTime<Utc> = SystemTime::now().into();
         format!(
@@ -26,6 +59,18 @@ impl SimpleLogger {
             record.args()
         )
     }
+
+    /// Log files are read later on, so they get the full date
+    fn format_file_log(&self, record: &Record) -> String {
+        let datetime: DateTime<Utc> = SystemTime::now().into();
+        format!(
+            "[{}] {} - {}: {}\n",
+            datetime.format("%F %T%.3f UTC"),
+            record.level(),
+            record.target(),
+            record.args()
+        )
+    }
 }
 
 impl log::Log for SimpleLogger {
@@ -35,17 +80,56 @@ impl log::Log for SimpleLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
+            // Everything goes to stderr, so callbacks can stream their output to stdout
             let line = self.format_log(record);
-            match record.level() {
-                Level::Error => {
-                    stderr().write_all(line.as_bytes()).unwrap();
-                }
-                _ => {
-                    stdout().write_all(line.as_bytes()).unwrap();
+            let _ = stderr().write_all(line.as_bytes());
+            // The file is unbuffered and written once per record, so a crash keeps all previous lines
+            if let Some(ref log_file) = self.log_file {
+                if let Ok(mut file) = log_file.lock() {
+                    let _ = file.write_all(self.format_file_log(record).as_bytes());
                 }
             }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(ref log_file) = self.log_file {
+            if let Ok(mut file) = log_file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Log};
+    use std::fs;
+
+    #[test]
+    fn test_log_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("parser.log");
+        fs::write(&path, "previous run\n").unwrap();
+        let logger = SimpleLogger::with_file(LevelFilter::Info, &path).unwrap();
+        for (level, msg) in [(Level::Info, "kept"), (Level::Debug, "filtered")] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", msg))
+                    .level(level)
+                    .target("test")
+                    .build(),
+            );
+        }
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "previous run");
+        // e.g. [2023-07-01 12:00:00.000 UTC] INFO - test: kept
+        assert!(lines[1].ends_with(" UTC] INFO - test: kept"));
+        assert_eq!(lines[1].find(" UTC]"), Some(24));
+
+        assert!(SimpleLogger::with_file(LevelFilter::Info, tmp_dir.path()).is_err());
+    }
 }
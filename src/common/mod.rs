@@ -1,2 +1,8 @@
+pub mod hashing;
+pub mod http;
+pub mod json;
 pub mod logger;
+pub mod shutdown;
+#[cfg(test)]
+pub mod testutil;
 pub mod utils;
@@ -0,0 +1,36 @@
+//! Graceful shutdown on Ctrl-C.
+//!
+//! The first SIGINT only sets a flag, the parser finishes the current block and runs the usual
+//! finalization (callback on_complete(), checkpoint). A second SIGINT exits immediately.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the SIGINT handler. Does nothing on platforms other than unix.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    // Only async-signal-safe calls are allowed here
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Asks the parser to stop after the current block, as if Ctrl-C was pressed once
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true once a shutdown was requested
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
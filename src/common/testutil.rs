@@ -0,0 +1,264 @@
+//! Builders for the transactions and blocks used by the unit tests.
+//!
+//! Unless given otherwise inputs spend with an `OP_TRUE` script_sig and a final sequence number,
+//! outputs pay to `OP_TRUE` and headers are all zeros apart from version 1.
+use bitcoin::hashes::{sha256d, Hash};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::tx::{RawTx, TxInput, TxOutpoint, TxOutput};
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::common::hashing;
+
+/// Outpoint spent by coinbase inputs
+pub fn null_outpoint() -> TxOutpoint {
+    TxOutpoint::new(sha256d::Hash::all_zeros(), 0xFFFFFFFF)
+}
+
+/// P2PKH script paying to the hash160 filled with `id`
+pub fn p2pkh(id: u8) -> Vec<u8> {
+    let mut script = vec![0x76, 0xa9, 0x14];
+    script.extend_from_slice(&[id; 20]);
+    script.extend_from_slice(&[0x88, 0xac]);
+    script
+}
+
+/// Transaction spending `inputs` with an `OP_TRUE` output for each of the values
+pub fn new_tx(inputs: Vec<TxOutpoint>, values: &[u64]) -> RawTx {
+    TxBuilder::new().inputs(inputs).outputs(values).build()
+}
+
+/// Coinbase with an `OP_TRUE` output for each of the values
+pub fn coinbase(values: &[u64]) -> RawTx {
+    TxBuilder::coinbase().outputs(values).build()
+}
+
+/// Block of the given transactions with an all zero header
+pub fn new_block(txs: Vec<RawTx>) -> Block {
+    BlockBuilder::new().txs(txs).build()
+}
+
+/// Block as stored in blk files and bootstrap.dat: network magic, size and the serialized block
+pub fn blk_record(magic: u32, block: &Block) -> Vec<u8> {
+    let mut raw = block.header.value.to_bytes();
+    raw.extend_from_slice(&block.tx_count.to_bytes());
+    for tx in &block.txs {
+        raw.extend_from_slice(&tx.value.to_bytes());
+    }
+    let mut record = magic.to_le_bytes().to_vec();
+    record.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+    record.extend_from_slice(&raw);
+    record
+}
+
+pub struct TxBuilder {
+    tx: RawTx,
+}
+
+impl Default for TxBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TxBuilder {
+    pub fn new() -> Self {
+        TxBuilder {
+            tx: RawTx {
+                version: 1,
+                in_count: VarUint::from(0u8),
+                inputs: vec![],
+                out_count: VarUint::from(0u8),
+                outputs: vec![],
+                locktime: 0,
+                version_id: 0x00,
+            },
+        }
+    }
+
+    /// Transaction with a single input spending the null outpoint
+    pub fn coinbase() -> Self {
+        Self::new().input(null_outpoint())
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.tx.version = version;
+        self
+    }
+
+    pub fn locktime(mut self, locktime: u32) -> Self {
+        self.tx.locktime = locktime;
+        self
+    }
+
+    pub fn input(mut self, outpoint: TxOutpoint) -> Self {
+        self.tx.inputs.push(TxInput {
+            outpoint,
+            script_len: VarUint::from(1u8),
+            script_sig: vec![0x51],
+            seq_no: 0xffffffff,
+            witness: vec![],
+        });
+        self
+    }
+
+    pub fn inputs<I: IntoIterator<Item = TxOutpoint>>(self, outpoints: I) -> Self {
+        outpoints
+            .into_iter()
+            .fold(self, |builder, outpoint| builder.input(outpoint))
+    }
+
+    /// Replaces the script_sig of the last input
+    pub fn script_sig(mut self, script_sig: Vec<u8>) -> Self {
+        let input = self
+            .tx
+            .inputs
+            .last_mut()
+            .expect("script_sig without an input");
+        input.script_len = VarUint::compact(script_sig.len() as u64);
+        input.script_sig = script_sig;
+        self
+    }
+
+    /// Replaces the sequence number of the last input
+    pub fn seq_no(mut self, seq_no: u32) -> Self {
+        self.tx
+            .inputs
+            .last_mut()
+            .expect("seq_no without an input")
+            .seq_no = seq_no;
+        self
+    }
+
+    /// Replaces the witness stack of the last input
+    pub fn witness(mut self, witness: Vec<Vec<u8>>) -> Self {
+        self.tx
+            .inputs
+            .last_mut()
+            .expect("witness without an input")
+            .witness = witness;
+        self
+    }
+
+    pub fn pay(mut self, value: u64, script_pubkey: Vec<u8>) -> Self {
+        self.tx.outputs.push(TxOutput {
+            value,
+            script_len: VarUint::compact(script_pubkey.len() as u64),
+            script_pubkey,
+        });
+        self
+    }
+
+    pub fn pays<I: IntoIterator<Item = (u64, Vec<u8>)>>(self, outputs: I) -> Self {
+        outputs
+            .into_iter()
+            .fold(self, |builder, (value, script_pubkey)| {
+                builder.pay(value, script_pubkey)
+            })
+    }
+
+    /// Adds an output paying `value` to `OP_TRUE`
+    pub fn output(self, value: u64) -> Self {
+        self.pay(value, vec![0x51])
+    }
+
+    pub fn outputs(self, values: &[u64]) -> Self {
+        values
+            .iter()
+            .fold(self, |builder, value| builder.output(*value))
+    }
+
+    pub fn build(mut self) -> RawTx {
+        self.tx.in_count = VarUint::compact(self.tx.inputs.len() as u64);
+        self.tx.out_count = VarUint::compact(self.tx.outputs.len() as u64);
+        self.tx
+    }
+}
+
+pub struct BlockBuilder {
+    size: u32,
+    header: BlockHeader,
+    txs: Vec<RawTx>,
+}
+
+impl Default for BlockBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockBuilder {
+    pub fn new() -> Self {
+        BlockBuilder {
+            size: 0,
+            header: BlockHeader {
+                version: 1,
+                prev_hash: sha256d::Hash::all_zeros(),
+                merkle_root: sha256d::Hash::all_zeros(),
+                timestamp: 0,
+                bits: 0,
+                nonce: 0,
+            },
+            txs: vec![],
+        }
+    }
+
+    pub fn size(mut self, size: u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.header.version = version;
+        self
+    }
+
+    pub fn prev_hash(mut self, prev_hash: sha256d::Hash) -> Self {
+        self.header.prev_hash = prev_hash;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u32) -> Self {
+        self.header.timestamp = timestamp;
+        self
+    }
+
+    pub fn bits(mut self, bits: u32) -> Self {
+        self.header.bits = bits;
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u32) -> Self {
+        self.header.nonce = nonce;
+        self
+    }
+
+    pub fn tx(mut self, tx: RawTx) -> Self {
+        self.txs.push(tx);
+        self
+    }
+
+    pub fn txs(mut self, txs: Vec<RawTx>) -> Self {
+        self.txs.extend(txs);
+        self
+    }
+
+    pub fn build(self) -> Block {
+        let tx_count = VarUint::compact(self.txs.len() as u64);
+        Block::new(self.size, self.header, None, tx_count, self.txs)
+    }
+
+    /// Builds the block with the merkle root of its transactions and the first nonce meeting the
+    /// target. Only feasible for easy targets like the 0x207fffff of regtest.
+    pub fn mine(self) -> Block {
+        let mut block = self.build();
+        let mut header = block.header.value.clone();
+        header.merkle_root = block.compute_merkle_root();
+        while !header.meets_target(&hashing::sha256d(&header.to_bytes())) {
+            header.nonce += 1;
+        }
+        block.header = Hashed::double_sha256(header);
+        block
+    }
+}
@@ -1,27 +1,90 @@
 use bitcoin::hashes::{sha256d, Hash};
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
 use crate::blockchain::parser::types::CoinType;
+use crate::common::hashing::{self, Backend};
 
 /// Calculates merkle root for the whole block
 /// See: https://en.bitcoin.it/wiki/Protocol_documentation#Merkle_Trees
 pub fn merkle_root(hashes: Vec<sha256d::Hash>) -> sha256d::Hash {
+    merkle_root_with(hashing::backend(), hashes)
+}
+
+/// Same as merkle_root(), but hashes with the given backend
+pub fn merkle_root_with(backend: &dyn Backend, hashes: Vec<sha256d::Hash>) -> sha256d::Hash {
+    merkle_tree(backend, hashes, |_| {})
+}
+
+/// Calculates merkle root and whether the tree is mutated (CVE-2012-2459).
+/// Since the last hash of a level with an odd length is paired with itself, duplicating the last
+/// transactions of a block yields the same root. Such a tree has two equal hashes at the
+/// positions of a pair in some level, which a block with unique transactions can't have.
+pub fn merkle_root_mutated(hashes: Vec<sha256d::Hash>) -> (sha256d::Hash, bool) {
+    let mut mutated = false;
+    let root = merkle_tree(hashing::backend(), hashes, |level| {
+        mutated |= level.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+    });
+    (root, mutated)
+}
+
+/// Calculates merkle root and the merkle branch of the hash at `index`, i.e. the hash it is paired
+/// with on each level from the leaves up to the root. Returns None if `index` is out of range.
+pub fn merkle_branch(
+    hashes: Vec<sha256d::Hash>,
+    index: usize,
+) -> Option<(sha256d::Hash, Vec<sha256d::Hash>)> {
+    if index >= hashes.len() {
+        return None;
+    }
+    let mut branch = Vec::new();
+    let mut position = index;
+    let root = merkle_tree(hashing::backend(), hashes, |level| {
+        // The last hash of an odd level is its own sibling
+        branch.push(*level.get(position ^ 1).unwrap_or(&level[position]));
+        position /= 2;
+    });
+    Some((root, branch))
+}
+
+/// Calculates merkle root from a hash, its index in the block and its merkle branch.
+/// This is how SPV clients check that a transaction is part of a block header.
+pub fn merkle_root_from_branch(
+    hash: sha256d::Hash,
+    index: usize,
+    branch: &[sha256d::Hash],
+) -> sha256d::Hash {
+    let mut hash = hash;
+    for (level, sibling) in branch.iter().enumerate() {
+        hash = match (index >> level) & 1 {
+            0 => hashing::sha256d(&[&hash[..], &sibling[..]].concat()),
+            _ => hashing::sha256d(&[&sibling[..], &hash[..]].concat()),
+        };
+    }
+    hash
+}
+
+/// Calculates merkle root, `visit` is called with each level below the root before it is hashed
+fn merkle_tree<F: FnMut(&[sha256d::Hash])>(
+    backend: &dyn Backend,
+    hashes: Vec<sha256d::Hash>,
+    mut visit: F,
+) -> sha256d::Hash {
     let mut hashes = hashes;
 
     while hashes.len() > 1 {
+        visit(&hashes);
         // Calculates double sha hash for each pair. If len is odd, last value is ignored.
         let mut new_hashes = hashes
             .chunks(2)
             .filter(|c| c.len() == 2)
-            .map(|c| sha256d::Hash::hash(&[c[0], c[1]].concat()))
+            .map(|c| backend.hash(&[c[0], c[1]].concat()))
             .collect::<Vec<sha256d::Hash>>();
 
         // If the length is odd, take the last hash twice
         if hashes.len() % 2 == 1 {
             let last_hash = hashes.last().unwrap();
-            new_hashes.push(sha256d::Hash::hash(
-                &[&last_hash[..], &last_hash[..]].concat(),
-            ));
+            new_hashes.push(backend.hash(&[&last_hash[..], &last_hash[..]].concat()));
         }
         hashes = new_hashes;
     }
@@ -35,7 +98,7 @@ pub fn arr_to_hex(data: &[u8]) -> String {
 }
 
 pub fn hex_to_vec(hex_str: &str) -> Vec<u8> {
-    if hex_str.len() % 2 != 0 {
+    if !hex_str.len().is_multiple_of(2) {
         panic!("string length is not even");
     }
 
@@ -45,11 +108,85 @@ pub fn hex_to_vec(hex_str: &str) -> Vec<u8> {
         .collect()
 }
 
-/// Returns default directory. TODO: test on windows
+/// Byte order in which block hashes and txids are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashOrder {
+    /// Reversed, the way Bitcoin Core and block explorers show them
+    #[default]
+    Display,
+    /// The order of the serialized hash, i.e. as the bytes appear in blocks
+    Internal,
+}
+
+/// Hash which is formatted as hex in the given byte order
+pub struct HashFmt<'a> {
+    hash: &'a sha256d::Hash,
+    order: HashOrder,
+}
+
+impl fmt::Display for HashFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.order {
+            HashOrder::Display => fmt::Display::fmt(self.hash, f),
+            HashOrder::Internal => f.write_str(&arr_to_hex(self.hash.as_byte_array())),
+        }
+    }
+}
+
+impl HashOrder {
+    pub fn fmt(self, hash: &sha256d::Hash) -> HashFmt<'_> {
+        HashFmt { hash, order: self }
+    }
+
+    /// Parses a hash written as hex in this byte order
+    pub fn parse(self, s: &str) -> Option<sha256d::Hash> {
+        let hash = s.parse::<sha256d::Hash>().ok()?;
+        match self {
+            HashOrder::Display => Some(hash),
+            HashOrder::Internal => {
+                let mut bytes = hash.to_byte_array();
+                bytes.reverse();
+                Some(sha256d::Hash::from_byte_array(bytes))
+            }
+        }
+    }
+}
+
+/// Blocks directory used if the one of the local node can't be found
+pub const FALLBACK_BLOCKCHAIN_DIR: &str = "blocks";
+
+/// Converts the Unix folder of a coin to the one used on Windows and macOS,
+/// e.g. `.bitcoin/blocks` to `Bitcoin/blocks`
+fn datadir_folder(default_folder: &Path) -> PathBuf {
+    let mut components = default_folder.components();
+    let name = match components.next() {
+        Some(Component::Normal(name)) => name.to_string_lossy(),
+        _ => return default_folder.to_path_buf(),
+    };
+    let mut chars = name.trim_start_matches('.').chars();
+    let capitalized: String = chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default();
+    Path::new(&capitalized).join(components.as_path())
+}
+
+/// Returns the blocks directory of the coin in the default datadir of its node:
+/// `~/.bitcoin/blocks` on Linux, `~/Library/Application Support/Bitcoin/blocks` on macOS
+/// and `%APPDATA%\Bitcoin\blocks` on Windows. Returns None if it doesn't exist.
+pub fn detect_blockchain_dir(coin: &CoinType) -> Option<PathBuf> {
+    let dir = if cfg!(any(target_os = "windows", target_os = "macos")) {
+        dirs::data_dir()?.join(datadir_folder(&coin.default_folder))
+    } else {
+        dirs::home_dir()?.join(&coin.default_folder)
+    };
+    Some(dir).filter(|dir| dir.is_dir())
+}
+
+/// Returns the detected blocks directory of the coin, see `detect_blockchain_dir`,
+/// or `./blocks` if there is none
 pub fn get_absolute_blockchain_dir(coin: &CoinType) -> PathBuf {
-    dirs::home_dir()
-        .expect("Unable to get home path from env!")
-        .join(&coin.default_folder)
+    detect_blockchain_dir(coin).unwrap_or_else(|| PathBuf::from(FALLBACK_BLOCKCHAIN_DIR))
 }
 
 /// Get mean value from u32 slice
@@ -64,6 +201,7 @@ pub fn get_mean(slice: &[u32]) -> f64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoin::hashes::Hash;
 
     #[test]
     fn test_arr_to_hex() {
@@ -76,6 +214,18 @@ mod tests {
         assert_eq!(arr_to_hex(&test), expected);
     }
 
+    #[test]
+    fn test_hash_order() {
+        let display = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let internal = "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000";
+        let genesis = display.parse::<sha256d::Hash>().unwrap();
+        assert_eq!(HashOrder::Display.fmt(&genesis).to_string(), display);
+        assert_eq!(HashOrder::Internal.fmt(&genesis).to_string(), internal);
+        assert_eq!(HashOrder::Display.parse(display), Some(genesis));
+        assert_eq!(HashOrder::Internal.parse(internal), Some(genesis));
+        assert_eq!(HashOrder::Internal.parse("6fe28c"), None);
+    }
+
     #[test]
     fn test_merkle_root() {
         let hashes = Vec::from([
@@ -120,4 +270,111 @@ mod tests {
         let merkle_hash = merkle_root(hashes);
         assert_eq!(merkle_hash, expected);
     }
+
+    fn pair(a: &sha256d::Hash, b: &sha256d::Hash) -> sha256d::Hash {
+        sha256d::Hash::hash(&[&a[..], &b[..]].concat())
+    }
+
+    #[test]
+    fn test_merkle_root_odd_levels() {
+        let h = (0..5u8)
+            .map(|i| sha256d::Hash::hash(&[i]))
+            .collect::<Vec<sha256d::Hash>>();
+        assert_eq!(merkle_root(vec![h[0]]), h[0]);
+        assert_eq!(merkle_root(h[..2].to_vec()), pair(&h[0], &h[1]));
+
+        // The last hash of an odd level is paired with itself
+        let expected = pair(&pair(&h[0], &h[1]), &pair(&h[2], &h[2]));
+        assert_eq!(merkle_root(h[..3].to_vec()), expected);
+
+        // 5 -> 3 -> 2 -> 1, duplicating on the first two levels
+        let level1 = [pair(&h[0], &h[1]), pair(&h[2], &h[3]), pair(&h[4], &h[4])];
+        let level2 = [pair(&level1[0], &level1[1]), pair(&level1[2], &level1[2])];
+        assert_eq!(merkle_root(h.clone()), pair(&level2[0], &level2[1]));
+        assert_eq!(
+            merkle_root_mutated(h),
+            (pair(&level2[0], &level2[1]), false)
+        );
+    }
+
+    #[test]
+    fn test_merkle_root_mutated() {
+        let h = (0..6u8)
+            .map(|i| sha256d::Hash::hash(&[i]))
+            .collect::<Vec<sha256d::Hash>>();
+
+        // [a, b, c] and [a, b, c, c] have the same root
+        let (root, mutated) = merkle_root_mutated(h[..3].to_vec());
+        assert!(!mutated);
+        assert_eq!(
+            merkle_root_mutated(vec![h[0], h[1], h[2], h[2]]),
+            (root, true)
+        );
+
+        // [a..f] and [a..f, e, f] only differ on the second level
+        let (root, mutated) = merkle_root_mutated(h.clone());
+        assert!(!mutated);
+        let mut duplicated = h.clone();
+        duplicated.extend_from_slice(&h[4..6]);
+        assert_eq!(merkle_root_mutated(duplicated), (root, true));
+
+        // Equal hashes which are not paired with each other don't mutate the root
+        let (_, mutated) = merkle_root_mutated(vec![h[0], h[1], h[1], h[2]]);
+        assert!(!mutated);
+    }
+
+    #[test]
+    fn test_merkle_branch() {
+        let h = (0..5u8)
+            .map(|i| sha256d::Hash::hash(&[i]))
+            .collect::<Vec<sha256d::Hash>>();
+        let root = merkle_root(h.clone());
+        for (index, hash) in h.iter().enumerate() {
+            let (branch_root, branch) = merkle_branch(h.clone(), index).unwrap();
+            assert_eq!(branch_root, root);
+            assert_eq!(branch.len(), 3);
+            assert_eq!(merkle_root_from_branch(*hash, index, &branch), root);
+            // The branch only proves the hash at its own position, except for a hash paired with itself
+            if index < 4 {
+                assert_ne!(merkle_root_from_branch(*hash, index ^ 1, &branch), root);
+            }
+        }
+        // The last hash is paired with itself on the first level
+        let (_, branch) = merkle_branch(h.clone(), 4).unwrap();
+        assert_eq!(branch[0], h[4]);
+        assert_eq!(branch[1], pair(&h[4], &h[4]));
+
+        // A single transaction is the root itself
+        assert_eq!(merkle_branch(vec![h[0]], 0), Some((h[0], vec![])));
+        assert!(merkle_branch(h, 5).is_none());
+    }
+
+    #[test]
+    fn test_datadir_folder() {
+        assert_eq!(
+            datadir_folder(&Path::new(".bitcoin").join("blocks")),
+            Path::new("Bitcoin").join("blocks")
+        );
+        assert_eq!(
+            datadir_folder(&Path::new(".bitcoin").join("testnet3").join("blocks")),
+            Path::new("Bitcoin").join("testnet3").join("blocks")
+        );
+        assert_eq!(
+            datadir_folder(Path::new(".namecoin")),
+            Path::new("Namecoin")
+        );
+    }
+
+    #[test]
+    fn test_get_absolute_blockchain_dir() {
+        let coin = CoinType {
+            default_folder: Path::new(".unknowncoin").join("blocks"),
+            ..Default::default()
+        };
+        assert_eq!(detect_blockchain_dir(&coin), None);
+        assert_eq!(
+            get_absolute_blockchain_dir(&coin),
+            PathBuf::from(FALLBACK_BLOCKCHAIN_DIR)
+        );
+    }
 }
@@ -41,6 +41,11 @@ impl OpError {
         }
     }
 
+    /// Returns true if the reading end of a pipe has been closed, e.g. by `| head`
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(&self.kind, OpErrorKind::IoError(err) if err.kind() == io::ErrorKind::BrokenPipe)
+    }
+
     /// Joins the Error with a new message and returns it
     pub fn join_msg(mut self, msg: &str) -> Self {
         self.message.push_str(msg);
@@ -94,12 +99,12 @@ impl fmt::Display for OpErrorKind {
             OpErrorKind::Utf8Error(ref err) => write!(f, "Utf8 Conversion: {}", err),
             OpErrorKind::ScriptError(ref err) => write!(f, "Script: {}", err),
             OpErrorKind::LevelDBError(ref err) => write!(f, "LevelDB: {}", err),
-            ref err @ OpErrorKind::PoisonError => write!(f, "Threading Error: {}", err),
-            ref err @ OpErrorKind::SendError => write!(f, "Sync: {}", err),
-            ref err @ OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs: {}", err),
-            ref err @ OpErrorKind::CallbackError => write!(f, "Callback: {}", err),
-            ref err @ OpErrorKind::ValidationError => write!(f, "Validation: {}", err),
-            ref err @ OpErrorKind::RuntimeError => write!(f, "RuntimeError: {}", err),
+            OpErrorKind::PoisonError => write!(f, "Threading Error"),
+            OpErrorKind::SendError => write!(f, "Sync Error"),
+            OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs"),
+            OpErrorKind::CallbackError => write!(f, "Callback Error"),
+            OpErrorKind::ValidationError => write!(f, "Validation Error"),
+            OpErrorKind::RuntimeError => write!(f, "RuntimeError"),
             OpErrorKind::None => write!(f, ""),
         }
     }
@@ -112,8 +117,6 @@ impl error::Error for OpErrorKind {
             OpErrorKind::ByteOrderError(ref err) => Some(err),
             OpErrorKind::Utf8Error(ref err) => Some(err),
             OpErrorKind::ScriptError(ref err) => Some(err),
-            ref err @ OpErrorKind::PoisonError => Some(err),
-            ref err @ OpErrorKind::SendError => Some(err),
             _ => None,
         }
     }
@@ -174,5 +177,11 @@ mod tests {
 
         let err = err.join_msg("Cannot proceed.");
         assert_eq!(format!("{}", err), "Cannot proceed. I/O Error: oh no!");
+
+        assert!(err.is_broken_pipe());
+
+        let err = OpError::new(OpErrorKind::InvalidArgsError).join_msg("Invalid height.");
+        assert_eq!(format!("{}", err), "Invalid height. InvalidArgs");
+        assert!(!err.is_broken_pipe());
     }
 }
@@ -0,0 +1,232 @@
+//! Bitcoin blockchain parser, see README.md for the command line interface.
+//!
+//! The parser can be embedded by constructing `ParserOptions` with a custom `Callback`:
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//!
+//! use clap::{ArgMatches, Command};
+//! use rusty_blockparser::blockchain::proto::block::Block;
+//! use rusty_blockparser::errors::OpResult;
+//! use rusty_blockparser::{BlockchainParser, Callback, ChainStorage, ParserOptions};
+//!
+//! struct TxCounter(u64);
+//!
+//! impl Callback for TxCounter {
+//!     fn build_subcommand() -> Command {
+//!         Command::new("txcounter")
+//!     }
+//!     fn new(_: &ArgMatches) -> OpResult<Self> {
+//!         Ok(TxCounter(0))
+//!     }
+//!     fn on_start(&mut self, _: u64) -> OpResult<()> {
+//!         Ok(())
+//!     }
+//!     fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+//!         self.0 += block.tx_count.value;
+//!         Ok(())
+//!     }
+//!     fn on_complete(&mut self, _: u64) -> OpResult<()> {
+//!         println!("{} transactions", self.0);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let options = ParserOptions::new(
+//!     Box::new(TxCounter(0)),
+//!     PathBuf::from("/home/user/.bitcoin/blocks"),
+//! );
+//! let chain_storage = ChainStorage::new(&options)?;
+//! BlockchainParser::new(options, chain_storage).start()?;
+//! # Ok::<(), rusty_blockparser::errors::OpError>(())
+//! ```
+//!
+//! Simple consumers can iterate over the blocks instead, see `BlockIterator` for the ordering and
+//! error semantics. The callback of the options is not invoked then:
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//!
+//! use rusty_blockparser::callbacks::benchmark::Benchmark;
+//! use rusty_blockparser::{BlockchainParser, ChainStorage, ParserOptions};
+//!
+//! let options = ParserOptions::new(Box::new(Benchmark), PathBuf::from("/home/user/.bitcoin/blocks"));
+//! let chain_storage = ChainStorage::new(&options)?;
+//! let mut parser = BlockchainParser::new(options, chain_storage);
+//! for block in parser.blocks() {
+//!     let (height, block) = block?;
+//!     println!("{}: {} transactions", height, block.tx_count.value);
+//! }
+//! # Ok::<(), rusty_blockparser::errors::OpError>(())
+//! ```
+use std::boxed::Box;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use crate::blockchain::parser::blkfile::{ALL_BLK_FILES, DEFAULT_READ_BUFFER};
+use crate::blockchain::parser::checkpoint::Checkpoint;
+use crate::blockchain::parser::types::CoinType;
+use crate::common::utils::HashOrder;
+use crate::errors::{OpError, OpResult};
+
+#[macro_use]
+extern crate log;
+extern crate bitcoin;
+extern crate byteorder;
+extern crate chrono;
+extern crate clap;
+extern crate rayon;
+extern crate rusty_leveldb;
+extern crate seek_bufread;
+
+#[macro_use]
+pub mod errors;
+pub mod blockchain;
+pub mod callbacks;
+pub mod common;
+
+pub use crate::blockchain::parser::blkfile::BlkFile;
+pub use crate::blockchain::parser::chain::ChainStorage;
+pub use crate::blockchain::parser::{BlockIterator, BlockchainParser};
+pub use crate::callbacks::Callback;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlockHeightRange {
+    start: u64,
+    end: Option<u64>,
+}
+
+impl BlockHeightRange {
+    pub fn new(start: u64, end: Option<u64>) -> OpResult<Self> {
+        // Both heights are inclusive, so a range of a single block is valid
+        if end.is_some() && start > end.unwrap() {
+            return Err(OpError::from(String::from(
+                "--start value must not be greater than --end value",
+            )));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn end(&self) -> Option<u64> {
+        self.end
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.start == 0 && self.end.is_none()
+    }
+}
+
+impl fmt::Display for BlockHeightRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let end = match self.end {
+            Some(e) => e.to_string(),
+            None => String::from("HEAD"),
+        };
+        write!(f, "{}..{}", self.start, end)
+    }
+}
+
+/// Holds all available user arguments
+pub struct ParserOptions {
+    // Name of the callback which gets executed for each block. (See callbacks/mod.rs)
+    pub callback: Box<dyn Callback>,
+    // Holds the relevant coin parameters we need for parsing
+    pub coin: CoinType,
+    // Enable this if you want to check the chain index integrity and merkle root for each block.
+    pub verify: bool,
+    // Checks if the block hash matches the block index (implied by verify)
+    pub verify_block_hash: bool,
+    // Checks the witness commitment in the coinbase of each block (BIP141)
+    pub verify_witness_commitment: bool,
+    // Excludes blocks failing the proof of work from the main chain selection
+    pub verify_pow: bool,
+    // Path to directory where blk.dat files are stored
+    pub blockchain_dir: PathBuf,
+    // Uses exactly these blk files instead of scanning blockchain_dir
+    pub blk_files: Vec<PathBuf>,
+    // Only blk files with a number in this range are read from blockchain_dir, unused with blk_files
+    pub blk_range: RangeInclusive<u64>,
+    // Fetches the blk files below this http:// URL with range requests instead
+    pub blk_url: Option<String>,
+    // Reads all blocks from this file of concatenated blocks (e.g. bootstrap.dat) without a block index
+    pub bootstrap: Option<PathBuf>,
+    // Keeps the chunks fetched from blk_url in this directory
+    pub http_cache: Option<PathBuf>,
+    // Verbosity level, 0 = Error, 1 = Info, 2 = Debug, 3+ = Trace
+    pub log_level_filter: log::LevelFilter,
+    // Appends all log output to this file as well
+    pub log_file: Option<PathBuf>,
+    // Range which is considered for parsing
+    pub range: BlockHeightRange,
+    // Only parses the last N blocks up to the tip (or --end)
+    pub max_depth: Option<u64>,
+    // Persists the last processed height periodically if set
+    pub checkpoint: Option<Checkpoint>,
+    // Shows a progress bar on stderr instead of periodic status logs
+    pub progress_bar: bool,
+    // Parses all blocks without a user callback and reports blocks which fail to parse
+    pub validate_only: bool,
+    // Logs and skips blocks which fail to parse instead of aborting the run
+    pub skip_errors: bool,
+    // Removes coinbase transactions before the callback sees them, all other transactions are unchanged
+    pub no_coinbase: bool,
+    // Parses all blocks without a user callback and reports the throughput
+    pub benchmark: bool,
+    // Size of the buffer of blk file reads in bytes
+    pub read_buffer: usize,
+    // Number of threads evaluating transactions, 0 uses all logical cores
+    pub threads: usize,
+    // Byte order of the hashes in the reports of the block index
+    pub hash_order: HashOrder,
+    // Writes all blocks which are not part of the main chain to this csv file
+    pub report_orphans: Option<PathBuf>,
+    // Writes blocks with out of order or future-dated timestamps to this csv file
+    pub report_timestamp_anomalies: Option<PathBuf>,
+    // Records size, checksum and block count of each blk file to this file
+    pub write_manifest: Option<PathBuf>,
+    // Checks all blk files against a manifest written with write_manifest
+    pub verify_manifest: Option<PathBuf>,
+}
+
+impl ParserOptions {
+    /// Returns the options the command line defaults to, parsing all Bitcoin blocks stored in
+    /// `blockchain_dir` with the given callback
+    pub fn new(callback: Box<dyn Callback>, blockchain_dir: PathBuf) -> Self {
+        ParserOptions {
+            callback,
+            coin: CoinType::default(),
+            verify: false,
+            verify_block_hash: false,
+            verify_witness_commitment: false,
+            verify_pow: false,
+            blockchain_dir,
+            blk_files: Vec::new(),
+            blk_range: ALL_BLK_FILES,
+            blk_url: None,
+            bootstrap: None,
+            http_cache: None,
+            log_level_filter: log::LevelFilter::Info,
+            log_file: None,
+            range: BlockHeightRange::new(0, None).unwrap(),
+            max_depth: None,
+            checkpoint: None,
+            progress_bar: false,
+            validate_only: false,
+            skip_errors: false,
+            no_coinbase: false,
+            benchmark: false,
+            read_buffer: DEFAULT_READ_BUFFER,
+            threads: 0,
+            hash_order: HashOrder::Display,
+            report_orphans: None,
+            report_timestamp_anomalies: None,
+            write_manifest: None,
+            verify_manifest: None,
+        }
+    }
+}
@@ -1,91 +1,44 @@
-use clap::{Arg, Command};
+use clap::builder::PossibleValue;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command};
 use std::boxed::Box;
-use std::fmt;
-use std::path::PathBuf;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
 
-use crate::blockchain::parser::chain::ChainStorage;
-use crate::blockchain::parser::types::{Bitcoin, CoinType};
-use crate::blockchain::parser::BlockchainParser;
-use crate::callbacks::balances::Balances;
-use crate::callbacks::csvdump::CsvDump;
-use crate::callbacks::opreturn::OpReturn;
-use crate::callbacks::simplestats::SimpleStats;
-use crate::callbacks::unspentcsvdump::UnspentCsvDump;
-use crate::callbacks::Callback;
-use crate::common::logger::SimpleLogger;
-use crate::common::utils;
-use crate::errors::{OpError, OpResult};
+use rusty_blockparser::blockchain::parser::blkfile::DEFAULT_READ_BUFFER;
+use rusty_blockparser::blockchain::parser::checkpoint::Checkpoint;
+use rusty_blockparser::blockchain::parser::manifest;
+use rusty_blockparser::blockchain::parser::types::{Bitcoin, CoinType, Regtest, TestNet3};
+use rusty_blockparser::blockchain::selftest;
+use rusty_blockparser::callbacks::benchmark::Benchmark;
+use rusty_blockparser::callbacks::registry::CallbackRegistry;
+use rusty_blockparser::callbacks::validate::Validate;
+use rusty_blockparser::common::hashing;
+use rusty_blockparser::common::http;
+use rusty_blockparser::common::json::{self, Value};
+use rusty_blockparser::common::logger::SimpleLogger;
+use rusty_blockparser::common::shutdown;
+use rusty_blockparser::common::utils::{self, HashOrder};
+use rusty_blockparser::errors::{OpError, OpErrorKind, OpResult};
+use rusty_blockparser::{
+    BlockHeightRange, BlockchainParser, Callback, ChainStorage, ParserOptions,
+};
 
 #[macro_use]
 extern crate log;
-extern crate chrono;
 #[macro_use]
 extern crate clap;
-extern crate bitcoin;
-extern crate byteorder;
-extern crate rayon;
-extern crate rusty_leveldb;
-extern crate seek_bufread;
-
-#[macro_use]
-pub mod errors;
-pub mod blockchain;
-pub mod common;
-pub mod callbacks;
-
-#[derive(Copy, Clone)]
-#[cfg_attr(test, derive(PartialEq, Debug))]
-pub struct BlockHeightRange {
-    start: u64,
-    end: Option<u64>,
-}
-
-impl BlockHeightRange {
-    pub fn new(start: u64, end: Option<u64>) -> OpResult<Self> {
-        if end.is_some() && start >= end.unwrap() {
-            return Err(OpError::from(String::from(
-                "--start value must be lower than --end value",
-            )));
-        }
-        Ok(Self { start, end })
-    }
-
-    pub fn is_default(&self) -> bool {
-        self.start == 0 && self.end.is_none()
-    }
-}
-
-impl fmt::Display for BlockHeightRange {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let end = match self.end {
-            Some(e) => e.to_string(),
-            None => String::from("HEAD"),
-        };
-        write!(f, "{}..{}", self.start, end)
-    }
-}
-
-/// Holds all available user arguments
-pub struct ParserOptions {
-    // Name of the callback which gets executed for each block. (See callbacks/mod.rs)
-    callback: Box<dyn Callback>,
-    // Holds the relevant coin parameters we need for parsing
-    coin: CoinType,
-    // Enable this if you want to check the chain index integrity and merkle root for each block.
-    verify: bool,
-    // Path to directory where blk.dat files are stored
-    blockchain_dir: PathBuf,
-    // Verbosity level, 0 = Error, 1 = Info, 2 = Debug, 3+ = Trace
-    log_level_filter: log::LevelFilter,
-    // Range which is considered for parsing
-    range: BlockHeightRange,
-}
 
 fn command() -> Command {
     let coins = [
         "bitcoin",
         "testnet3",
+        "regtest",
+        "bitcoincash",
         "namecoin",
         "litecoin",
         "dogecoin",
@@ -101,43 +54,366 @@ fn command() -> Command {
         .action(clap::ArgAction::SetTrue)
         .value_parser(clap::value_parser!(bool))
         .help("Verifies merkle roots and block hashes"))
+    .arg(Arg::new("verify-block-hash")
+        .long("verify-block-hash")
+        .action(clap::ArgAction::SetTrue)
+        .value_parser(clap::value_parser!(bool))
+        .help("Verifies block hashes against the block index only"))
+    .arg(Arg::new("verify-witness-commitment")
+        .long("verify-witness-commitment")
+        .action(clap::ArgAction::SetTrue)
+        .help("Verifies the witness commitment in the coinbase of each block (BIP141)"))
+    .arg(Arg::new("verify-pow")
+        .long("verify-pow")
+        .action(clap::ArgAction::SetTrue)
+        .help("Excludes blocks from the main chain whose header hash exceeds the target of nBits (double-SHA256 coins without merged mining only)"))
+    .arg(Arg::new("validate-only")
+        .long("validate-only")
+        .action(clap::ArgAction::SetTrue)
+        .help("Parses all blocks without a callback and reports blocks which fail to parse"))
+    .arg(Arg::new("skip-errors")
+        .long("skip-errors")
+        .action(clap::ArgAction::SetTrue)
+        .conflicts_with("validate-only")
+        .help("Logs and skips blocks which fail to parse instead of aborting, exits with 2 if any were skipped"))
+    .arg(Arg::new("no-coinbase")
+        .long("no-coinbase")
+        .action(clap::ArgAction::SetTrue)
+        .help("Hides coinbase transactions from the callback"))
+    .arg(Arg::new("benchmark")
+        .long("benchmark")
+        .action(clap::ArgAction::SetTrue)
+        .conflicts_with("validate-only")
+        .help("Parses all blocks without a callback and reports the throughput"))
+    .arg(Arg::new("self-test")
+        .long("self-test")
+        .action(clap::ArgAction::SetTrue)
+        .help("Checks the hashes of embedded known headers and transactions and exits"))
+    .arg(Arg::new("read-buffer-bytes")
+        .long("read-buffer-bytes")
+        .value_name("BYTES")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Size of the buffer for reading blk files (default: 8192)"))
+    .arg(Arg::new("threads")
+        .long("threads")
+        .value_name("N")
+        .value_parser(parse_threads)
+        .help("Number of threads evaluating transactions, 0 or auto uses all logical cores (default: auto)"))
     .arg(Arg::new("verbosity")
         .short('v')
         .action(clap::ArgAction::Count)
         .help("Increases verbosity level. Info=0, Debug=1, Trace=2 (default: 0)"))
+    .arg(Arg::new("quiet")
+        .short('q')
+        .long("quiet")
+        .action(clap::ArgAction::SetTrue)
+        .conflicts_with("verbosity")
+        .help("Only logs warnings and errors"))
+    .arg(Arg::new("log-file")
+        .long("log-file")
+        .value_name("FILE")
+        .help("Appends the log output with timestamps to FILE as well"))
+    .arg(Arg::new("config")
+        .long("config")
+        .value_name("FILE")
+        .help("Reads options and the callback from a JSON file, command line arguments take precedence"))
     // Add options
     .arg(Arg::new("coin")
         .short('c')
         .long("coin")
         .value_name("NAME")
-        .value_parser(clap::builder::PossibleValuesParser::new(coins))
+        .value_parser(clap::builder::PossibleValuesParser::new(coins.map(|coin| match coin {
+            "bitcoincash" => PossibleValue::new(coin).alias("bch"),
+            _ => PossibleValue::new(coin),
+        })))
         .help("Specify blockchain coin (default: bitcoin)"))
+    .arg(Arg::new("network")
+        .long("network")
+        .value_name("NAME")
+        .conflicts_with("coin")
+        .value_parser(["mainnet", "testnet", "regtest"])
+        .help("Specify Bitcoin network, shorthand for --coin bitcoin, testnet3 or regtest"))
+    .arg(Arg::new("hash-order")
+        .long("hash-order")
+        .value_name("ORDER")
+        .value_parser(["display", "internal"])
+        .default_value("display")
+        // Callbacks read it from the matches of their subcommand
+        .global(true)
+        .help("Byte order of block hashes and txids in the output, internal is the order in blocks"))
+    .arg(Arg::new("magic")
+        .long("magic")
+        .value_name("HEX")
+        .conflicts_with("coin")
+        .value_parser(parse_magic)
+        .help("Overrides the network magic of the default coin for custom chains, e.g. 0xd9b4bef9 for Bitcoin"))
     .arg(Arg::new("blockchain-dir")
         .short('d')
         .long("blockchain-dir")
-        .help("Sets blockchain directory which contains blk.dat files (default: ~/.bitcoin/blocks)"))
+        .help("Sets blockchain directory which contains blk.dat files (default: blocks directory of the local node, e.g. ~/.bitcoin/blocks, or ./blocks)"))
+    .arg(Arg::new("blk-file")
+        .long("blk-file")
+        .value_name("PATH")
+        .action(clap::ArgAction::Append)
+        .help("Uses the given blk file instead of scanning the blockchain directory (can be given multiple times)"))
+    .arg(Arg::new("blk-from")
+        .long("blk-from")
+        .value_name("N")
+        .value_parser(clap::value_parser!(u64))
+        .help("Only uses blk files from blkN.dat on, ignored with --blk-file"))
+    .arg(Arg::new("blk-to")
+        .long("blk-to")
+        .value_name("M")
+        .value_parser(clap::value_parser!(u64))
+        .help("Only uses blk files up to blkM.dat, ignored with --blk-file"))
+    .arg(Arg::new("blk-url")
+        .long("blk-url")
+        .value_name("URL")
+        .conflicts_with_all(["blk-file", "blk-from", "blk-to", "write-manifest", "verify-manifest"])
+        .help("Fetches the blk files below the given http:// URL with range requests (plain HTTP only, no HTTPS), the block index is still read from the blockchain directory"))
+    .arg(Arg::new("http-cache")
+        .long("http-cache")
+        .value_name("DIR")
+        .requires("blk-url")
+        .help("Keeps the data fetched with --blk-url in DIR, so it is only downloaded once"))
+    .arg(Arg::new("bootstrap")
+        .long("bootstrap")
+        .value_name("FILE")
+        .conflicts_with_all(["blk-file", "blk-from", "blk-to", "blk-url", "write-manifest", "verify-manifest"])
+        .help("Reads all blocks from FILE of concatenated blocks (e.g. bootstrap.dat) without the block index"))
     .arg(Arg::new("start")
         .short('s')
         .long("start")
+        .visible_alias("from")
         .value_name("HEIGHT")
         .value_parser(clap::value_parser!(u64))
         .help("Specify starting block for parsing (inclusive)"))
     .arg(Arg::new("end")
         .short('e')
         .long("end")
+        .visible_alias("to")
         .value_name("HEIGHT")
         .value_parser(clap::value_parser!(u64))
         .help("Specify last block for parsing (inclusive) (default: all known blocks)"))
+    .arg(Arg::new("max-depth")
+        .long("max-depth")
+        .value_name("COUNT")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Only parses the last COUNT blocks (combined with --start or --resume, the later start wins)"))
+    .arg(Arg::new("checkpoint")
+        .long("checkpoint")
+        .value_name("FILE")
+        .help("Persists the last processed height to FILE"))
+    .arg(Arg::new("checkpoint-interval")
+        .long("checkpoint-interval")
+        .value_name("COUNT")
+        .requires("checkpoint")
+        .value_parser(clap::value_parser!(u64))
+        .default_value("1000")
+        .help("Number of blocks between checkpoints"))
+    .arg(Arg::new("report-orphans")
+        .long("report-orphans")
+        .value_name("FILE")
+        .help("Writes stale blocks which are not part of the longest chain to FILE as csv"))
+    .arg(Arg::new("report-timestamp-anomalies")
+        .long("report-timestamp-anomalies")
+        .value_name("FILE")
+        .help("Writes blocks with a timestamp before their parent, the median-time-past or far ahead of their neighbors to FILE as csv"))
+    .arg(Arg::new("write-manifest")
+        .long("write-manifest")
+        .value_name("FILE")
+        .help("Writes size, SHA-256 and block count of each blk file to FILE"))
+    .arg(Arg::new("verify-manifest")
+        .long("verify-manifest")
+        .value_name("FILE")
+        .conflicts_with("write-manifest")
+        .help("Checks all blk files against a manifest written with --write-manifest"))
+    .arg(Arg::new("resume")
+        .long("resume")
+        .action(clap::ArgAction::SetTrue)
+        .requires("checkpoint")
+        .conflicts_with("start")
+        .help("Resumes parsing after the height stored in the checkpoint file instead of --start, refused for callbacks which need all previous blocks (e.g. utxodump or balances)"))
     // Add callbacks
-    .subcommand(UnspentCsvDump::build_subcommand())
-    .subcommand(CsvDump::build_subcommand())
-    .subcommand(SimpleStats::build_subcommand())
-    .subcommand(Balances::build_subcommand())
-    .subcommand(OpReturn::build_subcommand())
+    .subcommands(CallbackRegistry::with_builtin().subcommands())
+}
+
+/// Parses a network magic given as 4 byte hex value, with or without 0x prefix.
+/// The value is interpreted like `Coin::magic()`, so the bytes appear reversed in blk files.
+fn parse_magic(value: &str) -> Result<u32, String> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if hex.len() != 8 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(String::from("magic must be exactly 4 bytes (8 hex digits)"));
+    }
+    Ok(u32::from_str_radix(hex, 16).unwrap())
+}
+
+/// Parses a thread count, `auto` is the same as 0
+fn parse_threads(value: &str) -> Result<usize, String> {
+    match value {
+        "auto" => Ok(0),
+        _ => value
+            .parse::<usize>()
+            .map_err(|_| String::from("threads must be a number or auto")),
+    }
+}
+
+/// Loads the JSON file given with --config and merges it into the command line args
+fn load_config(args: Vec<OsString>) -> OpResult<(Vec<OsString>, Vec<String>)> {
+    // Invalid args are left for clap to report
+    let path = match command().try_get_matches_from(&args) {
+        Ok(matches) => match matches.get_one::<String>("config") {
+            Some(path) => PathBuf::from(path),
+            None => return Ok((args, Vec::new())),
+        },
+        Err(_) => return Ok((args, Vec::new())),
+    };
+    let content = fs::read_to_string(&path).map_err(|e| {
+        OpError::from(e).join_msg(&format!("Unable to read config {}:", path.display()))
+    })?;
+    let config =
+        json::parse(&content).map_err(|e| e.join_msg(&format!("in config {}", path.display())))?;
+    let mut warnings = Vec::new();
+    let args = apply_config(args, &config, &mut warnings)?;
+    Ok((args, warnings))
+}
+
+/// Converts the config entries into args and merges them with the given command line args.
+/// Options given on the command line are kept as they are, unknown keys are reported in warnings.
+///
+/// Keys are the long option names, with dashes or underscores. The callback is set with
+/// "callback" and its args with "callback_args".
+fn apply_config(
+    args: Vec<OsString>,
+    config: &Value,
+    warnings: &mut Vec<String>,
+) -> OpResult<Vec<OsString>> {
+    let entries = match config {
+        Value::Object(entries) => entries,
+        _ => {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("Config must be a JSON object."))
+        }
+    };
+    let matches = command()
+        .try_get_matches_from(&args)
+        .map_err(|e| OpError::new(OpErrorKind::InvalidArgsError).join_msg(&e.to_string()))?;
+    let command = command();
+    let mut options = Vec::new();
+    let mut callback = None;
+    let mut callback_args = Vec::new();
+    for (key, value) in entries {
+        let id = key.replace('_', "-");
+        if id == "callback" {
+            callback = Some(config_value(key, value)?);
+            continue;
+        }
+        if id == "callback-args" {
+            match value {
+                Value::Array(values) => {
+                    for value in values {
+                        callback_args.push(config_value(key, value)?);
+                    }
+                }
+                _ => callback_args.push(config_value(key, value)?),
+            }
+            continue;
+        }
+        let arg = match command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+        {
+            Some(arg) if id != "config" => arg,
+            _ => {
+                warnings.push(format!("Ignoring unknown config key: {}", key));
+                continue;
+            }
+        };
+        if matches.value_source(&id) == Some(ValueSource::CommandLine) || *value == Value::Null {
+            continue;
+        }
+        let flag = match arg.get_long() {
+            Some(long) => format!("--{}", long),
+            None => format!("-{}", arg.get_short().unwrap()),
+        };
+        match (arg.get_action(), value) {
+            (ArgAction::SetTrue, Value::Bool(enabled)) => {
+                if *enabled {
+                    options.push(flag);
+                }
+            }
+            (ArgAction::Count, Value::Number(count)) if *count >= 0.0 => {
+                options.extend((0..*count as u64).map(|_| flag.clone()));
+            }
+            (ArgAction::Append, Value::Array(values)) => {
+                for value in values {
+                    options.push(format!("{}={}", flag, config_value(key, value)?));
+                }
+            }
+            (ArgAction::Set | ArgAction::Append, value) => {
+                options.push(format!("{}={}", flag, config_value(key, value)?));
+            }
+            _ => return Err(invalid_config_value(key)),
+        }
+    }
+
+    let mut merged = args[..1].to_vec();
+    merged.extend(options.into_iter().map(OsString::from));
+    merged.extend_from_slice(&args[1..]);
+    // A callback on the command line replaces the configured one along with its args
+    match callback {
+        Some(callback) if matches.subcommand_name().is_none() => {
+            merged.push(OsString::from(callback));
+            merged.extend(callback_args.into_iter().map(OsString::from));
+        }
+        None if !callback_args.is_empty() => {
+            warnings.push(String::from("Ignoring callback_args without callback"))
+        }
+        _ => {}
+    }
+    Ok(merged)
+}
+
+/// Returns a string or number from the config as arg value
+fn config_value(key: &str, value: &Value) -> OpResult<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        _ => Err(invalid_config_value(key)),
+    }
+}
+
+fn invalid_config_value(key: &str) -> OpError {
+    OpError::new(OpErrorKind::InvalidArgsError)
+        .join_msg(&format!("Invalid value for config key {}.", key))
 }
 
 fn main() {
-    let options = match parse_args(command().get_matches()) {
+    let (args, config_warnings) = match load_config(env::args_os().collect()) {
+        Ok(loaded) => loaded,
+        Err(desc) => {
+            SimpleLogger::init(log::LevelFilter::Debug).unwrap();
+            error!(target: "main", "{}", desc);
+            process::exit(1);
+        }
+    };
+    let matches = command().get_matches_from(args);
+    if matches.get_flag("self-test") {
+        SimpleLogger::init(log::LevelFilter::Info).unwrap();
+        match selftest::run() {
+            Ok(count) => info!(target: "main", "All {} self-test checks passed.", count),
+            Err(e) => {
+                error!(target: "main", "{}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    let default_blockchain_dir = matches.get_one::<String>("blockchain-dir").is_none();
+    let options = match parse_args(matches) {
         Ok(o) => o,
         Err(desc) => {
             // Init logger to print outstanding error message
@@ -149,12 +425,42 @@ fn main() {
 
     // Apply log filter based on verbosity
     let log_level = options.log_level_filter;
-    SimpleLogger::init(log_level).expect("Unable to initialize logger!");
+    match options.log_file {
+        Some(ref path) => {
+            if let Err(e) = SimpleLogger::init_with_file(log_level, path) {
+                SimpleLogger::init(log_level).expect("Unable to initialize logger!");
+                error!(target: "main", "{}", e);
+                process::exit(1);
+            }
+        }
+        None => SimpleLogger::init(log_level).expect("Unable to initialize logger!"),
+    }
     info!(target: "main", "Starting rusty-blockparser v{} ...", env!("CARGO_PKG_VERSION"));
+    for warning in &config_warnings {
+        warn!(target: "main", "{}", warning);
+    }
+    if default_blockchain_dir && options.bootstrap.is_none() {
+        if options.blockchain_dir == Path::new(utils::FALLBACK_BLOCKCHAIN_DIR) {
+            warn!(target: "main", "No blocks directory of a local {} node found, falling back to ./{}",
+                  options.coin.name, utils::FALLBACK_BLOCKCHAIN_DIR);
+        } else {
+            info!(target: "main", "Detected blocks directory {}", options.blockchain_dir.display());
+        }
+    }
     debug!(target: "main", "Using log level {}", log_level);
+    debug!(target: "main", "Using {} double-SHA256", hashing::backend().name());
     if options.verify {
         info!(target: "main", "Configured to verify merkle roots and block hashes");
     }
+    if options.verify_block_hash && !options.verify {
+        info!(target: "main", "Configured to verify block hashes");
+    }
+    if options.verify_witness_commitment {
+        info!(target: "main", "Configured to verify witness commitments");
+    }
+    if options.verify_pow {
+        info!(target: "main", "Configured to verify the proof of work of all headers");
+    }
 
     let chain_storage = match ChainStorage::new(&options) {
         Ok(storage) => storage,
@@ -162,16 +468,38 @@ fn main() {
             error!(
                 target: "main",
                 "Cannot load blockchain data from: '{}'. {}",
-                options.blockchain_dir.display(),
+                options
+                    .bootstrap
+                    .as_ref()
+                    .unwrap_or(&options.blockchain_dir)
+                    .display(),
                 e
             );
             process::exit(1);
         }
     };
 
+    if let Err(e) = check_manifest(&options, &chain_storage) {
+        error!(target: "main", "{}", e);
+        process::exit(1);
+    }
+
+    shutdown::install();
     let mut parser = BlockchainParser::new(options, chain_storage);
     match parser.start() {
-        Ok(_) => info!(target: "main", "Fin."),
+        // Output is complete up to the interruption, but not up to the requested end
+        Ok(_) if shutdown::is_requested() => process::exit(130),
+        Ok(_) => {
+            info!(target: "main", "Fin.");
+            let code = parser.exit_code();
+            if code != 0 {
+                process::exit(code);
+            }
+        }
+        // The consumer of a streamed dump has seen enough, that's no reason to fail
+        Err(why) if why.is_broken_pipe() => {
+            info!(target: "main", "Output closed by the reader, stopping.")
+        }
         Err(why) => {
             error!("{}", why);
             process::exit(1);
@@ -179,38 +507,141 @@ fn main() {
     }
 }
 
+/// Writes or verifies the blk file manifest if requested
+fn check_manifest(options: &ParserOptions, chain_storage: &ChainStorage) -> OpResult<()> {
+    if options.write_manifest.is_none() && options.verify_manifest.is_none() {
+        return Ok(());
+    }
+    info!(target: "main", "Hashing blk files ...");
+    let actual = chain_storage.manifest()?;
+    if let Some(ref path) = options.write_manifest {
+        manifest::write(path, &actual)?;
+        info!(target: "main", "Wrote manifest of {} blk files to {}", actual.len(), path.display());
+    }
+    if let Some(ref path) = options.verify_manifest {
+        let changes = manifest::compare(&manifest::read(path)?, &actual);
+        if !changes.is_empty() {
+            for change in &changes {
+                error!(target: "main", "{}", change);
+            }
+            let msg = format!("{} blk files don't match the manifest.", changes.len());
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        info!(target: "main", "All {} blk files match the manifest", actual.len());
+    }
+    Ok(())
+}
+
+fn hash_order(matches: &clap::ArgMatches) -> HashOrder {
+    match matches.get_one::<String>("hash-order").map(String::as_str) {
+        Some("internal") => HashOrder::Internal,
+        _ => HashOrder::Display,
+    }
+}
+
 /// Parses args or panics if some requirements are not met.
 fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
     let verify = matches.get_flag("verify");
+    let verify_block_hash = matches.get_flag("verify-block-hash");
+    let verify_witness_commitment = matches.get_flag("verify-witness-commitment");
+    let verify_pow = matches.get_flag("verify-pow");
     let log_level_filter = match matches.get_count("verbosity") {
+        0 if matches.get_flag("quiet") => log::LevelFilter::Warn,
         0 => log::LevelFilter::Info,
         1 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     };
+    let log_file = matches.get_one::<String>("log-file").map(PathBuf::from);
+    // Keep the output clean for log files and pipes
+    let progress_bar = log_level_filter == log::LevelFilter::Info && io::stderr().is_terminal();
 
-    let coin = matches
-        .get_one::<String>("coin")
-        .map_or_else(|| CoinType::from(Bitcoin), |v| v.parse().unwrap());
+    let mut coin = match matches.get_one::<String>("network").map(String::as_str) {
+        Some("testnet") => CoinType::from(TestNet3),
+        Some("regtest") => CoinType::from(Regtest),
+        _ => matches
+            .get_one::<String>("coin")
+            .map_or_else(|| CoinType::from(Bitcoin), |v| v.parse().unwrap()),
+    };
+    if let Some(magic) = matches.get_one::<u32>("magic") {
+        coin.magic = *magic;
+    }
     let blockchain_dir = match matches.get_one::<String>("blockchain-dir") {
         Some(p) => PathBuf::from(p),
         None => utils::get_absolute_blockchain_dir(&coin),
     };
-    let start = matches.get_one::<u64>("start").copied().unwrap_or(0);
+    let blk_files = matches
+        .get_many::<String>("blk-file")
+        .map_or_else(Vec::new, |paths| paths.map(PathBuf::from).collect());
+    let blk_from = matches.get_one::<u64>("blk-from").copied().unwrap_or(0);
+    let blk_to = matches
+        .get_one::<u64>("blk-to")
+        .copied()
+        .unwrap_or(u64::MAX);
+    if blk_from > blk_to {
+        return Err(OpError::new(OpErrorKind::InvalidArgsError)
+            .join_msg("--blk-from can't be greater than --blk-to."));
+    }
+    let blk_url = matches.get_one::<String>("blk-url").cloned();
+    if let Some(ref url) = blk_url {
+        if let Err(e) = http::Url::parse(url) {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&e.to_string()));
+        }
+    }
+    let checkpoint = match matches.get_one::<String>("checkpoint") {
+        Some(path) => Some(Checkpoint::new(
+            PathBuf::from(path),
+            *matches.get_one::<u64>("checkpoint-interval").unwrap(),
+        )?),
+        None => None,
+    };
+    let start = match checkpoint {
+        Some(ref checkpoint) if matches.get_flag("resume") => checkpoint.load()? + 1,
+        _ => matches.get_one::<u64>("start").copied().unwrap_or(0),
+    };
     let end = matches.get_one::<u64>("end").copied();
     let range = BlockHeightRange::new(start, end)?;
+    let max_depth = matches.get_one::<u64>("max-depth").copied();
+
+    let validate_only = matches.get_flag("validate-only");
+    let skip_errors = matches.get_flag("skip-errors");
+    let no_coinbase = matches.get_flag("no-coinbase");
+    let benchmark = matches.get_flag("benchmark");
+    let read_buffer = matches
+        .get_one::<u64>("read-buffer-bytes")
+        .map_or(DEFAULT_READ_BUFFER, |bytes| *bytes as usize);
+    let threads = matches.get_one::<usize>("threads").copied().unwrap_or(0);
+    let report_orphans = matches
+        .get_one::<String>("report-orphans")
+        .map(PathBuf::from);
+    let report_timestamp_anomalies = matches
+        .get_one::<String>("report-timestamp-anomalies")
+        .map(PathBuf::from);
+    let write_manifest = matches
+        .get_one::<String>("write-manifest")
+        .map(PathBuf::from);
+    let verify_manifest = matches
+        .get_one::<String>("verify-manifest")
+        .map(PathBuf::from);
 
     // Set callback
     let callback: Box<dyn Callback>;
-    if let Some(matches) = matches.subcommand_matches("simplestats") {
-        callback = Box::new(SimpleStats::new(matches)?);
-    } else if let Some(matches) = matches.subcommand_matches("csvdump") {
-        callback = Box::new(CsvDump::new(matches)?);
-    } else if let Some(matches) = matches.subcommand_matches("unspentcsvdump") {
-        callback = Box::new(UnspentCsvDump::new(matches)?);
-    } else if let Some(matches) = matches.subcommand_matches("balances") {
-        callback = Box::new(Balances::new(matches)?);
-    } else if let Some(matches) = matches.subcommand_matches("opreturn") {
-        callback = Box::new(OpReturn::new(matches)?);
+    if validate_only {
+        if let Some(name) = matches.subcommand_name() {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("--validate-only can't be combined with {}.", name)));
+        }
+        callback = Box::new(Validate::default());
+    } else if benchmark {
+        if let Some(name) = matches.subcommand_name() {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("--benchmark can't be combined with {}.", name)));
+        }
+        callback = Box::new(Benchmark);
+    } else if let Some(result) = matches
+        .subcommand()
+        .and_then(|(name, matches)| CallbackRegistry::with_builtin().instantiate(name, matches))
+    {
+        callback = result?;
     } else {
         clap::error::Error::<clap::error::DefaultFormatter>::raw(
             clap::error::ErrorKind::MissingSubcommand,
@@ -218,14 +649,43 @@ fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
         )
         .exit();
     }
+    if matches.get_flag("resume") && callback.needs_previous_blocks() {
+        return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!(
+            "--resume can't be used with {}, its results depend on the blocks before the checkpoint.",
+            matches.subcommand_name().unwrap_or_default()
+        )));
+    }
 
     let options = ParserOptions {
         coin,
         callback,
         verify,
+        verify_block_hash,
+        verify_witness_commitment,
+        verify_pow,
         blockchain_dir,
+        blk_files,
+        blk_range: blk_from..=blk_to,
+        blk_url,
+        bootstrap: matches.get_one::<String>("bootstrap").map(PathBuf::from),
+        http_cache: matches.get_one::<String>("http-cache").map(PathBuf::from),
         log_level_filter,
+        log_file,
         range,
+        max_depth,
+        checkpoint,
+        progress_bar,
+        validate_only,
+        skip_errors,
+        no_coinbase,
+        benchmark,
+        read_buffer,
+        threads,
+        hash_order: hash_order(&matches),
+        report_orphans,
+        report_timestamp_anomalies,
+        write_manifest,
+        verify_manifest,
     };
     Ok(options)
 }
@@ -233,30 +693,200 @@ fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rusty_blockparser::blockchain::parser::blkfile::ALL_BLK_FILES;
 
     #[test]
     fn test_args_subcommand() {
         let tmp_dir = tempfile::tempdir().unwrap();
+        // Each callback writes into its own folder, it is created if missing
+        let folder = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
         parse_args(command().get_matches_from([
             "rusty-blockparser",
             "unspentcsvdump",
-            tmp_dir.path().to_str().unwrap(),
+            folder("unspentcsvdump").as_str(),
         ]))
         .unwrap();
         parse_args(command().get_matches_from([
             "rusty-blockparser",
             "csvdump",
-            tmp_dir.path().to_str().unwrap(),
+            folder("csvdump").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "csvdump",
+            "--with-fees",
+            "--dialect",
+            "postgres",
+            folder("csvdump2").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "csvdump",
+            "--with-fees",
+            "--utxo-snapshot",
+            "utxo-0-99.csv",
+            folder("csvdump4").as_str(),
+        ]))
+        .unwrap();
+        // The snapshot only fills in fees
+        assert!(command()
+            .try_get_matches_from([
+                "rusty-blockparser",
+                "csvdump",
+                "--utxo-snapshot",
+                "utxo-0-99.csv",
+                folder("csvdump5").as_str(),
+            ])
+            .is_err());
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "csvdump",
+            "--rows-per-file",
+            "1000000",
+            folder("csvdump3").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "jsondump",
+            folder("jsondump").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "jsondump",
+            "--format",
+            "bitcoind",
+            folder("jsondump2").as_str(),
         ]))
         .unwrap();
+        assert!(PathBuf::from(folder("jsondump2"))
+            .join("transactions.json.tmp")
+            .exists());
         parse_args(command().get_matches_from(["rusty-blockparser", "simplestats"])).unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "simplestats",
+            "--histograms",
+            "histograms.csv",
+        ]))
+        .unwrap();
         parse_args(command().get_matches_from([
             "rusty-blockparser",
             "balances",
-            tmp_dir.path().to_str().unwrap(),
+            folder("balances").as_str(),
         ]))
         .unwrap();
         parse_args(command().get_matches_from(["rusty-blockparser", "opreturn"])).unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "opreturn",
+            folder("opreturn").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "utxodump",
+            folder("utxodump").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "coinbase",
+            folder("coinbase").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "blockindex",
+            folder("blockindex").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "rawblocks",
+            "--single-file",
+            folder("rawblocks").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "feerates",
+            folder("feerates").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "feerates",
+            "--utxo-snapshot",
+            "utxo-0-99.csv",
+            folder("feerates2").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "spentoutputs",
+            folder("spentoutputs").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "outputswithspend",
+            "--spill-dir",
+            folder("outputswithspend").as_str(),
+            folder("outputswithspend2").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "coindays",
+            folder("coindays").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "clusters",
+            "--spill-dir",
+            folder("clusters").as_str(),
+            "--max-mem",
+            "1G",
+            folder("clusters2").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "merkleproof",
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "periodstats",
+            "--period",
+            "hour",
+            folder("periodstats").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "segwitstats",
+            folder("segwitstats").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "retarget",
+            folder("retarget").as_str(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "headers",
+            folder("headers").as_str(),
+        ]))
+        .unwrap();
     }
 
     #[test]
@@ -272,6 +902,78 @@ mod tests {
         let args = ["rusty-blockparser", "--coin", "namecoin", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
         assert_eq!(options.coin.name, "Namecoin");
+
+        for coin in ["bitcoincash", "bch"] {
+            let args = ["rusty-blockparser", "--coin", coin, "simplestats"];
+            let options = parse_args(command().get_matches_from(args)).unwrap();
+            assert_eq!(options.coin.name, "Bitcoin Cash");
+        }
+    }
+
+    #[test]
+    fn test_args_network() {
+        let args = ["rusty-blockparser", "--network", "mainnet", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.name, "Bitcoin");
+
+        let args = ["rusty-blockparser", "--network", "testnet", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.name, "TestNet3");
+        assert_eq!(options.coin.version_id, 0x6f);
+
+        let args = ["rusty-blockparser", "--network", "regtest", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.name, "Regtest");
+        assert_eq!(options.coin.magic, 0xdab5bffa);
+
+        // The magic can still be overridden, but the coin can't be given twice
+        let args = [
+            "rusty-blockparser",
+            "--network",
+            "regtest",
+            "--magic",
+            "0xd9b4bef9",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.magic, 0xd9b4bef9);
+        let args = [
+            "rusty-blockparser",
+            "--network",
+            "testnet",
+            "-c",
+            "bitcoin",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_magic() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.magic, 0xd9b4bef9);
+
+        let args = ["rusty-blockparser", "--magic", "0xDEADBEEF", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.magic, 0xdeadbeef);
+        let args = ["rusty-blockparser", "--magic", "0b110907", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.magic, 0x0b110907);
+
+        for magic in ["0xBEEF", "0xDEADBEEF00", "0xDEADBEEG", "+DEADBEE", ""] {
+            let args = ["rusty-blockparser", "--magic", magic, "simplestats"];
+            assert!(command().try_get_matches_from(args).is_err());
+        }
+        let args = [
+            "rusty-blockparser",
+            "--magic",
+            "0xDEADBEEF",
+            "-c",
+            "litecoin",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
     }
 
     #[test]
@@ -283,13 +985,32 @@ mod tests {
         let args = ["rusty-blockparser", "--verify", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
         assert!(options.verify);
+
+        let args = ["rusty-blockparser", "--verify-block-hash", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.verify);
+        assert!(options.verify_block_hash);
+
+        let args = [
+            "rusty-blockparser",
+            "--verify-witness-commitment",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.verify_witness_commitment);
+        assert!(!options.verify);
+
+        let args = ["rusty-blockparser", "--verify-pow", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.verify_pow);
+        assert!(!options.verify_witness_commitment);
     }
 
     #[test]
     fn test_args_blockchain_dir() {
         let args = ["rusty-blockparser", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        let bitcoin: crate::blockchain::parser::types::CoinType = "bitcoin".parse().unwrap();
+        let bitcoin: CoinType = "bitcoin".parse().unwrap();
         assert_eq!(
             options.blockchain_dir,
             utils::get_absolute_blockchain_dir(&bitcoin)
@@ -310,13 +1031,162 @@ mod tests {
     }
 
     #[test]
-    fn test_args_log_level() {
+    fn test_args_blk_file() {
         let args = ["rusty-blockparser", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        assert_eq!(options.log_level_filter, log::LevelFilter::Info,);
+        assert!(options.blk_files.is_empty());
 
-        let args = ["rusty-blockparser", "-v", "simplestats"];
-        let options = parse_args(command().get_matches_from(args)).unwrap();
+        let args = [
+            "rusty-blockparser",
+            "--blk-file",
+            "foo/blk00001.dat",
+            "--blk-file",
+            "bar/blk00000.dat",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.blk_files,
+            vec![
+                PathBuf::from("foo/blk00001.dat"),
+                PathBuf::from("bar/blk00000.dat")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_args_blk_range() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blk_range, ALL_BLK_FILES);
+
+        let args = ["rusty-blockparser", "--blk-from", "100", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blk_range, 100..=u64::MAX);
+        let args = [
+            "rusty-blockparser",
+            "--blk-from",
+            "100",
+            "--blk-to",
+            "100",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blk_range, 100..=100);
+
+        let args = [
+            "rusty-blockparser",
+            "--blk-from",
+            "5",
+            "--blk-to",
+            "4",
+            "simplestats",
+        ];
+        let err = parse_args(command().get_matches_from(args)).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        let args = [
+            "rusty-blockparser",
+            "--blk-url",
+            "http://host/",
+            "--blk-to",
+            "4",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_bootstrap() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.bootstrap, None);
+
+        let args = [
+            "rusty-blockparser",
+            "--bootstrap",
+            "/data/bootstrap.dat",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.bootstrap,
+            Some(PathBuf::from("/data/bootstrap.dat"))
+        );
+        let args = [
+            "rusty-blockparser",
+            "--bootstrap",
+            "bootstrap.dat",
+            "--blk-file",
+            "blk00000.dat",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_blk_url() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blk_url, None);
+        assert_eq!(options.http_cache, None);
+
+        let args = [
+            "rusty-blockparser",
+            "--blk-url",
+            "http://minio.local:9000/bucket/blocks",
+            "--http-cache",
+            "cache",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.blk_url.as_deref(),
+            Some("http://minio.local:9000/bucket/blocks")
+        );
+        assert_eq!(options.http_cache, Some(PathBuf::from("cache")));
+
+        let args = [
+            "rusty-blockparser",
+            "--blk-url",
+            "https://s3.amazonaws.com/b",
+            "simplestats",
+        ];
+        let err = parse_args(command().get_matches_from(args)).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+
+        let args = ["rusty-blockparser", "--http-cache", "cache", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_hash_order() {
+        let matches = command().get_matches_from(["rusty-blockparser", "simplestats"]);
+        assert_eq!(hash_order(&matches), HashOrder::Display);
+        let args = [
+            "rusty-blockparser",
+            "--hash-order",
+            "internal",
+            "simplestats",
+        ];
+        let matches = command().get_matches_from(args);
+        assert_eq!(hash_order(&matches), HashOrder::Internal);
+        // The callbacks read it from the matches of their subcommand
+        let (_, sub_matches) = matches.subcommand().unwrap();
+        assert_eq!(hash_order(sub_matches), HashOrder::Internal);
+        let options = parse_args(matches).unwrap();
+        assert_eq!(options.hash_order, HashOrder::Internal);
+        let args = ["rusty-blockparser", "--hash-order", "little", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_log_level() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.log_level_filter, log::LevelFilter::Info,);
+
+        let args = ["rusty-blockparser", "-v", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
         assert_eq!(options.log_level_filter, log::LevelFilter::Debug,);
 
         let args = ["rusty-blockparser", "-vv", "simplestats"];
@@ -326,77 +1196,393 @@ mod tests {
         let args = ["rusty-blockparser", "-vvv", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
         assert_eq!(options.log_level_filter, log::LevelFilter::Trace,);
+        assert!(options.log_file.is_none());
+
+        let args = ["rusty-blockparser", "--log-file", "run.log", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.log_file, Some(PathBuf::from("run.log")));
+
+        let args = ["rusty-blockparser", "--quiet", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.log_level_filter, log::LevelFilter::Warn);
+        assert!(!options.progress_bar);
+        assert!(command()
+            .try_get_matches_from(["rusty-blockparser", "-q", "-v", "simplestats"])
+            .is_err());
     }
 
     #[test]
     fn test_args_start() {
         let args = ["rusty-blockparser", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        assert_eq!(
-            options.range,
-            BlockHeightRange {
-                start: 0,
-                end: None
-            }
-        );
+        assert_eq!(options.range, BlockHeightRange::new(0, None).unwrap());
 
         let args = ["rusty-blockparser", "-s", "10", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        assert_eq!(
-            options.range,
-            BlockHeightRange {
-                start: 10,
-                end: None
-            }
-        );
+        assert_eq!(options.range, BlockHeightRange::new(10, None).unwrap());
 
         let args = ["rusty-blockparser", "--start", "10", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        assert_eq!(
-            options.range,
-            BlockHeightRange {
-                start: 10,
-                end: None
-            }
-        );
+        assert_eq!(options.range, BlockHeightRange::new(10, None).unwrap());
+
+        let args = ["rusty-blockparser", "--from", "10", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(10, None).unwrap());
     }
 
     #[test]
     fn test_args_end() {
         let args = ["rusty-blockparser", "-e", "10", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        assert_eq!(
-            options.range,
-            BlockHeightRange {
-                start: 0,
-                end: Some(10)
-            }
-        );
+        assert_eq!(options.range, BlockHeightRange::new(0, Some(10)).unwrap());
 
         let args = ["rusty-blockparser", "--end", "10", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
-        assert_eq!(
-            options.range,
-            BlockHeightRange {
-                start: 0,
-                end: Some(10)
-            }
-        );
+        assert_eq!(options.range, BlockHeightRange::new(0, Some(10)).unwrap());
+
+        let args = ["rusty-blockparser", "--to", "10", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(0, Some(10)).unwrap());
     }
 
     #[test]
     fn test_args_start_and_end() {
         let args = ["rusty-blockparser", "-s", "1", "-e", "2", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(1, Some(2)).unwrap());
+
+        let args = [
+            "rusty-blockparser",
+            "--from",
+            "5",
+            "--to",
+            "5",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(5, Some(5)).unwrap());
+
+        let args = ["rusty-blockparser", "-s", "2", "-e", "1", "simplestats"];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+    }
+
+    #[test]
+    fn test_args_resume() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("checkpoint");
+        let path = path.to_str().unwrap();
+
+        let args = ["rusty-blockparser", "--checkpoint", path, "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
         assert_eq!(
-            options.range,
-            BlockHeightRange {
-                start: 1,
-                end: Some(2)
-            }
+            options.checkpoint,
+            Some(Checkpoint::new(PathBuf::from(path), 1000).unwrap())
         );
 
-        let args = ["rusty-blockparser", "-s", "2", "-e", "1", "simplestats"];
+        // Resuming requires an existing checkpoint
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint",
+            path,
+            "--resume",
+            "simplestats",
+        ];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+
+        options.checkpoint.unwrap().save(41).unwrap();
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(42, None).unwrap());
+
+        // Only the last block of the range is left
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint",
+            path,
+            "--resume",
+            "-e",
+            "42",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(42, Some(42)).unwrap());
+
+        let args = ["rusty-blockparser", "--resume", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+        let args = [
+            "rusty-blockparser",
+            "--checkpoint",
+            path,
+            "--resume",
+            "-s",
+            "1",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+
+        // The UTXO set of the blocks before the checkpoint is gone
+        let resume = |callback: &[&str]| {
+            let dump_dir = tempfile::tempdir().unwrap();
+            let mut args = vec!["rusty-blockparser", "--checkpoint", path, "--resume"];
+            args.extend_from_slice(callback);
+            args.push(dump_dir.path().to_str().unwrap());
+            parse_args(command().get_matches_from(args))
+        };
+        let err = resume(&["utxodump"]).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        assert!(resume(&["csvdump", "--with-fees"]).is_err());
+        assert!(resume(&["csvdump"]).is_ok());
+    }
+
+    #[test]
+    fn test_args_max_depth() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.max_depth, None);
+
+        let args = ["rusty-blockparser", "--max-depth", "144", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.max_depth, Some(144));
+
+        let args = ["rusty-blockparser", "--max-depth", "0", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_report_orphans() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.report_orphans, None);
+
+        let args = [
+            "rusty-blockparser",
+            "--report-orphans",
+            "stale.csv",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.report_orphans, Some(PathBuf::from("stale.csv")));
+        assert_eq!(options.report_timestamp_anomalies, None);
+
+        let args = [
+            "rusty-blockparser",
+            "--report-timestamp-anomalies",
+            "timestamps.csv",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.report_timestamp_anomalies,
+            Some(PathBuf::from("timestamps.csv"))
+        );
+    }
+
+    #[test]
+    fn test_args_manifest() {
+        let args = [
+            "rusty-blockparser",
+            "--write-manifest",
+            "blk.csv",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.write_manifest, Some(PathBuf::from("blk.csv")));
+        assert_eq!(options.verify_manifest, None);
+
+        let args = [
+            "rusty-blockparser",
+            "--verify-manifest",
+            "blk.csv",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.verify_manifest, Some(PathBuf::from("blk.csv")));
+
+        let args = [
+            "rusty-blockparser",
+            "--write-manifest",
+            "a.csv",
+            "--verify-manifest",
+            "b.csv",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_validate_only() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.validate_only);
+
+        let args = ["rusty-blockparser", "--validate-only"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.validate_only);
+
+        let args = ["rusty-blockparser", "--validate-only", "simplestats"];
         assert!(parse_args(command().get_matches_from(args)).is_err());
     }
+
+    #[test]
+    fn test_args_skip_errors() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.skip_errors);
+
+        let args = ["rusty-blockparser", "--skip-errors", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.skip_errors);
+
+        let args = ["rusty-blockparser", "--skip-errors", "--validate-only"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_no_coinbase() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.no_coinbase);
+        assert!(!options.callback.needs_coinbase());
+
+        let args = [
+            "rusty-blockparser",
+            "--no-coinbase",
+            "merkleproof",
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.no_coinbase);
+        assert!(options.callback.needs_coinbase());
+
+        // Spends of coinbase outputs would look like spends of unknown outputs
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let args = [
+            "rusty-blockparser",
+            "--no-coinbase",
+            "utxodump",
+            tmp_dir.path().to_str().unwrap(),
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.callback.needs_coinbase());
+    }
+
+    #[test]
+    fn test_args_threads() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.threads, 0);
+
+        let args = ["rusty-blockparser", "--threads", "4", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.threads, 4);
+
+        let args = ["rusty-blockparser", "--threads", "auto", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.threads, 0);
+
+        let args = ["rusty-blockparser", "--threads", "many", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_read_buffer() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.read_buffer, DEFAULT_READ_BUFFER);
+
+        let args = [
+            "rusty-blockparser",
+            "--read-buffer-bytes",
+            "1048576",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.read_buffer, 1 << 20);
+
+        let args = [
+            "rusty-blockparser",
+            "--read-buffer-bytes",
+            "0",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_benchmark() {
+        let args = ["rusty-blockparser", "--benchmark"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.benchmark);
+        assert!(!options.validate_only);
+
+        let args = ["rusty-blockparser", "--benchmark", "simplestats"];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+        let args = ["rusty-blockparser", "--benchmark", "--validate-only"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_config() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_dir = dump_dir.path().to_str().unwrap();
+        let config = json::parse(&format!(
+            r#"{{"blockchain_dir": "/data/blocks", "network": "testnet", "end": 1000,
+                "verify": true, "verbosity": 1, "blk-file": ["a.dat", "b.dat"],
+                "threads": 4, "colour": true, "callback": "csvdump", "callback_args": ["{}"]}}"#,
+            dump_dir
+        ))
+        .unwrap();
+        let to_args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let mut warnings = Vec::new();
+        let args = apply_config(to_args(&["rusty-blockparser"]), &config, &mut warnings).unwrap();
+        assert_eq!(warnings, vec!["Ignoring unknown config key: colour"]);
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blockchain_dir, PathBuf::from("/data/blocks"));
+        assert_eq!(options.coin.name, "TestNet3");
+        assert_eq!(options.range.end(), Some(1000));
+        assert!(options.verify);
+        assert_eq!(options.log_level_filter, log::LevelFilter::Debug);
+        assert_eq!(options.threads, 4);
+        assert_eq!(
+            options.blk_files,
+            vec![PathBuf::from("a.dat"), PathBuf::from("b.dat")]
+        );
+
+        // Command line args take precedence, including the callback
+        let cli = to_args(&[
+            "rusty-blockparser",
+            "--end",
+            "5",
+            "--blk-file",
+            "c.dat",
+            "simplestats",
+        ]);
+        let args = apply_config(cli, &config, &mut Vec::new()).unwrap();
+        let matches = command().get_matches_from(args);
+        assert_eq!(matches.subcommand_name(), Some("simplestats"));
+        let options = parse_args(matches).unwrap();
+        assert_eq!(options.range.end(), Some(5));
+        assert_eq!(options.blk_files, vec![PathBuf::from("c.dat")]);
+        assert!(options.verify);
+
+        let config_path = tmp_dir.path().join("config.json");
+        fs::write(&config_path, r#"{"verify": "yes"}"#).unwrap();
+        let args = to_args(&[
+            "rusty-blockparser",
+            "--config",
+            config_path.to_str().unwrap(),
+        ]);
+        let err = load_config(args).err().unwrap();
+        assert!(matches!(err.kind, OpErrorKind::InvalidArgsError));
+        fs::write(&config_path, "[1, 2]").unwrap();
+        let args = to_args(&[
+            "rusty-blockparser",
+            "--config",
+            config_path.to_str().unwrap(),
+        ]);
+        assert!(load_config(args).is_err());
+        let args = to_args(&["rusty-blockparser", "simplestats"]);
+        assert_eq!(load_config(args.clone()).unwrap(), (args, Vec::new()));
+    }
 }
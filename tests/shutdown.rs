@@ -0,0 +1,13 @@
+//! Runs in its own test binary, the signal sets a process-wide flag which would stop the parser
+//! tests running in the same process.
+#![cfg(unix)]
+
+use rusty_blockparser::common::shutdown;
+
+#[test]
+fn test_sigint_requests_shutdown() {
+    shutdown::install();
+    assert!(!shutdown::is_requested());
+    unsafe { libc::raise(libc::SIGINT) };
+    assert!(shutdown::is_requested());
+}